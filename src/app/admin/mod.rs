@@ -2,13 +2,35 @@
 //!
 //! * `/metrics` -- reports prometheus-formatted metrics.
 //! * `/ready` -- returns 200 when the proxy is ready to participate in meshed traffic.
+//! * `/health-checks` -- lists, as JSON, the health of each actively probed
+//!   endpoint, by destination.
+//! * `/routers` -- lists, as JSON, the targets in each router's cache.
+//! * `/endpoints` -- lists, as JSON, each destination's resolved endpoints
+//!   with their metadata and, for peak-EWMA balancers, their load.
+//! * `/profiles` -- lists, as JSON, the routes applied to each destination.
+//! * `/identity` -- describes, as JSON, the local identity and its current
+//!   certificate chain's subjects, issuers, SANs, and validity.
+//! * `/proxy-log-level` -- returns the log filter on `GET`; on `PUT`, replaces
+//!   it with the request body, unless it is invalid. The previous filter is
+//!   restored after the duration given by the optional `revert_after` query
+//!   parameter (e.g. `?revert_after=5m`). `PUT` requests are only accepted
+//!   from loopback addresses, with bodies of at most 4KiB.
 
-use futures::future::{self, FutureResult};
-use http::StatusCode;
-use hyper::{service::Service, Body, Request, Response};
-use std::io;
+use futures::future::{self, Future};
+use futures::Stream;
+use http::{self, StatusCode};
+use hyper::{self, service::Service, Body, Request, Response};
+use std::net::IpAddr;
+use std::time::Duration;
+use std::{io, str};
+use tokio::executor::{DefaultExecutor, Executor};
 
+use super::{config, identity, profiles};
+use control::RemoteAddr;
+use json;
+use logging;
 use metrics;
+use proxy::http::{balance, health, router};
 
 mod readiness;
 pub use self::readiness::{Latch, Readiness};
@@ -20,16 +42,34 @@ where
 {
     metrics: metrics::Serve<M>,
     ready: Readiness,
+    health: health::Report,
+    routers: router::Report,
+    endpoints: balance::report::Report,
+    profiles: profiles::report::Report,
+    identity: identity::Report,
 }
 
 impl<M> Admin<M>
 where
     M: metrics::FmtMetrics,
 {
-    pub fn new(m: M, ready: Readiness) -> Self {
+    pub fn new(
+        m: M,
+        ready: Readiness,
+        health: health::Report,
+        routers: router::Report,
+        endpoints: balance::report::Report,
+        profiles: profiles::report::Report,
+        identity: identity::Report,
+    ) -> Self {
         Self {
             metrics: metrics::Serve::new(m),
             ready,
+            health,
+            routers,
+            endpoints,
+            profiles,
+            identity,
         }
     }
 
@@ -46,6 +86,153 @@ where
                 .expect("builder with known status code must not fail")
         }
     }
+
+    fn json_rsp(value: json::Value) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(format!("{}\n", value).into())
+            .expect("builder with known status code must not fail")
+    }
+}
+
+type ResponseFuture = Box<Future<Item = Response<Body>, Error = io::Error> + Send>;
+
+/// The largest log filter that may be set, in bytes.
+const MAX_LOG_LEVEL_BODY: usize = 4 * 1024;
+
+/// Fails to read a log filter from a request body.
+enum BodyError {
+    TooLarge,
+    Read(hyper::Error),
+}
+
+fn text_rsp(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .body(body.into())
+        .expect("builder with known status code must not fail")
+}
+
+fn log_level_rsp(req: Request<Body>) -> ResponseFuture {
+    match *req.method() {
+        http::Method::GET => {
+            let filter = format!("{}\n", logging::filter());
+            Box::new(future::ok(text_rsp(StatusCode::OK, filter)))
+        }
+        http::Method::PUT => {
+            if !is_loopback(&req) {
+                let msg = "the log filter may only be set from localhost\n".to_owned();
+                return Box::new(future::ok(text_rsp(StatusCode::FORBIDDEN, msg)));
+            }
+            let revert_after = match parse_revert_after(req.uri()) {
+                Ok(after) => after,
+                Err(msg) => {
+                    return Box::new(future::ok(text_rsp(StatusCode::BAD_REQUEST, msg)));
+                }
+            };
+            let rsp = req
+                .into_body()
+                .map_err(BodyError::Read)
+                .fold(Vec::new(), |mut body, chunk| {
+                    if body.len() + chunk.len() > MAX_LOG_LEVEL_BODY {
+                        return Err(BodyError::TooLarge);
+                    }
+                    body.extend_from_slice(&chunk);
+                    Ok(body)
+                })
+                .then(move |body| -> Result<_, io::Error> {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(BodyError::TooLarge) => {
+                            let msg = format!(
+                                "the log filter must be at most {} bytes\n",
+                                MAX_LOG_LEVEL_BODY
+                            );
+                            return Ok(text_rsp(StatusCode::PAYLOAD_TOO_LARGE, msg));
+                        }
+                        Err(BodyError::Read(e)) => {
+                            let msg = format!("failed to read request body: {}\n", e);
+                            return Ok(text_rsp(StatusCode::BAD_REQUEST, msg));
+                        }
+                    };
+                    let filter = match str::from_utf8(&body) {
+                        Ok(filter) if !filter.trim().is_empty() => filter.trim(),
+                        _ => {
+                            let msg = "a log filter is required\n".to_owned();
+                            return Ok(text_rsp(StatusCode::BAD_REQUEST, msg));
+                        }
+                    };
+
+                    Ok(set_log_level(filter, revert_after))
+                });
+            Box::new(rsp)
+        }
+        _ => Box::new(future::ok(
+            Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(http::header::ALLOW, "GET, PUT")
+                .body(Body::empty())
+                .expect("builder with known status code must not fail"),
+        )),
+    }
+}
+
+/// Returns true if the request was sent from a loopback address.
+fn is_loopback<B>(req: &Request<B>) -> bool {
+    match req.extensions().get::<RemoteAddr>() {
+        Some(&RemoteAddr(addr)) => match addr.ip() {
+            IpAddr::V4(ip) => ip.is_loopback(),
+            IpAddr::V6(ip) => {
+                ip.is_loopback() || ip.to_ipv4().map(|ip| ip.is_loopback()).unwrap_or(false)
+            }
+        },
+        None => false,
+    }
+}
+
+fn set_log_level(filter: &str, revert_after: Option<Duration>) -> Response<Body> {
+    let revert = match logging::set_filter(filter) {
+        Ok(revert) => revert,
+        Err(e) => {
+            let msg = format!("invalid log filter {:?}: {}\n", filter, e);
+            return text_rsp(StatusCode::BAD_REQUEST, msg);
+        }
+    };
+    info!("log filter set to {:?}", filter);
+
+    if let Some(after) = revert_after {
+        let spawn = DefaultExecutor::current().spawn(revert.revert_after(after));
+        if let Err(e) = spawn {
+            // The filter would otherwise never be reverted.
+            error!("failed to schedule log filter revert: {:?}", e);
+            return text_rsp(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to schedule log filter revert\n".to_owned(),
+            );
+        }
+    }
+
+    text_rsp(StatusCode::OK, format!("{}\n", filter))
+}
+
+/// Parses the optional `revert_after` query parameter.
+fn parse_revert_after(uri: &http::Uri) -> Result<Option<Duration>, String> {
+    let query = match uri.query() {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+    for param in query.split('&') {
+        let mut kv = param.splitn(2, '=');
+        if kv.next() == Some("revert_after") {
+            let value = kv.next().unwrap_or("");
+            return config::parse_duration(value)
+                .map(Some)
+                .map_err(|_| format!("invalid revert_after: {:?}\n", value));
+        }
+    }
+    Ok(None)
 }
 
 impl<M> Service for Admin<M>
@@ -55,19 +242,24 @@ where
     type ReqBody = Body;
     type ResBody = Body;
     type Error = io::Error;
-    type Future = FutureResult<Response<Body>, Self::Error>;
+    type Future = ResponseFuture;
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        match req.uri().path() {
-            "/metrics" => self.metrics.call(req),
-            "/ready" => future::ok(self.ready_rsp()),
-            _ => future::ok(
-                Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Body::empty())
-                    .expect("builder with known status code must not fail"),
-            ),
-        }
+        let rsp = match req.uri().path() {
+            "/metrics" => return Box::new(self.metrics.call(req)),
+            "/proxy-log-level" => return log_level_rsp(req),
+            "/ready" => self.ready_rsp(),
+            "/health-checks" => Self::json_rsp(self.health.to_json()),
+            "/routers" => Self::json_rsp(self.routers.to_json()),
+            "/endpoints" => Self::json_rsp(self.endpoints.to_json()),
+            "/profiles" => Self::json_rsp(self.profiles.to_json()),
+            "/identity" => Self::json_rsp(self.identity.to_json()),
+            _ => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::empty())
+                .expect("builder with known status code must not fail"),
+        };
+        Box::new(future::ok(rsp))
     }
 }
 
@@ -78,8 +270,21 @@ mod tests {
     use tokio::runtime::current_thread::Runtime;
 
     use super::*;
+    use control::RemoteAddr;
     use http::method::Method;
 
+    /// Returns a request to set the log filter from `remote`.
+    fn put_log_level(uri: &str, body: Body, remote: &str) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(Method::PUT)
+            .uri(uri)
+            .body(body)
+            .unwrap();
+        req.extensions_mut()
+            .insert(RemoteAddr(remote.parse().unwrap()));
+        req
+    }
+
     const TIMEOUT: Duration = Duration::from_secs(1);
 
     #[test]
@@ -88,7 +293,15 @@ mod tests {
         let l1 = l0.clone();
 
         let mut rt = Runtime::new().unwrap();
-        let mut srv = Admin::new((), r);
+        let mut srv = Admin::new(
+            (),
+            r,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
         macro_rules! call {
             () => {{
                 let r = Request::builder()
@@ -109,4 +322,98 @@ mod tests {
         drop(l1);
         assert_eq!(call!().status(), StatusCode::OK);
     }
+
+    #[test]
+    fn serves_routers_as_json() {
+        use futures::Stream;
+
+        let (r, _l) = Readiness::new();
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new(
+            (),
+            r,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("http://4.3.2.1:5678/routers")
+            .body(Body::empty())
+            .unwrap();
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::OK);
+        assert_eq!(
+            rsp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+
+        let body = rt
+            .block_on_for(TIMEOUT, rsp.into_body().concat2())
+            .expect("body");
+        let body = ::std::str::from_utf8(&body).expect("utf-8");
+        assert_eq!(json::parse(body).expect("json"), json::object(vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_log_level_changes() {
+        let (r, _l) = Readiness::new();
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new(
+            (),
+            r,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+
+        let req = put_log_level(
+            "http://4.3.2.1:5678/proxy-log-level?revert_after=soon",
+            "debug".into(),
+            "127.0.0.1:4567",
+        );
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::BAD_REQUEST);
+
+        let req = put_log_level(
+            "http://4.3.2.1:5678/proxy-log-level",
+            "linkerd2_proxy=loud".into(),
+            "127.0.0.1:4567",
+        );
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::BAD_REQUEST);
+
+        let req = put_log_level(
+            "http://4.3.2.1:5678/proxy-log-level",
+            "debug".into(),
+            "10.1.1.1:4567",
+        );
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(
+            rsp.status(),
+            StatusCode::FORBIDDEN,
+            "remote peers may not set the log filter"
+        );
+
+        let req = put_log_level(
+            "http://4.3.2.1:5678/proxy-log-level",
+            vec![b'a'; MAX_LOG_LEVEL_BODY + 1].into(),
+            "[::1]:4567",
+        );
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("http://4.3.2.1:5678/proxy-log-level")
+            .body("debug".into())
+            .unwrap();
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
 }
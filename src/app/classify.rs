@@ -3,7 +3,7 @@ use std::borrow::Cow;
 use http;
 
 pub use proxy::http::metrics::classify::{self, layer, CanClassify};
-use proxy::http::{profiles, timeout, HasH2Reason};
+use proxy::http::{outlier, profiles, timeout, HasH2Reason};
 
 #[derive(Clone, Debug)]
 pub enum Request {
@@ -23,9 +23,19 @@ pub enum Eos {
     Default(http::StatusCode),
     Grpc(GrpcEos),
     Profile(Class),
+    /// Classification is deferred until the end of the stream because a
+    /// response class depends on trailers.
+    ProfileTrailers(ProfileEos),
     Error(&'static str),
 }
 
+#[derive(Clone, Debug)]
+pub struct ProfileEos {
+    classes: profiles::ResponseClasses,
+    status: http::StatusCode,
+    headers: http::HeaderMap,
+}
+
 #[derive(Clone, Debug)]
 pub enum GrpcEos {
     NoBody(Class),
@@ -104,18 +114,19 @@ impl Response {
         rsp: &http::Response<B>,
         classes: &[profiles::ResponseClass],
     ) -> Option<Class> {
-        for class in classes {
-            if class.is_match(rsp) {
-                let result = if class.is_failure() {
-                    SuccessOrFailure::Failure
-                } else {
-                    SuccessOrFailure::Success
-                };
-                return Some(Class::Default(result));
-            }
-        }
+        classes
+            .iter()
+            .find(|class| class.is_match(rsp))
+            .map(Self::profile_class)
+    }
 
-        None
+    fn profile_class(class: &profiles::ResponseClass) -> Class {
+        let result = if class.is_failure() {
+            SuccessOrFailure::Failure
+        } else {
+            SuccessOrFailure::Success
+        };
+        Class::Default(result)
     }
 }
 
@@ -135,13 +146,23 @@ impl classify::ClassifyResponse for Response {
             Response::Grpc => grpc_class(rsp.headers())
                 .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
                 .unwrap_or(Eos::Grpc(GrpcEos::Open)),
-            Response::Profile(ref classes) => Self::match_class(rsp, classes.as_ref())
-                .map(Eos::Profile)
-                .unwrap_or_else(|| {
-                    grpc_class(rsp.headers())
-                        .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
-                        .unwrap_or_else(|| Eos::Default(rsp.status()))
-                }),
+            Response::Profile(classes) => {
+                if classes.requires_trailers() {
+                    return Eos::ProfileTrailers(ProfileEos {
+                        classes,
+                        status: rsp.status(),
+                        headers: rsp.headers().clone(),
+                    });
+                }
+
+                Self::match_class(rsp, classes.as_ref())
+                    .map(Eos::Profile)
+                    .unwrap_or_else(|| {
+                        grpc_class(rsp.headers())
+                            .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
+                            .unwrap_or_else(|| Eos::Default(rsp.status()))
+                    })
+            }
         }
     }
 
@@ -168,6 +189,7 @@ impl classify::ClassifyEos for Eos {
                 .and_then(grpc_class)
                 .unwrap_or_else(|| Class::Grpc(SuccessOrFailure::Failure, 0)),
             Eos::Profile(class) => class,
+            Eos::ProfileTrailers(eos) => eos.eos(trailers),
             Eos::Error(msg) => Class::Stream(SuccessOrFailure::Failure, msg.into()),
         }
     }
@@ -177,6 +199,29 @@ impl classify::ClassifyEos for Eos {
     }
 }
 
+// === impl ProfileEos ===
+
+impl ProfileEos {
+    fn eos(self, trailers: Option<&http::HeaderMap>) -> Class {
+        let ProfileEos {
+            classes,
+            status,
+            headers,
+        } = self;
+
+        classes
+            .iter()
+            .find(|class| class.is_match_eos(status, &headers, trailers))
+            .map(Response::profile_class)
+            .unwrap_or_else(|| {
+                let eos = grpc_class(&headers)
+                    .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
+                    .unwrap_or_else(|| Eos::Default(status));
+                classify::ClassifyEos::eos(eos, trailers)
+            })
+    }
+}
+
 fn grpc_class(headers: &http::HeaderMap) -> Option<Class> {
     headers
         .get("grpc-status")
@@ -216,12 +261,20 @@ impl Class {
     }
 }
 
+impl outlier::IsFailure for Class {
+    fn is_failure(&self) -> bool {
+        Class::is_failure(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use http::{HeaderMap, Response, StatusCode};
+    use regex::Regex;
 
     use super::{Class, SuccessOrFailure};
     use proxy::http::metrics::classify::{ClassifyEos as _CE, ClassifyResponse as _CR};
+    use proxy::http::profiles::{ResponseClass, ResponseMatch, Route, ValueMatch};
 
     #[test]
     fn http_response_status_ok() {
@@ -303,4 +356,101 @@ mod tests {
             .eos(Some(&trailers));
         assert_eq!(class, Class::Grpc(SuccessOrFailure::Failure, 3));
     }
+
+    fn profile(classes: Vec<ResponseClass>) -> super::Response {
+        let route = Route::new(None.into_iter(), classes);
+        super::Response::Profile(route.response_classes().clone())
+    }
+
+    #[test]
+    fn profile_response_header_failure() {
+        let match_ = ResponseMatch::Header {
+            name: "x-app-error".parse().unwrap(),
+            value: ValueMatch::Regex(Regex::new("^(fatal|retry)$").unwrap()),
+        };
+        let class = profile(vec![ResponseClass::new(true, match_)]);
+
+        let rsp = Response::builder()
+            .status(StatusCode::OK)
+            .header("x-app-error", "retry")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            class.clone().start(&rsp).eos(None),
+            Class::Default(SuccessOrFailure::Failure)
+        );
+
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+        assert_eq!(
+            class.start(&rsp).eos(None),
+            Class::Default(SuccessOrFailure::Success)
+        );
+    }
+
+    #[test]
+    fn profile_grpc_status_trailer_failure() {
+        let class = profile(vec![ResponseClass::new(
+            true,
+            ResponseMatch::GrpcStatus { min: 14, max: 14 },
+        )]);
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 14.into());
+        assert_eq!(
+            class.clone().start(&rsp).eos(Some(&trailers)),
+            Class::Default(SuccessOrFailure::Failure)
+        );
+
+        // Statuses that do not match fall back to gRPC classification.
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 0.into());
+        assert_eq!(
+            class.start(&rsp).eos(Some(&trailers)),
+            Class::Grpc(SuccessOrFailure::Success, 0)
+        );
+    }
+
+    #[test]
+    fn profile_grpc_status_trailers_only_response() {
+        let class = profile(vec![ResponseClass::new(
+            true,
+            ResponseMatch::GrpcStatus { min: 4, max: 4 },
+        )]);
+        let rsp = Response::builder()
+            .status(StatusCode::OK)
+            .header("grpc-status", "4")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            class.start(&rsp).eos(None),
+            Class::Default(SuccessOrFailure::Failure)
+        );
+    }
+
+    #[test]
+    fn profile_classes_match_in_order() {
+        let class = profile(vec![
+            ResponseClass::new(false, ResponseMatch::GrpcStatus { min: 5, max: 5 }),
+            ResponseClass::new(
+                true,
+                ResponseMatch::Status {
+                    min: StatusCode::OK,
+                    max: StatusCode::OK,
+                },
+            ),
+        ]);
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 5.into());
+        assert_eq!(
+            class.clone().start(&rsp).eos(Some(&trailers)),
+            Class::Default(SuccessOrFailure::Success)
+        );
+        assert_eq!(
+            class.start(&rsp).eos(None),
+            Class::Default(SuccessOrFailure::Failure)
+        );
+    }
 }
@@ -6,13 +6,18 @@ use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
 
+use http;
 use indexmap::IndexSet;
 
 use super::control::ControlAddr;
 use super::identity;
+use super::profiles;
 use addr;
+use control::destination;
 use convert::TryFrom;
 use dns;
+use fs_watch;
+use proxy::http::{balance, health, locality, outlier, retry};
 use proxy::reconnect::Backoff;
 use transport::tls;
 use {Addr, Conditional};
@@ -90,6 +95,31 @@ pub struct Config {
 
     pub outbound_max_requests_in_flight: usize,
 
+    /// The maximum number of request body bytes buffered so that a request
+    /// may be retried.
+    pub outbound_retry_max_buffer_bytes: usize,
+
+    /// Retry settings for retryable routes from the Destination service,
+    /// whose profiles cannot describe them.
+    pub outbound_retry_defaults: profiles::RetryDefaults,
+
+    /// The load-balancing algorithm used for outbound destinations whose
+    /// profiles do not specify one.
+    pub outbound_load_balancer: balance::Algorithm,
+
+    /// Configures when outbound endpoints are ejected from load balancers.
+    pub outbound_outlier: outlier::Config,
+
+    /// Configures which outbound endpoints are preferred as local.
+    pub outbound_locality: locality::Config,
+
+    /// Configures active health checks of outbound endpoints, if enabled.
+    pub outbound_health_check: Option<health::Config>,
+
+    /// Configures how the weights of new outbound endpoints ramp up, if
+    /// enabled.
+    pub outbound_slow_start: Option<balance::weight::SlowStart>,
+
     /// Age after which metrics may be dropped.
     pub metrics_retain_idle: Duration,
 
@@ -130,6 +160,33 @@ pub struct Config {
     /// call.
     pub destination_context: String,
 
+    /// The maximum number of destinations that may be resolved concurrently.
+    pub destination_max_resolutions: usize,
+
+    /// The maximum number of endpoints used for each destination.
+    pub destination_max_endpoints: usize,
+
+    /// The proxy's hostname, which distinguishes it from other proxies when
+    /// choosing a subset of a destination's endpoints.
+    pub hostname: Option<String>,
+
+    /// Configures a file that destinations are resolved from, in preference
+    /// to the Destination service.
+    pub destination_file: Option<destination::file::Config>,
+
+    /// Configures a file that service profiles are loaded from, in
+    /// preference to the Destination service.
+    pub destination_profile_file: Option<profiles::file::Config>,
+
+    /// Configures which destinations are resolved via DNS when the
+    /// Destination service has no endpoints for them.
+    pub destination_dns_fallback: Option<destination::dns_fallback::Config>,
+
+    /// Configures where recent resolutions and profiles are persisted, so
+    /// that they may be used after a restart until the Destination service
+    /// is available.
+    pub destination_snapshot: Option<destination::snapshot::Config>,
+
     //
     // DNS Config
     //
@@ -180,6 +237,11 @@ pub enum ParseError {
     NameError,
     InvalidTokenSource,
     InvalidTrustAnchors,
+    NotALoadBalancer,
+    NotALabel,
+    NotAHealthCheck,
+    NotATlsVersion,
+    NotACipherSuite,
 }
 
 /// The strings used to build a configuration.
@@ -230,6 +292,124 @@ pub const ENV_OUTBOUND_ROUTER_MAX_IDLE_AGE: &str = "LINKERD2_PROXY_OUTBOUND_ROUT
 pub const ENV_INBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT";
 pub const ENV_OUTBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MAX_IN_FLIGHT";
 
+/// Limits the size of request bodies that may be buffered for retries.
+/// Requests with larger bodies are not retried.
+pub const ENV_OUTBOUND_RETRY_MAX_BUFFER_BYTES: &str =
+    "LINKERD2_PROXY_OUTBOUND_RETRY_MAX_BUFFER_BYTES";
+
+/// Configures an exponential backoff between retries on routes from the
+/// Destination service, whose profiles cannot describe one. Routes from the
+/// profile file use their own `retry_backoff`.
+///
+/// The min and max must be set together. The jitter defaults to 0.
+pub const ENV_OUTBOUND_RETRY_BACKOFF_MIN: &str = "LINKERD2_PROXY_OUTBOUND_RETRY_BACKOFF_MIN";
+pub const ENV_OUTBOUND_RETRY_BACKOFF_MAX: &str = "LINKERD2_PROXY_OUTBOUND_RETRY_BACKOFF_MAX";
+pub const ENV_OUTBOUND_RETRY_BACKOFF_JITTER: &str = "LINKERD2_PROXY_OUTBOUND_RETRY_BACKOFF_JITTER";
+
+/// Limits each attempt of a retried request on routes from the Destination
+/// service, whose profiles cannot describe a per-try timeout.
+pub const ENV_OUTBOUND_RETRY_PER_TRY_TIMEOUT: &str =
+    "LINKERD2_PROXY_OUTBOUND_RETRY_PER_TRY_TIMEOUT";
+
+/// If set, requests on retryable routes from the Destination service are
+/// retried when they fail due to a connection error before any part of a
+/// response is received. Routes from the profile file use their own
+/// `retry_connection_errors`.
+pub const ENV_OUTBOUND_RETRY_CONNECTION_ERRORS: &str =
+    "LINKERD2_PROXY_OUTBOUND_RETRY_CONNECTION_ERRORS";
+
+/// Selects the default load-balancing algorithm for outbound destinations.
+///
+/// One of `peak-ewma` (the default), `least-requests`, `round-robin`,
+/// `consistent-hash:header:<name>`, `consistent-hash:cookie:<name>`, or
+/// `consistent-hash:forwarded-for`. A destination's profile (in the profile
+/// file) may override this. Round-robin and consistent hashing respect
+/// endpoint weights.
+///
+/// Outbound requests are always sent by the local application, so
+/// `forwarded-for` hashes the original client's address as reported by the
+/// `forwarded` or `x-forwarded-for` headers. These headers are set by
+/// clients, so they may be spoofed; they should only be relied upon when they
+/// are set by a trusted proxy in front of the application. Requests without
+/// them are distributed in turn.
+pub const ENV_OUTBOUND_LOAD_BALANCER: &str = "LINKERD2_PROXY_OUTBOUND_LOAD_BALANCER";
+
+/// Ejects an outbound endpoint from its load balancer after this many
+/// consecutive failed responses.
+///
+/// If unspecified, endpoints are not ejected for consecutive failures.
+pub const ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES";
+
+/// Ejects an outbound endpoint from its load balancer when the ratio of its
+/// failed responses within a window reaches this value (between 0 and 1).
+///
+/// If unspecified, endpoints are not ejected for their failure rate.
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE: &str = "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE";
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS";
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW";
+
+/// The duration of an endpoint's first ejection. Each subsequent ejection of
+/// the same endpoint doubles this, up to the maximum.
+pub const ENV_OUTBOUND_OUTLIER_EJECTION_BASE: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_EJECTION_BASE";
+pub const ENV_OUTBOUND_OUTLIER_EJECTION_MAX: &str = "LINKERD2_PROXY_OUTBOUND_OUTLIER_EJECTION_MAX";
+
+/// The proxy's locality, as a comma-separated list of `<name>=<value>`
+/// endpoint labels (e.g. `zone=us-west-2a`).
+///
+/// Outbound load balancers only use endpoints with all of these labels, unless
+/// there are none available or they are overloaded. If unspecified, all
+/// endpoints are used.
+pub const ENV_OUTBOUND_LOCALITY: &str = "LINKERD2_PROXY_OUTBOUND_LOCALITY";
+
+/// The number of pending requests per local endpoint at which outbound load
+/// balancers spill over to endpoints outside of the proxy's locality.
+///
+/// If unspecified, endpoints outside of the proxy's locality are only used
+/// when there are no local endpoints.
+pub const ENV_OUTBOUND_LOCALITY_MAX_PENDING: &str = "LINKERD2_PROXY_OUTBOUND_LOCALITY_MAX_PENDING";
+
+/// Enables active health checks of outbound endpoints.
+///
+/// One of `http:<path>`, which requests the path and expects a successful
+/// status, or `grpc` (or `grpc:<service>`), which calls the standard gRPC
+/// health checking service. If unspecified, endpoints are not probed.
+pub const ENV_OUTBOUND_HEALTH_CHECK: &str = "LINKERD2_PROXY_OUTBOUND_HEALTH_CHECK";
+pub const ENV_OUTBOUND_HEALTH_CHECK_INTERVAL: &str =
+    "LINKERD2_PROXY_OUTBOUND_HEALTH_CHECK_INTERVAL";
+pub const ENV_OUTBOUND_HEALTH_CHECK_TIMEOUT: &str = "LINKERD2_PROXY_OUTBOUND_HEALTH_CHECK_TIMEOUT";
+
+/// Enables slow start: the duration over which the weight of a new outbound
+/// endpoint ramps up to its full weight.
+///
+/// Ramping endpoints receive fewer requests with every load-balancing
+/// algorithm, and fewer points on the hash ring with consistent hashing. If
+/// unspecified, endpoints receive their full weight when they are added.
+pub const ENV_OUTBOUND_SLOW_START_WINDOW: &str = "LINKERD2_PROXY_OUTBOUND_SLOW_START_WINDOW";
+
+/// The fraction of its full weight (between 0 and 1) with which a new outbound
+/// endpoint starts.
+pub const ENV_OUTBOUND_SLOW_START_MIN_WEIGHT: &str =
+    "LINKERD2_PROXY_OUTBOUND_SLOW_START_MIN_WEIGHT";
+
+/// Shapes the slow start ramp. `1.0` ramps linearly; larger values ramp up
+/// more quickly at first.
+pub const ENV_OUTBOUND_SLOW_START_AGGRESSION: &str =
+    "LINKERD2_PROXY_OUTBOUND_SLOW_START_AGGRESSION";
+
+/// The number of consecutive failed probes after which an outbound endpoint
+/// is removed from its load balancer.
+pub const ENV_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD: &str =
+    "LINKERD2_PROXY_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD";
+
+/// The number of consecutive successful probes after which an unhealthy
+/// outbound endpoint is added back to its load balancer.
+pub const ENV_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD: &str =
+    "LINKERD2_PROXY_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD";
+
 /// Constrains which destination names are resolved through the destination
 /// service.
 ///
@@ -268,13 +448,138 @@ pub const ENV_IDENTITY_TOKEN_FILE: &str = "LINKERD2_PROXY_IDENTITY_TOKEN_FILE";
 pub const ENV_IDENTITY_MIN_REFRESH: &str = "LINKERD2_PROXY_IDENTITY_MIN_REFRESH";
 pub const ENV_IDENTITY_MAX_REFRESH: &str = "LINKERD2_PROXY_IDENTITY_MAX_REFRESH";
 
+/// The path to a PEM-encoded bundle of trust anchors.
+///
+/// The file is reloaded when it changes, so that trust anchors may be rotated
+/// without restarting the proxy. Until it is loaded, the trust anchors in
+/// `ENV_IDENTITY_TRUST_ANCHORS` are used.
+pub const ENV_IDENTITY_TRUST_ANCHORS_FILE: &str = "LINKERD2_PROXY_IDENTITY_TRUST_ANCHORS_FILE";
+
+/// How often the trust anchors file is checked for changes.
+pub const ENV_IDENTITY_TRUST_ANCHORS_FILE_POLL_INTERVAL: &str =
+    "LINKERD2_PROXY_IDENTITY_TRUST_ANCHORS_FILE_POLL_INTERVAL";
+
+/// How long trust anchors that have been replaced in the trust anchors file
+/// continue to be trusted.
+pub const ENV_IDENTITY_TRUST_ANCHORS_ROTATION_WINDOW: &str =
+    "LINKERD2_PROXY_IDENTITY_TRUST_ANCHORS_ROTATION_WINDOW";
+
+/// The paths to a PEM-encoded certificate chain and PKCS#8 key, e.g. as
+/// written by cert-manager.
+///
+/// When set, certificates are read from these files instead of being
+/// requested from the Identity service. The files are reloaded when they
+/// change.
+pub const ENV_IDENTITY_CRT_FILE: &str = "LINKERD2_PROXY_IDENTITY_CRT_FILE";
+pub const ENV_IDENTITY_KEY_FILE: &str = "LINKERD2_PROXY_IDENTITY_KEY_FILE";
+
+/// How often the certificate and key files are checked for changes.
+pub const ENV_IDENTITY_CRT_FILE_POLL_INTERVAL: &str =
+    "LINKERD2_PROXY_IDENTITY_CRT_FILE_POLL_INTERVAL";
+
+/// The path to a SPIFFE Workload API's Unix socket.
+///
+/// When set, certificates are X.509-SVIDs obtained from the Workload API
+/// instead of being requested from the Identity service.
+pub const ENV_IDENTITY_SPIFFE_WORKLOAD_API_SOCKET: &str =
+    "LINKERD2_PROXY_IDENTITY_SPIFFE_WORKLOAD_API_SOCKET";
+
 pub const ENV_IDENTITY_SVC_BASE: &str = "LINKERD2_PROXY_IDENTITY_SVC";
 
+/// The minimum TLS version used for meshed TLS, either `1.2` or `1.3`.
+///
+/// By default, servers only accept TLS 1.2, and clients offer TLS 1.3 and
+/// 1.2. Setting a minimum removes the versions below it; servers accept TLS
+/// 1.3 when it is the minimum. Ed25519 keys can only be used when the minimum
+/// is `1.3`.
+pub const ENV_TLS_MIN_VERSION: &str = "LINKERD2_PROXY_TLS_MIN_VERSION";
+
+/// A comma-separated list of the IANA names of the cipher suites used for
+/// meshed TLS, e.g. `TLS_AES_128_GCM_SHA256`. By default, all cipher suites
+/// that rustls supports are used.
+///
+/// Key exchange groups cannot be configured (see `ENV_TLS_KX_GROUPS`).
+pub const ENV_TLS_CIPHER_SUITES: &str = "LINKERD2_PROXY_TLS_CIPHER_SUITES";
+
+/// Not supported: the proxy fails to start if this is set.
+///
+/// rustls does not support restricting key exchange groups: X25519, P-384,
+/// and P-256 are always offered. The groups are listed with the other TLS
+/// parameters by the admin server's `/identity` endpoint, so that they may be
+/// audited.
+pub const ENV_TLS_KX_GROUPS: &str = "LINKERD2_PROXY_TLS_KX_GROUPS";
+
 pub const ENV_DESTINATION_SVC_BASE: &str = "LINKERD2_PROXY_DESTINATION_SVC";
 pub const ENV_DESTINATION_SVC_ADDR: &str = "LINKERD2_PROXY_DESTINATION_SVC_ADDR";
 
 pub const ENV_DESTINATION_CONTEXT: &str = "LINKERD2_PROXY_DESTINATION_CONTEXT";
 
+/// The maximum number of destinations that may be resolved through the
+/// destination service concurrently.
+///
+/// Further destinations are not resolved, and requests to them are routed to
+/// their original destination addresses.
+pub const ENV_DESTINATION_MAX_RESOLUTIONS: &str = "LINKERD2_PROXY_DESTINATION_MAX_RESOLUTIONS";
+
+/// The maximum number of endpoints used for each destination resolved through
+/// the destination service.
+///
+/// If a destination has more endpoints, a subset of them is used. Each proxy
+/// chooses a different subset, based on its hostname (see `ENV_HOSTNAME`).
+pub const ENV_DESTINATION_MAX_ENDPOINTS: &str = "LINKERD2_PROXY_DESTINATION_MAX_ENDPOINTS";
+
+/// The proxy's hostname. Kubernetes sets this to the pod's name.
+///
+/// When unset, proxies choose subsets of endpoints based on their identity,
+/// or, without identity, on `ENV_DESTINATION_CONTEXT`. Since these are
+/// shared by other proxies, such proxies all choose the same subset.
+pub const ENV_HOSTNAME: &str = "HOSTNAME";
+
+/// The path to a file of static destinations.
+///
+/// Authorities that are named in the file are resolved from it rather than
+/// from the destination service. The file is reloaded when it changes.
+pub const ENV_DESTINATION_FILE: &str = "LINKERD2_PROXY_DESTINATION_FILE";
+
+/// How often the destination file is checked for changes.
+pub const ENV_DESTINATION_FILE_POLL_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_FILE_POLL_INTERVAL";
+
+/// Destinations in these suffixes are resolved via DNS when the destination
+/// service has no endpoints for them.
+///
+/// If unspecified, destinations are not resolved via DNS.
+pub const ENV_DESTINATION_DNS_FALLBACK_SUFFIXES: &str =
+    "LINKERD2_PROXY_DESTINATION_DNS_FALLBACK_SUFFIXES";
+
+/// The name of a service (e.g. `http`) whose SRV records are used when a
+/// destination is resolved via DNS. The records of `_<service>._tcp.<name>`
+/// are used if they exist; otherwise, the name's A and AAAA records are used.
+///
+/// If unspecified, SRV records are not used.
+pub const ENV_DESTINATION_DNS_FALLBACK_SRV: &str = "LINKERD2_PROXY_DESTINATION_DNS_FALLBACK_SRV";
+
+/// The path to which recent resolutions and profiles are persisted.
+///
+/// On startup, resolutions and profiles are seeded from the snapshot until the
+/// destination service responds, so that a restart during a control plane
+/// outage does not leave the proxy without endpoints.
+pub const ENV_DESTINATION_SNAPSHOT_PATH: &str = "LINKERD2_PROXY_DESTINATION_SNAPSHOT_PATH";
+
+/// How often the snapshot is written, if it has changed.
+pub const ENV_DESTINATION_SNAPSHOT_INTERVAL: &str = "LINKERD2_PROXY_DESTINATION_SNAPSHOT_INTERVAL";
+
+/// The path to a file of static service profiles.
+///
+/// Profiles in the file take precedence over those from the destination
+/// service. Only destinations within `ENV_DESTINATION_PROFILE_SUFFIXES` have
+/// profiles. The file is reloaded when it changes.
+pub const ENV_DESTINATION_PROFILE_FILE: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_FILE";
+
+/// How often the profile file is checked for changes.
+pub const ENV_DESTINATION_PROFILE_FILE_POLL_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_FILE_POLL_INTERVAL";
+
 pub const ENV_CONTROL_EXP_BACKOFF_MIN: &str = "LINKERD2_PROXY_CONTROL_EXP_BACKOFF_MIN";
 pub const ENV_CONTROL_EXP_BACKOFF_MAX: &str = "LINKERD2_PROXY_CONTROL_EXP_BACKOFF_MAX";
 pub const ENV_CONTROL_EXP_BACKOFF_JITTER: &str = "LINKERD2_PROXY_CONTROL_EXP_BACKOFF_JITTER";
@@ -345,13 +650,34 @@ const DEFAULT_OUTBOUND_ROUTER_MAX_IDLE_AGE: Duration = Duration::from_secs(60);
 const DEFAULT_INBOUND_MAX_IN_FLIGHT: usize = 10_000;
 const DEFAULT_OUTBOUND_MAX_IN_FLIGHT: usize = 10_000;
 
+const DEFAULT_OUTBOUND_RETRY_MAX_BUFFER_BYTES: usize = 64 * 1024;
+
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS: u32 = 10;
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_OUTBOUND_OUTLIER_EJECTION_BASE: Duration = Duration::from_secs(30);
+const DEFAULT_OUTBOUND_OUTLIER_EJECTION_MAX: Duration = Duration::from_secs(300);
+const DEFAULT_OUTBOUND_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_OUTBOUND_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD: u32 = 3;
+const DEFAULT_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD: u32 = 2;
+const DEFAULT_OUTBOUND_SLOW_START_MIN_WEIGHT: f64 = 0.1;
+const DEFAULT_OUTBOUND_SLOW_START_AGGRESSION: f64 = 1.0;
+
 const DEFAULT_DESTINATION_BUFFER_CAPACITY: usize = 100;
+const DEFAULT_DESTINATION_MAX_RESOLUTIONS: usize = 10_000;
+const DEFAULT_DESTINATION_MAX_ENDPOINTS: usize = 5_000;
+const DEFAULT_DESTINATION_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_DESTINATION_PROFILE_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_DESTINATION_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(10);
 
 const DEFAULT_DESTINATION_GET_SUFFIXES: &str = "svc.cluster.local.";
 const DEFAULT_DESTINATION_PROFILE_SUFFIXES: &str = "svc.cluster.local.";
 
 const DEFAULT_IDENTITY_MIN_REFRESH: Duration = Duration::from_secs(10);
 const DEFAULT_IDENTITY_MAX_REFRESH: Duration = Duration::from_secs(60 * 60 * 24);
+const DEFAULT_IDENTITY_TRUST_ANCHORS_FILE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_IDENTITY_TRUST_ANCHORS_ROTATION_WINDOW: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_IDENTITY_CRT_FILE_POLL_INTERVAL: Duration = Duration::from_secs(10);
 
 // By default, we keep a list of known assigned ports of server-first protocols.
 //
@@ -426,6 +752,10 @@ impl Config {
 
         let inbound_max_in_flight = parse(strings, ENV_INBOUND_MAX_IN_FLIGHT, parse_number);
         let outbound_max_in_flight = parse(strings, ENV_OUTBOUND_MAX_IN_FLIGHT, parse_number);
+        let outbound_retry_max_buffer_bytes =
+            parse(strings, ENV_OUTBOUND_RETRY_MAX_BUFFER_BYTES, parse_number);
+        let outbound_load_balancer =
+            parse(strings, ENV_OUTBOUND_LOAD_BALANCER, parse_load_balancer);
 
         let metrics_retain_idle = parse(strings, ENV_METRICS_RETAIN_IDLE, parse_duration);
 
@@ -451,6 +781,9 @@ impl Config {
         };
 
         let dst_token = strings.get(ENV_DESTINATION_CONTEXT);
+        let dst_max_resolutions = parse(strings, ENV_DESTINATION_MAX_RESOLUTIONS, parse_number);
+        let dst_max_endpoints = parse(strings, ENV_DESTINATION_MAX_ENDPOINTS, parse_number);
+        let hostname = strings.get(ENV_HOSTNAME);
 
         let dst_get_suffixes = parse(strings, ENV_DESTINATION_GET_SUFFIXES, parse_dns_suffixes);
         let dst_profile_suffixes = parse(
@@ -533,6 +866,14 @@ impl Config {
                 .unwrap_or(DEFAULT_INBOUND_MAX_IN_FLIGHT),
             outbound_max_requests_in_flight: outbound_max_in_flight?
                 .unwrap_or(DEFAULT_OUTBOUND_MAX_IN_FLIGHT),
+            outbound_retry_max_buffer_bytes: outbound_retry_max_buffer_bytes?
+                .unwrap_or(DEFAULT_OUTBOUND_RETRY_MAX_BUFFER_BYTES),
+            outbound_retry_defaults: parse_retry_defaults(strings)?,
+            outbound_load_balancer: outbound_load_balancer?.unwrap_or(balance::Algorithm::PeakEwma),
+            outbound_outlier: parse_outlier_config(strings)?,
+            outbound_locality: parse_locality_config(strings)?,
+            outbound_health_check: parse_health_check_config(strings)?,
+            outbound_slow_start: parse_slow_start_config(strings)?,
 
             destination_buffer_capacity: DEFAULT_DESTINATION_BUFFER_CAPACITY,
 
@@ -544,6 +885,25 @@ impl Config {
 
             destination_addr: dst_addr?,
             destination_context: dst_token?.unwrap_or_default(),
+            destination_max_resolutions: dst_max_resolutions?
+                .unwrap_or(DEFAULT_DESTINATION_MAX_RESOLUTIONS),
+            destination_max_endpoints: dst_max_endpoints?
+                .unwrap_or(DEFAULT_DESTINATION_MAX_ENDPOINTS),
+            hostname: hostname?.filter(|h| !h.is_empty()),
+            destination_file: parse_watched_file_config(
+                strings,
+                ENV_DESTINATION_FILE,
+                ENV_DESTINATION_FILE_POLL_INTERVAL,
+                DEFAULT_DESTINATION_FILE_POLL_INTERVAL,
+            )?,
+            destination_profile_file: parse_watched_file_config(
+                strings,
+                ENV_DESTINATION_PROFILE_FILE,
+                ENV_DESTINATION_PROFILE_FILE_POLL_INTERVAL,
+                DEFAULT_DESTINATION_PROFILE_FILE_POLL_INTERVAL,
+            )?,
+            destination_dns_fallback: parse_dns_fallback_config(strings)?,
+            destination_snapshot: parse_snapshot_config(strings)?,
 
             identity_config: identity_config?
                 .map(Conditional::Some)
@@ -635,7 +995,7 @@ where
     s.parse().map_err(|_| ParseError::NotANumber)
 }
 
-fn parse_duration(s: &str) -> Result<Duration, ParseError> {
+pub(super) fn parse_duration(s: &str) -> Result<Duration, ParseError> {
     use regex::Regex;
 
     let re = Regex::new(r"^\s*(\d+)(ms|s|m|h|d)?\s*$").expect("duration regex");
@@ -729,6 +1089,65 @@ where
     }
 }
 
+pub(super) fn parse_load_balancer(s: &str) -> Result<balance::Algorithm, ParseError> {
+    use proxy::http::balance::{Algorithm, HashKey};
+
+    match s.trim() {
+        "peak-ewma" => Ok(Algorithm::PeakEwma),
+        "least-requests" => Ok(Algorithm::LeastRequests),
+        "round-robin" => Ok(Algorithm::RoundRobin),
+        "consistent-hash:forwarded-for" => Ok(Algorithm::ConsistentHash(HashKey::ForwardedFor)),
+        s => {
+            let mut parts = s.splitn(3, ':');
+            let key = match (parts.next(), parts.next(), parts.next()) {
+                (Some("consistent-hash"), Some("header"), Some(name)) => {
+                    let name = http::header::HeaderName::from_bytes(name.as_bytes())
+                        .map_err(|_| ParseError::NotALoadBalancer)?;
+                    HashKey::Header(name)
+                }
+                (Some("consistent-hash"), Some("cookie"), Some(name)) if !name.is_empty() => {
+                    HashKey::Cookie(name.to_owned())
+                }
+                _ => return Err(ParseError::NotALoadBalancer),
+            };
+            Ok(Algorithm::ConsistentHash(key))
+        }
+    }
+}
+
+fn parse_labels(list: &str) -> Result<Vec<(String, String)>, ParseError> {
+    let mut labels = Vec::new();
+    for item in list.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+
+        let mut parts = item.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) if !k.trim().is_empty() => {
+                labels.push((k.trim().to_owned(), v.trim().to_owned()));
+            }
+            _ => return Err(ParseError::NotALabel),
+        }
+    }
+
+    Ok(labels)
+}
+
+fn parse_health_check(s: &str) -> Result<health::Probe, ParseError> {
+    let mut parts = s.trim().splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("http"), Some(path)) if path.starts_with('/') => Ok(health::Probe::Http {
+            path: path.to_owned(),
+        }),
+        (Some("grpc"), service) => Ok(health::Probe::Grpc {
+            service: service.unwrap_or_default().to_owned(),
+        }),
+        _ => Err(ParseError::NotAHealthCheck),
+    }
+}
+
 fn parse_dns_suffixes(list: &str) -> Result<Vec<dns::Suffix>, ParseError> {
     let mut suffixes = Vec::new();
     for item in list.split(',') {
@@ -791,6 +1210,266 @@ pub fn parse_backoff<S: Strings>(
     }
 }
 
+fn parse_retry_defaults<S: Strings>(strings: &S) -> Result<profiles::RetryDefaults, Error> {
+    let min = parse(strings, ENV_OUTBOUND_RETRY_BACKOFF_MIN, parse_duration);
+    let max = parse(strings, ENV_OUTBOUND_RETRY_BACKOFF_MAX, parse_duration);
+    let jitter = parse(
+        strings,
+        ENV_OUTBOUND_RETRY_BACKOFF_JITTER,
+        parse_number::<f64>,
+    );
+    let per_try_timeout = parse(strings, ENV_OUTBOUND_RETRY_PER_TRY_TIMEOUT, parse_duration);
+    let retry_connection_errors = strings
+        .get(ENV_OUTBOUND_RETRY_CONNECTION_ERRORS)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+
+    let backoff = match (min?, max?, jitter?) {
+        (None, None, None) => None,
+        (Some(min), Some(max), jitter) => {
+            match retry::Backoff::new(min, max, jitter.unwrap_or(0.0)) {
+                Ok(backoff) => Some(backoff),
+                Err(e) => {
+                    error!(
+                        "{}, {}, and {} are invalid: {}",
+                        ENV_OUTBOUND_RETRY_BACKOFF_MIN,
+                        ENV_OUTBOUND_RETRY_BACKOFF_MAX,
+                        ENV_OUTBOUND_RETRY_BACKOFF_JITTER,
+                        e
+                    );
+                    return Err(Error::InvalidEnvVar);
+                }
+            }
+        }
+        _ => {
+            error!(
+                "{} and {} must be set together",
+                ENV_OUTBOUND_RETRY_BACKOFF_MIN, ENV_OUTBOUND_RETRY_BACKOFF_MAX
+            );
+            return Err(Error::InvalidEnvVar);
+        }
+    };
+
+    Ok(profiles::RetryDefaults {
+        backoff,
+        per_try_timeout: per_try_timeout?,
+        retry_connection_errors: retry_connection_errors?,
+    })
+}
+
+fn parse_outlier_config<S: Strings>(strings: &S) -> Result<outlier::Config, Error> {
+    let consecutive_failures = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES,
+        parse_number::<u32>,
+    );
+    let threshold = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE,
+        parse_number::<f64>,
+    );
+    let min_requests = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS,
+        parse_number,
+    );
+    let window = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW,
+        parse_duration,
+    );
+    let base_ejection = parse(strings, ENV_OUTBOUND_OUTLIER_EJECTION_BASE, parse_duration);
+    let max_ejection = parse(strings, ENV_OUTBOUND_OUTLIER_EJECTION_MAX, parse_duration);
+
+    let consecutive_failures = consecutive_failures?;
+    if consecutive_failures == Some(0) {
+        error!(
+            "{} must be greater than 0",
+            ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    let failure_rate = match threshold? {
+        None => None,
+        Some(threshold) if threshold <= 0.0 || threshold > 1.0 => {
+            error!(
+                "{} must be greater than 0 and at most 1",
+                ENV_OUTBOUND_OUTLIER_FAILURE_RATE
+            );
+            return Err(Error::InvalidEnvVar);
+        }
+        Some(threshold) => Some(outlier::FailureRate {
+            threshold,
+            min_requests: min_requests?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS),
+            window: window?.unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_WINDOW),
+        }),
+    };
+
+    let base_ejection = base_ejection?.unwrap_or(DEFAULT_OUTBOUND_OUTLIER_EJECTION_BASE);
+    let max_ejection = max_ejection?.unwrap_or(DEFAULT_OUTBOUND_OUTLIER_EJECTION_MAX);
+    if max_ejection < base_ejection {
+        error!(
+            "{} must not be less than {}",
+            ENV_OUTBOUND_OUTLIER_EJECTION_MAX, ENV_OUTBOUND_OUTLIER_EJECTION_BASE
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    Ok(outlier::Config {
+        consecutive_failures,
+        failure_rate,
+        base_ejection,
+        max_ejection,
+    })
+}
+
+fn parse_locality_config<S: Strings>(strings: &S) -> Result<locality::Config, Error> {
+    let labels = parse(strings, ENV_OUTBOUND_LOCALITY, parse_labels);
+    let max_pending = parse(strings, ENV_OUTBOUND_LOCALITY_MAX_PENDING, parse_number);
+
+    let max_pending = max_pending?;
+    if max_pending == Some(0) {
+        error!(
+            "{} must be greater than 0",
+            ENV_OUTBOUND_LOCALITY_MAX_PENDING
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    Ok(locality::Config {
+        labels: labels?.unwrap_or_default(),
+        max_pending,
+    })
+}
+
+fn parse_health_check_config<S: Strings>(strings: &S) -> Result<Option<health::Config>, Error> {
+    let probe = parse(strings, ENV_OUTBOUND_HEALTH_CHECK, parse_health_check);
+    let interval = parse(strings, ENV_OUTBOUND_HEALTH_CHECK_INTERVAL, parse_duration);
+    let timeout = parse(strings, ENV_OUTBOUND_HEALTH_CHECK_TIMEOUT, parse_duration);
+    let unhealthy_threshold = parse(
+        strings,
+        ENV_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD,
+        parse_number::<u32>,
+    );
+    let healthy_threshold = parse(
+        strings,
+        ENV_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD,
+        parse_number::<u32>,
+    );
+
+    let probe = match probe? {
+        Some(probe) => probe,
+        None => return Ok(None),
+    };
+
+    let unhealthy_threshold =
+        unhealthy_threshold?.unwrap_or(DEFAULT_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD);
+    let healthy_threshold =
+        healthy_threshold?.unwrap_or(DEFAULT_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD);
+    if unhealthy_threshold == 0 || healthy_threshold == 0 {
+        error!(
+            "{} and {} must be greater than 0",
+            ENV_OUTBOUND_HEALTH_CHECK_UNHEALTHY_THRESHOLD,
+            ENV_OUTBOUND_HEALTH_CHECK_HEALTHY_THRESHOLD
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    Ok(Some(health::Config {
+        probe,
+        interval: interval?.unwrap_or(DEFAULT_OUTBOUND_HEALTH_CHECK_INTERVAL),
+        timeout: timeout?.unwrap_or(DEFAULT_OUTBOUND_HEALTH_CHECK_TIMEOUT),
+        unhealthy_threshold,
+        healthy_threshold,
+    }))
+}
+
+fn parse_slow_start_config<S: Strings>(
+    strings: &S,
+) -> Result<Option<balance::weight::SlowStart>, Error> {
+    let window = parse(strings, ENV_OUTBOUND_SLOW_START_WINDOW, parse_duration);
+    let min_weight = parse(
+        strings,
+        ENV_OUTBOUND_SLOW_START_MIN_WEIGHT,
+        parse_number::<f64>,
+    );
+    let aggression = parse(
+        strings,
+        ENV_OUTBOUND_SLOW_START_AGGRESSION,
+        parse_number::<f64>,
+    );
+
+    let window = match window? {
+        Some(window) => window,
+        None => return Ok(None),
+    };
+
+    let min_weight = min_weight?.unwrap_or(DEFAULT_OUTBOUND_SLOW_START_MIN_WEIGHT);
+    if min_weight <= 0.0 || min_weight > 1.0 {
+        error!(
+            "{} must be greater than 0 and at most 1",
+            ENV_OUTBOUND_SLOW_START_MIN_WEIGHT
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    let aggression = aggression?.unwrap_or(DEFAULT_OUTBOUND_SLOW_START_AGGRESSION);
+    if aggression <= 0.0 {
+        error!(
+            "{} must be greater than 0",
+            ENV_OUTBOUND_SLOW_START_AGGRESSION
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+
+    Ok(Some(balance::weight::SlowStart {
+        window,
+        min_weight,
+        aggression,
+    }))
+}
+
+fn parse_watched_file_config<S: Strings>(
+    strings: &S,
+    path_env: &str,
+    interval_env: &str,
+    default_interval: Duration,
+) -> Result<Option<fs_watch::Config>, Error> {
+    let path = parse(strings, path_env, |s| Ok(PathBuf::from(s)));
+    let interval = parse(strings, interval_env, parse_duration);
+
+    let interval = interval?.unwrap_or(default_interval);
+    Ok(path?.map(|path| fs_watch::Config { path, interval }))
+}
+
+fn parse_snapshot_config<S: Strings>(
+    strings: &S,
+) -> Result<Option<destination::snapshot::Config>, Error> {
+    let path = parse(strings, ENV_DESTINATION_SNAPSHOT_PATH, |s| {
+        Ok(PathBuf::from(s))
+    });
+    let interval = parse(strings, ENV_DESTINATION_SNAPSHOT_INTERVAL, parse_duration);
+
+    let interval = interval?.unwrap_or(DEFAULT_DESTINATION_SNAPSHOT_INTERVAL);
+    Ok(path?.map(|path| destination::snapshot::Config { path, interval }))
+}
+
+fn parse_dns_fallback_config<S: Strings>(
+    strings: &S,
+) -> Result<Option<destination::dns_fallback::Config>, Error> {
+    let suffixes = parse(
+        strings,
+        ENV_DESTINATION_DNS_FALLBACK_SUFFIXES,
+        parse_dns_suffixes,
+    );
+    let srv = strings
+        .get(ENV_DESTINATION_DNS_FALLBACK_SRV)?
+        .filter(|s| !s.is_empty());
+
+    Ok(suffixes?.map(|suffixes| destination::dns_fallback::Config { suffixes, srv }))
+}
+
 pub fn parse_control_addr<S: Strings>(
     strings: &S,
     base: &str,
@@ -844,106 +1523,124 @@ pub fn parse_identity_config<S: Strings>(strings: &S) -> Result<Option<identity:
     let li = parse(strings, ENV_IDENTITY_IDENTITY_LOCAL_NAME, parse_identity);
     let min_refresh = parse(strings, ENV_IDENTITY_MIN_REFRESH, parse_duration);
     let max_refresh = parse(strings, ENV_IDENTITY_MAX_REFRESH, parse_duration);
+    let trust_anchors_file = parse_watched_file_config(
+        strings,
+        ENV_IDENTITY_TRUST_ANCHORS_FILE,
+        ENV_IDENTITY_TRUST_ANCHORS_FILE_POLL_INTERVAL,
+        DEFAULT_IDENTITY_TRUST_ANCHORS_FILE_POLL_INTERVAL,
+    );
+    let rotation_window = parse(
+        strings,
+        ENV_IDENTITY_TRUST_ANCHORS_ROTATION_WINDOW,
+        parse_duration,
+    );
+    let crt_file = parse(strings, ENV_IDENTITY_CRT_FILE, |s| Ok(PathBuf::from(s)));
+    let key_file = parse(strings, ENV_IDENTITY_KEY_FILE, |s| Ok(PathBuf::from(s)));
+    let crt_file_interval = parse(strings, ENV_IDENTITY_CRT_FILE_POLL_INTERVAL, parse_duration);
+    let spiffe_socket = parse(strings, ENV_IDENTITY_SPIFFE_WORKLOAD_API_SOCKET, |s| {
+        Ok(PathBuf::from(s))
+    });
+    let tls_params = parse_tls_params(strings);
 
     let disabled = strings
         .get(ENV_IDENTITY_DISABLED)?
         .map(|d| !d.is_empty())
         .unwrap_or(false);
 
-    match (
-        disabled,
-        sa?,
-        ta?,
-        dir?,
-        li?,
-        tok?,
-        min_refresh?,
-        max_refresh?,
-    ) {
-        (disabled, None, None, None, None, None, None, None) => {
-            if !disabled {
-                error!(
-                    "{} must be set or identity configuration must be specified.",
-                    ENV_IDENTITY_DISABLED
-                );
-                Err(Error::InvalidEnvVar)
-            } else {
-                Ok(None)
-            }
+    let (svc, trust_anchors, dir, local_name, token) = (sa?, ta?, dir?, li?, tok?);
+    let (min_refresh, max_refresh) = (min_refresh?, max_refresh?);
+    let (crt_file, key_file, spiffe_socket) = (crt_file?, key_file?, spiffe_socket?);
+    let crt_file_interval = crt_file_interval?;
+
+    let unset = svc.is_none()
+        && trust_anchors.is_none()
+        && dir.is_none()
+        && local_name.is_none()
+        && token.is_none()
+        && min_refresh.is_none()
+        && max_refresh.is_none()
+        && crt_file.is_none()
+        && key_file.is_none()
+        && spiffe_socket.is_none();
+    if unset {
+        if !disabled {
+            error!(
+                "{} must be set or identity configuration must be specified.",
+                ENV_IDENTITY_DISABLED
+            );
+            return Err(Error::InvalidEnvVar);
         }
-        (
-            false,
-            Some(svc),
-            Some(trust_anchors),
-            Some(dir),
-            Some(local_name),
-            Some(token),
-            min_refresh,
-            max_refresh,
-        ) => {
-            let key = {
-                let mut p = dir.clone();
-                p.push("key");
-                p.set_extension("p8");
-
-                fs::read(p)
-                    .map_err(|e| {
-                        error!("Failed to read key: {}", e);
-                        Error::InvalidEnvVar
-                    })
-                    .and_then(|b| {
-                        identity::Key::from_pkcs8(&b).map_err(|e| {
-                            error!("Invalid key: {}", e);
-                            Error::InvalidEnvVar
-                        })
-                    })
-            };
-
-            let csr = {
-                let mut p = dir;
-                p.push("csr");
-                p.set_extension("der");
-
-                fs::read(p)
-                    .map_err(|e| {
-                        error!("Failed to read Csr: {}", e);
-                        Error::InvalidEnvVar
-                    })
-                    .and_then(|b| {
-                        identity::Csr::from_der(b).ok_or_else(|| {
-                            error!("No CSR found");
-                            Error::InvalidEnvVar
-                        })
-                    })
-            };
+        return Ok(None);
+    }
+    if disabled {
+        error!(
+            "{} must be unset when other identity variables are set.",
+            ENV_IDENTITY_DISABLED,
+        );
+        return Err(Error::InvalidEnvVar);
+    }
 
+    // Every certificate source requires the trust anchors and local name, in
+    // addition to its own variables.
+    let svc_env = format!("{0}_ADDR and {0}_NAME", ENV_IDENTITY_SVC_BASE);
+    let mut required: Vec<(bool, &str)> = vec![
+        (trust_anchors.is_none(), ENV_IDENTITY_TRUST_ANCHORS),
+        (local_name.is_none(), ENV_IDENTITY_IDENTITY_LOCAL_NAME),
+    ];
+    let source = if let Some(socket) = spiffe_socket {
+        Some(Ok(identity::Source::Spiffe(identity::spiffe::Config {
+            socket,
+        })))
+    } else if crt_file.is_some() || key_file.is_some() {
+        required.push((crt_file.is_none(), ENV_IDENTITY_CRT_FILE));
+        required.push((key_file.is_none(), ENV_IDENTITY_KEY_FILE));
+        match (crt_file, key_file) {
+            (Some(crt), Some(key)) => Some(Ok(identity::Source::Files(identity::file::Config {
+                crt,
+                key,
+                interval: crt_file_interval.unwrap_or(DEFAULT_IDENTITY_CRT_FILE_POLL_INTERVAL),
+            }))),
+            _ => None,
+        }
+    } else {
+        required.push((svc.is_none(), svc_env.as_str()));
+        required.push((dir.is_none(), ENV_IDENTITY_DIR));
+        required.push((token.is_none(), ENV_IDENTITY_TOKEN_FILE));
+        match (svc, dir, token) {
+            (Some(svc), Some(dir), Some(token)) => Some(
+                parse_identity_service_config(svc, dir, token, min_refresh, max_refresh)
+                    .map(identity::Source::Service),
+            ),
+            _ => None,
+        }
+    };
+
+    match (source, trust_anchors, local_name) {
+        (Some(source), Some(trust_anchors), Some(local_name)) => {
+            let tls = tls_params?;
+            let source = source?;
+            // Keys from other sources are checked when they are certified.
+            if let identity::Source::Service(ref svc) = source {
+                if !tls.supports(&svc.key) {
+                    error!(
+                        "Ed25519 keys can only be used when {} is 1.3",
+                        ENV_TLS_MIN_VERSION
+                    );
+                    return Err(Error::InvalidEnvVar);
+                }
+            }
             Ok(Some(identity::Config {
-                svc,
+                source,
                 local_name,
-                token,
-                trust_anchors,
-                csr: csr?,
-                key: key?,
-                min_refresh: min_refresh.unwrap_or(DEFAULT_IDENTITY_MIN_REFRESH),
-                max_refresh: max_refresh.unwrap_or(DEFAULT_IDENTITY_MAX_REFRESH),
+                trust_anchors: trust_anchors.with_params(&tls),
+                tls,
+                trust_anchors_file: trust_anchors_file?,
+                trust_anchors_rotation_window: rotation_window?
+                    .unwrap_or(DEFAULT_IDENTITY_TRUST_ANCHORS_ROTATION_WINDOW),
             }))
         }
-        (disabled, svc, trust_anchors, end_entity_dir, local_id, token, _minr, _maxr) => {
-            if disabled {
-                error!(
-                    "{} must be unset when other identity variables are set.",
-                    ENV_IDENTITY_DISABLED,
-                );
-            }
-            let s = format!("{0}_ADDR and {0}_NAME", ENV_IDENTITY_SVC_BASE);
-            let svc_env: &str = &s.as_str();
-            for (unset, name) in &[
-                (svc.is_none(), svc_env),
-                (trust_anchors.is_none(), ENV_IDENTITY_TRUST_ANCHORS),
-                (end_entity_dir.is_none(), ENV_IDENTITY_DIR),
-                (local_id.is_none(), ENV_IDENTITY_IDENTITY_LOCAL_NAME),
-                (token.is_none(), ENV_IDENTITY_TOKEN_FILE),
-            ] {
+        _ => {
+            for (unset, name) in &required {
                 if *unset {
                     error!(
                         "{} must be set when other identity variables are set.",
@@ -956,6 +1653,111 @@ pub fn parse_identity_config<S: Strings>(strings: &S) -> Result<Option<identity:
     }
 }
 
+fn parse_tls_params<S: Strings>(strings: &S) -> Result<identity::TlsParams, Error> {
+    if strings
+        .get(ENV_TLS_KX_GROUPS)?
+        .map_or(false, |s| !s.is_empty())
+    {
+        error!(
+            "{} is not supported: key exchange groups cannot be configured, and X25519, P-384, and P-256 are always offered",
+            ENV_TLS_KX_GROUPS,
+        );
+        return Err(Error::InvalidEnvVar);
+    }
+    let min_version = parse(strings, ENV_TLS_MIN_VERSION, parse_tls_version)?;
+    let cipher_suites = parse(strings, ENV_TLS_CIPHER_SUITES, parse_cipher_suites)?;
+    identity::TlsParams::new(min_version, cipher_suites).map_err(
+        |identity::NoUsableCipherSuites| {
+            error!(
+                "{} does not include a cipher suite that can be used with the allowed TLS versions",
+                ENV_TLS_CIPHER_SUITES,
+            );
+            Error::InvalidEnvVar
+        },
+    )
+}
+
+fn parse_tls_version(s: &str) -> Result<identity::TlsVersion, ParseError> {
+    match s.trim() {
+        "1.2" => Ok(identity::TlsVersion::Tls12),
+        "1.3" => Ok(identity::TlsVersion::Tls13),
+        _ => Err(ParseError::NotATlsVersion),
+    }
+}
+
+fn parse_cipher_suites(list: &str) -> Result<Vec<identity::CipherSuite>, ParseError> {
+    let mut suites = Vec::new();
+    for name in list.split(',') {
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        match identity::CipherSuite::from_name(name) {
+            Some(suite) => suites.push(suite),
+            None => {
+                error!("Not a supported cipher suite: {}", name);
+                return Err(ParseError::NotACipherSuite);
+            }
+        }
+    }
+    Ok(suites)
+}
+
+/// Reads the key and CSR that are used to request certificates from the
+/// Identity service.
+fn parse_identity_service_config(
+    svc: ControlAddr,
+    dir: PathBuf,
+    token: identity::TokenSource,
+    min_refresh: Option<Duration>,
+    max_refresh: Option<Duration>,
+) -> Result<identity::service::Config, Error> {
+    let key = {
+        let mut p = dir.clone();
+        p.push("key");
+        p.set_extension("p8");
+
+        fs::read(p)
+            .map_err(|e| {
+                error!("Failed to read key: {}", e);
+                Error::InvalidEnvVar
+            })
+            .and_then(|b| {
+                identity::Key::from_pkcs8(&b).map_err(|e| {
+                    error!("Invalid key: {}", e);
+                    Error::InvalidEnvVar
+                })
+            })
+    };
+
+    let csr = {
+        let mut p = dir;
+        p.push("csr");
+        p.set_extension("der");
+
+        fs::read(p)
+            .map_err(|e| {
+                error!("Failed to read Csr: {}", e);
+                Error::InvalidEnvVar
+            })
+            .and_then(|b| {
+                identity::Csr::from_der(b).ok_or_else(|| {
+                    error!("No CSR found");
+                    Error::InvalidEnvVar
+                })
+            })
+    };
+
+    Ok(identity::service::Config {
+        svc,
+        token,
+        csr: csr?,
+        key: key?,
+        min_refresh: min_refresh.unwrap_or(DEFAULT_IDENTITY_MIN_REFRESH),
+        max_refresh: max_refresh.unwrap_or(DEFAULT_IDENTITY_MAX_REFRESH),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1071,4 +1873,160 @@ mod tests {
             "names are coerced to lowercase"
         );
     }
+
+    #[test]
+    fn parse_load_balancer_algorithms() {
+        use proxy::http::balance::{Algorithm, HashKey};
+
+        assert_eq!(parse_load_balancer("peak-ewma"), Ok(Algorithm::PeakEwma));
+        assert_eq!(
+            parse_load_balancer("least-requests"),
+            Ok(Algorithm::LeastRequests)
+        );
+        assert_eq!(
+            parse_load_balancer("round-robin"),
+            Ok(Algorithm::RoundRobin)
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:header:x-user-id"),
+            Ok(Algorithm::ConsistentHash(HashKey::Header(
+                http::header::HeaderName::from_static("x-user-id")
+            )))
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:cookie:session"),
+            Ok(Algorithm::ConsistentHash(HashKey::Cookie(
+                "session".to_owned()
+            )))
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:forwarded-for"),
+            Ok(Algorithm::ConsistentHash(HashKey::ForwardedFor))
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:source-ip"),
+            Err(ParseError::NotALoadBalancer)
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:header:"),
+            Err(ParseError::NotALoadBalancer)
+        );
+        assert_eq!(
+            parse_load_balancer("consistent-hash:cookie:"),
+            Err(ParseError::NotALoadBalancer)
+        );
+        assert_eq!(
+            parse_load_balancer("random"),
+            Err(ParseError::NotALoadBalancer)
+        );
+    }
+
+    #[test]
+    fn parse_locality_labels() {
+        let label = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+
+        assert_eq!(parse_labels(""), Ok(vec![]));
+        assert_eq!(
+            parse_labels("zone=us-west-2a"),
+            Ok(vec![label("zone", "us-west-2a")])
+        );
+        assert_eq!(
+            parse_labels(" zone = us-west-2a , region=us-west-2 "),
+            Ok(vec![
+                label("zone", "us-west-2a"),
+                label("region", "us-west-2")
+            ]),
+            "whitespace is ignored"
+        );
+        assert_eq!(parse_labels("zone"), Err(ParseError::NotALabel));
+        assert_eq!(parse_labels("=us-west-2a"), Err(ParseError::NotALabel));
+    }
+
+    #[test]
+    fn parse_health_checks() {
+        use proxy::http::health::Probe;
+
+        assert_eq!(
+            parse_health_check("http:/ready"),
+            Ok(Probe::Http {
+                path: "/ready".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_health_check("grpc"),
+            Ok(Probe::Grpc {
+                service: "".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_health_check("grpc:books.Library"),
+            Ok(Probe::Grpc {
+                service: "books.Library".to_owned()
+            })
+        );
+        assert_eq!(
+            parse_health_check("http:ready"),
+            Err(ParseError::NotAHealthCheck)
+        );
+        assert_eq!(parse_health_check("tcp"), Err(ParseError::NotAHealthCheck));
+    }
+
+    #[test]
+    fn parse_retry_defaults_from_env() {
+        let mut env = TestEnv::new();
+        let defaults = parse_retry_defaults(&env).unwrap();
+        assert_eq!(defaults.backoff, None);
+        assert_eq!(defaults.per_try_timeout, None);
+        assert!(!defaults.retry_connection_errors);
+
+        env.put(ENV_OUTBOUND_RETRY_BACKOFF_MIN, "10ms".into());
+        assert!(parse_retry_defaults(&env).is_err());
+
+        env.put(ENV_OUTBOUND_RETRY_BACKOFF_MAX, "1s".into());
+        env.put(ENV_OUTBOUND_RETRY_PER_TRY_TIMEOUT, "500ms".into());
+        let defaults = parse_retry_defaults(&env).unwrap();
+        assert_eq!(
+            defaults.backoff,
+            Some(
+                retry::Backoff::new(Duration::from_millis(10), Duration::from_secs(1), 0.0)
+                    .unwrap()
+            )
+        );
+        assert_eq!(defaults.per_try_timeout, Some(Duration::from_millis(500)));
+
+        env.put(ENV_OUTBOUND_RETRY_CONNECTION_ERRORS, "true".into());
+        assert!(parse_retry_defaults(&env).unwrap().retry_connection_errors);
+
+        env.put(ENV_OUTBOUND_RETRY_BACKOFF_JITTER, "2".into());
+        assert!(parse_retry_defaults(&env).is_err());
+    }
+
+    #[test]
+    fn parse_tls_versions_and_cipher_suites() {
+        assert_eq!(parse_tls_version("1.2"), Ok(identity::TlsVersion::Tls12));
+        assert_eq!(parse_tls_version(" 1.3 "), Ok(identity::TlsVersion::Tls13));
+        assert_eq!(parse_tls_version("1.1"), Err(ParseError::NotATlsVersion));
+
+        let suites =
+            parse_cipher_suites("TLS_AES_128_GCM_SHA256, TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,")
+                .unwrap();
+        let names = suites.iter().map(|s| s.name()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "TLS_AES_128_GCM_SHA256",
+                "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256"
+            ]
+        );
+        assert!(parse_cipher_suites("TLS_NULL_WITH_NULL_NULL").is_err());
+    }
+
+    #[test]
+    fn tls_kx_groups_are_rejected() {
+        let mut env = TestEnv::new();
+        assert!(parse_tls_params(&env).is_ok());
+
+        env.put(ENV_TLS_KX_GROUPS, "X25519".into());
+        assert!(parse_tls_params(&env).is_err());
+    }
 }
@@ -332,6 +332,14 @@ pub mod client {
         }
     }
 
+    /// Control plane clients speak HTTP/2, but control plane servers need not
+    /// be proxies that accept `l5d-h2`, so no protocol is offered.
+    impl tls::client::HasAlpnProtocol for Target {
+        fn alpn_protocol(&self) -> Option<&'static [u8]> {
+            None
+        }
+    }
+
     // === impl Layer ===
 
     pub fn layer<C, B>() -> impl svc::Layer<C, Service = Client<C, B>> + Copy
@@ -5,9 +5,11 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use proxy::http::{
+    balance,
     metrics::classify::{CanClassify, Classify, ClassifyEos, ClassifyResponse},
     profiles, retry, settings, timeout,
 };
+use proxy::Error;
 use {Addr, NameAddr};
 
 use super::classify;
@@ -27,6 +29,8 @@ pub struct Route {
 #[derive(Clone, Debug)]
 pub struct Retry {
     budget: Arc<retry::Budget>,
+    backoff: Option<retry::Backoff>,
+    retry_connection_errors: bool,
     response_classes: profiles::ResponseClasses,
 }
 
@@ -35,6 +39,10 @@ pub struct DstAddr {
     addr: Addr,
     direction: Direction,
     pub(super) http_settings: settings::Settings,
+
+    /// Overrides the default load-balancing algorithm, as configured by the
+    /// destination's profile.
+    load_balancer: Option<balance::Algorithm>,
 }
 
 // === impl Route ===
@@ -53,6 +61,8 @@ impl retry::CanRetry for Route {
     fn can_retry(&self) -> Option<Self::Retry> {
         self.route.retries().map(|retries| Retry {
             budget: retries.budget().clone(),
+            backoff: retries.backoff().cloned(),
+            retry_connection_errors: retries.retry_connection_errors(),
             response_classes: self.route.response_classes().clone(),
         })
     }
@@ -62,6 +72,13 @@ impl timeout::HasTimeout for Route {
     fn timeout(&self) -> Option<Duration> {
         self.route.timeout()
     }
+
+    fn per_try_timeout(&self) -> Option<Duration> {
+        // Per-try timeouts only apply to retryable routes.
+        self.route
+            .retries()
+            .and_then(|_| self.route.per_try_timeout())
+    }
 }
 
 // === impl Retry ===
@@ -78,16 +95,27 @@ impl retry::Retry for Retry {
             .eos(None);
 
         if class.is_failure() {
-            return self
-                .budget
-                .withdraw()
-                .map_err(|_overdrawn| retry::NoRetry::Budget);
+            return Ok(());
         }
 
         self.budget.deposit();
         Err(retry::NoRetry::Success)
     }
 
+    fn retry_error(&self, err: &Error) -> Result<(), retry::NoRetry> {
+        if !self.retry_connection_errors || !retry::is_connection_error(err) {
+            return Err(retry::NoRetry::Error);
+        }
+
+        Ok(())
+    }
+
+    fn withdraw(&self) -> Result<(), retry::NoRetry> {
+        self.budget
+            .withdraw()
+            .map_err(|_overdrawn| retry::NoRetry::Budget)
+    }
+
     fn clone_request<B: retry::TryClone>(
         &self,
         req: &http::Request<B>,
@@ -99,6 +127,10 @@ impl retry::Retry for Retry {
             clone
         })
     }
+
+    fn backoff(&self) -> Option<&retry::Backoff> {
+        self.backoff.as_ref()
+    }
 }
 
 // === impl DstAddr ===
@@ -115,6 +147,7 @@ impl DstAddr {
             addr,
             direction: Direction::Out,
             http_settings,
+            load_balancer: None,
         }
     }
 
@@ -123,6 +156,7 @@ impl DstAddr {
             addr,
             direction: Direction::In,
             http_settings,
+            load_balancer: None,
         }
     }
 
@@ -149,6 +183,35 @@ impl profiles::CanGetDestination for DstAddr {
     }
 }
 
+impl profiles::WithAddr for DstAddr {
+    /// Inbound destinations are never split: traffic is split by the client's
+    /// outbound proxy, which sets the concrete destination.
+    fn with_addr(self, addr: NameAddr) -> Self {
+        match self.direction {
+            Direction::Out => DstAddr {
+                addr: Addr::Name(addr),
+                ..self
+            },
+            Direction::In => self,
+        }
+    }
+}
+
+impl profiles::WithLoadBalancer for DstAddr {
+    fn with_load_balancer(self, load_balancer: Option<balance::Algorithm>) -> Self {
+        DstAddr {
+            load_balancer,
+            ..self
+        }
+    }
+}
+
+impl balance::HasAlgorithm for DstAddr {
+    fn algorithm(&self) -> Option<&balance::Algorithm> {
+        self.load_balancer.as_ref()
+    }
+}
+
 impl profiles::WithRoute for DstAddr {
     type Output = Route;
 
@@ -0,0 +1,151 @@
+//! Reads the local identity's certificate and key from files, e.g. as written
+//! by cert-manager or Vault Agent.
+//!
+//! The certificate file contains a PEM-encoded certificate chain, leaf first.
+//! The key file contains a PEM-encoded PKCS#8 key of any type that identities
+//! support (see `identity::Key`). Both files are polled for changes, and are
+//! reloaded when either changes. The key must match the certificate, which is
+//! checked when the certificate is certified.
+
+use futures::{Async, Future, Poll, Stream};
+use futures_watch::Watch;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{CertificateSource, Crt, Key, Name, Refresh, TrustAnchors};
+use fs_watch;
+use never::Never;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub crt: PathBuf,
+    pub key: PathBuf,
+    /// How often the files are checked for changes.
+    pub interval: Duration,
+}
+
+/// Loads a certificate and key whenever their files change.
+pub struct Source {
+    local_name: Name,
+    crt: File,
+    key: File,
+    /// Whether the files have changed since they were last loaded.
+    changed: bool,
+}
+
+struct File {
+    path: PathBuf,
+    daemon: fs_watch::Daemon<String>,
+    watch: Watch<Arc<String>>,
+}
+
+// === impl Source ===
+
+impl Source {
+    pub fn new(config: Config, local_name: Name) -> Self {
+        Self {
+            local_name,
+            crt: File::new(config.crt, config.interval),
+            key: File::new(config.key, config.interval),
+            changed: true,
+        }
+    }
+
+    fn load(&self) -> Result<(Key, Crt), String> {
+        let key = Key::from_pkcs8_pem(self.key.watch.borrow().as_bytes()).ok_or_else(|| {
+            format!(
+                "{} does not contain a valid PKCS#8 key",
+                self.key.path.display()
+            )
+        })?;
+        let crt = Crt::from_pem(self.local_name.clone(), self.crt.watch.borrow().as_bytes())
+            .map_err(|e| format!("{}: {}", self.crt.path.display(), e))?;
+        Ok((key, crt))
+    }
+}
+
+impl CertificateSource for Source {
+    fn poll_certificate(&mut self, _: &TrustAnchors) -> Poll<Refresh, Never> {
+        // Both files are always polled so that both continue to be watched.
+        let crt_changed = self.crt.poll_changed();
+        let key_changed = self.key.poll_changed();
+        if !(self.changed || crt_changed || key_changed) {
+            return Ok(Async::NotReady);
+        }
+        self.changed = false;
+
+        match self.load() {
+            Ok((key, crt)) => Ok(Async::Ready(Refresh::Certified(key, crt))),
+            Err(e) => {
+                // The files may be partially updated, so they will be loaded
+                // again when they next change.
+                warn!("failed to load certificate: {}", e);
+                Ok(Async::Ready(Refresh::Failed))
+            }
+        }
+    }
+}
+
+// === impl File ===
+
+impl File {
+    fn new(path: PathBuf, interval: Duration) -> Self {
+        let config = fs_watch::Config {
+            path: path.clone(),
+            interval,
+        };
+        let (watch, daemon) = fs_watch::watch(config, read);
+        Self {
+            path,
+            daemon,
+            watch,
+        }
+    }
+
+    /// Returns whether the file has changed since it was last polled.
+    fn poll_changed(&mut self) -> bool {
+        // The daemon only completes once the watch has been dropped.
+        let _ = self.daemon.poll();
+
+        let mut changed = false;
+        while let Ok(Async::Ready(Some(()))) = self.watch.poll() {
+            changed = true;
+        }
+        changed
+    }
+}
+
+fn read(contents: &str) -> Result<String, String> {
+    Ok(contents.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn source(dir: &str) -> Source {
+        let dir = PathBuf::from("src/identity/testdata").join(dir);
+        let config = Config {
+            crt: dir.join("crt.pem"),
+            key: dir.join("key.pem"),
+            interval: Duration::from_secs(1),
+        };
+        let name = "foo.ns1.serviceaccount.identity.linkerd.cluster.local";
+        Source::new(config, Name::from_hostname(name.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn loads_certificate_and_key() {
+        let pem = fs::read_to_string("src/identity/testdata/ca1.pem").unwrap();
+        let trust_anchors = TrustAnchors::from_pem(&pem).unwrap();
+
+        let (key, crt) = source("foo-ns1-ca1").load().expect("files must load");
+        assert!(trust_anchors.certify(key, crt).is_ok());
+
+        let (key, crt) = source("foo-ns1-ca2").load().expect("files must load");
+        assert!(trust_anchors.certify(key, crt).is_err());
+    }
+}
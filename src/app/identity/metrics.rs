@@ -0,0 +1,148 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge};
+
+metrics! {
+    identity_cert_expiration_timestamp_seconds: Gauge {
+        "Time when the current certificate expires, in seconds since the Unix epoch"
+    },
+    identity_cert_refresh_seconds: Gauge {
+        "Seconds until the certificate is next refreshed"
+    },
+    identity_cert_refresh_total: Counter {
+        "Total count of attempts to obtain a certificate, by result"
+    }
+}
+
+/// Records the lifecycle of the local identity's certificates.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Metrics>>);
+
+/// Implements `FmtMetrics` to render prometheus-formatted identity metrics.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<Metrics>>);
+
+#[derive(Debug, Default)]
+struct Metrics {
+    expiry: Option<SystemTime>,
+    next_refresh: Option<SystemTime>,
+    refresh_succeeded: Counter,
+    refresh_failed: Counter,
+}
+
+struct ResultLabel(&'static str);
+
+pub fn new() -> (Registry, Report) {
+    let inner = Arc::new(Mutex::new(Metrics::default()));
+    (Registry(inner.clone()), Report(inner))
+}
+
+// === impl Registry ===
+
+impl Registry {
+    /// Records that a certificate, which expires at `expiry`, is now used.
+    pub(super) fn certified(&self, expiry: SystemTime) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.refresh_succeeded.incr();
+            metrics.expiry = Some(expiry);
+        }
+    }
+
+    pub(super) fn failed(&self) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.refresh_failed.incr();
+        }
+    }
+
+    pub(super) fn next_refresh(&self, at: Option<SystemTime>) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.next_refresh = at;
+        }
+    }
+}
+
+// === impl Report ===
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let metrics = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        if let Some(expiry) = metrics.expiry {
+            let secs = expiry
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            identity_cert_expiration_timestamp_seconds.fmt_help(f)?;
+            Gauge::from(secs).fmt_metric(f, identity_cert_expiration_timestamp_seconds.name)?;
+        }
+
+        // Only some certificate sources schedule refreshes.
+        if let Some(at) = metrics.next_refresh {
+            let secs = at
+                .duration_since(SystemTime::now())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            identity_cert_refresh_seconds.fmt_help(f)?;
+            Gauge::from(secs).fmt_metric(f, identity_cert_refresh_seconds.name)?;
+        }
+
+        identity_cert_refresh_total.fmt_help(f)?;
+        for (label, counter) in &[
+            ("success", metrics.refresh_succeeded),
+            ("failure", metrics.refresh_failed),
+        ] {
+            counter.fmt_metric_labeled(f, identity_cert_refresh_total.name, ResultLabel(label))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for ResultLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "result=\"{}\"", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_certificate_lifecycle() {
+        let (registry, report) = new();
+        let text = report.as_display().to_string();
+        assert!(!text.contains(identity_cert_expiration_timestamp_seconds.name));
+        assert!(text.contains("identity_cert_refresh_total{result=\"failure\"} 0"));
+
+        registry.failed();
+        registry.certified(UNIX_EPOCH + Duration::from_secs(1_577_836_800));
+        registry.next_refresh(Some(SystemTime::now() + Duration::from_secs(3_630)));
+
+        let text = report.as_display().to_string();
+        assert!(text.contains("identity_cert_expiration_timestamp_seconds 1577836800"));
+        // The refresh is reported relative to when the metrics are formatted.
+        let refresh_secs = text
+            .lines()
+            .find(|l| l.starts_with("identity_cert_refresh_seconds "))
+            .and_then(|l| {
+                l["identity_cert_refresh_seconds ".len()..]
+                    .parse::<u64>()
+                    .ok()
+            })
+            .expect("refresh must be reported");
+        assert!(
+            refresh_secs == 3_629 || refresh_secs == 3_630,
+            "unexpected refresh: {}",
+            refresh_secs
+        );
+        assert!(text.contains("identity_cert_refresh_total{result=\"success\"} 1"));
+        assert!(text.contains("identity_cert_refresh_total{result=\"failure\"} 1"));
+    }
+}
@@ -0,0 +1,431 @@
+use futures::{Async, Future, Poll, Stream};
+use futures_watch::{Store, Watch};
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio_timer::{clock, Delay};
+
+use fs_watch;
+use json;
+use never::Never;
+
+pub use identity::{
+    CipherSuite, Crt, CrtInfo, CrtKey, Csr, InvalidName, Key, Name, NoUsableCipherSuites,
+    TlsParams, TlsVersion, TokenSource, TrustAnchors,
+};
+use transport::tls;
+
+pub mod file;
+pub mod metrics;
+pub mod service;
+pub mod spiffe;
+
+/// Configures the local identity and how its certificates are obtained.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub source: Source,
+    pub trust_anchors: TrustAnchors,
+    pub trust_anchors_file: Option<fs_watch::Config>,
+    /// How long trust anchors that have been replaced in the trust anchors
+    /// file continue to be trusted.
+    pub trust_anchors_rotation_window: Duration,
+    /// Configures TLS for the trust anchors that are loaded from the trust
+    /// anchors file.
+    pub tls: TlsParams,
+    pub local_name: Name,
+}
+
+/// Configures a `CertificateSource`.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// Certificates are issued by the Identity service.
+    Service(service::Config),
+    /// Certificates are read from files, e.g. as written by cert-manager.
+    Files(file::Config),
+    /// Certificates are X.509-SVIDs from a SPIFFE Workload API.
+    Spiffe(spiffe::Config),
+}
+
+/// Obtains certificates for the local identity.
+pub trait CertificateSource {
+    /// Polls for the result of the next attempt to obtain a certificate.
+    ///
+    /// Certificates must be valid for `trust_anchors`; those that are not are
+    /// not used.
+    fn poll_certificate(&mut self, trust_anchors: &TrustAnchors) -> Poll<Refresh, Never>;
+
+    /// Obtains a new certificate as soon as possible, e.g. because the current
+    /// certificate is not valid for new trust anchors.
+    fn refresh(&mut self) {}
+
+    /// Returns when a new certificate will next be obtained, if the source
+    /// schedules refreshes.
+    fn next_refresh(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// The result of an attempt to obtain a certificate.
+pub enum Refresh {
+    /// A certificate, with the key that it certifies.
+    Certified(Key, Crt),
+    /// No certificate was obtained. Sources log the reason.
+    Failed,
+}
+
+/// Holds the process's local TLS identity state.
+///
+/// Updates dynamically as certificates are provisioned by a
+/// `CertificateSource`.
+#[derive(Clone, Debug)]
+pub struct Local {
+    trust_anchors: CurrentTrustAnchors,
+    name: Name,
+    crt_key: Watch<Option<CrtKey>>,
+}
+
+/// The trust anchors that are currently in use, which change as they are
+/// rotated.
+#[derive(Clone, Debug)]
+pub struct CurrentTrustAnchors(Watch<TrustAnchors>);
+
+/// Describes the local identity for the admin server.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Option<Local>);
+
+/// Produces a `Local` identity once a certificate is available.
+#[derive(Debug)]
+pub struct AwaitCrt(Option<Local>);
+
+#[derive(Copy, Clone, Debug)]
+pub struct LostDaemon;
+
+/// Updates a `Local` identity's certificate and trust anchors.
+pub struct LocalStore {
+    crt_key: Store<Option<CrtKey>>,
+    trust_anchors: Store<TrustAnchors>,
+}
+
+/// Drives updates.
+pub struct Daemon {
+    source: Box<CertificateSource + Send>,
+    store: LocalStore,
+    trust_anchors: TrustAnchors,
+    /// The most recently certified certificate, which must be certified
+    /// again when the trust anchors change.
+    certified: Option<(Key, Crt)>,
+    trust_anchors_file: Option<TrustAnchorsFile>,
+    metrics: metrics::Registry,
+}
+
+/// Reloads trust anchors from a file.
+struct TrustAnchorsFile {
+    daemon: fs_watch::Daemon<Option<TrustAnchors>>,
+    watch: Watch<Arc<Option<TrustAnchors>>>,
+    rotation_window: Duration,
+    tls: TlsParams,
+    /// The trust anchors most recently loaded from the file.
+    current: TrustAnchors,
+    /// The trust anchors that `current` replaced, which remain trusted until
+    /// the rotation window elapses.
+    previous: Option<(TrustAnchors, Delay)>,
+}
+
+// === impl Local ===
+
+impl Local {
+    pub fn new(config: &Config) -> (Self, LocalStore) {
+        let (crt_key, crt_key_store) = Watch::new(None);
+        let (trust_anchors, trust_anchors_store) = Watch::new(config.trust_anchors.clone());
+        let l = Local {
+            name: config.local_name.clone(),
+            trust_anchors: CurrentTrustAnchors(trust_anchors),
+            crt_key,
+        };
+        let s = LocalStore {
+            crt_key: crt_key_store,
+            trust_anchors: trust_anchors_store,
+        };
+        (l, s)
+    }
+
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn trust_anchors(&self) -> CurrentTrustAnchors {
+        self.trust_anchors.clone()
+    }
+
+    pub fn await_crt(self) -> AwaitCrt {
+        AwaitCrt(Some(self))
+    }
+}
+
+impl tls::client::HasConfig for Local {
+    fn tls_client_config(&self) -> Arc<tls::client::Config> {
+        if let Some(ref c) = *self.crt_key.borrow() {
+            return c.tls_client_config();
+        }
+
+        self.trust_anchors.tls_client_config()
+    }
+
+    fn tls_client_config_offering(
+        &self,
+        protocol: &'static [u8],
+    ) -> Option<Arc<tls::client::Config>> {
+        if let Some(ref c) = *self.crt_key.borrow() {
+            return c.tls_client_config_offering(protocol);
+        }
+
+        self.trust_anchors.tls_client_config_offering(protocol)
+    }
+}
+
+impl tls::listen::HasConfig for Local {
+    fn tls_server_name(&self) -> Name {
+        self.name.clone()
+    }
+
+    fn tls_server_config(&self) -> Arc<tls::listen::Config> {
+        if let Some(ref c) = *self.crt_key.borrow() {
+            return c.tls_server_config();
+        }
+
+        tls::listen::empty_config()
+    }
+}
+
+// === impl CurrentTrustAnchors ===
+
+impl tls::client::HasConfig for CurrentTrustAnchors {
+    fn tls_client_config(&self) -> Arc<tls::client::Config> {
+        self.0.borrow().tls_client_config()
+    }
+
+    fn tls_client_config_offering(
+        &self,
+        protocol: &'static [u8],
+    ) -> Option<Arc<tls::client::Config>> {
+        self.0.borrow().tls_client_config_offering(protocol)
+    }
+}
+
+// === impl Daemon ===
+
+impl Daemon {
+    pub fn new(
+        config: Config,
+        mut store: LocalStore,
+        source: Box<CertificateSource + Send>,
+        metrics: metrics::Registry,
+    ) -> Self {
+        let rotation_window = config.trust_anchors_rotation_window;
+        let tls = config.tls;
+        let mut trust_anchors = config.trust_anchors;
+        let trust_anchors_file = config.trust_anchors_file.map(|file| {
+            let (watch, daemon) = fs_watch::watch(file, parse_trust_anchors);
+            // The file's trust anchors replace those that were configured
+            // without a rotation window, since they have not yet been used.
+            if let Some(ref loaded) = **watch.borrow() {
+                trust_anchors = loaded.with_params(&tls);
+                let _ = store.trust_anchors.store(trust_anchors.clone());
+            }
+            TrustAnchorsFile {
+                daemon,
+                watch,
+                rotation_window,
+                tls,
+                current: trust_anchors.clone(),
+                previous: None,
+            }
+        });
+
+        Self {
+            source,
+            store,
+            trust_anchors,
+            certified: None,
+            trust_anchors_file,
+            metrics,
+        }
+    }
+
+    /// Trusts a new set of trust anchors, certifying the current certificate
+    /// with them.
+    fn rotate(&mut self, trust_anchors: TrustAnchors) {
+        info!("updating trust anchors");
+        self.trust_anchors = trust_anchors.clone();
+        let _ = self.store.trust_anchors.store(trust_anchors.clone());
+
+        if let Some((key, crt)) = self.certified.clone() {
+            match trust_anchors.certify(key, crt) {
+                Ok(crt_key) => {
+                    let _ = self.store.crt_key.store(Some(crt_key));
+                }
+                Err(e) => {
+                    // The previous certificate continues to be used until a
+                    // new one is obtained.
+                    warn!("certificate is not valid for new trust anchors: {}", e);
+                    self.source.refresh();
+                }
+            }
+        }
+    }
+}
+
+fn parse_trust_anchors(pem: &str) -> Result<Option<TrustAnchors>, String> {
+    TrustAnchors::from_pem(pem)
+        .map(Some)
+        .ok_or_else(|| "no valid trust anchors".to_owned())
+}
+
+impl Future for Daemon {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rotated = self
+            .trust_anchors_file
+            .as_mut()
+            .and_then(TrustAnchorsFile::poll_rotate);
+        if let Some(trust_anchors) = rotated {
+            self.rotate(trust_anchors);
+        }
+
+        loop {
+            let (key, crt) = match self.source.poll_certificate(&self.trust_anchors) {
+                Ok(Async::NotReady) => {
+                    self.metrics.next_refresh(self.source.next_refresh());
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(Refresh::Failed)) => {
+                    self.metrics.failed();
+                    continue;
+                }
+                Ok(Async::Ready(Refresh::Certified(key, crt))) => (key, crt),
+                Err(never) => match never {},
+            };
+            let expiry = crt.expiry();
+            match self.trust_anchors.certify(key.clone(), crt.clone()) {
+                Err(e) => {
+                    error!("Received invalid ceritficate: {}", e);
+                    self.metrics.failed();
+                }
+                Ok(crt_key) => {
+                    debug!("daemon certified until {:?}", expiry);
+                    if self.store.crt_key.store(Some(crt_key)).is_err() {
+                        // If we can't store a value, than all observations
+                        // have been dropped and we can stop refreshing.
+                        return Ok(Async::Ready(()));
+                    }
+
+                    self.metrics.certified(expiry);
+                    self.certified = Some((key, crt));
+                }
+            }
+        }
+    }
+}
+
+// === impl TrustAnchorsFile ===
+
+impl TrustAnchorsFile {
+    /// Returns the trust anchors that should be trusted, if they have changed.
+    fn poll_rotate(&mut self) -> Option<TrustAnchors> {
+        // The daemon only completes once the watch has been dropped.
+        let _ = self.daemon.poll();
+
+        let mut changed = false;
+        while let Ok(Async::Ready(Some(()))) = self.watch.poll() {
+            let loaded = (**self.watch.borrow()).clone();
+            if let Some(trust_anchors) = loaded {
+                let trust_anchors = trust_anchors.with_params(&self.tls);
+                let previous = mem::replace(&mut self.current, trust_anchors);
+                let expiry = Delay::new(clock::now() + self.rotation_window);
+                self.previous = Some((previous, expiry));
+                changed = true;
+            }
+        }
+
+        let expired = match self.previous {
+            Some((_, ref mut expiry)) => match expiry.poll() {
+                Ok(Async::NotReady) => false,
+                _ => true,
+            },
+            None => false,
+        };
+        if expired {
+            debug!("no longer trusting replaced trust anchors");
+            self.previous = None;
+            changed = true;
+        }
+
+        if !changed {
+            return None;
+        }
+        match self.previous {
+            Some((ref previous, _)) => Some(self.current.union(previous)),
+            None => Some(self.current.clone()),
+        }
+    }
+}
+
+// === impl Report ===
+
+impl Report {
+    pub fn new(local: Local) -> Self {
+        Report(Some(local))
+    }
+
+    pub fn to_json(&self) -> json::Value {
+        let local = match self.0 {
+            Some(ref local) => local,
+            None => return json::object(vec![("enabled", json::Value::Bool(false))]),
+        };
+
+        let crt = match *local.crt_key.borrow() {
+            Some(ref crt_key) => json::Value::Array(
+                crt_key
+                    .describe_chain()
+                    .iter()
+                    .map(CrtInfo::to_json)
+                    .collect(),
+            ),
+            None => json::Value::Null,
+        };
+        let tls = local.trust_anchors.0.borrow().params().to_json();
+        json::object(vec![
+            ("enabled", json::Value::Bool(true)),
+            ("name", json::Value::String(local.name.as_ref().to_owned())),
+            ("certificates", crt),
+            ("tls", tls),
+        ])
+    }
+}
+
+// === impl AwaitCrt ===
+
+impl Future for AwaitCrt {
+    type Item = Local;
+    type Error = LostDaemon;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut local = self.0.take().expect("polled after ready");
+        loop {
+            if (*local.crt_key.borrow()).is_some() {
+                return Ok(Async::Ready(local));
+            }
+
+            match local.crt_key.poll() {
+                Ok(Async::NotReady) => {
+                    self.0 = Some(local);
+                    return Ok(Async::NotReady);
+                }
+                Ok(Async::Ready(Some(()))) => {} // continue
+                Err(_) | Ok(Async::Ready(None)) => return Err(LostDaemon),
+            }
+        }
+    }
+}
@@ -1,57 +1,37 @@
+//! Obtains certificates from the Identity service by sending it a `Csr` and
+//! an authentication token.
+
 use futures::{Async, Future, Poll};
-use futures_watch::{Store, Watch};
-use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio_timer::{clock, Delay};
 use tower_grpc::{self as grpc, generic::client::GrpcService, BoxBody};
 
+use super::{CertificateSource, Crt, Csr, Key, Name, Refresh, TokenSource, TrustAnchors};
 use api::identity as api;
+use app::control::ControlAddr;
 use never::Never;
 
-pub use identity::{Crt, CrtKey, Csr, InvalidName, Key, Name, TokenSource, TrustAnchors};
-use transport::tls;
-
-/// Configures the Identity service and local identity.
+/// Configures the Identity service.
 #[derive(Clone, Debug)]
 pub struct Config {
-    pub svc: super::control::ControlAddr,
-    pub trust_anchors: TrustAnchors,
+    pub svc: ControlAddr,
     pub key: Key,
     pub csr: Csr,
     pub token: TokenSource,
-    pub local_name: Name,
     pub min_refresh: Duration,
     pub max_refresh: Duration,
 }
 
-/// Holds the process's local TLS identity state.
-///
-/// Updates dynamically as certificates are provisioned from the Identity service.
-#[derive(Clone, Debug)]
-pub struct Local {
-    trust_anchors: TrustAnchors,
-    name: Name,
-    crt_key: Watch<Option<CrtKey>>,
-}
-
-/// Produces a `Local` identity once a certificate is available.
-#[derive(Debug)]
-pub struct AwaitCrt(Option<Local>);
-
-#[derive(Copy, Clone, Debug)]
-pub struct LostDaemon;
-
-pub type CrtKeyStore = Store<Option<CrtKey>>;
-
-/// Drives updates.
-pub struct Daemon<T>
+/// Requests a certificate each time the previous certificate should be
+/// refreshed.
+pub struct Source<T>
 where
     T: GrpcService<BoxBody>,
     T::ResponseBody: grpc::Body,
 {
     config: Config,
+    local_name: Name,
     client: api::client::Identity<T>,
-    crt_key: Store<Option<CrtKey>>,
     expiry: SystemTime,
     inner: Inner<T>,
 }
@@ -91,62 +71,16 @@ impl Config {
     }
 }
 
-// === impl Local ===
-
-impl Local {
-    pub fn new(config: &Config) -> (Self, CrtKeyStore) {
-        let (w, s) = Watch::new(None);
-        let l = Local {
-            name: config.local_name.clone(),
-            trust_anchors: config.trust_anchors.clone(),
-            crt_key: w,
-        };
-        (l, s)
-    }
-
-    pub fn name(&self) -> &Name {
-        &self.name
-    }
-
-    pub fn await_crt(self) -> AwaitCrt {
-        AwaitCrt(Some(self))
-    }
-}
-
-impl tls::client::HasConfig for Local {
-    fn tls_client_config(&self) -> Arc<tls::client::Config> {
-        if let Some(ref c) = *self.crt_key.borrow() {
-            return c.tls_client_config();
-        }
-
-        self.trust_anchors.tls_client_config()
-    }
-}
-
-impl tls::listen::HasConfig for Local {
-    fn tls_server_name(&self) -> Name {
-        self.name.clone()
-    }
-
-    fn tls_server_config(&self) -> Arc<tls::listen::Config> {
-        if let Some(ref c) = *self.crt_key.borrow() {
-            return c.tls_server_config();
-        }
+// === impl Source ===
 
-        tls::listen::empty_config()
-    }
-}
-
-// === impl Daemon ===
-
-impl<T> Daemon<T>
+impl<T> Source<T>
 where
     T: GrpcService<BoxBody> + Clone,
 {
-    pub fn new(config: Config, crt_key: CrtKeyStore, client: T) -> Self {
+    pub fn new(config: Config, local_name: Name, client: T) -> Self {
         Self {
             config,
-            crt_key,
+            local_name,
             inner: Inner::ShouldRefresh,
             expiry: UNIX_EPOCH,
             client: api::client::Identity::new(client),
@@ -154,14 +88,12 @@ where
     }
 }
 
-impl<T> Future for Daemon<T>
+impl<T> CertificateSource for Source<T>
 where
     T: GrpcService<BoxBody> + Clone,
 {
-    type Item = ();
-    type Error = Never;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    fn poll_certificate(&mut self, trust_anchors: &TrustAnchors) -> Poll<Refresh, Never> {
+        let mut refreshed = None;
         loop {
             self.inner = match self.inner {
                 Inner::Waiting(ref mut d) => {
@@ -182,7 +114,7 @@ where
                         Ok(token) => {
                             let req = grpc::Request::new(api::CertifyRequest {
                                 token,
-                                identity: self.config.local_name.as_ref().to_owned(),
+                                identity: self.local_name.as_ref().to_owned(),
                                 certificate_signing_request: self.config.csr.to_vec(),
                             });
                             trace!("daemon certifying");
@@ -190,6 +122,7 @@ where
                         }
                         Err(e) => {
                             error!("Failed to read authentication token: {}", e);
+                            refreshed = Some(Refresh::Failed);
                             Inner::Waiting(self.config.refresh(self.expiry))
                         }
                     }
@@ -208,31 +141,29 @@ where
                             match valid_until
                                 .and_then(|d| Result::<SystemTime, Duration>::from(d).ok())
                             {
-                                None => error!(
-                                    "Identity service did not specify a certificate expiration."
-                                ),
+                                None => {
+                                    error!("Identity service did not specify a certificate expiration.");
+                                    refreshed = Some(Refresh::Failed);
+                                }
                                 Some(expiry) => {
                                     let key = self.config.key.clone();
                                     let crt = Crt::new(
-                                        self.config.local_name.clone(),
+                                        self.local_name.clone(),
                                         leaf_certificate,
                                         intermediate_certificates,
                                         expiry,
                                     );
 
-                                    match self.config.trust_anchors.certify(key, crt) {
+                                    // Invalid certificates are refreshed as
+                                    // though they had not been issued.
+                                    match trust_anchors.certify(key.clone(), crt.clone()) {
                                         Err(e) => {
                                             error!("Received invalid ceritficate: {}", e);
+                                            refreshed = Some(Refresh::Failed);
                                         }
-                                        Ok(crt_key) => {
-                                            debug!("daemon certified until {:?}", expiry);
-                                            if self.crt_key.store(Some(crt_key)).is_err() {
-                                                // If we can't store a value, than all observations
-                                                // have been dropped and we can stop refreshing.
-                                                return Ok(Async::Ready(()));
-                                            }
-
+                                        Ok(_) => {
                                             self.expiry = expiry;
+                                            refreshed = Some(Refresh::Certified(key, crt));
                                         }
                                     }
                                 }
@@ -242,38 +173,37 @@ where
                         }
                         Err(e) => {
                             error!("Failed to certify identity: {}", e);
+                            refreshed = Some(Refresh::Failed);
                             Inner::Waiting(self.config.refresh(self.expiry))
                         }
                     }
                 }
             };
+
+            if let Some(refreshed) = refreshed.take() {
+                return Ok(Async::Ready(refreshed));
+            }
         }
     }
-}
-
-// === impl AwaitCrt ===
-
-impl Future for AwaitCrt {
-    type Item = Local;
-    type Error = LostDaemon;
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        use futures::Stream;
-
-        let mut local = self.0.take().expect("polled after ready");
-        loop {
-            if (*local.crt_key.borrow()).is_some() {
-                return Ok(Async::Ready(local));
-            }
+    fn refresh(&mut self) {
+        if let Inner::Waiting(_) = self.inner {
+            self.inner = Inner::ShouldRefresh;
+        }
+    }
 
-            match local.crt_key.poll() {
-                Ok(Async::NotReady) => {
-                    self.0 = Some(local);
-                    return Ok(Async::NotReady);
+    fn next_refresh(&self) -> Option<SystemTime> {
+        let now = SystemTime::now();
+        match self.inner {
+            Inner::Waiting(ref delay) => {
+                let (deadline, now_instant) = (delay.deadline(), clock::now());
+                if deadline > now_instant {
+                    Some(now + (deadline - now_instant))
+                } else {
+                    Some(now)
                 }
-                Ok(Async::Ready(Some(()))) => {} // continue
-                Err(_) | Ok(Async::Ready(None)) => return Err(LostDaemon),
             }
+            Inner::ShouldRefresh | Inner::Pending(_) => Some(now),
         }
     }
 }
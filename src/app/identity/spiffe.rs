@@ -0,0 +1,327 @@
+//! Obtains X.509-SVIDs from a SPIFFE Workload API (e.g. as served by a SPIRE
+//! agent) over a Unix socket.
+//!
+//! The Workload API streams a new SVID each time it is rotated. The first
+//! SVID in each update is used. Its SPIFFE ID must be one of its URI SANs.
+//! Since peers verify the proxy's identity by its DNS name, rather than by
+//! its SPIFFE ID, the SVID must also have a DNS SAN for the local identity's
+//! name; SVIDs with only a URI SAN are rejected when they are certified. Its
+//! key may be of any type that identities support (see `identity::Key`).
+//!
+//! The SVID's bundle is not used: peers are verified against the configured
+//! trust anchors, which the SVID must chain to, so that the Workload API
+//! cannot change which peers are trusted. The trust anchors must therefore
+//! still be configured, and be rotated as the trust domain's bundle is.
+
+use bytes::{Bytes, BytesMut};
+use futures::{Async, Future, Poll, Stream};
+use h2;
+use http;
+use prost::Message;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::unix::{ConnectFuture, UnixStream};
+use tokio_timer::{clock, Delay};
+
+use super::{CertificateSource, Crt, Key, Name, Refresh, TrustAnchors};
+use never::Never;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The path of the Workload API's socket.
+    pub socket: PathBuf,
+}
+
+/// Streams X.509-SVIDs from the Workload API, reconnecting when the stream
+/// fails.
+pub struct Source {
+    config: Config,
+    local_name: Name,
+    conn: Option<h2::client::Connection<UnixStream>>,
+    state: State,
+}
+
+enum State {
+    Disconnected(Delay),
+    Connecting(ConnectFuture),
+    Handshaking(h2::client::Handshake<UnixStream>),
+    Requesting(h2::client::SendRequest<Bytes>),
+    Fetching(h2::client::SendRequest<Bytes>, h2::client::ResponseFuture),
+    /// Holds the data that has been received but not yet decoded.
+    Streaming(h2::client::SendRequest<Bytes>, h2::RecvStream, BytesMut),
+}
+
+/// Messages of the SPIFFE Workload API.
+mod api {
+    #[derive(Clone, PartialEq, Message)]
+    pub struct X509SvidResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub svids: Vec<X509Svid>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct X509Svid {
+        #[prost(string, tag = "1")]
+        pub spiffe_id: String,
+        /// DER-encoded certificates, leaf first.
+        #[prost(bytes, tag = "2")]
+        pub x509_svid: Vec<u8>,
+        /// A DER-encoded PKCS#8 key.
+        #[prost(bytes, tag = "3")]
+        pub x509_svid_key: Vec<u8>,
+        #[prost(bytes, tag = "4")]
+        pub bundle: Vec<u8>,
+    }
+}
+
+const FETCH_X509_SVID_URI: &str = "http://localhost/SpiffeWorkloadAPI/FetchX509SVID";
+
+/// An empty `X509SVIDRequest`, framed as a gRPC message.
+const FETCH_X509_SVID_REQUEST: &[u8] = &[0, 0, 0, 0, 0];
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// === impl Source ===
+
+impl Source {
+    pub fn new(config: Config, local_name: Name) -> Self {
+        Self {
+            config,
+            local_name,
+            conn: None,
+            state: State::Disconnected(Delay::new(clock::now())),
+        }
+    }
+}
+
+impl CertificateSource for Source {
+    fn poll_certificate(&mut self, _: &TrustAnchors) -> Poll<Refresh, Never> {
+        loop {
+            // The connection must be polled for its streams to make progress.
+            if let Some(Err(e)) = self.conn.as_mut().map(poll_connection) {
+                self.conn = None;
+                self.state = reconnect(&e);
+                return Ok(Async::Ready(Refresh::Failed));
+            }
+
+            self.state = match self.state {
+                State::Disconnected(ref mut delay) => {
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                    debug!("connecting to {}", self.config.socket.display());
+                    State::Connecting(UnixStream::connect(&self.config.socket))
+                }
+                State::Connecting(ref mut connect) => match connect.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(io)) => State::Handshaking(h2::client::handshake(io)),
+                    Err(e) => reconnect(&e),
+                },
+                State::Handshaking(ref mut handshake) => match handshake.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready((send, conn))) => {
+                        self.conn = Some(conn);
+                        State::Requesting(send)
+                    }
+                    Err(e) => reconnect(&e),
+                },
+                State::Requesting(ref mut send) => match send.poll_ready() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => {
+                        let sent = send.send_request(fetch_request(), false).and_then(
+                            |(rsp, mut body)| {
+                                let req = Bytes::from_static(FETCH_X509_SVID_REQUEST);
+                                body.send_data(req, true).map(|()| rsp)
+                            },
+                        );
+                        match sent {
+                            Ok(rsp) => State::Fetching(send.clone(), rsp),
+                            Err(e) => reconnect(&e),
+                        }
+                    }
+                    Err(e) => reconnect(&e),
+                },
+                State::Fetching(ref send, ref mut rsp) => match rsp.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(rsp)) => match check_response(&rsp) {
+                        Ok(()) => State::Streaming(send.clone(), rsp.into_body(), BytesMut::new()),
+                        Err(e) => reconnect(&e),
+                    },
+                    Err(e) => reconnect(&e),
+                },
+                State::Streaming(_, ref mut body, ref mut buf) => {
+                    // Messages that have already been received must be decoded
+                    // before the stream is polled, since it will not notify
+                    // this task of them again.
+                    if let Some(msg) = next_message(buf) {
+                        match decode_svid(&msg, &self.local_name) {
+                            Ok((key, crt)) => {
+                                return Ok(Async::Ready(Refresh::Certified(key, crt)));
+                            }
+                            Err(e) => {
+                                warn!("ignoring invalid X.509-SVID: {}", e);
+                                return Ok(Async::Ready(Refresh::Failed));
+                            }
+                        }
+                    }
+
+                    match body.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(Some(data))) => {
+                            let _ = body.release_capacity().release_capacity(data.len());
+                            buf.extend_from_slice(&data);
+                            continue;
+                        }
+                        Ok(Async::Ready(None)) => reconnect(&"the stream ended"),
+                        Err(e) => reconnect(&e),
+                    }
+                }
+            };
+
+            if let State::Disconnected(_) = self.state {
+                self.conn = None;
+                return Ok(Async::Ready(Refresh::Failed));
+            }
+        }
+    }
+}
+
+fn reconnect(e: &fmt::Display) -> State {
+    warn!("SPIFFE Workload API failed: {}", e);
+    State::Disconnected(Delay::new(clock::now() + RECONNECT_DELAY))
+}
+
+fn poll_connection(conn: &mut h2::client::Connection<UnixStream>) -> Result<(), String> {
+    match conn.poll() {
+        Ok(Async::NotReady) => Ok(()),
+        Ok(Async::Ready(())) => Err("the connection closed".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn fetch_request() -> http::Request<()> {
+    http::Request::builder()
+        .method(http::Method::POST)
+        .uri(FETCH_X509_SVID_URI)
+        .header(http::header::CONTENT_TYPE, "application/grpc")
+        .header(http::header::TE, "trailers")
+        // The Workload API rejects requests without this header.
+        .header("workload.spiffe.io", "true")
+        .body(())
+        .expect("request must be valid")
+}
+
+fn check_response<B>(rsp: &http::Response<B>) -> Result<(), String> {
+    if rsp.status() != http::StatusCode::OK {
+        return Err(format!("unexpected status: {}", rsp.status()));
+    }
+
+    // Errors may be returned without a body, in the response's headers.
+    let header = |name: &str| {
+        rsp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+    };
+    match header("grpc-status") {
+        "" | "0" => Ok(()),
+        status => Err(format!(
+            "grpc-status {}: {}",
+            status,
+            header("grpc-message")
+        )),
+    }
+}
+
+/// Takes the next gRPC message from `buf`, if it has been received entirely.
+fn next_message(buf: &mut BytesMut) -> Option<BytesMut> {
+    if buf.len() < 5 {
+        return None;
+    }
+
+    // Each message is prefixed by a compression flag and a 4-byte length.
+    let len = buf[1..5]
+        .iter()
+        .fold(0usize, |len, b| (len << 8) | *b as usize);
+    if buf.len() < 5 + len {
+        return None;
+    }
+    let mut msg = buf.split_to(5 + len);
+    Some(msg.split_off(5))
+}
+
+fn decode_svid(msg: &[u8], local_name: &Name) -> Result<(Key, Crt), String> {
+    let rsp = api::X509SvidResponse::decode(msg).map_err(|e| e.to_string())?;
+    let svid = rsp
+        .svids
+        .into_iter()
+        .next()
+        .ok_or_else(|| "no X.509-SVIDs".to_owned())?;
+    debug!("received X.509-SVID for {}", svid.spiffe_id);
+
+    let key = Key::from_pkcs8(&svid.x509_svid_key).map_err(|e| format!("invalid key: {}", e))?;
+    let crt = Crt::from_der(local_name.clone(), &svid.x509_svid).map_err(|e| e.to_string())?;
+    if !svid.spiffe_id.starts_with("spiffe://") || !crt.has_uri_san(&svid.spiffe_id) {
+        return Err(format!("not a valid X.509-SVID for {:?}", svid.spiffe_id));
+    }
+    Ok((key, crt))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn read(path: &str) -> Vec<u8> {
+        fs::read(format!("src/identity/testdata/{}", path)).unwrap()
+    }
+
+    fn encode(spiffe_id: &str, dir: &str) -> Vec<u8> {
+        let svid = api::X509Svid {
+            spiffe_id: spiffe_id.to_owned(),
+            x509_svid: read(&format!("{}/crt.der", dir)),
+            x509_svid_key: read(&format!("{}/key.p8", dir)),
+            bundle: Vec::new(),
+        };
+        let rsp = api::X509SvidResponse { svids: vec![svid] };
+        let mut msg = Vec::new();
+        rsp.encode(&mut msg).unwrap();
+        msg
+    }
+
+    fn local_name() -> Name {
+        let name = "foo.ns1.serviceaccount.identity.linkerd.cluster.local";
+        Name::from_hostname(name.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn decodes_svids_from_framed_messages() {
+        let msg = encode("spiffe://cluster.local/ns/ns1/sa/foo", "foo-ns1-ca1-svid");
+
+        let len = msg.len();
+        let mut buf = BytesMut::from(vec![0, 0, 0, (len >> 8) as u8, len as u8]);
+        buf.extend_from_slice(&msg[..10]);
+        assert!(next_message(&mut buf).is_none());
+        buf.extend_from_slice(&msg[10..]);
+        let msg = next_message(&mut buf).expect("message must be complete");
+        assert!(buf.is_empty());
+
+        let (key, crt) = decode_svid(&msg, &local_name()).expect("SVID must be valid");
+
+        let pem = String::from_utf8(read("ca1.pem")).unwrap();
+        let trust_anchors = TrustAnchors::from_pem(&pem).unwrap();
+        assert!(trust_anchors.certify(key, crt).is_ok());
+    }
+
+    #[test]
+    fn rejects_svids_without_their_spiffe_id() {
+        let other = encode("spiffe://cluster.local/ns/ns1/sa/bar", "foo-ns1-ca1-svid");
+        assert!(decode_svid(&other, &local_name()).is_err());
+
+        // The certificate has a DNS SAN, but no URI SAN.
+        let no_uri = encode("spiffe://cluster.local/ns/ns1/sa/foo", "foo-ns1-ca1");
+        assert!(decode_svid(&no_uri, &local_name()).is_err());
+    }
+}
@@ -51,6 +51,12 @@ impl tls::HasPeerIdentity for Endpoint {
     }
 }
 
+impl tls::client::HasAlpnProtocol for Endpoint {
+    fn alpn_protocol(&self) -> Option<&'static [u8]> {
+        None
+    }
+}
+
 impl settings::HasSettings for Endpoint {
     fn http_settings(&self) -> &settings::Settings {
         &self.http_settings
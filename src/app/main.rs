@@ -59,7 +59,7 @@ pub struct Main<G> {
 
 struct ProxyParts<G> {
     config: Config,
-    identity: tls::Conditional<(identity::Local, identity::CrtKeyStore)>,
+    identity: tls::Conditional<(identity::Local, identity::LocalStore)>,
 
     start_time: SystemTime,
 
@@ -206,7 +206,17 @@ where
 
         info!("using destination service at {:?}", config.destination_addr);
         match config.identity_config.as_ref() {
-            Conditional::Some(config) => info!("using identity service at {:?}", config.svc.addr),
+            Conditional::Some(config) => match config.source {
+                identity::Source::Service(ref svc) => {
+                    info!("using identity service at {:?}", svc.svc.addr)
+                }
+                identity::Source::Files(ref files) => {
+                    info!("using identity certificate from {}", files.crt.display())
+                }
+                identity::Source::Spiffe(ref spiffe) => {
+                    info!("using SPIFFE Workload API at {}", spiffe.socket.display())
+                }
+            },
             Conditional::None(reason) => info!("identity is DISABLED: {}", reason),
         }
         info!("routing on {:?}", outbound_listener.local_addr());
@@ -256,10 +266,28 @@ where
 
         let (transport_metrics, transport_report) = transport::metrics::new();
 
+        let (outlier_metrics, outlier_report) = proxy::http::outlier::new();
+
+        let (health_metrics, health_report) = proxy::http::health::new();
+
+        let (dst_metrics, dst_report) = control::destination::metrics::new();
+
+        let (identity_metrics, identity_report) = identity::metrics::new();
+
+        // Describe the proxy's routers, balancers, and profiles for the admin
+        // server.
+        let router_report = proxy::http::router::Report::default();
+        let endpoint_report = proxy::http::balance::report::Report::default();
+        let profile_report = super::profiles::report::Report::default();
+
         let report = endpoint_http_report
             .and_then(route_http_report)
             .and_then(retry_http_report)
             .and_then(transport_report)
+            .and_then(outlier_report)
+            .and_then(health_report.clone())
+            .and_then(dst_report)
+            .and_then(identity_report)
             //.and_then(tls_config_report)
             .and_then(ctl_http_report)
             .and_then(telemetry::process::Report::new(start_time));
@@ -271,7 +299,7 @@ where
                 ready_latch.release();
                 Conditional::None(r)
             }
-            Conditional::Some((local_identity, crt_store)) => {
+            Conditional::Some((local_identity, local_store)) => {
                 use super::control;
 
                 let id_config = match config.identity_config.as_ref() {
@@ -279,36 +307,57 @@ where
                     Conditional::None(_) => unreachable!(),
                 };
 
-                // If the service is on localhost, use the inbound keepalive.
-                // If the service. is remote, use the outbound keepalive.
-                let keepalive = if id_config.svc.addr.is_loopback() {
-                    config.inbound_connect_keepalive
-                } else {
-                    config.outbound_connect_keepalive
+                let local_name = id_config.local_name.clone();
+                let source: Box<identity::CertificateSource + Send> = match id_config.source {
+                    identity::Source::Service(ref svc_config) => {
+                        // If the service is on localhost, use the inbound keepalive.
+                        // If the service. is remote, use the outbound keepalive.
+                        let keepalive = if svc_config.svc.addr.is_loopback() {
+                            config.inbound_connect_keepalive
+                        } else {
+                            config.outbound_connect_keepalive
+                        };
+
+                        let svc = svc::builder()
+                            .buffer_pending(
+                                config.destination_buffer_capacity,
+                                config.control_dispatch_timeout,
+                            )
+                            .layer(control::add_origin::layer())
+                            .layer(proxy::grpc::req_body_as_payload::layer().per_make())
+                            .layer(http_metrics::layer::<_, classify::Response>(
+                                ctl_http_metrics.clone(),
+                            ))
+                            .layer(reconnect::layer().with_backoff(config.control_backoff.clone()))
+                            .layer(control::resolve::layer(dns_resolver.clone()))
+                            .layer(control::client::layer())
+                            .timeout(config.control_connect_timeout)
+                            .layer(keepalive::connect::layer(keepalive))
+                            .layer(tls::client::layer(Conditional::Some(
+                                local_identity.trust_anchors(),
+                            )))
+                            .service(connect::svc())
+                            .make(svc_config.svc.clone());
+
+                        Box::new(identity::service::Source::new(
+                            svc_config.clone(),
+                            local_name,
+                            svc,
+                        ))
+                    }
+                    identity::Source::Files(ref files) => {
+                        Box::new(identity::file::Source::new(files.clone(), local_name))
+                    }
+                    identity::Source::Spiffe(ref spiffe) => {
+                        Box::new(identity::spiffe::Source::new(spiffe.clone(), local_name))
+                    }
                 };
-
-                let svc = svc::builder()
-                    .buffer_pending(
-                        config.destination_buffer_capacity,
-                        config.control_dispatch_timeout,
-                    )
-                    .layer(control::add_origin::layer())
-                    .layer(proxy::grpc::req_body_as_payload::layer().per_make())
-                    .layer(http_metrics::layer::<_, classify::Response>(
-                        ctl_http_metrics.clone(),
-                    ))
-                    .layer(reconnect::layer().with_backoff(config.control_backoff.clone()))
-                    .layer(control::resolve::layer(dns_resolver.clone()))
-                    .layer(control::client::layer())
-                    .timeout(config.control_connect_timeout)
-                    .layer(keepalive::connect::layer(keepalive))
-                    .layer(tls::client::layer(Conditional::Some(
-                        id_config.trust_anchors.clone(),
-                    )))
-                    .service(connect::svc())
-                    .make(id_config.svc.clone());
-
-                identity_daemon = Some(identity::Daemon::new(id_config, crt_store, svc));
+                identity_daemon = Some(identity::Daemon::new(
+                    id_config,
+                    local_store,
+                    source,
+                    identity_metrics,
+                ));
 
                 task::spawn(
                     local_identity
@@ -359,10 +408,67 @@ where
                 .make(addr.clone())
         });
 
+        // Destinations' endpoints are subset by the proxy's hostname, which is
+        // unique to each pod. Identities and destination contexts are shared
+        // by other proxies, so they are only used if the hostname is unknown.
+        let subset_key = match (config.hostname.as_ref(), &config.identity_config) {
+            (Some(hostname), _) => hostname.clone(),
+            (None, &Conditional::Some(ref id)) => id.local_name.as_ref().to_owned(),
+            (None, &Conditional::None(_)) => config.destination_context.clone(),
+        };
+        let (dst_snapshot, dst_snapshot_daemon) = match config.destination_snapshot.clone() {
+            Some(c) => {
+                let (s, d) = control::destination::snapshot::Snapshot::load(c, dst_metrics.clone());
+                (Some(s), Some(d))
+            }
+            None => (None, None),
+        };
         let resolver = control::destination::Resolver::new(
             dst_svc.clone(),
             config.destination_get_suffixes,
             config.destination_context.clone(),
+            control::destination::Limits {
+                max_resolutions: config.destination_max_resolutions,
+                max_endpoints: config.destination_max_endpoints,
+                subset_key,
+            },
+            dst_metrics.clone(),
+            dst_snapshot.clone(),
+        );
+        let resolver = control::destination::dns_fallback::Resolve::new(
+            resolver,
+            dns_resolver.clone(),
+            config.destination_dns_fallback.clone(),
+        );
+        let (resolver, dst_file_daemon) =
+            control::destination::file::Resolve::new(resolver, config.destination_file.clone());
+
+        let profiles_client = ProfilesClient::new(
+            dst_svc,
+            Duration::from_secs(3),
+            config.destination_context,
+            dst_snapshot,
+            dst_metrics,
+            config.outbound_retry_defaults.clone(),
+        );
+        let (profiles_client, profiles_file_daemon) = super::profiles::file::GetRoutes::new(
+            profiles_client,
+            config.destination_profile_file.clone(),
+        );
+        let profiles_client =
+            super::profiles::report::GetRoutes::new(profiles_client, profile_report.clone());
+
+        let admin = Admin::new(
+            report,
+            readiness,
+            health_report,
+            router_report.clone(),
+            endpoint_report.clone(),
+            profile_report,
+            match local_identity {
+                Conditional::Some(ref local) => identity::Report::new(local.clone()),
+                Conditional::None(_) => identity::Report::default(),
+            },
         );
 
         // Spawn a separate thread to handle the admin stuff.
@@ -376,11 +482,7 @@ where
                     let mut rt =
                         current_thread::Runtime::new().expect("initialize admin thread runtime");
 
-                    rt.spawn(control::serve_http(
-                        "admin",
-                        admin_listener,
-                        Admin::new(report, readiness),
-                    ));
+                    rt.spawn(control::serve_http("admin", admin_listener, admin));
 
                     if let Some(listener) = control_listener {
                         rt.spawn(tap_daemon.map_err(|_| ()));
@@ -389,6 +491,30 @@ where
 
                     rt.spawn(::logging::admin().bg("dns-resolver").future(dns_bg));
 
+                    if let Some(d) = dst_file_daemon {
+                        rt.spawn(
+                            ::logging::admin()
+                                .bg("destination-file")
+                                .future(d.map_err(|_| ())),
+                        );
+                    }
+
+                    if let Some(d) = dst_snapshot_daemon {
+                        rt.spawn(
+                            ::logging::admin()
+                                .bg("destination-snapshot")
+                                .future(d.map_err(|_| ())),
+                        );
+                    }
+
+                    if let Some(d) = profiles_file_daemon {
+                        rt.spawn(
+                            ::logging::admin()
+                                .bg("profiles-file")
+                                .future(d.map_err(|_| ())),
+                        );
+                    }
+
                     if let Some(d) = identity_daemon {
                         rt.spawn(
                             ::logging::admin()
@@ -418,9 +544,6 @@ where
 
         // Build the outbound and inbound proxies using the dst_svc client.
 
-        let profiles_client =
-            ProfilesClient::new(dst_svc, Duration::from_secs(3), config.destination_context);
-
         let outbound = {
             use super::outbound::{
                 self,
@@ -429,7 +552,10 @@ where
                 //add_remote_ip_on_rsp, add_server_id_on_rsp,
             };
             use proxy::{
-                http::{balance, canonicalize, fallback, header_from_target, metrics, retry},
+                http::{
+                    balance, canonicalize, fallback, header_from_target, health, locality, metrics,
+                    outlier, retry,
+                },
                 resolve,
             };
 
@@ -459,6 +585,12 @@ where
                 .layer(client::layer("out", config.h2_settings))
                 .service(connect.clone());
 
+            // Instantiates an HTTP client that probes an `outbound::Endpoint`
+            // for active health checks.
+            let health_client = svc::builder()
+                .layer(client::layer("out health", config.h2_settings))
+                .service(connect.clone());
+
             // A per-`outbound::Endpoint` stack that:
             //
             // 1. Records http metrics  with per-endpoint labels.
@@ -494,25 +626,52 @@ where
             //    specifies a timeout. This goes before `retry` to cap
             //    retries.
             // 3. Retries are optionally enabled depending on if the route
-            //    is retryable.
+            //    is retryable. Request bodies are buffered (up to a limit)
+            //    so that they may be replayed.
+            // 4. Each attempt of a retryable request may have its own
+            //    timeout. This goes after `retry` so that a timed out
+            //    attempt may be retried, and after the retry metrics so
+            //    that timed out attempts are recorded as such.
             let dst_route_layer = svc::builder()
                 .buffer_pending(max_in_flight, DispatchDeadline::extract)
                 .layer(classify::layer())
                 .layer(metrics::layer::<_, classify::Response>(route_http_metrics))
                 .layer(proxy::http::timeout::layer())
-                .layer(retry::layer(retry_http_metrics.clone()))
+                .layer(retry::layer(
+                    retry_http_metrics.clone(),
+                    config.outbound_retry_max_buffer_bytes,
+                ))
                 .layer(metrics::layer::<_, classify::Response>(retry_http_metrics))
+                .layer(proxy::http::timeout::per_try_layer())
                 .layer(insert::target::layer());
 
             let balancer = svc::builder()
-                .layer(balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY))
-                .layer(resolve::layer(Resolve::new(resolver)));
+                .layer(balance::layer(
+                    EWMA_DEFAULT_RTT,
+                    EWMA_DECAY,
+                    config.outbound_load_balancer.clone(),
+                    endpoint_report.clone(),
+                ))
+                .layer(resolve::layer(locality::Resolve::new(
+                    outlier::Resolve::new(
+                        health::Resolve::new(
+                            balance::report::Resolve::new(Resolve::new(resolver), endpoint_report),
+                            health_client,
+                            config.outbound_health_check.clone(),
+                            health_metrics,
+                        ),
+                        config.outbound_outlier.clone(),
+                        outlier_metrics,
+                    ),
+                    config.outbound_locality.clone(),
+                )));
 
             // Routes requests to their original destination endpoints. Used as
             // a fallback when service discovery has no endpoints for a destination.
             let orig_dst_router = svc::builder()
                 .layer(router::layer(
-                    router::Config::new("out ep", capacity, max_idle_age),
+                    router::Config::new("out ep", capacity, max_idle_age)
+                        .with_report(router_report.clone()),
                     |req: &http::Request<_>| {
                         let ep = outbound::Endpoint::from_orig_dst(req);
                         debug!("outbound ep={:?}", ep);
@@ -523,8 +682,10 @@ where
 
             let balancer_stack = svc::builder()
                 .layer(fallback::layer(balancer, orig_dst_router))
+                .layer(balance::weight::layer(config.outbound_slow_start))
                 .layer(pending::layer())
-                .layer(balance::weight::layer())
+                .layer(locality::layer())
+                .layer(outlier::layer::<classify::Response>())
                 .service(endpoint_stack);
 
             // A per-`DstAddr` stack that does the following:
@@ -532,8 +693,12 @@ where
             // 1. Adds the `CANONICAL_DST_HEADER` from the `DstAddr`.
             // 2. Determines the profile of the destination and applies
             //    per-route policy.
-            // 3. Creates a load balancer , configured by resolving the
-            //   `DstAddr` with a resolver.
+            // 3. If the profile splits traffic, picks a concrete `DstAddr`
+            //    for each request according to the split's weights.
+            // 4. Creates a load balancer , configured by resolving the
+            //   `DstAddr` with a resolver. Each concrete destination has its
+            //   own balancer (and so its own endpoint metrics), which uses the
+            //   profile's load-balancing algorithm if it specifies one.
             let dst_stack = svc::builder()
                 .layer(header_from_target::layer(super::CANONICAL_DST_HEADER))
                 .layer(profiles::router::layer(
@@ -550,7 +715,8 @@ where
             // canonicalize to the same DstAddr use the same dst-stack service.
             let dst_router = svc::builder()
                 .layer(router::layer(
-                    router::Config::new("out dst", capacity, max_idle_age),
+                    router::Config::new("out dst", capacity, max_idle_age)
+                        .with_report(router_report.clone()),
                     |req: &http::Request<_>| {
                         let addr = req.extensions().get::<Addr>().cloned().map(|addr| {
                             let settings = settings::Settings::from_request(req);
@@ -585,7 +751,8 @@ where
             // address is used.
             let addr_router = svc::builder()
                 .layer(router::layer(
-                    router::Config::new("out addr", capacity, max_idle_age),
+                    router::Config::new("out addr", capacity, max_idle_age)
+                        .with_report(router_report.clone()),
                     |req: &http::Request<_>| {
                         super::http_request_l5d_override_dst_addr(req)
                             .map(|override_addr| {
@@ -685,7 +852,8 @@ where
             // `default_fwd_addr` may be used.
             let endpoint_router = svc::builder()
                 .layer(router::layer(
-                    router::Config::new("in endpoint", capacity, max_idle_age),
+                    router::Config::new("in endpoint", capacity, max_idle_age)
+                        .with_report(router_report.clone()),
                     RecognizeEndpoint::new(default_fwd_addr),
                 ))
                 .buffer_pending(max_in_flight, DispatchDeadline::extract)
@@ -743,7 +911,8 @@ where
             // address is used.
             let dst_router = svc::builder()
                 .layer(router::layer(
-                    router::Config::new("in dst", capacity, max_idle_age),
+                    router::Config::new("in dst", capacity, max_idle_age)
+                        .with_report(router_report.clone()),
                     |req: &http::Request<_>| {
                         let canonical = req
                             .headers()
@@ -5,11 +5,12 @@ use std::{fmt, hash};
 
 use super::identity;
 use control::destination::{Metadata, ProtocolHint};
+use json;
 use proxy::{
     self,
     http::{
-        balance::{HasWeight, Weight},
-        settings,
+        balance::{report::Describe, HasWeight, Weight},
+        locality, settings,
     },
 };
 use tap;
@@ -28,6 +29,11 @@ pub struct Endpoint {
 // === impl Endpoint ===
 
 impl Endpoint {
+    /// Determines whether HTTP/1 requests to this endpoint may be upgraded to
+    /// HTTP/2 because discovery provides a protocol hint.
+    ///
+    /// Requests to meshed endpoints without a hint are upgraded by the HTTP
+    /// client instead, once a connection has negotiated `l5d-h2`.
     pub fn can_use_orig_proto(&self) -> bool {
         match self.metadata.protocol_hint() {
             ProtocolHint::Unknown => return false,
@@ -102,6 +108,27 @@ impl tls::HasPeerIdentity for Endpoint {
     }
 }
 
+/// Meshed endpoints, which have an identity, are offered `l5d-h2` whenever
+/// their connections may carry HTTP/2. HTTP/1 connections that carry
+/// upgrades, such as WebSockets, must remain HTTP/1, so they offer no
+/// protocol.
+impl tls::client::HasAlpnProtocol for Endpoint {
+    fn alpn_protocol(&self) -> Option<&'static [u8]> {
+        if let Conditional::None(_) = self.identity {
+            return None;
+        }
+
+        match self.http_settings {
+            settings::Settings::Http2 => Some(tls::L5D_H2),
+            settings::Settings::Http1 {
+                wants_h1_upgrade: false,
+                ..
+            } => Some(tls::L5D_H2),
+            _ => None,
+        }
+    }
+}
+
 impl connect::HasPeerAddr for Endpoint {
     fn peer_addr(&self) -> SocketAddr {
         self.addr
@@ -114,6 +141,34 @@ impl HasWeight for Endpoint {
     }
 }
 
+impl Describe for Endpoint {
+    fn describe(&self) -> json::Value {
+        let labels = self
+            .metadata
+            .labels()
+            .iter()
+            .map(|(k, v)| (k.as_str(), json::Value::String(v.clone())));
+        let identity = match self.identity {
+            Conditional::Some(ref id) => json::Value::String(id.as_ref().to_owned()),
+            Conditional::None(ref reason) => {
+                json::object(vec![("none", json::Value::String(reason.to_string()))])
+            }
+        };
+        let weight: f64 = self.metadata.weight().into();
+        json::object(vec![
+            ("labels", json::object(labels)),
+            ("identity", identity),
+            ("weight", json::Value::Number(weight)),
+        ])
+    }
+}
+
+impl locality::HasLabels for Endpoint {
+    fn label(&self, key: &str) -> Option<&str> {
+        self.metadata.labels().get(key).map(|v| v.as_str())
+    }
+}
+
 impl settings::HasSettings for Endpoint {
     fn http_settings(&self) -> &settings::Settings {
         &self.http_settings
@@ -435,3 +490,59 @@ pub mod add_remote_ip_on_rsp {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Endpoint;
+    use control::destination::Metadata;
+    use identity;
+    use proxy::http::Settings;
+    use transport::tls::{self, client::HasAlpnProtocol};
+    use Conditional;
+
+    fn endpoint(identity: tls::PeerIdentity, http_settings: Settings) -> Endpoint {
+        Endpoint {
+            dst_name: None,
+            addr: ([10, 1, 1, 1], 8080).into(),
+            identity,
+            metadata: Metadata::empty(),
+            http_settings,
+        }
+    }
+
+    fn meshed() -> tls::PeerIdentity {
+        let name =
+            identity::Name::from_hostname(b"foo.ns1.serviceaccount.identity.linkerd.cluster.local")
+                .expect("name must be valid");
+        Conditional::Some(name)
+    }
+
+    const HTTP1: Settings = Settings::Http1 {
+        keep_alive: true,
+        wants_h1_upgrade: false,
+        was_absolute_form: false,
+    };
+
+    #[test]
+    fn meshed_endpoints_offer_l5d_h2_without_a_hint() {
+        assert_eq!(endpoint(meshed(), HTTP1).alpn_protocol(), Some(tls::L5D_H2));
+        assert_eq!(
+            endpoint(meshed(), Settings::Http2).alpn_protocol(),
+            Some(tls::L5D_H2)
+        );
+    }
+
+    #[test]
+    fn unmeshed_and_upgrading_endpoints_offer_no_protocol() {
+        let unmeshed =
+            Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into());
+        assert_eq!(endpoint(unmeshed, HTTP1).alpn_protocol(), None);
+
+        let upgrade = Settings::Http1 {
+            keep_alive: true,
+            wants_h1_upgrade: true,
+            was_absolute_form: false,
+        };
+        assert_eq!(endpoint(meshed(), upgrade).alpn_protocol(), None);
+    }
+}
@@ -0,0 +1,763 @@
+//! Loads service profiles from a local file.
+//!
+//! This allows routes, retries, timeouts, and traffic splits to be configured
+//! without a Destination service. The file is a JSON document that maps
+//! authorities to their profiles:
+//!
+//! ```json
+//! {
+//!   "web.default.svc.cluster.local:8080": {
+//!     "retry_budget": {
+//!       "min_retries_per_second": 10,
+//!       "retry_ratio": 0.2,
+//!       "ttl": "10s"
+//!     },
+//!     "routes": [
+//!       {
+//!         "condition": {
+//!           "all": [
+//!             { "method": "GET" },
+//!             { "path": "/books/[^/]+" },
+//!             { "header": { "name": "x-tenant", "exact": "a" } }
+//!           ]
+//!         },
+//!         "labels": { "route": "get-book" },
+//!         "response_classes": [
+//!           { "condition": { "status": { "min": 500, "max": 599 } }, "is_failure": true }
+//!         ],
+//!         "retryable": true,
+//!         "retry_backoff": { "min": "10ms", "max": "1s", "jitter": 0.5 },
+//!         "retry_connection_errors": true,
+//!         "timeout": "10s",
+//!         "per_try_timeout": "1s"
+//!       }
+//!     ],
+//!     "dst_overrides": [
+//!       { "authority": "web-v2.default.svc.cluster.local:8080", "weight": 1 }
+//!     ],
+//!     "load_balancer": "round-robin"
+//!   }
+//! }
+//! ```
+//!
+//! Request conditions are objects with a single field: `all`, `any`, `not`,
+//! `path`, `method`, `authority`, `header`, `query_param`, or `scheme`.
+//! Response conditions may be `all`, `any`, `not`, `status`, `header`, or
+//! `grpc_status`. Values are matched by an `exact` string or a `regex` that
+//! must match the entire value; if neither is set, the value need only be
+//! present.
+//!
+//! Profiles in the file take precedence over those from the Destination
+//! service. The file is polled for changes, and a destination's routes are
+//! updated when its profile is added, changed, or removed. If the file cannot
+//! be read or is invalid, the previously loaded profiles continue to be used.
+
+use futures::{Async, Poll, Stream};
+use futures_watch::Watch;
+use http;
+use indexmap::IndexMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use api::destination as api;
+use app::config;
+use fs_watch;
+use json;
+use never::Never;
+use proxy::http::{
+    profiles,
+    retry::{Backoff, Budget},
+};
+use NameAddr;
+
+pub use fs_watch::Config;
+
+/// Gets routes for authorities that are named in the file, falling back to a
+/// `G`-typed `GetRoutes` for all others.
+#[derive(Clone, Debug)]
+pub struct GetRoutes<G> {
+    inner: G,
+    table: Option<Watch<Arc<Table>>>,
+}
+
+/// Watches an authority's routes, preferring its profile in the file.
+pub struct Rx<S> {
+    authority: String,
+    inner: Option<S>,
+    table: Option<Watch<Arc<Table>>>,
+    /// The most recent routes from the inner stream.
+    inner_routes: Option<profiles::Routes>,
+    /// The definition of the authority's profile in the file, if it has one.
+    file: Option<json::Value>,
+}
+
+/// Reloads the file when it changes.
+pub type Daemon = fs_watch::Daemon<Table>;
+
+/// Maps normalized authorities to their profiles.
+type Table = IndexMap<String, Profile>;
+
+#[derive(Debug)]
+pub struct Profile {
+    /// The profile's definition, so that changes to it may be detected.
+    definition: json::Value,
+    routes: profiles::Routes,
+}
+
+// === impl GetRoutes ===
+
+impl<G> GetRoutes<G> {
+    /// Returns a `GetRoutes` and, if a file is configured, a `Daemon` that
+    /// must be spawned to reload it.
+    pub fn new(inner: G, config: Option<Config>) -> (Self, Option<Daemon>) {
+        let config = match config {
+            Some(c) => c,
+            None => return (GetRoutes { inner, table: None }, None),
+        };
+
+        let (watch, daemon) = fs_watch::watch(config, parse);
+        let get_routes = GetRoutes {
+            inner,
+            table: Some(watch),
+        };
+        (get_routes, Some(daemon))
+    }
+}
+
+impl<G: profiles::GetRoutes> profiles::GetRoutes for GetRoutes<G> {
+    type Stream = Rx<G::Stream>;
+
+    fn get_routes(&self, dst: &NameAddr) -> Option<Self::Stream> {
+        let inner = self.inner.get_routes(dst);
+        if inner.is_none() && self.table.is_none() {
+            return None;
+        }
+
+        Some(Rx {
+            authority: normalize(dst),
+            inner,
+            table: self.table.clone(),
+            inner_routes: None,
+            file: None,
+        })
+    }
+}
+
+// === impl Rx ===
+
+impl<S> Rx<S>
+where
+    S: Stream<Item = profiles::Routes, Error = Never>,
+{
+    /// Records the inner stream's latest routes, returning true if they were
+    /// updated.
+    fn poll_inner(&mut self) -> bool {
+        let mut updated = false;
+        loop {
+            let polled = match self.inner {
+                Some(ref mut inner) => inner.poll(),
+                None => return updated,
+            };
+            match polled {
+                Ok(Async::Ready(Some(routes))) => {
+                    self.inner_routes = Some(routes);
+                    updated = true;
+                }
+                Ok(Async::NotReady) => return updated,
+                Ok(Async::Ready(None)) | Err(_) => {
+                    self.inner = None;
+                    return updated;
+                }
+            }
+        }
+    }
+
+    /// Returns the authority's routes from the file if its profile has been
+    /// added, changed, or (as `Some(None)`) removed.
+    fn poll_file(&mut self) -> Option<Option<profiles::Routes>> {
+        let table = match self.table {
+            Some(ref mut table) => table,
+            None => return None,
+        };
+
+        // Drain notifications so that the task is notified of the next change.
+        while let Ok(Async::Ready(Some(()))) = table.poll() {}
+
+        let table = table.borrow();
+        let profile = table.get(&self.authority);
+        if profile.map(|p| &p.definition) == self.file.as_ref() {
+            return None;
+        }
+
+        self.file = profile.map(|p| p.definition.clone());
+        Some(profile.map(|p| p.routes.clone()))
+    }
+}
+
+impl<S> Stream for Rx<S>
+where
+    S: Stream<Item = profiles::Routes, Error = Never>,
+{
+    type Item = profiles::Routes;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let inner_updated = self.poll_inner();
+
+        match self.poll_file() {
+            Some(Some(routes)) => {
+                debug!("using profile for {} from file", self.authority);
+                return Ok(Async::Ready(Some(routes)));
+            }
+            Some(None) => {
+                // The file no longer overrides the authority's profile.
+                let routes = self.inner_routes.clone().unwrap_or_default();
+                return Ok(Async::Ready(Some(routes)));
+            }
+            None => {}
+        }
+
+        if inner_updated && self.file.is_none() {
+            if let Some(ref routes) = self.inner_routes {
+                return Ok(Async::Ready(Some(routes.clone())));
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// Authorities are compared case-insensitively and without trailing dots.
+fn normalize(authority: &NameAddr) -> String {
+    format!(
+        "{}:{}",
+        authority.name().without_trailing_dot().to_ascii_lowercase(),
+        authority.port()
+    )
+}
+
+fn parse(contents: &str) -> Result<Table, String> {
+    let doc = json::parse(contents).map_err(|e| e.to_string())?;
+    let authorities = doc
+        .as_object()
+        .ok_or_else(|| "expected an object of authorities".to_owned())?;
+
+    let mut table = Table::with_capacity(authorities.len());
+    for (authority, profile) in authorities {
+        let key = NameAddr::from_str(authority)
+            .map(|a| normalize(&a))
+            .map_err(|_| format!("invalid authority: {}", authority))?;
+        let routes = parse_profile(profile).map_err(|e| format!("{}: {}", authority, e))?;
+        table.insert(
+            key,
+            Profile {
+                definition: profile.clone(),
+                routes,
+            },
+        );
+    }
+
+    Ok(table)
+}
+
+pub(super) fn parse_profile(profile: &json::Value) -> Result<profiles::Routes, String> {
+    if profile.as_object().is_none() {
+        return Err("expected a profile object".to_owned());
+    }
+
+    let retry_budget = match profile.get("retry_budget") {
+        None => None,
+        Some(budget) => Some(parse_retry_budget(budget)?),
+    };
+
+    let mut routes = Vec::new();
+    for (i, route) in array(profile.get("routes"), "routes")?.iter().enumerate() {
+        let route =
+            parse_route(route, retry_budget.as_ref()).map_err(|e| format!("route {}: {}", i, e))?;
+        routes.push(route);
+    }
+
+    let mut dst_overrides = Vec::new();
+    for dst in array(profile.get("dst_overrides"), "dst_overrides")? {
+        let authority = dst
+            .get("authority")
+            .and_then(json::Value::as_str)
+            .ok_or_else(|| "dst_overrides must have an authority".to_owned())?;
+        let addr = NameAddr::from_str(authority)
+            .map_err(|_| format!("invalid dst_overrides authority: {}", authority))?;
+        // Weights are scaled by 10,000, as they are by the Destination service.
+        let weight = match dst.get("weight") {
+            None => 10_000,
+            Some(w) => w
+                .as_f64()
+                .filter(|w| *w >= 0.0 && *w * 10_000.0 <= f64::from(::std::u32::MAX))
+                .map(|w| (w * 10_000.0).round() as u32)
+                .ok_or_else(|| format!("{}: invalid weight", authority))?,
+        };
+        dst_overrides.push(profiles::WeightedAddr { addr, weight });
+    }
+
+    let load_balancer = match profile.get("load_balancer") {
+        None => None,
+        Some(lb) => {
+            let lb = lb
+                .as_str()
+                .and_then(|lb| config::parse_load_balancer(lb).ok())
+                .ok_or_else(|| format!("invalid load_balancer: {}", lb))?;
+            Some(lb)
+        }
+    };
+
+    Ok(profiles::Routes {
+        routes,
+        dst_overrides,
+        load_balancer,
+    })
+}
+
+fn parse_route(
+    route: &json::Value,
+    retry_budget: Option<&Arc<Budget>>,
+) -> Result<(profiles::RequestMatch, profiles::Route), String> {
+    let condition = route
+        .get("condition")
+        .ok_or_else(|| "route must have a condition".to_owned())?;
+    let req_match = parse_req_match(condition)?;
+
+    let labels = match route.get("labels") {
+        None => Vec::new(),
+        Some(labels) => {
+            let labels = labels
+                .as_object()
+                .ok_or_else(|| "labels must be an object".to_owned())?;
+            let mut pairs = Vec::with_capacity(labels.len());
+            for (k, v) in labels {
+                let v = v
+                    .as_str()
+                    .ok_or_else(|| format!("label {} must be a string", k))?;
+                pairs.push((k.clone(), v.to_owned()));
+            }
+            pairs
+        }
+    };
+
+    let mut rsp_classes = Vec::new();
+    for class in array(route.get("response_classes"), "response_classes")? {
+        let condition = class
+            .get("condition")
+            .ok_or_else(|| "response class must have a condition".to_owned())?;
+        let is_failure = boolean(class.get("is_failure"), "is_failure")?;
+        rsp_classes.push(profiles::ResponseClass::new(
+            is_failure,
+            parse_rsp_match(condition)?,
+        ));
+    }
+
+    let mut r = profiles::Route::new(labels.into_iter(), rsp_classes);
+
+    if boolean(route.get("retryable"), "retryable")? {
+        let budget =
+            retry_budget.ok_or_else(|| "retryable routes require a retry_budget".to_owned())?;
+        let mut retries = profiles::Retries::new(budget.clone());
+        if let Some(backoff) = route.get("retry_backoff") {
+            retries = retries.with_backoff(parse_backoff(backoff)?);
+        }
+        if boolean(
+            route.get("retry_connection_errors"),
+            "retry_connection_errors",
+        )? {
+            retries = retries.with_retry_connection_errors();
+        }
+        r.set_retries(retries);
+    }
+
+    if let Some(timeout) = route.get("timeout") {
+        r.set_timeout(duration(timeout, "timeout")?);
+    }
+    if let Some(timeout) = route.get("per_try_timeout") {
+        r.set_per_try_timeout(duration(timeout, "per_try_timeout")?);
+    }
+
+    Ok((req_match, r))
+}
+
+fn parse_req_match(m: &json::Value) -> Result<profiles::RequestMatch, String> {
+    let (kind, value) = single_field(m)?;
+    let m = match kind {
+        "all" => {
+            let ms = array(Some(value), kind)?.iter().map(parse_req_match);
+            profiles::RequestMatch::All(ms.collect::<Result<_, _>>()?)
+        }
+        "any" => {
+            let ms = array(Some(value), kind)?.iter().map(parse_req_match);
+            profiles::RequestMatch::Any(ms.collect::<Result<_, _>>()?)
+        }
+        "not" => profiles::RequestMatch::Not(Box::new(parse_req_match(value)?)),
+        "path" => {
+            let re = string(value, kind)?;
+            let re = super::anchored_regex(re).map_err(|e| format!("invalid path: {}", e))?;
+            profiles::RequestMatch::Path(re)
+        }
+        "method" => {
+            let method = string(value, kind)?;
+            let method = http::Method::from_bytes(method.as_bytes())
+                .map_err(|_| format!("invalid method: {}", method))?;
+            profiles::RequestMatch::Method(method)
+        }
+        "authority" => profiles::RequestMatch::Authority(parse_value_match(value)?),
+        "header" => profiles::RequestMatch::Header {
+            name: header_name(value)?,
+            value: parse_value_match(value)?,
+        },
+        "query_param" => {
+            let name = value
+                .get("name")
+                .and_then(json::Value::as_str)
+                .ok_or_else(|| "query_param must have a name".to_owned())?;
+            profiles::RequestMatch::QueryParam {
+                name: name.to_owned(),
+                value: parse_value_match(value)?,
+            }
+        }
+        "scheme" => match string(value, kind)? {
+            "http" => profiles::RequestMatch::Scheme(http::uri::Scheme::HTTP),
+            "https" => profiles::RequestMatch::Scheme(http::uri::Scheme::HTTPS),
+            s => return Err(format!("invalid scheme: {}", s)),
+        },
+        _ => return Err(format!("unknown request condition: {}", kind)),
+    };
+
+    Ok(m)
+}
+
+fn parse_rsp_match(m: &json::Value) -> Result<profiles::ResponseMatch, String> {
+    let (kind, value) = single_field(m)?;
+    let m = match kind {
+        "all" | "any" => {
+            let ms = array(Some(value), kind)?
+                .iter()
+                .map(parse_rsp_match)
+                .collect::<Result<Vec<_>, _>>()?;
+            if ms.is_empty() {
+                return Err(format!("{} must not be empty", kind));
+            }
+            if kind == "all" {
+                profiles::ResponseMatch::All(ms)
+            } else {
+                profiles::ResponseMatch::Any(ms)
+            }
+        }
+        "not" => profiles::ResponseMatch::Not(Box::new(parse_rsp_match(value)?)),
+        "status" => {
+            let (min, max) = range(value, kind)?;
+            let status = |n: u32| {
+                http::StatusCode::from_u16(n as u16)
+                    .ok()
+                    .filter(|_| n <= u32::from(::std::u16::MAX))
+                    .ok_or_else(|| format!("invalid status: {}", n))
+            };
+            profiles::ResponseMatch::Status {
+                min: status(min)?,
+                max: status(max)?,
+            }
+        }
+        "header" => profiles::ResponseMatch::Header {
+            name: header_name(value)?,
+            value: parse_value_match(value)?,
+        },
+        "grpc_status" => {
+            let (min, max) = range(value, kind)?;
+            profiles::ResponseMatch::GrpcStatus { min, max }
+        }
+        _ => return Err(format!("unknown response condition: {}", kind)),
+    };
+
+    Ok(m)
+}
+
+fn parse_value_match(value: &json::Value) -> Result<profiles::ValueMatch, String> {
+    match (value.get("exact"), value.get("regex")) {
+        (None, None) => Ok(profiles::ValueMatch::Present),
+        (Some(exact), None) => Ok(profiles::ValueMatch::Exact(
+            string(exact, "exact")?.to_owned(),
+        )),
+        (None, Some(re)) => {
+            let re = super::anchored_regex(string(re, "regex")?)
+                .map_err(|e| format!("invalid regex: {}", e))?;
+            Ok(profiles::ValueMatch::Regex(re))
+        }
+        (Some(_), Some(_)) => Err("only one of exact and regex may be set".to_owned()),
+    }
+}
+
+fn parse_retry_budget(budget: &json::Value) -> Result<Arc<Budget>, String> {
+    let min_retries_per_second = budget
+        .get("min_retries_per_second")
+        .and_then(integer)
+        .ok_or_else(|| "invalid retry_budget min_retries_per_second".to_owned())?;
+    let retry_ratio = budget
+        .get("retry_ratio")
+        .and_then(json::Value::as_f64)
+        .ok_or_else(|| "invalid retry_budget retry_ratio".to_owned())?;
+    let ttl = budget
+        .get("ttl")
+        .ok_or_else(|| "retry_budget must have a ttl".to_owned())
+        .and_then(|ttl| duration(ttl, "ttl"))?;
+
+    // Validate the budget as it would be if it were from the Destination
+    // service.
+    super::convert_retry_budget(api::RetryBudget {
+        min_retries_per_second,
+        retry_ratio: retry_ratio as f32,
+        ttl: Some(::prost_types::Duration {
+            seconds: ttl.as_secs() as i64,
+            nanos: ttl.subsec_nanos() as i32,
+        }),
+    })
+    .ok_or_else(|| "invalid retry_budget".to_owned())
+}
+
+fn parse_backoff(backoff: &json::Value) -> Result<Backoff, String> {
+    let min = backoff
+        .get("min")
+        .ok_or_else(|| "retry_backoff must have a min".to_owned())
+        .and_then(|min| duration(min, "min"))?;
+    let max = backoff
+        .get("max")
+        .ok_or_else(|| "retry_backoff must have a max".to_owned())
+        .and_then(|max| duration(max, "max"))?;
+    let jitter = match backoff.get("jitter") {
+        None => 0.0,
+        Some(jitter) => jitter
+            .as_f64()
+            .ok_or_else(|| "jitter must be a number".to_owned())?,
+    };
+    Backoff::new(min, max, jitter).map_err(|e| e.to_string())
+}
+
+/// Returns the single field of a condition object.
+fn single_field(m: &json::Value) -> Result<(&str, &json::Value), String> {
+    match m.as_object() {
+        Some(fields) if fields.len() == 1 => {
+            let (kind, value) = fields.get_index(0).expect("object must have a field");
+            Ok((kind.as_str(), value))
+        }
+        _ => Err("conditions must be objects with a single field".to_owned()),
+    }
+}
+
+fn header_name(value: &json::Value) -> Result<http::header::HeaderName, String> {
+    let name = value
+        .get("name")
+        .and_then(json::Value::as_str)
+        .ok_or_else(|| "header must have a name".to_owned())?;
+    http::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("invalid header name: {}", name))
+}
+
+fn range(value: &json::Value, field: &str) -> Result<(u32, u32), String> {
+    let min = value.get("min").and_then(integer);
+    let max = value.get("max").and_then(integer);
+    match (min, max) {
+        (Some(min), Some(max)) if min <= max => Ok((min, max)),
+        _ => Err(format!("{} must have a min and max", field)),
+    }
+}
+
+fn array<'a>(value: Option<&'a json::Value>, field: &str) -> Result<&'a [json::Value], String> {
+    match value {
+        None => Ok(&[]),
+        Some(v) => v
+            .as_array()
+            .map(|a| a.as_slice())
+            .ok_or_else(|| format!("{} must be a list", field)),
+    }
+}
+
+fn boolean(value: Option<&json::Value>, field: &str) -> Result<bool, String> {
+    match value {
+        None => Ok(false),
+        Some(v) => v
+            .as_bool()
+            .ok_or_else(|| format!("{} must be a boolean", field)),
+    }
+}
+
+fn string<'a>(value: &'a json::Value, field: &str) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("{} must be a string", field))
+}
+
+fn duration(value: &json::Value, field: &str) -> Result<Duration, String> {
+    value
+        .as_str()
+        .and_then(|d| config::parse_duration(d).ok())
+        .ok_or_else(|| format!("{} must be a duration", field))
+}
+
+fn integer(value: &json::Value) -> Option<u32> {
+    value
+        .as_f64()
+        .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= f64::from(::std::u32::MAX))
+        .map(|n| n as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, stream, Future};
+
+    const FILE: &str = r#"{
+        "web.default.svc.cluster.local:8080": {
+            "retry_budget": { "min_retries_per_second": 10, "retry_ratio": 0.2, "ttl": "10s" },
+            "routes": [
+                {
+                    "condition": {
+                        "all": [
+                            { "method": "GET" },
+                            { "path": "/books/[^/]+" },
+                            { "header": { "name": "x-tenant", "exact": "a" } },
+                            { "not": { "query_param": { "name": "debug" } } }
+                        ]
+                    },
+                    "labels": { "route": "get-book" },
+                    "response_classes": [
+                        {
+                            "condition": { "status": { "min": 500, "max": 599 } },
+                            "is_failure": true
+                        },
+                        { "condition": { "grpc_status": { "min": 1, "max": 16 } } }
+                    ],
+                    "retryable": true,
+                    "retry_backoff": { "min": "10ms", "max": "1s", "jitter": 0.5 },
+                    "retry_connection_errors": true,
+                    "timeout": "10s",
+                    "per_try_timeout": "1s"
+                }
+            ],
+            "dst_overrides": [
+                { "authority": "web-v2.default.svc.cluster.local:8080", "weight": 0.5 }
+            ],
+            "load_balancer": "round-robin"
+        },
+        "empty.default.svc.cluster.local:80": {}
+    }"#;
+
+    fn web() -> NameAddr {
+        NameAddr::from_str("web.default.svc.cluster.local.:8080").unwrap()
+    }
+
+    fn routes(label: &str) -> profiles::Routes {
+        let route = profiles::Route::new(
+            Some(("route".to_owned(), label.to_owned())).into_iter(),
+            Vec::new(),
+        );
+        profiles::Routes {
+            routes: vec![(profiles::RequestMatch::All(Vec::new()), route)],
+            ..profiles::Routes::default()
+        }
+    }
+
+    fn label(routes: &profiles::Routes) -> Option<&str> {
+        let (_, route) = routes.routes.get(0)?;
+        route.labels().get("route").map(|l| l.as_str())
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let table = parse(FILE).expect("file must parse");
+        assert_eq!(table.len(), 2);
+
+        let web = &table[&normalize(&web())].routes;
+        assert_eq!(web.routes.len(), 1);
+        let (req_match, route) = &web.routes[0];
+        match req_match {
+            profiles::RequestMatch::All(ms) => assert_eq!(ms.len(), 4),
+            m => panic!("unexpected condition: {:?}", m),
+        }
+        assert_eq!(label(web), Some("get-book"));
+        assert_eq!(route.timeout(), Some(Duration::from_secs(10)));
+        assert_eq!(route.per_try_timeout(), Some(Duration::from_secs(1)));
+        let retries = route.retries().expect("route must be retryable");
+        assert!(retries.backoff().is_some());
+        assert!(retries.retry_connection_errors());
+
+        assert_eq!(web.dst_overrides.len(), 1);
+        assert_eq!(web.dst_overrides[0].weight, 5_000);
+        assert!(web.load_balancer.is_some());
+
+        let empty = NameAddr::from_str("empty.default.svc.cluster.local:80").unwrap();
+        assert!(table[&normalize(&empty)].routes.routes.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        for file in &[
+            "[]",
+            r#"{"web": {}}"#,
+            r#"{"web:80": []}"#,
+            r#"{"web:80": {"routes": [{}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {}}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"path": "("}}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"method": "GET", "path": "/"}}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"scheme": "ftp"}}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"method": "GET"}, "retryable": true}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"method": "GET"}, "timeout": 10}]}}"#,
+            r#"{"web:80": {"routes": [{"condition": {"method": "GET"},
+                "response_classes": [{"condition": {"status": {"min": 600, "max": 500}}}]}]}}"#,
+            r#"{"web:80": {"retry_budget": {"min_retries_per_second": 1, "retry_ratio": 0.2,
+                "ttl": "1h"}}}"#,
+            r#"{"web:80": {"dst_overrides": [{"authority": "web"}]}}"#,
+            r#"{"web:80": {"load_balancer": "random"}}"#,
+        ] {
+            assert!(parse(file).is_err(), "{} must not parse", file);
+        }
+    }
+
+    #[test]
+    fn prefers_profiles_from_file() {
+        let table = Table::new();
+        let (watch, mut store) = Watch::new(Arc::new(table));
+        let mut rx = Rx {
+            authority: normalize(&web()),
+            inner: Some(stream::iter_ok::<_, Never>(vec![routes("inner")])),
+            table: Some(watch),
+            inner_routes: None,
+            file: None,
+        };
+
+        future::lazy(move || {
+            // Without a profile in the file, the inner routes are used.
+            match rx.poll() {
+                Ok(Async::Ready(Some(ref r))) if label(r) == Some("inner") => {}
+                r => panic!("unexpected routes: {:?}", r),
+            }
+            assert!(rx.poll().unwrap().is_not_ready());
+
+            store.store(Arc::new(parse(FILE).unwrap())).unwrap();
+            match rx.poll() {
+                Ok(Async::Ready(Some(ref r))) if label(r) == Some("get-book") => {}
+                r => panic!("unexpected routes: {:?}", r),
+            }
+            assert!(rx.poll().unwrap().is_not_ready());
+
+            // An unchanged profile is not published again.
+            store.store(Arc::new(parse(FILE).unwrap())).unwrap();
+            assert!(rx.poll().unwrap().is_not_ready());
+
+            // When the profile is removed, the inner routes are used again.
+            store.store(Arc::new(Table::new())).unwrap();
+            match rx.poll() {
+                Ok(Async::Ready(Some(ref r))) if label(r) == Some("inner") => {}
+                r => panic!("unexpected routes: {:?}", r),
+            }
+            assert!(rx.poll().unwrap().is_not_ready());
+
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
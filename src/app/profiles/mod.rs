@@ -0,0 +1,889 @@
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use http;
+use regex::Regex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::executor::{DefaultExecutor, Executor};
+use tokio_timer::{clock, Delay};
+use tower_grpc::{self as grpc, generic::client::GrpcService, Body, BoxBody};
+
+use api::destination as api;
+use control::destination::{
+    metrics::{self, ProfileState, ProfileStream, Stale},
+    snapshot::Snapshot,
+};
+use json;
+use never::Never;
+
+use proxy::http::{
+    profiles,
+    retry::{Backoff, Budget},
+};
+use NameAddr;
+
+pub mod file;
+pub mod report;
+
+#[derive(Clone, Debug)]
+pub struct Client<T> {
+    service: Option<T>,
+    backoff: Duration,
+    context_token: String,
+    snapshot: Option<Snapshot>,
+    metrics: metrics::Registry,
+    retry_defaults: RetryDefaults,
+}
+
+/// Configures retries on the Destination service's retryable routes.
+///
+/// The Destination API cannot describe retry backoffs, per-try timeouts, or
+/// connection error retries, so these apply to all of its routes.
+#[derive(Clone, Debug, Default)]
+pub struct RetryDefaults {
+    pub backoff: Option<Backoff>,
+    pub per_try_timeout: Option<Duration>,
+    pub retry_connection_errors: bool,
+}
+
+pub struct Rx {
+    rx: mpsc::Receiver<profiles::Routes>,
+    _hangup: oneshot::Sender<Never>,
+}
+
+struct Daemon<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    dst: String,
+    backoff: Duration,
+    service: Option<T>,
+    state: State<T>,
+    tx: mpsc::Sender<profiles::Routes>,
+    context_token: String,
+    hangup: oneshot::Receiver<Never>,
+    snapshot: Option<Snapshotter>,
+    metrics: ProfileStream,
+    retry_defaults: RetryDefaults,
+}
+
+/// Records a destination's profiles in the snapshot.
+struct Snapshotter {
+    snapshot: Snapshot,
+    /// Set while the stream uses a snapshotted profile that the Destination
+    /// service has not yet confirmed.
+    stale: Option<Stale>,
+}
+
+enum State<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    Disconnected,
+    Backoff(Delay),
+    Waiting(grpc::client::server_streaming::ResponseFuture<api::DestinationProfile, T::Future>),
+    Streaming(grpc::Streaming<api::DestinationProfile, T::ResponseBody>),
+}
+
+// === impl Client ===
+
+impl<T> Client<T>
+where
+    // These bounds aren't *required* here, they just help detect the problem
+    // earlier (as Client::new), instead of when trying to passing a `Client`
+    // to something that wants `impl profiles::GetRoutes`.
+    T: GrpcService<BoxBody> + Clone + Send + 'static,
+    T::ResponseBody: Send,
+    <T::ResponseBody as Body>::Data: Send,
+    T::Future: Send,
+{
+    /// Returns a `Client` for requesting profiles. If a `Snapshot` is
+    /// provided, profiles are recorded in it and streams are seeded from it.
+    pub fn new(
+        service: Option<T>,
+        backoff: Duration,
+        context_token: String,
+        snapshot: Option<Snapshot>,
+        metrics: metrics::Registry,
+        retry_defaults: RetryDefaults,
+    ) -> Self {
+        Self {
+            service,
+            backoff,
+            context_token,
+            snapshot,
+            metrics,
+            retry_defaults,
+        }
+    }
+}
+
+impl<T> profiles::GetRoutes for Client<T>
+where
+    T: GrpcService<BoxBody> + Clone + Send + 'static,
+    T::ResponseBody: Send,
+    <T::ResponseBody as Body>::Data: Send,
+    T::Future: Send,
+{
+    type Stream = Rx;
+
+    fn get_routes(&self, dst: &NameAddr) -> Option<Self::Stream> {
+        let (mut tx, rx) = mpsc::channel(1);
+        // This oneshot allows the daemon to be notified when the Self::Stream
+        // is dropped.
+        let (hangup_tx, hangup_rx) = oneshot::channel();
+
+        let dst = format!("{}", dst);
+        let snapshot = self.snapshot.as_ref().map(|snapshot| {
+            // Use the snapshotted profile until the Destination service
+            // returns one.
+            let seeded = snapshot.profile(&dst).and_then(|(profile, stale)| {
+                let mut routes = file::parse_profile(&profile)
+                    .map_err(|e| warn!("ignoring invalid snapshot of {}: {}", dst, e))
+                    .ok()?;
+                // Snapshots only record the Destination service's profiles.
+                self.retry_defaults.apply(&mut routes);
+                tx.try_send(routes).ok()?;
+                debug!("using snapshotted profile for {}", dst);
+                Some(stale)
+            });
+            Snapshotter {
+                snapshot: snapshot.clone(),
+                stale: seeded,
+            }
+        });
+
+        let daemon = Daemon {
+            tx,
+            hangup: hangup_rx,
+            snapshot,
+            metrics: self.metrics.profile_stream(),
+            dst,
+            state: State::Disconnected,
+            service: self.service.clone(),
+            backoff: self.backoff,
+            context_token: self.context_token.clone(),
+            retry_defaults: self.retry_defaults.clone(),
+        };
+        let spawn = DefaultExecutor::current().spawn(Box::new(daemon.map_err(|_| ())));
+
+        spawn.ok().map(|_| Rx {
+            rx,
+            _hangup: hangup_tx,
+        })
+    }
+}
+
+// === impl Rx ===
+
+impl Stream for Rx {
+    type Item = profiles::Routes;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.rx.poll().or_else(|_| Ok(None.into()))
+    }
+}
+
+// === impl Daemon ===
+
+enum StreamState {
+    SendLost,
+    RecvDone,
+}
+
+impl<T> Daemon<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    fn proxy_stream(
+        dst: &str,
+        rx: &mut grpc::Streaming<api::DestinationProfile, T::ResponseBody>,
+        tx: &mut mpsc::Sender<profiles::Routes>,
+        hangup: &mut oneshot::Receiver<Never>,
+        snapshot: &mut Option<Snapshotter>,
+        retry_defaults: &RetryDefaults,
+    ) -> Async<StreamState> {
+        loop {
+            match tx.poll_ready() {
+                Ok(Async::NotReady) => return Async::NotReady,
+                Ok(Async::Ready(())) => {}
+                Err(_) => return StreamState::SendLost.into(),
+            }
+
+            match rx.poll() {
+                Ok(Async::NotReady) => match hangup.poll() {
+                    Ok(Async::Ready(never)) => match never {}, // unreachable!
+                    Ok(Async::NotReady) => {
+                        // We are now scheduled to be notified if the hangup tx
+                        // is dropped.
+                        return Async::NotReady;
+                    }
+                    Err(_) => {
+                        // Hangup tx has been dropped.
+                        debug!("profile stream cancelled");
+                        return StreamState::SendLost.into();
+                    }
+                },
+                Ok(Async::Ready(None)) => return StreamState::RecvDone.into(),
+                Ok(Async::Ready(Some(profile))) => {
+                    debug!("profile received: {:?}", profile);
+                    let retry_budget = profile.retry_budget.clone().and_then(convert_retry_budget);
+                    if let Some(ref mut snapshot) = *snapshot {
+                        snapshot.record(dst, profile_to_json(&profile, retry_budget.is_some()));
+                    }
+                    let dst_overrides = profile
+                        .dst_overrides
+                        .into_iter()
+                        .filter_map(convert_dst_override);
+                    let routes = profile
+                        .routes
+                        .into_iter()
+                        .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()));
+                    let mut routes = profiles::Routes {
+                        routes: routes.collect(),
+                        dst_overrides: dst_overrides.collect(),
+                        // The Destination API does not select load-balancing
+                        // algorithms.
+                        load_balancer: None,
+                    };
+                    retry_defaults.apply(&mut routes);
+                    match tx.start_send(routes) {
+                        Ok(AsyncSink::Ready) => {} // continue
+                        Ok(AsyncSink::NotReady(_)) => {
+                            info!("dropping profile update due to a full buffer");
+                            // This must have been because another task stole
+                            // our tx slot? It seems pretty unlikely, but possible?
+                            return Async::NotReady;
+                        }
+                        Err(_) => {
+                            return StreamState::SendLost.into();
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("profile stream failed: {:?}", e);
+                    return StreamState::RecvDone.into();
+                }
+            }
+        }
+    }
+}
+
+impl<T> Future for Daemon<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Disconnected => {
+                    let svc = match self.service {
+                        Some(ref mut svc) => match svc.poll_ready() {
+                            Ok(Async::Ready(())) => svc.as_service(),
+                            Ok(Async::NotReady) => return Ok(Async::NotReady),
+                            Err(err) => {
+                                error!(
+                                    "profile service unexpected error (dst = {}): {:?}",
+                                    self.dst,
+                                    err.into(),
+                                );
+                                return Ok(Async::Ready(()));
+                            }
+                        },
+                        None => return Ok(Async::Ready(())),
+                    };
+
+                    let req = api::GetDestination {
+                        scheme: "k8s".to_owned(),
+                        path: self.dst.clone(),
+                        context_token: self.context_token.clone(),
+                    };
+                    debug!("getting profile: {:?}", req);
+                    let mut client = api::client::Destination::new(svc);
+                    let rspf = client.get_profile(grpc::Request::new(req));
+                    State::Waiting(rspf)
+                }
+                State::Waiting(ref mut f) => match f.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(rsp)) => {
+                        trace!("response received");
+                        State::Streaming(rsp.into_inner())
+                    }
+                    Err(e) => {
+                        warn!("error fetching profile for {}: {:?}", self.dst, e);
+                        State::Backoff(Delay::new(clock::now() + self.backoff))
+                    }
+                },
+                State::Streaming(ref mut s) => {
+                    match Self::proxy_stream(
+                        &self.dst,
+                        s,
+                        &mut self.tx,
+                        &mut self.hangup,
+                        &mut self.snapshot,
+                        &self.retry_defaults,
+                    ) {
+                        Async::NotReady => return Ok(Async::NotReady),
+                        Async::Ready(StreamState::SendLost) => return Ok(().into()),
+                        Async::Ready(StreamState::RecvDone) => {
+                            State::Backoff(Delay::new(clock::now() + self.backoff))
+                        }
+                    }
+                }
+                State::Backoff(ref mut f) => match f.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(_) | Ok(Async::Ready(())) => State::Disconnected,
+                },
+            };
+            self.metrics.set(self.state.metric());
+        }
+    }
+}
+
+// === impl State ===
+
+impl<T> State<T>
+where
+    T: GrpcService<BoxBody>,
+{
+    fn metric(&self) -> ProfileState {
+        match self {
+            State::Disconnected => ProfileState::Disconnected,
+            State::Backoff(_) => ProfileState::Backoff,
+            State::Waiting(_) => ProfileState::Waiting,
+            State::Streaming(_) => ProfileState::Streaming,
+        }
+    }
+}
+
+// === impl RetryDefaults ===
+
+impl RetryDefaults {
+    /// Configures the retryable routes that do not set their own backoffs or
+    /// per-try timeouts, and enables connection error retries if configured.
+    fn apply(&self, routes: &mut profiles::Routes) {
+        for &mut (_, ref mut route) in routes.routes.iter_mut() {
+            let mut retries = match route.retries() {
+                Some(retries) => retries.clone(),
+                None => continue,
+            };
+            if retries.backoff().is_none() {
+                if let Some(ref backoff) = self.backoff {
+                    retries = retries.with_backoff(backoff.clone());
+                }
+            }
+            if self.retry_connection_errors {
+                retries = retries.with_retry_connection_errors();
+            }
+            route.set_retries(retries);
+            if route.per_try_timeout().is_none() {
+                if let Some(timeout) = self.per_try_timeout {
+                    route.set_per_try_timeout(timeout);
+                }
+            }
+        }
+    }
+}
+
+// === impl Snapshotter ===
+
+impl Snapshotter {
+    fn record(&mut self, dst: &str, profile: json::Value) {
+        self.stale = None;
+        self.snapshot.record_profile(dst, profile);
+    }
+}
+
+fn convert_dst_override(orig: api::WeightedDst) -> Option<profiles::WeightedAddr> {
+    match NameAddr::from_str(&orig.authority) {
+        Ok(addr) => Some(profiles::WeightedAddr {
+            addr,
+            weight: orig.weight,
+        }),
+        Err(_) => {
+            warn!("invalid dst_overrides authority: {:?}", orig.authority);
+            None
+        }
+    }
+}
+
+fn convert_route(
+    orig: api::Route,
+    retry_budget: Option<&Arc<Budget>>,
+) -> Option<(profiles::RequestMatch, profiles::Route)> {
+    let req_match = orig.condition.and_then(convert_req_match)?;
+    let rsp_classes = orig
+        .response_classes
+        .into_iter()
+        .filter_map(convert_rsp_class)
+        .collect();
+    let mut route = profiles::Route::new(orig.metrics_labels.into_iter(), rsp_classes);
+    if orig.is_retryable {
+        set_route_retry(&mut route, retry_budget);
+    }
+    if let Some(timeout) = orig.timeout {
+        set_route_timeout(&mut route, timeout.into());
+    }
+    Some((req_match, route))
+}
+
+fn set_route_retry(route: &mut profiles::Route, retry_budget: Option<&Arc<Budget>>) {
+    let budget = match retry_budget {
+        Some(budget) => budget.clone(),
+        None => {
+            warn!("retry_budget is missing: {:?}", route);
+            return;
+        }
+    };
+
+    // Backoffs, per-try timeouts, and connection error retries are set by
+    // `RetryDefaults`.
+    route.set_retries(profiles::Retries::new(budget));
+}
+
+fn set_route_timeout(route: &mut profiles::Route, timeout: Result<Duration, Duration>) {
+    match timeout {
+        Ok(dur) => {
+            route.set_timeout(dur);
+        }
+        Err(_) => {
+            warn!("route timeout is negative: {:?}", route);
+        }
+    }
+}
+
+/// Converts the Destination API's request matches.
+///
+/// The API does not (yet) describe header, authority, query parameter, or
+/// scheme matches, so routes that use them must be defined in the file named
+/// by `LINKERD2_PROXY_DESTINATION_PROFILE_FILE`, which takes precedence over
+/// the Destination service. When the API describes them, they should be
+/// converted here, as `file::parse_req_match` does for local profiles.
+fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch> {
+    let m = match orig.r#match? {
+        api::request_match::Match::All(ms) => {
+            let ms = ms.matches.into_iter().filter_map(convert_req_match);
+            profiles::RequestMatch::All(ms.collect())
+        }
+        api::request_match::Match::Any(ms) => {
+            let ms = ms.matches.into_iter().filter_map(convert_req_match);
+            profiles::RequestMatch::Any(ms.collect())
+        }
+        api::request_match::Match::Not(m) => {
+            let m = convert_req_match(*m)?;
+            profiles::RequestMatch::Not(Box::new(m))
+        }
+        api::request_match::Match::Path(api::PathMatch { regex }) => {
+            profiles::RequestMatch::Path(anchored_regex(&regex).ok()?)
+        }
+        api::request_match::Match::Method(mm) => {
+            let m = mm.r#type.and_then(|m| m.try_as_http().ok())?;
+            profiles::RequestMatch::Method(m)
+        }
+    };
+
+    Some(m)
+}
+
+/// Compiles a regex so that it must match an entire value.
+fn anchored_regex(regex: &str) -> Result<Regex, ::regex::Error> {
+    let regex = regex.trim();
+    match (regex.starts_with('^'), regex.ends_with('$')) {
+        (true, true) => Regex::new(regex),
+        (hd_anchor, tl_anchor) => {
+            let hd = if hd_anchor { "" } else { "^" };
+            let tl = if tl_anchor { "" } else { "$" };
+            Regex::new(&format!("{}{}{}", hd, regex, tl))
+        }
+    }
+}
+
+fn convert_rsp_class(orig: api::ResponseClass) -> Option<profiles::ResponseClass> {
+    let c = orig.condition.and_then(convert_rsp_match)?;
+    Some(profiles::ResponseClass::new(orig.is_failure, c))
+}
+
+/// Converts the Destination API's response matches.
+///
+/// The API does not (yet) describe header or `grpc-status` matches, so
+/// response classes that use them must be defined in the file named by
+/// `LINKERD2_PROXY_DESTINATION_PROFILE_FILE`.
+fn convert_rsp_match(orig: api::ResponseMatch) -> Option<profiles::ResponseMatch> {
+    let m = match orig.r#match? {
+        api::response_match::Match::All(ms) => {
+            let ms = ms
+                .matches
+                .into_iter()
+                .filter_map(convert_rsp_match)
+                .collect::<Vec<_>>();
+            if ms.is_empty() {
+                return None;
+            }
+            profiles::ResponseMatch::All(ms)
+        }
+        api::response_match::Match::Any(ms) => {
+            let ms = ms
+                .matches
+                .into_iter()
+                .filter_map(convert_rsp_match)
+                .collect::<Vec<_>>();
+            if ms.is_empty() {
+                return None;
+            }
+            profiles::ResponseMatch::Any(ms)
+        }
+        api::response_match::Match::Not(m) => {
+            let m = convert_rsp_match(*m)?;
+            profiles::ResponseMatch::Not(Box::new(m))
+        }
+        api::response_match::Match::Status(range) => {
+            let min = http::StatusCode::from_u16(range.min as u16).ok()?;
+            let max = http::StatusCode::from_u16(range.max as u16).ok()?;
+            profiles::ResponseMatch::Status { min, max }
+        }
+    };
+
+    Some(m)
+}
+
+fn convert_retry_budget(orig: api::RetryBudget) -> Option<Arc<Budget>> {
+    let min_retries = if orig.min_retries_per_second <= ::std::i32::MAX as u32 {
+        orig.min_retries_per_second
+    } else {
+        warn!(
+            "retry_budget min_retries_per_second overflow: {:?}",
+            orig.min_retries_per_second
+        );
+        return None;
+    };
+    let retry_ratio = orig.retry_ratio;
+    if retry_ratio > 1000.0 || retry_ratio < 0.0 {
+        warn!("retry_budget retry_ratio invalid: {:?}", retry_ratio);
+        return None;
+    }
+    let ttl = match orig.ttl {
+        Some(pb_dur) => match pb_dur.into() {
+            Ok(dur) => {
+                if dur > Duration::from_secs(60) || dur < Duration::from_secs(1) {
+                    warn!("retry_budget ttl invalid: {:?}", dur);
+                    return None;
+                }
+                dur
+            }
+            Err(negative) => {
+                warn!("retry_budget ttl negative: {:?}", negative);
+                return None;
+            }
+        },
+        None => {
+            warn!("retry_budget ttl missing");
+            return None;
+        }
+    };
+
+    Some(Arc::new(Budget::new(ttl, min_retries, retry_ratio)))
+}
+
+/// Describes a profile in the format of the profile file, so that it may be
+/// snapshotted.
+///
+/// Parts of the profile that cannot be converted are omitted, as they are when
+/// the profile is converted to `Routes`.
+fn profile_to_json(profile: &api::DestinationProfile, has_budget: bool) -> json::Value {
+    let mut fields = Vec::new();
+    if has_budget {
+        if let Some(ref budget) = profile.retry_budget {
+            let ttl = budget.ttl.clone().and_then(duration_to_json);
+            fields.push((
+                "retry_budget",
+                json::object(vec![
+                    (
+                        "min_retries_per_second",
+                        json::Value::Number(f64::from(budget.min_retries_per_second)),
+                    ),
+                    (
+                        "retry_ratio",
+                        json::Value::Number(f64::from(budget.retry_ratio)),
+                    ),
+                    ("ttl", ttl.unwrap_or(json::Value::Null)),
+                ]),
+            ));
+        }
+    }
+    let routes = profile
+        .routes
+        .iter()
+        .filter_map(|route| route_to_json(route, has_budget));
+    fields.push(("routes", json::Value::Array(routes.collect())));
+    let dst_overrides = profile
+        .dst_overrides
+        .iter()
+        .filter(|dst| NameAddr::from_str(&dst.authority).is_ok())
+        .map(|dst| {
+            json::object(vec![
+                ("authority", json::Value::String(dst.authority.clone())),
+                // The file's weights are scaled by 10,000.
+                (
+                    "weight",
+                    json::Value::Number(f64::from(dst.weight) / 10_000.0),
+                ),
+            ])
+        });
+    fields.push(("dst_overrides", json::Value::Array(dst_overrides.collect())));
+    json::object(fields)
+}
+
+fn route_to_json(route: &api::Route, has_budget: bool) -> Option<json::Value> {
+    let condition = route.condition.as_ref().and_then(req_match_to_json)?;
+    let labels = route
+        .metrics_labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), json::Value::String(v.clone())));
+    let rsp_classes = route.response_classes.iter().filter_map(|class| {
+        let condition = class.condition.as_ref().and_then(rsp_match_to_json)?;
+        Some(json::object(vec![
+            ("condition", condition),
+            ("is_failure", json::Value::Bool(class.is_failure)),
+        ]))
+    });
+
+    let mut fields = vec![
+        ("condition", condition),
+        ("labels", json::object(labels)),
+        (
+            "response_classes",
+            json::Value::Array(rsp_classes.collect()),
+        ),
+        // Routes are not retried without a retry budget.
+        (
+            "retryable",
+            json::Value::Bool(route.is_retryable && has_budget),
+        ),
+    ];
+    if let Some(timeout) = route.timeout.clone().and_then(duration_to_json) {
+        fields.push(("timeout", timeout));
+    }
+    Some(json::object(fields))
+}
+
+fn req_match_to_json(m: &api::RequestMatch) -> Option<json::Value> {
+    let (kind, value) = match m.r#match.as_ref()? {
+        api::request_match::Match::All(ms) => {
+            let ms = ms.matches.iter().filter_map(req_match_to_json);
+            ("all", json::Value::Array(ms.collect()))
+        }
+        api::request_match::Match::Any(ms) => {
+            let ms = ms.matches.iter().filter_map(req_match_to_json);
+            ("any", json::Value::Array(ms.collect()))
+        }
+        api::request_match::Match::Not(m) => ("not", req_match_to_json(m)?),
+        api::request_match::Match::Path(api::PathMatch { regex }) => {
+            let re = anchored_regex(regex).ok()?;
+            ("path", json::Value::String(re.as_str().to_owned()))
+        }
+        api::request_match::Match::Method(mm) => {
+            let m = mm.r#type.clone().and_then(|m| m.try_as_http().ok())?;
+            ("method", json::Value::String(m.as_str().to_owned()))
+        }
+    };
+    Some(json::object(vec![(kind, value)]))
+}
+
+fn rsp_match_to_json(m: &api::ResponseMatch) -> Option<json::Value> {
+    let (kind, value) = match m.r#match.as_ref()? {
+        api::response_match::Match::All(ms) => {
+            let ms = ms
+                .matches
+                .iter()
+                .filter_map(rsp_match_to_json)
+                .collect::<Vec<_>>();
+            if ms.is_empty() {
+                return None;
+            }
+            ("all", json::Value::Array(ms))
+        }
+        api::response_match::Match::Any(ms) => {
+            let ms = ms
+                .matches
+                .iter()
+                .filter_map(rsp_match_to_json)
+                .collect::<Vec<_>>();
+            if ms.is_empty() {
+                return None;
+            }
+            ("any", json::Value::Array(ms))
+        }
+        api::response_match::Match::Not(m) => ("not", rsp_match_to_json(m)?),
+        api::response_match::Match::Status(range) => {
+            let min = http::StatusCode::from_u16(range.min as u16).ok()?;
+            let max = http::StatusCode::from_u16(range.max as u16).ok()?;
+            if min > max {
+                return None;
+            }
+            let range = json::object(vec![
+                ("min", json::Value::Number(f64::from(min.as_u16()))),
+                ("max", json::Value::Number(f64::from(max.as_u16()))),
+            ]);
+            ("status", range)
+        }
+    };
+    Some(json::object(vec![(kind, value)]))
+}
+
+fn duration_to_json(d: ::prost_types::Duration) -> Option<json::Value> {
+    let d: Result<Duration, Duration> = d.into();
+    let d = d.ok()?;
+    let ms = d.as_secs() * 1_000 + u64::from(d.subsec_millis());
+    Some(json::Value::String(format!("{}ms", ms)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::*;
+
+    quickcheck! {
+        fn retry_budget_from_proto(
+            min_retries_per_second: u32,
+            retry_ratio: f32,
+            seconds: i64,
+            nanos: i32
+        ) -> bool {
+            let proto = api::RetryBudget {
+                min_retries_per_second,
+                retry_ratio,
+                ttl: Some(::prost_types::Duration {
+                    seconds,
+                    nanos,
+                }),
+            };
+            convert_retry_budget(proto);
+            // simply not panicking is good enough
+            true
+        }
+    }
+
+    #[test]
+    fn retry_defaults_apply_to_retryable_routes() {
+        let budget = Arc::new(Budget::new(Duration::from_secs(10), 10, 0.2));
+        let mut retryable = profiles::Route::new(::std::iter::empty(), Vec::new());
+        retryable.set_retries(profiles::Retries::new(budget));
+        let unretryable = profiles::Route::new(::std::iter::empty(), Vec::new());
+        let path = || profiles::RequestMatch::Path(Regex::new("^/$").unwrap());
+        let mut routes = profiles::Routes {
+            routes: vec![(path(), retryable), (path(), unretryable)],
+            ..Default::default()
+        };
+
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1), 0.5).unwrap();
+        let defaults = RetryDefaults {
+            backoff: Some(backoff.clone()),
+            per_try_timeout: Some(Duration::from_millis(500)),
+            retry_connection_errors: true,
+        };
+        defaults.apply(&mut routes);
+
+        let retryable = &routes.routes[0].1;
+        assert_eq!(retryable.retries().unwrap().backoff(), Some(&backoff));
+        assert!(retryable.retries().unwrap().retry_connection_errors());
+        assert_eq!(
+            retryable.per_try_timeout(),
+            Some(Duration::from_millis(500))
+        );
+        let unretryable = &routes.routes[1].1;
+        assert!(unretryable.retries().is_none());
+        assert_eq!(unretryable.per_try_timeout(), None);
+    }
+
+    #[test]
+    fn dst_overrides_from_proto() {
+        let dst = convert_dst_override(api::WeightedDst {
+            authority: "books-v2.ns.svc.cluster.local:8080".into(),
+            weight: 2_500,
+        })
+        .expect("dst override must be valid");
+        assert_eq!(dst.addr.port(), 8080);
+        assert_eq!(dst.weight, 2_500);
+
+        assert!(convert_dst_override(api::WeightedDst {
+            authority: "not an authority".into(),
+            weight: 2_500,
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn snapshots_profiles() {
+        let status =
+            api::response_match::Match::Status(api::HttpStatusRange { min: 500, max: 599 });
+        let route = api::Route {
+            condition: Some(api::RequestMatch {
+                r#match: Some(api::request_match::Match::Path(api::PathMatch {
+                    regex: "/books/[^/]+".into(),
+                })),
+            }),
+            response_classes: vec![api::ResponseClass {
+                condition: Some(api::ResponseMatch {
+                    r#match: Some(status),
+                }),
+                is_failure: true,
+            }],
+            is_retryable: true,
+            timeout: Some(::prost_types::Duration {
+                seconds: 10,
+                nanos: 0,
+            }),
+            ..Default::default()
+        };
+        let profile = api::DestinationProfile {
+            routes: vec![route],
+            retry_budget: Some(api::RetryBudget {
+                min_retries_per_second: 10,
+                retry_ratio: 0.2,
+                ttl: Some(::prost_types::Duration {
+                    seconds: 10,
+                    nanos: 0,
+                }),
+            }),
+            dst_overrides: vec![
+                api::WeightedDst {
+                    authority: "books-v2.ns.svc.cluster.local:8080".into(),
+                    weight: 2_500,
+                },
+                api::WeightedDst {
+                    authority: "not an authority".into(),
+                    weight: 7_500,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let json = profile_to_json(&profile, true).to_string();
+        let routes =
+            file::parse_profile(&::json::parse(&json).unwrap()).expect("snapshot must parse");
+        assert_eq!(routes.routes.len(), 1);
+        let (req_match, route) = &routes.routes[0];
+        match req_match {
+            profiles::RequestMatch::Path(re) => assert_eq!(re.as_str(), "^/books/[^/]+$"),
+            m => panic!("unexpected condition: {:?}", m),
+        }
+        assert_eq!(route.timeout(), Some(Duration::from_secs(10)));
+        assert!(route.retries().is_some());
+        assert_eq!(
+            routes.dst_overrides,
+            vec![profiles::WeightedAddr {
+                addr: NameAddr::from_str("books-v2.ns.svc.cluster.local:8080").unwrap(),
+                weight: 2_500,
+            }],
+        );
+
+        // Without a valid budget, the route is not retried.
+        let json = profile_to_json(&profile, false).to_string();
+        let routes = file::parse_profile(&::json::parse(&json).unwrap()).unwrap();
+        assert!(routes.routes[0].1.retries().is_none());
+    }
+}
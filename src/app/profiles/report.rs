@@ -0,0 +1,339 @@
+//! Lists the routes that are currently applied to each destination, for the
+//! admin server.
+//!
+//! Routes are described in the format of the profile file (see the `file`
+//! module), though retry budgets and backoffs are omitted.
+
+use futures::{Async, Poll, Stream};
+use indexmap::IndexMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use json;
+use never::Never;
+use proxy::http::profiles;
+use NameAddr;
+
+/// Records the latest routes of each stream obtained from a `G`-typed
+/// `GetRoutes`.
+#[derive(Clone, Debug)]
+pub struct GetRoutes<G> {
+    inner: G,
+    report: Report,
+}
+
+/// Lists the routes applied to each destination.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<Streams>>);
+
+pub struct Rx<S> {
+    inner: S,
+    id: u64,
+    report: Weak<Mutex<Streams>>,
+}
+
+#[derive(Debug, Default)]
+struct Streams {
+    next_id: u64,
+    /// Each stream's destination and, once they are known, its routes.
+    by_id: IndexMap<u64, (String, Option<json::Value>)>,
+}
+
+// === impl GetRoutes ===
+
+impl<G> GetRoutes<G> {
+    pub fn new(inner: G, report: Report) -> Self {
+        Self { inner, report }
+    }
+}
+
+impl<G: profiles::GetRoutes> profiles::GetRoutes for GetRoutes<G> {
+    type Stream = Rx<G::Stream>;
+
+    fn get_routes(&self, dst: &NameAddr) -> Option<Self::Stream> {
+        let inner = self.inner.get_routes(dst)?;
+        let id = match self.report.0.lock() {
+            Ok(mut streams) => {
+                let id = streams.next_id;
+                streams.next_id += 1;
+                streams.by_id.insert(id, (dst.to_string(), None));
+                id
+            }
+            Err(_) => 0,
+        };
+        Some(Rx {
+            inner,
+            id,
+            report: Arc::downgrade(&self.report.0),
+        })
+    }
+}
+
+// === impl Report ===
+
+impl Report {
+    /// Describes the routes applied to each destination.
+    pub fn to_json(&self) -> json::Value {
+        let streams = match self.0.lock() {
+            Ok(streams) => streams,
+            Err(_) => return json::Value::Null,
+        };
+
+        let dsts = streams.by_id.values().map(|&(ref dst, ref routes)| {
+            json::object(vec![
+                ("dst", json::Value::String(dst.clone())),
+                ("profile", routes.clone().unwrap_or(json::Value::Null)),
+            ])
+        });
+        json::Value::Array(dsts.collect())
+    }
+}
+
+// === impl Rx ===
+
+impl<S> Stream for Rx<S>
+where
+    S: Stream<Item = profiles::Routes, Error = Never>,
+{
+    type Item = profiles::Routes;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let routes = try_ready!(self.inner.poll());
+        if let Some(ref routes) = routes {
+            if let Some(streams) = self.report.upgrade() {
+                if let Ok(mut streams) = streams.lock() {
+                    if let Some(entry) = streams.by_id.get_mut(&self.id) {
+                        entry.1 = Some(routes_to_json(routes));
+                    }
+                }
+            }
+        }
+        Ok(Async::Ready(routes))
+    }
+}
+
+impl<S> Drop for Rx<S> {
+    fn drop(&mut self) {
+        if let Some(streams) = self.report.upgrade() {
+            if let Ok(mut streams) = streams.lock() {
+                streams.by_id.swap_remove(&self.id);
+            }
+        }
+    }
+}
+
+fn routes_to_json(routes: &profiles::Routes) -> json::Value {
+    let rs = routes
+        .routes
+        .iter()
+        .map(|&(ref condition, ref route)| route_to_json(condition, route));
+    let dst_overrides = routes.dst_overrides.iter().map(|dst| {
+        json::object(vec![
+            ("authority", json::Value::String(dst.addr.to_string())),
+            // Weights are scaled by 10,000, as they are when parsed.
+            (
+                "weight",
+                json::Value::Number(f64::from(dst.weight) / 10_000.0),
+            ),
+        ])
+    });
+
+    let mut fields = vec![
+        ("routes", json::Value::Array(rs.collect())),
+        ("dst_overrides", json::Value::Array(dst_overrides.collect())),
+    ];
+    if let Some(ref lb) = routes.load_balancer {
+        fields.push(("load_balancer", json::Value::String(lb.to_string())));
+    }
+    json::object(fields)
+}
+
+fn route_to_json(condition: &profiles::RequestMatch, route: &profiles::Route) -> json::Value {
+    let labels = route
+        .labels()
+        .iter()
+        .map(|(k, v)| (k.as_str(), json::Value::String(v.clone())));
+    let rsp_classes = route.response_classes().iter().map(|class| {
+        json::object(vec![
+            ("condition", rsp_match_to_json(class.response_match())),
+            ("is_failure", json::Value::Bool(class.is_failure())),
+        ])
+    });
+
+    let mut fields = vec![
+        ("condition", req_match_to_json(condition)),
+        ("labels", json::object(labels)),
+        (
+            "response_classes",
+            json::Value::Array(rsp_classes.collect()),
+        ),
+        ("retryable", json::Value::Bool(route.retries().is_some())),
+    ];
+    if let Some(retries) = route.retries() {
+        fields.push((
+            "retry_connection_errors",
+            json::Value::Bool(retries.retry_connection_errors()),
+        ));
+    }
+    if let Some(timeout) = route.timeout() {
+        fields.push(("timeout", duration_to_json(timeout)));
+    }
+    if let Some(timeout) = route.per_try_timeout() {
+        fields.push(("per_try_timeout", duration_to_json(timeout)));
+    }
+    json::object(fields)
+}
+
+fn req_match_to_json(m: &profiles::RequestMatch) -> json::Value {
+    use proxy::http::profiles::RequestMatch;
+
+    let (kind, value) = match m {
+        RequestMatch::All(ms) => (
+            "all",
+            json::Value::Array(ms.iter().map(req_match_to_json).collect()),
+        ),
+        RequestMatch::Any(ms) => (
+            "any",
+            json::Value::Array(ms.iter().map(req_match_to_json).collect()),
+        ),
+        RequestMatch::Not(m) => ("not", req_match_to_json(m)),
+        RequestMatch::Path(re) => ("path", json::Value::String(re.as_str().to_owned())),
+        RequestMatch::Method(method) => ("method", json::Value::String(method.to_string())),
+        RequestMatch::Authority(value) => ("authority", value_match_to_json(None, value)),
+        RequestMatch::Header { name, value } => {
+            ("header", value_match_to_json(Some(name.as_str()), value))
+        }
+        RequestMatch::QueryParam { name, value } => (
+            "query_param",
+            value_match_to_json(Some(name.as_str()), value),
+        ),
+        RequestMatch::Scheme(scheme) => ("scheme", json::Value::String(scheme.to_string())),
+    };
+    json::object(vec![(kind, value)])
+}
+
+fn rsp_match_to_json(m: &profiles::ResponseMatch) -> json::Value {
+    use proxy::http::profiles::ResponseMatch;
+
+    let range = |min: f64, max: f64| {
+        json::object(vec![
+            ("min", json::Value::Number(min)),
+            ("max", json::Value::Number(max)),
+        ])
+    };
+    let (kind, value) = match m {
+        ResponseMatch::All(ms) => (
+            "all",
+            json::Value::Array(ms.iter().map(rsp_match_to_json).collect()),
+        ),
+        ResponseMatch::Any(ms) => (
+            "any",
+            json::Value::Array(ms.iter().map(rsp_match_to_json).collect()),
+        ),
+        ResponseMatch::Not(m) => ("not", rsp_match_to_json(m)),
+        ResponseMatch::Status { min, max } => (
+            "status",
+            range(f64::from(min.as_u16()), f64::from(max.as_u16())),
+        ),
+        ResponseMatch::Header { name, value } => {
+            ("header", value_match_to_json(Some(name.as_str()), value))
+        }
+        ResponseMatch::GrpcStatus { min, max } => {
+            ("grpc_status", range(f64::from(*min), f64::from(*max)))
+        }
+    };
+    json::object(vec![(kind, value)])
+}
+
+fn value_match_to_json(name: Option<&str>, value: &profiles::ValueMatch) -> json::Value {
+    use proxy::http::profiles::ValueMatch;
+
+    let mut fields = Vec::new();
+    if let Some(name) = name {
+        fields.push(("name", json::Value::String(name.to_owned())));
+    }
+    match value {
+        ValueMatch::Exact(s) => fields.push(("exact", json::Value::String(s.clone()))),
+        ValueMatch::Regex(re) => {
+            fields.push(("regex", json::Value::String(re.as_str().to_owned())))
+        }
+        ValueMatch::Present => {}
+    }
+    json::object(fields)
+}
+
+fn duration_to_json(d: Duration) -> json::Value {
+    let ms = d.as_secs() * 1_000 + u64::from(d.subsec_millis());
+    json::Value::String(format!("{}ms", ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Fixed(json::Value);
+
+    impl profiles::GetRoutes for Fixed {
+        type Stream = stream::Once<profiles::Routes, Never>;
+
+        fn get_routes(&self, _: &NameAddr) -> Option<Self::Stream> {
+            let routes = super::super::file::parse_profile(&self.0).expect("profile must parse");
+            Some(stream::once(Ok(routes)))
+        }
+    }
+
+    #[test]
+    fn reports_applied_routes() {
+        let profile = json::parse(
+            r#"{
+                "routes": [
+                    {
+                        "condition": {
+                            "all": [
+                                { "method": "GET" },
+                                { "path": "^/books/[^/]+$" },
+                                { "header": { "name": "x-tenant", "exact": "a" } }
+                            ]
+                        },
+                        "labels": { "route": "get-book" },
+                        "response_classes": [
+                            { "condition": { "status": { "min": 500, "max": 599 } }, "is_failure": true }
+                        ],
+                        "retryable": false,
+                        "timeout": "10000ms"
+                    }
+                ],
+                "dst_overrides": [
+                    { "authority": "web-v2.default.svc.cluster.local:8080", "weight": 1 }
+                ],
+                "load_balancer": "round-robin"
+            }"#,
+        )
+        .unwrap();
+
+        let report = Report::default();
+        let get_routes = GetRoutes::new(Fixed(profile.clone()), report.clone());
+        let dst = "web.default.svc.cluster.local:8080".parse().unwrap();
+        let rx = profiles::GetRoutes::get_routes(&get_routes, &dst).expect("must get routes");
+        assert_eq!(
+            report.to_json().as_array().unwrap()[0].get("profile"),
+            Some(&json::Value::Null)
+        );
+
+        let (_, rx) = rx.into_future().wait().ok().expect("stream must not fail");
+        let applied = report.to_json();
+        let applied = applied.as_array().unwrap()[0].clone();
+        assert_eq!(
+            applied.get("dst").and_then(json::Value::as_str),
+            Some("web.default.svc.cluster.local:8080")
+        );
+        assert_eq!(applied.get("profile"), Some(&profile));
+
+        drop(rx);
+        assert_eq!(report.to_json(), json::Value::Array(vec![]));
+    }
+}
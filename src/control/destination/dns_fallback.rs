@@ -0,0 +1,472 @@
+//! Resolves destinations via DNS when the Destination service has no endpoints
+//! for them.
+//!
+//! Otherwise, requests to such destinations are routed to the original
+//! destination address of each connection. Instead, all of a name's A and AAAA
+//! records (or, optionally, the SRV records of a service it serves) are
+//! resolved into a set of endpoints, so that requests are balanced over all of
+//! them. The records are resolved again when their TTLs expire.
+//!
+//! If the Destination service later returns endpoints for the name, the
+//! endpoints resolved via DNS are replaced by them.
+
+use futures::{future, Async, Future, Poll};
+use indexmap::IndexMap;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_timer::{clock, Delay};
+
+use super::{Endpoints, Metadata, ProtocolHint};
+use dns;
+use proxy::resolve::{self, Update};
+use NameAddr;
+
+/// Duration to wait before resolving a name again after an error (or a
+/// NXDOMAIN response with no TTL).
+const DNS_ERROR_TTL: Duration = Duration::from_secs(3);
+
+/// Limits how often a name is resolved, even if its records have shorter TTLs.
+const MIN_REFRESH: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Only names in these suffixes are resolved via DNS.
+    pub suffixes: Vec<dns::Suffix>,
+    /// The service (e.g. `http`) whose SRV records are used, if the name has
+    /// any.
+    pub srv: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    resolver: dns::Resolver,
+    config: Option<Arc<Config>>,
+}
+
+pub struct Resolution<R> {
+    inner: R,
+    dns: Option<Dns>,
+    /// Whether endpoints are being resolved via DNS.
+    fallback: bool,
+    updates: VecDeque<Update<Metadata>>,
+}
+
+/// Resolves a name's endpoints via DNS.
+struct Dns {
+    authority: NameAddr,
+    lookup: Box<FnMut() -> Lookup + Send>,
+    state: State,
+    endpoints: Endpoints,
+    updates: VecDeque<Update<Metadata>>,
+}
+
+enum State {
+    Init,
+    Pending(Lookup),
+    ValidUntil(Delay),
+    /// The timer failed, so the records cannot be refreshed.
+    Stopped,
+}
+
+type Lookup = Box<Future<Item = Resolved, Error = dns::ResolveError> + Send>;
+
+struct Resolved {
+    endpoints: Endpoints,
+    valid_until: Instant,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R>
+where
+    R: resolve::Resolve<NameAddr, Endpoint = Metadata>,
+{
+    /// Returns a `Resolve` that uses DNS as configured, or that only uses the
+    /// inner resolver if there is no configuration.
+    pub fn new(inner: R, resolver: dns::Resolver, config: Option<Config>) -> Self {
+        Self {
+            inner,
+            resolver,
+            config: config.map(Arc::new),
+        }
+    }
+}
+
+impl<R> resolve::Resolve<NameAddr> for Resolve<R>
+where
+    R: resolve::Resolve<NameAddr, Endpoint = Metadata>,
+{
+    type Endpoint = Metadata;
+    type Resolution = Resolution<R::Resolution>;
+
+    fn resolve(&self, authority: &NameAddr) -> Self::Resolution {
+        let dns = self.config.as_ref().and_then(|config| {
+            if !config.suffixes.iter().any(|s| s.contains(authority.name())) {
+                return None;
+            }
+            let resolver = self.resolver.clone();
+            let srv = config.srv.clone();
+            let name = authority.clone();
+            let dns = Dns::new(authority.clone(), move || {
+                lookup(&resolver, &name, srv.as_ref().map(String::as_str))
+            });
+            Some(dns)
+        });
+
+        Resolution {
+            inner: self.inner.resolve(authority),
+            dns,
+            fallback: false,
+            updates: VecDeque::new(),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution<Endpoint = Metadata>,
+{
+    type Endpoint = Metadata;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        loop {
+            if let Some(update) = self.updates.pop_front() {
+                return Ok(Async::Ready(update));
+            }
+
+            match self.inner.poll()? {
+                Async::Ready(Update::NoEndpoints) => {
+                    if let Some(ref dns) = self.dns {
+                        if !self.fallback {
+                            debug!("resolving {} via DNS", dns.authority);
+                            self.fallback = true;
+                        }
+                    }
+                    self.updates.push_back(Update::NoEndpoints);
+                }
+                Async::Ready(Update::Add(addr, meta)) => {
+                    if self.fallback {
+                        self.fallback = false;
+                        if let Some(ref mut dns) = self.dns {
+                            debug!("no longer resolving {} via DNS", dns.authority);
+                            for addr in dns.clear() {
+                                self.updates.push_back(Update::Remove(addr));
+                            }
+                        }
+                    }
+                    self.updates.push_back(Update::Add(addr, meta));
+                }
+                Async::Ready(update @ Update::Remove(_)) => self.updates.push_back(update),
+                Async::NotReady => {
+                    if self.fallback {
+                        if let Some(ref mut dns) = self.dns {
+                            return Ok(dns.poll());
+                        }
+                    }
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+    }
+}
+
+// === impl Dns ===
+
+impl Dns {
+    fn new<F>(authority: NameAddr, lookup: F) -> Self
+    where
+        F: FnMut() -> Lookup + Send + 'static,
+    {
+        Self {
+            authority,
+            lookup: Box::new(lookup),
+            state: State::Init,
+            endpoints: Endpoints::new(),
+            updates: VecDeque::new(),
+        }
+    }
+
+    fn poll(&mut self) -> Async<Update<Metadata>> {
+        loop {
+            if let Some(update) = self.updates.pop_front() {
+                return Async::Ready(update);
+            }
+
+            self.state = match self.state {
+                State::Init => State::Pending((self.lookup)()),
+                State::Pending(ref mut pending) => match pending.poll() {
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    Ok(Async::Ready(resolved)) => {
+                        trace!(
+                            "resolved {} endpoints for {}",
+                            resolved.endpoints.len(),
+                            self.authority
+                        );
+                        super::update_endpoints(
+                            &mut self.endpoints,
+                            resolved.endpoints,
+                            &mut self.updates,
+                        );
+                        let min = clock::now() + MIN_REFRESH;
+                        State::ValidUntil(Delay::new(resolved.valid_until.max(min)))
+                    }
+                    Err(e) => {
+                        let valid_until = match e.kind() {
+                            dns::ResolveErrorKind::NoRecordsFound { valid_until, .. } => {
+                                // The name no longer has any addresses.
+                                debug!("{} has no DNS records", self.authority);
+                                super::update_endpoints(
+                                    &mut self.endpoints,
+                                    Endpoints::new(),
+                                    &mut self.updates,
+                                );
+                                *valid_until
+                            }
+                            _ => {
+                                // Keep using the last resolved endpoints.
+                                debug!("failed to resolve {}: {}", self.authority, e);
+                                None
+                            }
+                        };
+                        State::ValidUntil(Delay::new(
+                            valid_until.unwrap_or_else(|| clock::now() + DNS_ERROR_TTL),
+                        ))
+                    }
+                },
+                State::ValidUntil(ref mut delay) => match delay.poll() {
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    // The records' TTLs have expired, so resolve them again.
+                    Ok(Async::Ready(())) => State::Init,
+                    Err(e) => {
+                        // Keep using the last resolved endpoints.
+                        error!("{} will not be resolved again: {}", self.authority, e);
+                        State::Stopped
+                    }
+                },
+                State::Stopped => return Async::NotReady,
+            };
+        }
+    }
+
+    /// Stops resolving the name, returning the addresses of the endpoints that
+    /// had been resolved.
+    fn clear(&mut self) -> Vec<SocketAddr> {
+        self.state = State::Init;
+        self.updates.clear();
+        self.endpoints.drain(..).map(|(addr, _)| addr).collect()
+    }
+}
+
+/// Resolves the SRV records of a service, if one is configured, falling back
+/// to the name's A and AAAA records.
+fn lookup(resolver: &dns::Resolver, authority: &NameAddr, srv: Option<&str>) -> Lookup {
+    let service = match srv {
+        Some(service) => service,
+        None => return lookup_ips(resolver, authority),
+    };
+
+    let resolver = resolver.clone();
+    let authority = authority.clone();
+    let srvs = resolver.resolve_srv(service, authority.name());
+    Box::new(srvs.then(move |srvs| match srvs {
+        Ok(ref srvs) if !srvs.records.is_empty() => lookup_srv_targets(&resolver, srvs),
+        _ => lookup_ips(&resolver, &authority),
+    }))
+}
+
+/// Resolves all of a name's addresses, using the authority's port.
+fn lookup_ips(resolver: &dns::Resolver, authority: &NameAddr) -> Lookup {
+    let port = authority.port();
+    let lookup = resolver.resolve_all_ips(authority.name()).map(move |ips| {
+        let endpoints = ips
+            .addrs
+            .into_iter()
+            .map(|ip| (SocketAddr::new(ip, port), Metadata::empty()))
+            .collect();
+        Resolved {
+            endpoints,
+            valid_until: ips.valid_until,
+        }
+    });
+    Box::new(lookup)
+}
+
+/// Resolves the addresses of the most preferred SRV targets.
+///
+/// Only the records with the lowest priority are used. Their weights are
+/// scaled so that the average endpoint has the default weight.
+fn lookup_srv_targets(resolver: &dns::Resolver, srvs: &dns::Srvs) -> Lookup {
+    let priority = srvs.records.iter().map(|r| r.priority).min();
+    let records = srvs
+        .records
+        .iter()
+        .filter(|r| Some(r.priority) == priority)
+        .collect::<Vec<_>>();
+    let weights = srv_weights(&records.iter().map(|r| r.weight).collect::<Vec<_>>());
+
+    let targets = records.into_iter().zip(weights).map(|(srv, weight)| {
+        let port = srv.port;
+        resolver
+            .resolve_all_ips(&srv.target)
+            .then(move |ips| Ok::<_, dns::ResolveError>(ips.ok().map(|ips| (ips, port, weight))))
+    });
+
+    let valid_until = srvs.valid_until;
+    let lookup = future::join_all(targets).map(move |targets| {
+        let mut resolved = Resolved {
+            endpoints: IndexMap::new(),
+            valid_until,
+        };
+        // Targets that cannot be resolved are skipped.
+        for (ips, port, weight) in targets.into_iter().flatten() {
+            resolved.valid_until = resolved.valid_until.min(ips.valid_until);
+            for ip in ips.addrs {
+                let meta = Metadata::new(IndexMap::new(), ProtocolHint::Unknown, None, weight);
+                resolved.endpoints.insert(SocketAddr::new(ip, port), meta);
+            }
+        }
+        resolved
+    });
+    Box::new(lookup)
+}
+
+/// Scales SRV weights so that their average is the default endpoint weight.
+fn srv_weights(weights: &[u16]) -> Vec<u32> {
+    let total = weights.iter().map(|w| u64::from(*w)).sum::<u64>();
+    let n = weights.len() as u64;
+    weights
+        .iter()
+        .map(|w| {
+            if total == 0 {
+                return Metadata::empty().weight;
+            }
+            let scaled = u64::from(*w) * u64::from(Metadata::empty().weight) * n / total;
+            scaled.min(u64::from(::std::u32::MAX)) as u32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Poll};
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio_timer::clock;
+
+    use super::{srv_weights, Dns, Lookup, Resolution, Resolved};
+    use control::destination::{Endpoints, Metadata};
+    use proxy::resolve::{self, Update};
+    use NameAddr;
+
+    /// A resolution whose updates are added by the test.
+    #[derive(Clone, Default)]
+    struct Scripted(Arc<Mutex<VecDeque<Update<Metadata>>>>);
+
+    impl Scripted {
+        fn push(&self, update: Update<Metadata>) {
+            self.0.lock().unwrap().push_back(update);
+        }
+    }
+
+    impl resolve::Resolution for Scripted {
+        type Endpoint = Metadata;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Update<Metadata>, ()> {
+            Ok(self
+                .0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    #[test]
+    fn scales_srv_weights() {
+        assert_eq!(srv_weights(&[]), Vec::<u32>::new());
+        assert_eq!(srv_weights(&[0, 0]), vec![10_000, 10_000]);
+        assert_eq!(srv_weights(&[5, 5]), vec![10_000, 10_000]);
+        assert_eq!(srv_weights(&[1, 3]), vec![5_000, 15_000]);
+        assert_eq!(srv_weights(&[0, 60, 20]), vec![0, 22_500, 7_500]);
+    }
+
+    #[test]
+    fn falls_back_to_dns_until_the_controller_has_endpoints() {
+        let dns_addr: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        let ctl_addr: SocketAddr = "10.1.1.1:80".parse().unwrap();
+        let lookups = Arc::new(Mutex::new(0));
+
+        let controller = Scripted::default();
+        let dns = {
+            let lookups = lookups.clone();
+            Dns::new(
+                NameAddr::from_str("web.example.com:80").unwrap(),
+                move || {
+                    *lookups.lock().unwrap() += 1;
+                    let mut endpoints = Endpoints::new();
+                    endpoints.insert(dns_addr, Metadata::empty());
+                    let resolved = Resolved {
+                        endpoints,
+                        valid_until: clock::now() + Duration::from_secs(60),
+                    };
+                    Box::new(future::ok(resolved)) as Lookup
+                },
+            )
+        };
+        let mut resolution = Resolution {
+            inner: controller.clone(),
+            dns: Some(dns),
+            fallback: false,
+            updates: VecDeque::new(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(future::lazy(move || {
+            let mut poll = || match resolve::Resolution::poll(&mut resolution) {
+                Ok(Async::Ready(Update::Add(addr, _))) => Some(format!("add {}", addr)),
+                Ok(Async::Ready(Update::Remove(addr))) => Some(format!("remove {}", addr)),
+                Ok(Async::Ready(Update::NoEndpoints)) => Some("none".to_owned()),
+                Ok(Async::NotReady) => None,
+                Err(()) => panic!("resolution failed"),
+            };
+
+            // DNS is not used until the controller has no endpoints.
+            assert_eq!(poll(), None);
+            assert_eq!(*lookups.lock().unwrap(), 0);
+
+            controller.push(Update::NoEndpoints);
+            assert_eq!(poll(), Some("none".to_owned()));
+            assert_eq!(poll(), Some(format!("add {}", dns_addr)));
+            assert_eq!(poll(), None, "records are valid until their TTL expires");
+            assert_eq!(*lookups.lock().unwrap(), 1);
+
+            // The controller's endpoints replace those resolved via DNS.
+            controller.push(Update::Add(ctl_addr, Metadata::empty()));
+            assert_eq!(poll(), Some(format!("remove {}", dns_addr)));
+            assert_eq!(poll(), Some(format!("add {}", ctl_addr)));
+            assert_eq!(poll(), None);
+
+            // DNS is used again when the controller has no endpoints.
+            controller.push(Update::Remove(ctl_addr));
+            controller.push(Update::NoEndpoints);
+            assert_eq!(poll(), Some(format!("remove {}", ctl_addr)));
+            assert_eq!(poll(), Some("none".to_owned()));
+            assert_eq!(poll(), Some(format!("add {}", dns_addr)));
+            assert_eq!(*lookups.lock().unwrap(), 2);
+
+            Ok::<(), ()>(())
+        }))
+        .unwrap();
+    }
+}
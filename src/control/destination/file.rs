@@ -0,0 +1,645 @@
+//! Resolves destinations from a local file.
+//!
+//! This allows the proxy to discover endpoints without a Destination service,
+//! for instance when it runs outside of Kubernetes or in tests. The file is a
+//! JSON document that maps authorities to their endpoints. YAML is not
+//! supported, since the proxy has no YAML parser; YAML files must be converted
+//! to JSON (e.g. with `yq`) before they are loaded.
+//!
+//! ```json
+//! {
+//!   "web.default.svc.cluster.local:8080": [
+//!     {
+//!       "addr": "10.1.2.3:8080",
+//!       "weight": 1.5,
+//!       "labels": { "pod": "web-0" },
+//!       "identity": "web.default.serviceaccount.identity.linkerd.cluster.local",
+//!       "protocol_hint": "h2"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Only an endpoint's `addr` is required. Weights default to 1.0.
+//!
+//! The file is polled for changes, and resolutions are updated as endpoints are
+//! added, changed, or removed. If the file cannot be read or is invalid, the
+//! previously loaded destinations continue to be used.
+//!
+//! Authorities that are named in the file are resolved from the file; all
+//! others are resolved by the inner resolver. Resolutions switch between the
+//! file and the inner resolver as authorities are added to and removed from
+//! the file.
+
+use futures::{Async, Poll, Stream};
+use futures_watch::Watch;
+use indexmap::{IndexMap, IndexSet};
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use super::{Endpoints, Metadata, ProtocolHint};
+use fs_watch;
+use identity;
+use json;
+use never::Never;
+use proxy::resolve::{self, Resolution as _, Update};
+use NameAddr;
+
+pub use fs_watch::Config;
+
+/// Resolves authorities named in the file, delegating all others to an
+/// `R`-typed resolver.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    table: Option<Watch<Arc<Table>>>,
+}
+
+pub struct Resolution<R: resolve::Resolve<NameAddr>> {
+    authority: NameAddr,
+    key: String,
+    resolve: R,
+    table: Option<Watch<Arc<Table>>>,
+    state: State<R::Resolution>,
+
+    /// The endpoints added by the inner resolution, which are removed if the
+    /// authority is added to the file.
+    inner_endpoints: IndexSet<SocketAddr>,
+
+    /// Updates that are sent before the new state's updates when the
+    /// resolution switches between the file and the inner resolver.
+    updates: VecDeque<Update<Metadata>>,
+}
+
+enum State<R> {
+    Inner(R),
+    File(FileResolution),
+}
+
+/// A resolution of an authority that is named in the file.
+#[derive(Debug)]
+pub struct FileResolution {
+    authority: String,
+    table: Watch<Arc<Table>>,
+    endpoints: Endpoints,
+    updates: VecDeque<Update<Metadata>>,
+}
+
+/// Reloads the file when it changes.
+pub type Daemon = fs_watch::Daemon<Table>;
+
+/// Maps normalized authorities to their endpoints.
+type Table = IndexMap<String, Endpoints>;
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Returns a `Resolve` and, if a file is configured, a `Daemon` that must
+    /// be spawned to reload it.
+    ///
+    /// The file is loaded before this returns, so that its destinations may be
+    /// resolved immediately.
+    pub fn new(inner: R, config: Option<Config>) -> (Self, Option<Daemon>) {
+        let config = match config {
+            Some(c) => c,
+            None => return (Resolve { inner, table: None }, None),
+        };
+
+        let (watch, daemon) = fs_watch::watch(config, parse);
+        let resolve = Resolve {
+            inner,
+            table: Some(watch),
+        };
+        (resolve, Some(daemon))
+    }
+}
+
+impl<R> resolve::Resolve<NameAddr> for Resolve<R>
+where
+    R: resolve::Resolve<NameAddr, Endpoint = Metadata> + Clone,
+{
+    type Endpoint = Metadata;
+    type Resolution = Resolution<R>;
+
+    fn resolve(&self, authority: &NameAddr) -> Self::Resolution {
+        let key = normalize(authority);
+        let in_file = self
+            .table
+            .as_ref()
+            .map(|t| t.borrow().contains_key(&key))
+            .unwrap_or(false);
+        let state = match self.table {
+            Some(ref table) if in_file => {
+                debug!("resolving {} from file", authority);
+                State::File(FileResolution::new(key.clone(), table.clone()))
+            }
+            _ => State::Inner(self.inner.resolve(authority)),
+        };
+
+        Resolution {
+            authority: authority.clone(),
+            key,
+            resolve: self.inner.clone(),
+            table: self.table.clone(),
+            state,
+            inner_endpoints: IndexSet::new(),
+            updates: VecDeque::new(),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R>
+where
+    R: resolve::Resolve<NameAddr, Endpoint = Metadata>,
+{
+    /// Switches between the file and the inner resolver if the authority has
+    /// been added to or removed from the file.
+    fn poll_table(&mut self) {
+        let in_file = match self.table {
+            Some(ref mut table) => {
+                // Poll until the watch is not ready, so that the task is
+                // notified of the next change.
+                let mut changed = false;
+                while let Ok(Async::Ready(Some(()))) = table.poll() {
+                    changed = true;
+                }
+                if !changed {
+                    return;
+                }
+                table.borrow().contains_key(&self.key)
+            }
+            None => return,
+        };
+
+        let state = match self.state {
+            State::Inner(_) if in_file => {
+                debug!("resolving {} from file", self.authority);
+                for addr in self.inner_endpoints.drain(..) {
+                    self.updates.push_back(Update::Remove(addr));
+                }
+                let table = self.table.as_ref().expect("table must be set").clone();
+                State::File(FileResolution::new(self.key.clone(), table))
+            }
+            State::File(ref mut file) if !in_file => {
+                debug!("{} was removed from file", self.authority);
+                // Remove the file's endpoints.
+                file.update();
+                self.updates.extend(file.updates.drain(..));
+                State::Inner(self.resolve.resolve(&self.authority))
+            }
+            _ => return,
+        };
+        self.state = state;
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolve<NameAddr, Endpoint = Metadata>,
+{
+    type Endpoint = Metadata;
+    type Error = <R::Resolution as resolve::Resolution>::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        self.poll_table();
+
+        if let Some(update) = self.updates.pop_front() {
+            return Ok(Async::Ready(update));
+        }
+
+        match self.state {
+            State::Inner(ref mut r) => {
+                let update = try_ready!(r.poll());
+                match update {
+                    Update::Add(addr, _) => {
+                        self.inner_endpoints.insert(addr);
+                    }
+                    Update::Remove(ref addr) => {
+                        self.inner_endpoints.remove(addr);
+                    }
+                    Update::NoEndpoints => {}
+                }
+                Ok(Async::Ready(update))
+            }
+            State::File(ref mut r) => r.poll().map_err(|n| match n {}),
+        }
+    }
+}
+
+impl<R> fmt::Debug for Resolution<R>
+where
+    R: resolve::Resolve<NameAddr>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Inner(_) => "inner",
+            State::File(_) => "file",
+        };
+        f.debug_struct("Resolution")
+            .field("authority", &self.authority)
+            .field("state", &state)
+            .finish()
+    }
+}
+
+// === impl FileResolution ===
+
+impl FileResolution {
+    fn new(authority: String, table: Watch<Arc<Table>>) -> Self {
+        let mut resolution = Self {
+            authority,
+            table,
+            endpoints: Endpoints::new(),
+            updates: VecDeque::new(),
+        };
+        resolution.update();
+        if resolution.endpoints.is_empty() {
+            resolution.updates.push_back(Update::NoEndpoints);
+        }
+        resolution
+    }
+
+    /// Queues the updates that are needed to reflect the current table.
+    fn update(&mut self) {
+        let endpoints = {
+            let table = self.table.borrow();
+            table.get(&self.authority).cloned().unwrap_or_default()
+        };
+
+        super::update_endpoints(&mut self.endpoints, endpoints, &mut self.updates);
+    }
+}
+
+impl resolve::Resolution for FileResolution {
+    type Endpoint = Metadata;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        loop {
+            if let Some(update) = self.updates.pop_front() {
+                return Ok(Async::Ready(update));
+            }
+
+            match self.table.poll() {
+                Ok(Async::Ready(Some(()))) => self.update(),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // If the daemon has stopped, the endpoints will not change again.
+                Ok(Async::Ready(None)) | Err(_) => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// Authorities are compared case-insensitively and without trailing dots.
+fn normalize(authority: &NameAddr) -> String {
+    format!(
+        "{}:{}",
+        authority.name().without_trailing_dot().to_ascii_lowercase(),
+        authority.port()
+    )
+}
+
+fn parse(contents: &str) -> Result<Table, String> {
+    let doc = json::parse(contents).map_err(|e| e.to_string())?;
+    parse_table(&doc)
+}
+
+/// Parses an object that maps authorities to their endpoints.
+pub(super) fn parse_table(doc: &json::Value) -> Result<Table, String> {
+    let authorities = doc
+        .as_object()
+        .ok_or_else(|| "expected an object of authorities".to_owned())?;
+
+    let mut table = Table::with_capacity(authorities.len());
+    for (authority, endpoints) in authorities {
+        let key = NameAddr::from_str(authority)
+            .map(|a| normalize(&a))
+            .map_err(|_| format!("invalid authority: {}", authority))?;
+        let endpoints = endpoints
+            .as_array()
+            .ok_or_else(|| format!("{}: expected a list of endpoints", authority))?;
+
+        let mut set = Endpoints::with_capacity(endpoints.len());
+        for endpoint in endpoints {
+            let (addr, meta) =
+                parse_endpoint(endpoint).map_err(|e| format!("{}: {}", authority, e))?;
+            set.insert(addr, meta);
+        }
+        table.insert(key, set);
+    }
+
+    Ok(table)
+}
+
+fn parse_endpoint(endpoint: &json::Value) -> Result<(SocketAddr, Metadata), String> {
+    let addr = endpoint
+        .get("addr")
+        .and_then(json::Value::as_str)
+        .ok_or_else(|| "endpoint must have an addr".to_owned())?;
+    let addr = addr
+        .parse::<SocketAddr>()
+        .map_err(|_| format!("invalid addr: {}", addr))?;
+
+    // Weights are scaled by 10,000, as they are by the Destination service.
+    let weight = match endpoint.get("weight") {
+        None => 10_000,
+        Some(w) => w
+            .as_f64()
+            .filter(|w| *w >= 0.0 && *w * 10_000.0 <= f64::from(::std::u32::MAX))
+            .map(|w| (w * 10_000.0).round() as u32)
+            .ok_or_else(|| format!("{}: invalid weight", addr))?,
+    };
+
+    let labels = match endpoint.get("labels") {
+        None => IndexMap::new(),
+        Some(labels) => {
+            let labels = labels
+                .as_object()
+                .ok_or_else(|| format!("{}: labels must be an object", addr))?;
+            let mut sorted = Vec::with_capacity(labels.len());
+            for (k, v) in labels {
+                let v = v
+                    .as_str()
+                    .ok_or_else(|| format!("{}: label {} must be a string", addr, k))?;
+                sorted.push((k.clone(), v.to_owned()));
+            }
+            sorted.sort_by(|(k0, _), (k1, _)| k0.cmp(k1));
+            sorted.into_iter().collect()
+        }
+    };
+
+    let identity = match endpoint.get("identity") {
+        None => None,
+        Some(id) => {
+            let name = id
+                .as_str()
+                .and_then(|id| identity::Name::from_hostname(id.as_bytes()).ok())
+                .ok_or_else(|| format!("{}: invalid identity", addr))?;
+            Some(name)
+        }
+    };
+
+    let protocol_hint = match endpoint.get("protocol_hint").map(json::Value::as_str) {
+        None => ProtocolHint::Unknown,
+        Some(Some("h2")) => ProtocolHint::Http2,
+        Some(_) => return Err(format!("{}: protocol_hint must be \"h2\"", addr)),
+    };
+
+    Ok((addr, Metadata::new(labels, protocol_hint, identity, weight)))
+}
+
+/// Describes endpoints in the format of the file.
+pub(super) fn format_endpoints(endpoints: &Endpoints) -> json::Value {
+    let endpoints = endpoints.iter().map(|(addr, meta)| {
+        let mut fields = vec![
+            ("addr", json::Value::String(addr.to_string())),
+            (
+                "weight",
+                json::Value::Number(f64::from(meta.weight) / 10_000.0),
+            ),
+        ];
+        if !meta.labels.is_empty() {
+            let labels = meta
+                .labels
+                .iter()
+                .map(|(k, v)| (k.as_str(), json::Value::String(v.clone())));
+            fields.push(("labels", json::object(labels)));
+        }
+        if let Some(ref id) = meta.identity {
+            fields.push(("identity", json::Value::String(id.as_ref().to_owned())));
+        }
+        if meta.protocol_hint == ProtocolHint::Http2 {
+            fields.push(("protocol_hint", json::Value::String("h2".to_owned())));
+        }
+        json::object(fields)
+    });
+    json::Value::Array(endpoints.collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Future};
+    use proxy::resolve::Resolution as _;
+
+    const FILE: &str = r#"{
+        "web.default.svc.cluster.local:8080": [
+            {
+                "addr": "10.1.2.3:8080",
+                "weight": 1.5,
+                "labels": { "zone": "b", "pod": "web-0" },
+                "identity": "web.default.serviceaccount.identity.linkerd.cluster.local",
+                "protocol_hint": "h2"
+            },
+            { "addr": "10.1.2.4:8080" }
+        ],
+        "empty.default.svc.cluster.local:80": []
+    }"#;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn web() -> String {
+        normalize(&NameAddr::from_str("web.default.svc.cluster.local.:8080").unwrap())
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let table = parse(FILE).expect("file must parse");
+        assert_eq!(table.len(), 2);
+
+        let web = &table[&web()];
+        let meta = &web[&addr("10.1.2.3:8080")];
+        assert_eq!(meta.weight, 15_000);
+        assert_eq!(
+            meta.labels().iter().collect::<Vec<_>>(),
+            vec![
+                (&"pod".to_owned(), &"web-0".to_owned()),
+                (&"zone".to_owned(), &"b".to_owned())
+            ]
+        );
+        assert_eq!(meta.protocol_hint(), ProtocolHint::Http2);
+        assert_eq!(
+            meta.identity().map(|id| id.as_ref()),
+            Some("web.default.serviceaccount.identity.linkerd.cluster.local")
+        );
+        assert_eq!(web[&addr("10.1.2.4:8080")], Metadata::empty());
+    }
+
+    #[test]
+    fn formats_endpoints() {
+        let table = parse(FILE).unwrap();
+        for endpoints in table.values() {
+            let formatted = format_endpoints(endpoints).to_string();
+            let doc = format!(r#"{{"web:80": {}}}"#, formatted);
+            assert_eq!(&parse(&doc).unwrap()["web:80"], endpoints);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        for file in &[
+            "[]",
+            r#"{"web": []}"#,
+            r#"{"web:80": {}}"#,
+            r#"{"web:80": [{}]}"#,
+            r#"{"web:80": [{"addr": "web:80"}]}"#,
+            r#"{"web:80": [{"addr": "10.1.2.3:80", "weight": -1}]}"#,
+            r#"{"web:80": [{"addr": "10.1.2.3:80", "labels": {"a": 1}}]}"#,
+            r#"{"web:80": [{"addr": "10.1.2.3:80", "protocol_hint": "h3"}]}"#,
+        ] {
+            assert!(parse(file).is_err(), "{} must not parse", file);
+        }
+    }
+
+    #[test]
+    fn updates_resolutions() {
+        let (watch, mut store) = Watch::new(Arc::new(parse(FILE).unwrap()));
+        let mut resolution = FileResolution::new(web(), watch.clone());
+        let mut empty = FileResolution::new(
+            normalize(&NameAddr::from_str("empty.default.svc.cluster.local:80").unwrap()),
+            watch,
+        );
+
+        future::lazy(move || {
+            let mut updates = Vec::new();
+            while let Ok(Async::Ready(up)) = resolution.poll() {
+                updates.push(up);
+            }
+            match updates.as_slice() {
+                [Update::Add(a, _), Update::Add(b, _)] => {
+                    assert_eq!((*a, *b), (addr("10.1.2.3:8080"), addr("10.1.2.4:8080")))
+                }
+                ups => panic!("unexpected updates: {:?}", ups),
+            }
+            match empty.poll() {
+                Ok(Async::Ready(Update::NoEndpoints)) => {}
+                up => panic!("unexpected update: {:?}", up),
+            }
+
+            // Change one endpoint's weight and remove the other.
+            let changed = FILE.replace("1.5", "2").replace(
+                r#"{ "addr": "10.1.2.4:8080" }"#,
+                r#"{ "addr": "10.1.2.5:8080" }"#,
+            );
+            store.store(Arc::new(parse(&changed).unwrap())).unwrap();
+
+            let mut updates = Vec::new();
+            while let Ok(Async::Ready(up)) = resolution.poll() {
+                updates.push(up);
+            }
+            match updates.as_slice() {
+                [Update::Remove(removed), Update::Add(a, meta), Update::Add(b, _)] => {
+                    assert_eq!(*removed, addr("10.1.2.4:8080"));
+                    assert_eq!(*a, addr("10.1.2.3:8080"));
+                    assert_eq!(meta.weight, 20_000);
+                    assert_eq!(*b, addr("10.1.2.5:8080"));
+                }
+                ups => panic!("unexpected updates: {:?}", ups),
+            }
+            assert!(empty.poll().unwrap().is_not_ready());
+
+            // Remove the authority.
+            store.store(Arc::new(Table::new())).unwrap();
+            let mut updates = Vec::new();
+            while let Ok(Async::Ready(up)) = resolution.poll() {
+                updates.push(up);
+            }
+            match updates.as_slice() {
+                [Update::NoEndpoints, Update::Remove(_), Update::Remove(_)] => {}
+                ups => panic!("unexpected updates: {:?}", ups),
+            }
+
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[derive(Clone)]
+    struct Inner;
+
+    impl resolve::Resolve<NameAddr> for Inner {
+        type Endpoint = Metadata;
+        type Resolution = InnerResolution;
+
+        fn resolve(&self, _: &NameAddr) -> InnerResolution {
+            InnerResolution(Some(addr("10.9.9.9:80")))
+        }
+    }
+
+    struct InnerResolution(Option<SocketAddr>);
+
+    impl resolve::Resolution for InnerResolution {
+        type Endpoint = Metadata;
+        type Error = Never;
+
+        fn poll(&mut self) -> Poll<Update<Metadata>, Never> {
+            Ok(match self.0.take() {
+                Some(a) => Async::Ready(Update::Add(a, Metadata::empty())),
+                None => Async::NotReady,
+            })
+        }
+    }
+
+    #[test]
+    fn switches_between_file_and_inner() {
+        use proxy::resolve::Resolve as _;
+
+        let (watch, mut store) = Watch::new(Arc::new(Table::new()));
+        let resolve = Resolve {
+            inner: Inner,
+            table: Some(watch),
+        };
+        let mut resolution =
+            resolve.resolve(&NameAddr::from_str("web.default.svc.cluster.local:8080").unwrap());
+
+        future::lazy(move || {
+            let mut poll_all = || {
+                let mut updates = Vec::new();
+                while let Ok(Async::Ready(up)) = resolution.poll() {
+                    updates.push(match up {
+                        Update::Add(a, _) => format!("add {}", a),
+                        Update::Remove(a) => format!("remove {}", a),
+                        Update::NoEndpoints => "none".to_owned(),
+                    });
+                }
+                updates
+            };
+
+            assert_eq!(poll_all(), vec!["add 10.9.9.9:80"]);
+
+            // The authority is added to the file.
+            store.store(Arc::new(parse(FILE).unwrap())).unwrap();
+            assert_eq!(
+                poll_all(),
+                vec![
+                    "remove 10.9.9.9:80",
+                    "add 10.1.2.3:8080",
+                    "add 10.1.2.4:8080",
+                ]
+            );
+
+            // The authority is removed from the file.
+            store.store(Arc::new(Table::new())).unwrap();
+            assert_eq!(
+                poll_all(),
+                vec![
+                    "none",
+                    "remove 10.1.2.3:8080",
+                    "remove 10.1.2.4:8080",
+                    "add 10.9.9.9:80",
+                ]
+            );
+
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
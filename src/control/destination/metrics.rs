@@ -0,0 +1,436 @@
+use indexmap::IndexMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge};
+use proxy::resolve::Update;
+
+metrics! {
+    destination_resolutions_active: Gauge {
+        "Number of resolutions that are currently active"
+    },
+    destination_resolutions_rejected_total: Counter {
+        "Total count of resolutions refused because too many resolutions were active"
+    },
+    destination_endpoints: Gauge {
+        "Number of endpoints in each destination's active resolutions"
+    },
+    destination_endpoints_truncated_total: Counter {
+        "Total count of endpoints withheld from resolutions that had too many endpoints"
+    },
+    destination_updates_total: Counter {
+        "Total count of updates to resolutions, by type of update"
+    },
+    destination_reconnects_total: Counter {
+        "Total count of Destination service queries that were reconnected after failing or ending"
+    },
+    destination_stale_resolutions: Gauge {
+        "Number of resolutions using snapshotted endpoints that the Destination service has not yet confirmed"
+    },
+    destination_stale_profiles: Gauge {
+        "Number of profiles using snapshotted routes that the Destination service has not yet confirmed"
+    },
+    destination_profile_streams: Gauge {
+        "Number of profile streams in each state"
+    }
+}
+
+/// Records the state of discovery.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<Metrics>>);
+
+/// Implements `FmtMetrics` to render prometheus-formatted discovery metrics.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<Metrics>>);
+
+/// Counts a resolution as active, and records its endpoints, until it is
+/// dropped.
+#[derive(Debug)]
+pub struct ResolutionMetrics {
+    metrics: Arc<Mutex<Metrics>>,
+    dst: String,
+    endpoints: usize,
+}
+
+/// Counts a resolution or profile as stale until it is dropped.
+#[derive(Debug)]
+pub struct Stale {
+    metrics: Arc<Mutex<Metrics>>,
+    kind: Kind,
+}
+
+/// Records the state of a profile stream until it is dropped.
+#[derive(Debug)]
+pub struct ProfileStream {
+    metrics: Arc<Mutex<Metrics>>,
+    state: ProfileState,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProfileState {
+    /// The stream is not connected to the Destination service.
+    Disconnected,
+    /// The stream is waiting for the Destination service to respond.
+    Waiting,
+    /// The stream is receiving profiles.
+    Streaming,
+    /// The stream is waiting to reconnect after failing or ending.
+    Backoff,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Kind {
+    Resolution,
+    Profile,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    resolutions_active: Gauge,
+    resolutions_rejected: Counter,
+    /// The endpoints of each destination's active resolutions.
+    endpoints: IndexMap<String, DstEndpoints>,
+    endpoints_truncated: Counter,
+    adds: Counter,
+    removes: Counter,
+    no_endpoints: Counter,
+    reconnects: Counter,
+    stale_resolutions: Gauge,
+    stale_profiles: Gauge,
+    profile_streams: ProfileStreams,
+}
+
+#[derive(Debug, Default)]
+struct DstEndpoints {
+    resolutions: usize,
+    endpoints: Gauge,
+}
+
+#[derive(Debug, Default)]
+struct ProfileStreams {
+    disconnected: Gauge,
+    waiting: Gauge,
+    streaming: Gauge,
+    backoff: Gauge,
+}
+
+struct Dst<'a>(&'a str);
+
+struct UpdateLabel(&'static str);
+
+pub fn new() -> (Registry, Report) {
+    let inner = Arc::new(Mutex::new(Metrics::default()));
+    (Registry(inner.clone()), Report(inner))
+}
+
+// === impl Registry ===
+
+impl Registry {
+    pub(super) fn resolution(&self, dst: String) -> ResolutionMetrics {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.resolutions_active.incr();
+            metrics
+                .endpoints
+                .entry(dst.clone())
+                .or_insert_with(DstEndpoints::default)
+                .resolutions += 1;
+        }
+        ResolutionMetrics {
+            metrics: self.0.clone(),
+            dst,
+            endpoints: 0,
+        }
+    }
+
+    pub(super) fn resolution_rejected(&self) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.resolutions_rejected.incr();
+        }
+    }
+
+    pub(super) fn endpoint_truncated(&self) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.endpoints_truncated.incr();
+        }
+    }
+
+    pub(super) fn reconnected(&self) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics.reconnects.incr();
+        }
+    }
+
+    pub(super) fn stale_resolution(&self) -> Stale {
+        Stale::new(self.0.clone(), Kind::Resolution)
+    }
+
+    pub(super) fn stale_profile(&self) -> Stale {
+        Stale::new(self.0.clone(), Kind::Profile)
+    }
+
+    /// Records a new profile stream, which is initially disconnected.
+    pub fn profile_stream(&self) -> ProfileStream {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics
+                .profile_streams
+                .gauge(ProfileState::Disconnected)
+                .incr();
+        }
+        ProfileStream {
+            metrics: self.0.clone(),
+            state: ProfileState::Disconnected,
+        }
+    }
+}
+
+// === impl ResolutionMetrics ===
+
+impl ResolutionMetrics {
+    /// Records an update, with the resolution's resulting number of endpoints.
+    pub(super) fn update<T>(&mut self, update: &Update<T>, endpoints: usize) {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            match update {
+                Update::Add(..) => metrics.adds.incr(),
+                Update::Remove(..) => metrics.removes.incr(),
+                Update::NoEndpoints => metrics.no_endpoints.incr(),
+            }
+
+            if let Some(dst) = metrics.endpoints.get_mut(&self.dst) {
+                let gauge: u64 = dst.endpoints.into();
+                dst.endpoints = (gauge + endpoints as u64 - self.endpoints as u64).into();
+            }
+        }
+        self.endpoints = endpoints;
+    }
+}
+
+impl Drop for ResolutionMetrics {
+    fn drop(&mut self) {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.resolutions_active.decr();
+
+            let remove = match metrics.endpoints.get_mut(&self.dst) {
+                Some(dst) => {
+                    dst.resolutions -= 1;
+                    let gauge: u64 = dst.endpoints.into();
+                    dst.endpoints = (gauge - self.endpoints as u64).into();
+                    dst.resolutions == 0
+                }
+                None => false,
+            };
+            if remove {
+                metrics.endpoints.swap_remove(&self.dst);
+            }
+        }
+    }
+}
+
+// === impl Stale ===
+
+impl Stale {
+    fn new(metrics: Arc<Mutex<Metrics>>, kind: Kind) -> Self {
+        if let Ok(mut m) = metrics.lock() {
+            m.stale(kind).incr();
+        }
+        Stale { metrics, kind }
+    }
+}
+
+impl Drop for Stale {
+    fn drop(&mut self) {
+        if let Ok(mut m) = self.metrics.lock() {
+            m.stale(self.kind).decr();
+        }
+    }
+}
+
+// === impl ProfileStream ===
+
+impl ProfileStream {
+    pub fn set(&mut self, state: ProfileState) {
+        if state == self.state {
+            return;
+        }
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.profile_streams.gauge(self.state).decr();
+            metrics.profile_streams.gauge(state).incr();
+        }
+        self.state = state;
+    }
+}
+
+impl Drop for ProfileStream {
+    fn drop(&mut self) {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            metrics.profile_streams.gauge(self.state).decr();
+        }
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn stale(&mut self, kind: Kind) -> &mut Gauge {
+        match kind {
+            Kind::Resolution => &mut self.stale_resolutions,
+            Kind::Profile => &mut self.stale_profiles,
+        }
+    }
+}
+
+// === impl ProfileStreams ===
+
+impl ProfileStreams {
+    fn gauge(&mut self, state: ProfileState) -> &mut Gauge {
+        match state {
+            ProfileState::Disconnected => &mut self.disconnected,
+            ProfileState::Waiting => &mut self.waiting,
+            ProfileState::Streaming => &mut self.streaming,
+            ProfileState::Backoff => &mut self.backoff,
+        }
+    }
+}
+
+// === impl Report ===
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let metrics = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        destination_resolutions_active.fmt_help(f)?;
+        metrics
+            .resolutions_active
+            .fmt_metric(f, destination_resolutions_active.name)?;
+
+        destination_resolutions_rejected_total.fmt_help(f)?;
+        metrics
+            .resolutions_rejected
+            .fmt_metric(f, destination_resolutions_rejected_total.name)?;
+
+        if !metrics.endpoints.is_empty() {
+            destination_endpoints.fmt_help(f)?;
+            for (dst, m) in &metrics.endpoints {
+                m.endpoints
+                    .fmt_metric_labeled(f, destination_endpoints.name, Dst(dst))?;
+            }
+        }
+
+        destination_endpoints_truncated_total.fmt_help(f)?;
+        metrics
+            .endpoints_truncated
+            .fmt_metric(f, destination_endpoints_truncated_total.name)?;
+
+        destination_updates_total.fmt_help(f)?;
+        for (label, counter) in &[
+            ("add", metrics.adds),
+            ("remove", metrics.removes),
+            ("no_endpoints", metrics.no_endpoints),
+        ] {
+            counter.fmt_metric_labeled(f, destination_updates_total.name, UpdateLabel(label))?;
+        }
+
+        destination_reconnects_total.fmt_help(f)?;
+        metrics
+            .reconnects
+            .fmt_metric(f, destination_reconnects_total.name)?;
+
+        destination_stale_resolutions.fmt_help(f)?;
+        metrics
+            .stale_resolutions
+            .fmt_metric(f, destination_stale_resolutions.name)?;
+
+        destination_stale_profiles.fmt_help(f)?;
+        metrics
+            .stale_profiles
+            .fmt_metric(f, destination_stale_profiles.name)?;
+
+        destination_profile_streams.fmt_help(f)?;
+        let streams = &metrics.profile_streams;
+        for (state, gauge) in &[
+            (ProfileState::Disconnected, streams.disconnected),
+            (ProfileState::Waiting, streams.waiting),
+            (ProfileState::Streaming, streams.streaming),
+            (ProfileState::Backoff, streams.backoff),
+        ] {
+            gauge.fmt_metric_labeled(f, destination_profile_streams.name, *state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> FmtLabels for Dst<'a> {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dst=\"{}\"", self.0)
+    }
+}
+
+impl FmtLabels for UpdateLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "update=\"{}\"", self.0)
+    }
+}
+
+impl FmtLabels for ProfileState {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            ProfileState::Disconnected => "disconnected",
+            ProfileState::Waiting => "waiting",
+            ProfileState::Streaming => "streaming",
+            ProfileState::Backoff => "backoff",
+        };
+        write!(f, "state=\"{}\"", state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(registry: &Registry, dst: &str) -> Option<u64> {
+        let metrics = registry.0.lock().unwrap();
+        metrics.endpoints.get(dst).map(|m| m.endpoints.into())
+    }
+
+    #[test]
+    fn records_endpoints_by_destination() {
+        let (registry, _) = new();
+        let mut a = registry.resolution("web:80".to_owned());
+        let mut b = registry.resolution("web:80".to_owned());
+
+        a.update(&Update::<()>::NoEndpoints, 2);
+        b.update(&Update::<()>::NoEndpoints, 3);
+        assert_eq!(endpoints(&registry, "web:80"), Some(5));
+        a.update(&Update::<()>::NoEndpoints, 1);
+        assert_eq!(endpoints(&registry, "web:80"), Some(4));
+
+        drop(a);
+        assert_eq!(endpoints(&registry, "web:80"), Some(3));
+        drop(b);
+        assert_eq!(endpoints(&registry, "web:80"), None);
+
+        let metrics = registry.0.lock().unwrap();
+        assert_eq!(metrics.no_endpoints, 3u64.into());
+        assert_eq!(metrics.resolutions_active, 0u64.into());
+    }
+
+    #[test]
+    fn records_profile_stream_states() {
+        let (registry, _) = new();
+        let mut stream = registry.profile_stream();
+        stream.set(ProfileState::Waiting);
+        stream.set(ProfileState::Streaming);
+        {
+            let metrics = registry.0.lock().unwrap();
+            assert_eq!(metrics.profile_streams.disconnected, 0u64.into());
+            assert_eq!(metrics.profile_streams.streaming, 1u64.into());
+        }
+
+        drop(stream);
+        let metrics = registry.0.lock().unwrap();
+        assert_eq!(metrics.profile_streams.streaming, 0u64.into());
+    }
+}
@@ -6,32 +6,48 @@
 //! that the thread responsible for proxying data need not also do this administrative
 //! work of communicating with the control plane.
 //!
-//! The number of active resolutions is not currently bounded by this module. Instead, we
-//! trust that callers of `Resolver` enforce such a constraint (for example, via
+//! The number of active resolutions is bounded by `Limits::max_resolutions`. Once the
+//! limit is reached, further resolutions are refused and resolve to no endpoints, so
+//! that requests are routed to their original destinations. A refused resolution
+//! periodically tries again, so that it is started once other resolutions end. Callers
+//! of `Resolver`
+//! should also constrain the number of resolutions (for example, via
 //! `linkerd2_proxy_router`'s LRU cache). Additionally, users of this module must ensure
 //! they consume resolutions as they are sent so that the response channels don't grow
 //! without bounds.
 //!
-//! Furthermore, there are not currently any bounds on the number of endpoints that may be
-//! returned for a single resolution. It is expected that the Destination service enforce
-//! some reasonable upper bounds.
+//! The number of endpoints in each resolution is bounded by `Limits::max_endpoints`.
+//! When the Destination service returns more endpoints than this, a deterministic
+//! subset of them is used (see the `subset` module).
 //!
-//! ## TODO
+//! Both limits are reported through metrics when they are exceeded. The metrics also
+//! describe active resolutions, their endpoints and updates, and the state of profile
+//! streams (see the `metrics` module).
 //!
-//! - Given that the underlying gRPC client has some max number of concurrent streams, we
-//!   actually do have an upper bound on concurrent resolutions. This needs to be made
-//!   more explicit.
-//! - We need some means to limit the number of endpoints that can be returned for a
-//!   single resolution so that `control::Cache` is not effectively unbounded.
+//! Recent resolutions may be persisted so that they survive restarts during
+//! control plane outages (see the `snapshot` module).
+//!
+//! Destinations may also be resolved from a local file, without the Destination
+//! service (see the `file` module), or via DNS when the Destination service has no
+//! endpoints for them (see the `dns_fallback` module).
 use indexmap::IndexMap;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tower_grpc::{generic::client::GrpcService, Body, BoxBody};
 
 use dns;
 use identity;
 use proxy::resolve::{Resolve, Update};
 
+pub mod dns_fallback;
+pub mod file;
+pub mod metrics;
 mod resolution;
+pub mod snapshot;
+mod subset;
 pub use self::resolution::Resolution;
 use proxy::http::balance::Weight;
 use NameAddr;
@@ -41,6 +57,24 @@ use NameAddr;
 pub struct Resolver<T> {
     client: Option<Client<T>>,
     suffixes: Arc<Vec<dns::Suffix>>,
+    limits: Arc<Limits>,
+    active: Arc<AtomicUsize>,
+    metrics: metrics::Registry,
+    snapshot: Option<snapshot::Snapshot>,
+}
+
+/// Bounds the resources used by resolutions.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The maximum number of resolutions that may be active at once.
+    pub max_resolutions: usize,
+
+    /// The maximum number of endpoints in a resolution.
+    pub max_endpoints: usize,
+
+    /// Identifies this proxy when choosing a subset of a resolution's
+    /// endpoints, so that different proxies choose different subsets.
+    pub subset_key: String,
 }
 
 /// Metadata describing an endpoint.
@@ -77,6 +111,12 @@ pub enum ProtocolHint {
     Http2,
 }
 
+/// The endpoints of a resolution, by address.
+type Endpoints = IndexMap<SocketAddr, Metadata>;
+
+/// How often a refused resolution tries to start.
+const RETRY_REFUSED_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 struct Client<T> {
     client: T,
@@ -93,7 +133,14 @@ where
     T::Future: Send,
 {
     /// Returns a `Resolver` for requesting destination resolutions.
-    pub fn new(client: Option<T>, suffixes: Vec<dns::Suffix>, proxy_id: String) -> Resolver<T> {
+    pub fn new(
+        client: Option<T>,
+        suffixes: Vec<dns::Suffix>,
+        proxy_id: String,
+        limits: Limits,
+        metrics: metrics::Registry,
+        snapshot: Option<snapshot::Snapshot>,
+    ) -> Resolver<T> {
         let client = client.map(|client| Client {
             context_token: Arc::new(proxy_id),
             client,
@@ -101,6 +148,10 @@ where
         Resolver {
             suffixes: Arc::new(suffixes),
             client,
+            limits: Arc::new(limits),
+            active: Arc::new(AtomicUsize::new(0)),
+            metrics,
+            snapshot,
         }
     }
 }
@@ -120,8 +171,21 @@ where
         trace!("resolve; authority={:?}", authority);
 
         if self.suffixes.iter().any(|s| s.contains(authority.name())) {
-            if let Some(client) = self.client.as_ref().cloned() {
-                return Resolution::new(authority.clone(), client);
+            if self.client.is_some() {
+                if let Some(resolution) = self.start(authority) {
+                    return resolution;
+                }
+
+                warn!(
+                    "not resolving {}: {} resolutions are active",
+                    authority, self.limits.max_resolutions
+                );
+                self.metrics.resolution_rejected();
+                let resolver = self.clone();
+                let authority = authority.clone();
+                return Resolution::refused(RETRY_REFUSED_INTERVAL, move || {
+                    resolver.start(&authority)
+                });
             } else {
                 trace!("-> control plane client disabled");
             }
@@ -132,6 +196,59 @@ where
     }
 }
 
+impl<T> Resolver<T>
+where
+    T: GrpcService<BoxBody> + Clone + Send + 'static,
+    T::ResponseBody: Send,
+    <T::ResponseBody as Body>::Data: Send,
+    T::Future: Send,
+{
+    /// Starts a resolution, unless `Limits::max_resolutions` are active.
+    fn start(&self, authority: &NameAddr) -> Option<Resolution> {
+        let client = self.client.as_ref().cloned()?;
+        let active = self.active.fetch_add(1, Ordering::AcqRel);
+        if active >= self.limits.max_resolutions {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+
+        Some(Resolution::new(
+            authority.clone(),
+            client,
+            &self.limits,
+            self.active.clone(),
+            self.metrics.clone(),
+            self.snapshot.clone(),
+        ))
+    }
+}
+
+/// Queues the updates that change a resolution's `endpoints` to `next`.
+fn update_endpoints(
+    endpoints: &mut Endpoints,
+    next: Endpoints,
+    updates: &mut VecDeque<Update<Metadata>>,
+) {
+    if next.is_empty() && !endpoints.is_empty() {
+        trace!("no endpoints");
+        updates.push_back(Update::NoEndpoints);
+    }
+    for addr in endpoints.keys() {
+        if !next.contains_key(addr) {
+            trace!("remove {}", addr);
+            updates.push_back(Update::Remove(*addr));
+        }
+    }
+    for (addr, meta) in &next {
+        if endpoints.get(addr) != Some(meta) {
+            trace!("add {}", addr);
+            updates.push_back(Update::Add(*addr, meta.clone()));
+        }
+    }
+
+    *endpoints = next;
+}
+
 // ===== impl Metadata =====
 
 impl Metadata {
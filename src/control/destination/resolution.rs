@@ -1,8 +1,12 @@
 use futures::{future::Future, sync::mpsc, Async, Poll, Stream};
 use indexmap::{IndexMap, IndexSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, fmt, net::SocketAddr};
 
 use tokio;
+use tokio_timer::{clock, Delay};
 use tower_grpc::{self as grpc, generic::client::GrpcService, Body, BoxBody};
 
 use api::{
@@ -24,11 +28,35 @@ use never::Never;
 use proxy::resolve;
 use NameAddr;
 
-use super::Client;
+use super::{
+    metrics,
+    snapshot::{Recorder, Snapshot},
+    subset::Subset,
+    Client, Limits,
+};
 
 /// A resolution for a single authority.
 pub struct Resolution {
     rx: mpsc::UnboundedReceiver<Update<Metadata>>,
+    active: Option<Active>,
+    /// Set when the resolution was refused, so that it is started once
+    /// other resolutions end.
+    retry: Option<Retry>,
+}
+
+/// Periodically tries to start a refused resolution.
+struct Retry {
+    delay: Delay,
+    interval: Duration,
+    start: Box<FnMut() -> Option<Resolution> + Send>,
+}
+
+/// Counts a resolution as active until it is dropped.
+struct Active {
+    count: Arc<AtomicUsize>,
+    metrics: metrics::ResolutionMetrics,
+    /// The addresses of the endpoints that have been added to the resolution.
+    endpoints: IndexSet<SocketAddr>,
 }
 
 /// Drives the query associated with a `Resolution`.
@@ -50,14 +78,21 @@ where
 /// Updates the `Resolution` when the set of discovered endpoints changes.
 ///
 /// This is more than just the send end of the channel, as it also tracks the
-/// state necessary to reset stale endpoints when reconnecting.
+/// state necessary to reset stale endpoints when reconnecting, and limits the
+/// number of endpoints sent to the resolution.
 struct Updater {
     tx: mpsc::UnboundedSender<Update<Metadata>>,
-    /// All the endpoint addresses seen since the last reset.
-    seen: IndexSet<SocketAddr>,
+    /// All the endpoints seen since the last reset.
+    subset: Subset,
     /// Set to true on reconnects to indicate that previously seen addresses
     /// should be reset when the query reconnects.
     reset: bool,
+    metrics: metrics::Registry,
+    /// Records the endpoints, if snapshots are enabled.
+    recorder: Option<Recorder>,
+    /// Set while the resolution uses snapshotted endpoints that the
+    /// Destination service has not yet confirmed.
+    stale: Option<metrics::Stale>,
 }
 
 #[derive(Clone, Debug)]
@@ -72,8 +107,21 @@ impl resolve::Resolution for Resolution {
     type Error = Never;
 
     fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        let started = match self.retry {
+            Some(ref mut retry) => retry.poll(),
+            None => None,
+        };
+        if let Some(resolution) = started {
+            *self = resolution;
+        }
+
         match self.rx.poll() {
-            Ok(Async::Ready(Some(up))) => Ok(Async::Ready(up)),
+            Ok(Async::Ready(Some(up))) => {
+                if let Some(ref mut active) = self.active {
+                    active.record(&up);
+                }
+                Ok(Async::Ready(up))
+            }
             Ok(Async::NotReady) => Ok(Async::NotReady),
             Err(_) | Ok(Async::Ready(None)) => {
                 trace!("resolution daemon has terminated");
@@ -84,7 +132,18 @@ impl resolve::Resolution for Resolution {
 }
 
 impl Resolution {
-    pub(super) fn new<T>(auth: NameAddr, client: Client<T>) -> Self
+    /// Starts a resolution, which is counted in `active` until it is dropped.
+    ///
+    /// If the authority has snapshotted endpoints, they are used until the
+    /// Destination service returns endpoints.
+    pub(super) fn new<T>(
+        auth: NameAddr,
+        client: Client<T>,
+        limits: &Limits,
+        active: Arc<AtomicUsize>,
+        metrics: metrics::Registry,
+        snapshot: Option<Snapshot>,
+    ) -> Self
     where
         T: GrpcService<BoxBody> + Send + 'static,
         T::ResponseBody: Send,
@@ -92,16 +151,96 @@ impl Resolution {
         T::Future: Send,
     {
         let (tx, rx) = mpsc::unbounded();
-        let daemon = Daemon::new(auth.clone(), client, tx);
+        let active = Active {
+            count: active,
+            metrics: metrics.resolution(auth.to_string()),
+            endpoints: IndexSet::new(),
+        };
+        let subset = Subset::new(&limits.subset_key, limits.max_endpoints);
+        let mut updater = Updater::new(tx, subset, metrics);
+        if let Some(ref snapshot) = snapshot {
+            updater.seed(snapshot, &auth.to_string());
+        }
+        let daemon = Daemon::new(auth.clone(), client, updater);
         let daemon = logging::Section::Proxy.bg(LogCtx(auth)).future(daemon);
         tokio::spawn(Box::new(daemon));
-        Self { rx }
+        Self {
+            rx,
+            active: Some(active),
+            retry: None,
+        }
     }
 
     pub(super) fn none() -> Self {
         let (tx, rx) = mpsc::unbounded();
         let _ = tx.unbounded_send(Update::NoEndpoints);
-        Self { rx }
+        Self {
+            rx,
+            active: None,
+            retry: None,
+        }
+    }
+
+    /// A resolution that was refused, which has no endpoints until `start`
+    /// returns a resolution. `start` is called every `interval`.
+    pub(super) fn refused<F>(interval: Duration, start: F) -> Self
+    where
+        F: FnMut() -> Option<Resolution> + Send + 'static,
+    {
+        let retry = Retry {
+            delay: Delay::new(clock::now() + interval),
+            interval,
+            start: Box::new(start),
+        };
+        Self {
+            retry: Some(retry),
+            ..Self::none()
+        }
+    }
+}
+
+// ===== impl Retry =====
+
+impl Retry {
+    fn poll(&mut self) -> Option<Resolution> {
+        match self.delay.poll() {
+            Ok(Async::NotReady) => return None,
+            Ok(Async::Ready(())) => {}
+            Err(e) => debug!("resolution retry timer failed: {}", e),
+        }
+
+        let started = (self.start)();
+        if started.is_none() {
+            trace!("resolution still refused");
+            self.delay.reset(clock::now() + self.interval);
+            // Register interest in the new deadline.
+            let _ = self.delay.poll();
+        }
+        started
+    }
+}
+
+// ===== impl Active =====
+
+impl Active {
+    /// Records an update in the resolution's metrics.
+    fn record(&mut self, update: &Update<Metadata>) {
+        match update {
+            Update::Add(addr, _) => {
+                self.endpoints.insert(*addr);
+            }
+            Update::Remove(addr) => {
+                self.endpoints.remove(addr);
+            }
+            Update::NoEndpoints => {}
+        }
+        self.metrics.update(update, self.endpoints.len());
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
     }
 }
 
@@ -111,17 +250,13 @@ impl<T> Daemon<T>
 where
     T: GrpcService<BoxBody> + Send,
 {
-    fn new(
-        auth: NameAddr,
-        mut client: Client<T>,
-        tx: mpsc::UnboundedSender<Update<Metadata>>,
-    ) -> Self {
+    fn new(auth: NameAddr, mut client: Client<T>, updater: Updater) -> Self {
         let query = client.query(&auth, "connect");
         Self {
             query,
             auth,
             client,
-            updater: Updater::new(tx),
+            updater,
         }
     }
 }
@@ -190,7 +325,10 @@ where
                 },
                 Remote::NeedsReconnect => match self.client.query(&self.auth, "reconnect") {
                     Remote::NeedsReconnect => return Ok(Async::NotReady),
-                    query => query,
+                    query => {
+                        self.updater.metrics.reconnected();
+                        query
+                    }
                 },
             };
         }
@@ -230,17 +368,56 @@ where
 // ===== impl Updater =====
 
 impl Updater {
-    fn new(tx: mpsc::UnboundedSender<Update<Metadata>>) -> Self {
+    fn new(
+        tx: mpsc::UnboundedSender<Update<Metadata>>,
+        subset: Subset,
+        metrics: metrics::Registry,
+    ) -> Self {
         Self {
             tx,
-            seen: IndexSet::new(),
+            subset,
             reset: false,
+            metrics,
+            recorder: None,
+            stale: None,
+        }
+    }
+
+    /// Records the resolution's endpoints in the snapshot, and adds the
+    /// authority's snapshotted endpoints, if it has any. They are reset when
+    /// the Destination service first updates the resolution.
+    fn seed(&mut self, snapshot: &Snapshot, authority: &str) {
+        self.recorder = Some(snapshot.recorder(authority));
+        if let Some((endpoints, stale)) = snapshot.resolution(authority) {
+            debug!("using {} snapshotted endpoints", endpoints.len());
+            let mut updates = Vec::new();
+            for (addr, meta) in endpoints {
+                self.subset.add(addr, meta, &mut updates);
+            }
+            // The endpoints are already in the snapshot, so they are not
+            // recorded.
+            let _ = self.forward(updates);
+            self.stale = Some(stale);
+            self.reset = true;
+        }
+    }
+
+    /// Records updates from the Destination service, which are no longer
+    /// stale, and sends them to the resolution.
+    fn send(&mut self, updates: Vec<Update<Metadata>>) -> Result<(), ()> {
+        self.stale = None;
+        if let Some(ref recorder) = self.recorder {
+            recorder.record(&updates);
         }
+        self.forward(updates)
     }
 
-    fn send(&mut self, update: Update<Metadata>) -> Result<(), ()> {
-        trace!("{}", DisplayUpdate(&update));
-        self.tx.unbounded_send(update).map_err(|_| ())
+    fn forward(&mut self, updates: Vec<Update<Metadata>>) -> Result<(), ()> {
+        for update in updates {
+            trace!("{}", DisplayUpdate(&update));
+            self.tx.unbounded_send(update).map_err(|_| ())?;
+        }
+        Ok(())
     }
 
     /// Indicates that the resolution should be reset on the next update
@@ -249,48 +426,48 @@ impl Updater {
         self.reset = true;
     }
 
-    /// If the
-    fn reset_if_needed(&mut self) -> Result<(), ()> {
+    /// If the resolution should be reset, removes all of its endpoints.
+    fn reset_if_needed(&mut self, updates: &mut Vec<Update<Metadata>>) {
         if self.reset {
             trace!("query reconnected; removing stale endpoints");
-            for addr in self.seen.drain(..) {
-                trace!("remove {} (stale)", addr);
-                self.tx
-                    .unbounded_send(Update::Remove(addr))
-                    .map_err(|_| ())?;
+            if let Some(ref recorder) = self.recorder {
+                // Snapshotted endpoints may not all have been added.
+                recorder.record(&[Update::NoEndpoints]);
+            }
+            for addr in self.subset.clear() {
+                updates.push(Update::Remove(addr));
             }
             self.reset = false;
         }
-        Ok(())
     }
 
     fn add(&mut self, addrs: impl Iterator<Item = (SocketAddr, Metadata)>) -> Result<(), ()> {
-        self.reset_if_needed()?;
+        let mut updates = Vec::new();
+        self.reset_if_needed(&mut updates);
         for (addr, meta) in addrs {
-            self.seen.insert(addr);
-            self.send(Update::Add(addr, meta))?;
+            if self.subset.add(addr, meta, &mut updates) {
+                debug!("too many endpoints; withholding an endpoint");
+                self.metrics.endpoint_truncated();
+            }
         }
-        Ok(())
+        self.send(updates)
     }
 
     fn remove(&mut self, addrs: impl Iterator<Item = SocketAddr>) -> Result<(), ()> {
-        self.reset_if_needed()?;
+        let mut updates = Vec::new();
+        self.reset_if_needed(&mut updates);
         for addr in addrs {
-            self.seen.remove(&addr);
-            self.send(Update::Remove(addr))?;
+            self.subset.remove(addr, &mut updates);
         }
-        Ok(())
+        self.send(updates)
     }
 
     fn no_endpoints(&mut self) -> Result<(), ()> {
-        self.send(Update::NoEndpoints)?;
-        for addr in self.seen.drain(..) {
-            trace!("remove {} (no endpoints)", addr);
-            self.tx
-                .unbounded_send(Update::Remove(addr))
-                .map_err(|_| ())?;
+        let mut updates = vec![Update::NoEndpoints];
+        for addr in self.subset.clear() {
+            updates.push(Update::Remove(addr));
         }
-        Ok(())
+        self.send(updates)
     }
 }
 
@@ -412,3 +589,51 @@ fn pb_to_sock_addr(pb: TcpAddress) -> Option<SocketAddr> {
         None => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, sync::mpsc, Async};
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::Resolution;
+    use control::destination::{Metadata, Update};
+    use proxy::resolve;
+
+    #[test]
+    fn refused_resolutions_retry() {
+        let addr = "10.1.1.1:80".parse().unwrap();
+        let mut attempts = 0;
+        let mut resolution = Resolution::refused(Duration::from_millis(1), move || {
+            attempts += 1;
+            if attempts < 3 {
+                return None;
+            }
+            let (tx, rx) = mpsc::unbounded();
+            tx.unbounded_send(Update::Add(addr, Metadata::empty()))
+                .unwrap();
+            Some(Resolution {
+                rx,
+                active: None,
+                retry: None,
+            })
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let resolution = &mut resolution;
+        let update = rt
+            .block_on(future::lazy(move || {
+                match resolve::Resolution::poll(&mut *resolution) {
+                    Ok(Async::Ready(Update::NoEndpoints)) => {}
+                    _ => panic!("refused resolutions must have no endpoints"),
+                }
+                future::poll_fn(move || resolve::Resolution::poll(&mut *resolution))
+            }))
+            .unwrap();
+
+        match update {
+            Update::Add(a, _) => assert_eq!(a, addr),
+            _ => panic!("resolution must be started"),
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Persists recent resolutions and profiles, so that they may be used when the
+//! Destination service is unavailable.
+//!
+//! When the proxy restarts during a control plane outage, it would otherwise
+//! have no endpoints or routes until the Destination service answers. Instead,
+//! new resolutions and profile streams are seeded from the snapshot. Seeded
+//! state is counted as stale in metrics until the Destination service confirms
+//! or replaces it.
+//!
+//! The snapshot is a JSON document that describes endpoints in the format of
+//! the destination file (see the `file` module) and profiles in the format of
+//! the profile file:
+//!
+//! ```json
+//! {
+//!   "resolutions": { "web.default.svc.cluster.local:8080": [...] },
+//!   "profiles": { "web.default.svc.cluster.local:8080": {...} }
+//! }
+//! ```
+//!
+//! Resolutions record their updates without copying their endpoints; the
+//! snapshot is serialized periodically, if it has changed, and is written by a
+//! dedicated thread. The file is replaced atomically so that a crash cannot
+//! leave a partially written snapshot.
+
+use futures::{Async, Future, Poll};
+use indexmap::IndexMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+
+use super::{file, metrics, Endpoints, Metadata, Update};
+use json;
+use never::Never;
+
+/// Limits the number of resolutions and of profiles in the snapshot. The least
+/// recently updated are evicted first.
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    /// How often the snapshot is written, if it has changed.
+    pub interval: Duration,
+}
+
+/// A handle to the snapshot, shared by all resolutions and profile streams.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    inner: Arc<Mutex<Inner>>,
+    changes: Arc<Changes>,
+    metrics: metrics::Registry,
+}
+
+/// Serializes the snapshot when it changes.
+///
+/// The snapshot is written by a dedicated thread, so that the runtime is not
+/// blocked on the filesystem.
+pub struct Daemon {
+    config: Config,
+    inner: Arc<Mutex<Inner>>,
+    changes: Arc<Changes>,
+    delay: Delay,
+    writer: mpsc::SyncSender<String>,
+}
+
+/// Records a resolution's endpoints as it is updated.
+///
+/// Each resolution updates its own endpoints, so that the snapshot's lock is
+/// not taken on every update and the endpoints are only copied when the
+/// snapshot is written.
+#[derive(Debug)]
+pub(super) struct Recorder {
+    endpoints: Arc<Mutex<Endpoints>>,
+    seq: Arc<AtomicUsize>,
+    changes: Arc<Changes>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    resolutions: IndexMap<String, Entry<Arc<Mutex<Endpoints>>>>,
+    profiles: IndexMap<String, Entry<json::Value>>,
+}
+
+#[derive(Debug, Default)]
+struct Changes {
+    /// Orders entries by when they were last updated.
+    seq: AtomicUsize,
+    /// Whether the snapshot has changed since it was last written.
+    dirty: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: T,
+    seq: Arc<AtomicUsize>,
+}
+
+// === impl Snapshot ===
+
+impl Snapshot {
+    /// Loads the snapshot, returning it with a `Daemon` that must be spawned to
+    /// write it.
+    ///
+    /// If the snapshot cannot be loaded, it starts empty.
+    pub fn load(config: Config, metrics: metrics::Registry) -> (Self, Daemon) {
+        let changes = Arc::new(Changes::default());
+        let inner = match fs::read_to_string(&config.path) {
+            Ok(contents) => parse(&contents, &changes).unwrap_or_else(|e| {
+                warn!("ignoring invalid snapshot {}: {}", config.path.display(), e);
+                Inner::default()
+            }),
+            Err(e) => {
+                debug!("no snapshot at {}: {}", config.path.display(), e);
+                Inner::default()
+            }
+        };
+        let inner = Arc::new(Mutex::new(inner));
+
+        // The writer exits when the daemon is dropped.
+        let (writer, rx) = mpsc::sync_channel::<String>(1);
+        let path = config.path.clone();
+        thread::Builder::new()
+            .name("destination-snapshot".into())
+            .spawn(move || {
+                for contents in rx {
+                    match write(&path, &contents) {
+                        Ok(()) => trace!("wrote snapshot to {}", path.display()),
+                        Err(e) => warn!("failed to write snapshot to {}: {}", path.display(), e),
+                    }
+                }
+            })
+            .expect("initialize snapshot writer thread");
+
+        let daemon = Daemon {
+            delay: Delay::new(clock::now() + config.interval),
+            config,
+            inner: inner.clone(),
+            changes: changes.clone(),
+            writer,
+        };
+        let snapshot = Snapshot {
+            inner,
+            changes,
+            metrics,
+        };
+        (snapshot, daemon)
+    }
+
+    /// Returns an authority's snapshotted endpoints, with a guard that counts
+    /// the resolution as stale until it is dropped.
+    pub(super) fn resolution(&self, authority: &str) -> Option<(Endpoints, metrics::Stale)> {
+        let inner = self.inner.lock().ok()?;
+        let entry = inner.resolutions.get(&normalize(authority))?;
+        let endpoints = entry.value.lock().ok()?;
+        if endpoints.is_empty() {
+            return None;
+        }
+        Some((endpoints.clone(), self.metrics.stale_resolution()))
+    }
+
+    /// Returns a `Recorder` for an authority's endpoints.
+    ///
+    /// Resolutions of the same authority share its endpoints.
+    pub(super) fn recorder(&self, authority: &str) -> Recorder {
+        let key = normalize(authority);
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = inner.resolutions.get(&key) {
+            return Recorder {
+                endpoints: e.value.clone(),
+                seq: e.seq.clone(),
+                changes: self.changes.clone(),
+            };
+        }
+
+        let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+        let seq = Arc::new(AtomicUsize::new(self.changes.seq.load(Ordering::Relaxed)));
+        let entry = Entry {
+            value: endpoints.clone(),
+            seq: seq.clone(),
+        };
+        inner.resolutions.insert(key, entry);
+        evict(&mut inner.resolutions);
+        Recorder {
+            endpoints,
+            seq,
+            changes: self.changes.clone(),
+        }
+    }
+
+    /// Returns an authority's snapshotted profile, with a guard that counts
+    /// the profile as stale until it is dropped.
+    pub fn profile(&self, authority: &str) -> Option<(json::Value, metrics::Stale)> {
+        let inner = self.inner.lock().ok()?;
+        let entry = inner.profiles.get(&normalize(authority))?;
+        Some((entry.value.clone(), self.metrics.stale_profile()))
+    }
+
+    pub fn record_profile(&self, authority: &str, profile: json::Value) {
+        if let Ok(mut inner) = self.inner.lock() {
+            let seq = self.changes.next();
+            record(&mut inner.profiles, authority, profile, seq);
+        }
+    }
+}
+
+// === impl Recorder ===
+
+impl Recorder {
+    /// Applies updates to the recorded endpoints.
+    pub(super) fn record(&self, updates: &[Update<Metadata>]) {
+        if updates.is_empty() {
+            return;
+        }
+        if let Ok(mut endpoints) = self.endpoints.lock() {
+            for update in updates {
+                match *update {
+                    Update::Add(addr, ref meta) => {
+                        endpoints.insert(addr, meta.clone());
+                    }
+                    Update::Remove(ref addr) => {
+                        endpoints.swap_remove(addr);
+                    }
+                    Update::NoEndpoints => endpoints.clear(),
+                }
+            }
+        }
+        self.seq.store(self.changes.next(), Ordering::Relaxed);
+    }
+}
+
+// === impl Changes ===
+
+impl Changes {
+    /// Marks the snapshot as changed, returning the sequence number of the
+    /// change.
+    fn next(&self) -> usize {
+        self.dirty.store(true, Ordering::Release);
+        self.seq.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+// === impl Inner ===
+
+impl Inner {
+    fn to_json(&self) -> json::Value {
+        let resolutions = self.resolutions.iter().filter_map(|(k, e)| {
+            let endpoints = e.value.lock().ok()?;
+            if endpoints.is_empty() {
+                return None;
+            }
+            Some((k.as_str(), file::format_endpoints(&endpoints)))
+        });
+        let profiles = self
+            .profiles
+            .iter()
+            .map(|(k, e)| (k.as_str(), e.value.clone()));
+        json::object(vec![
+            ("resolutions", json::object(resolutions)),
+            ("profiles", json::object(profiles)),
+        ])
+    }
+}
+
+// === impl Daemon ===
+
+impl Future for Daemon {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(e) => {
+                    // The timer has failed, so the snapshot cannot be written.
+                    error!("{} will not be written: {}", self.config.path.display(), e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+            self.delay.reset(clock::now() + self.config.interval);
+
+            if !self.changes.dirty.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            let contents = match self.inner.lock() {
+                Ok(inner) => inner.to_json().to_string(),
+                Err(_) => return Ok(Async::Ready(())),
+            };
+
+            match self.writer.try_send(contents) {
+                Ok(()) => {}
+                Err(mpsc::TrySendError::Full(_)) => {
+                    // The previous snapshot is still being written, so try
+                    // again at the next interval.
+                    self.changes.dirty.store(true, Ordering::Release);
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => return Ok(Async::Ready(())),
+            }
+        }
+    }
+}
+
+/// Writes to a temporary file that replaces the snapshot, so that the snapshot
+/// is never partially written.
+fn write(path: &Path, contents: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Authorities are compared case-insensitively.
+fn normalize(authority: &str) -> String {
+    authority.to_ascii_lowercase()
+}
+
+/// Updates an authority's entry, evicting the least recently updated entry if
+/// there are too many.
+fn record<T>(entries: &mut IndexMap<String, Entry<T>>, authority: &str, value: T, seq: usize) {
+    let seq = Arc::new(AtomicUsize::new(seq));
+    entries.insert(normalize(authority), Entry { value, seq });
+    evict(entries);
+}
+
+fn evict<T>(entries: &mut IndexMap<String, Entry<T>>) {
+    if entries.len() > MAX_ENTRIES {
+        let oldest = entries
+            .iter()
+            .min_by_key(|(_, e)| e.seq.load(Ordering::Relaxed))
+            .map(|(k, _)| k.clone());
+        if let Some(k) = oldest {
+            entries.swap_remove(&k);
+        }
+    }
+}
+
+fn parse(contents: &str, changes: &Changes) -> Result<Inner, String> {
+    let doc = json::parse(contents).map_err(|e| e.to_string())?;
+    let mut inner = Inner::default();
+
+    if let Some(resolutions) = doc.get("resolutions") {
+        for (authority, endpoints) in file::parse_table(resolutions)? {
+            let endpoints = Arc::new(Mutex::new(endpoints));
+            record(
+                &mut inner.resolutions,
+                &authority,
+                endpoints,
+                changes.next(),
+            );
+        }
+    }
+
+    if let Some(profiles) = doc.get("profiles") {
+        let profiles = profiles
+            .as_object()
+            .ok_or_else(|| "profiles must be an object".to_owned())?;
+        for (authority, profile) in profiles {
+            record(
+                &mut inner.profiles,
+                authority,
+                profile.clone(),
+                changes.next(),
+            );
+        }
+    }
+
+    // The loaded snapshot need not be written again until it changes.
+    changes.dirty.store(false, Ordering::Release);
+    Ok(inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            inner: Arc::new(Mutex::new(Inner::default())),
+            changes: Arc::new(Changes::default()),
+            metrics: metrics::Registry::default(),
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let snapshot = snapshot();
+        let addr = "10.1.2.3:8080".parse::<SocketAddr>().unwrap();
+        snapshot
+            .recorder("Web:8080")
+            .record(&[Update::Add(addr, Metadata::empty())]);
+        let profile = json::parse(r#"{"routes": []}"#).unwrap();
+        snapshot.record_profile("web:8080", profile.clone());
+
+        let contents = snapshot.inner.lock().unwrap().to_json().to_string();
+        let changes = Changes::default();
+        let parsed = parse(&contents, &changes).expect("snapshot must parse");
+        assert!(!changes.dirty.load(Ordering::Acquire));
+        let endpoints = parsed.resolutions["web:8080"].value.lock().unwrap();
+        assert_eq!(endpoints.keys().collect::<Vec<_>>(), vec![&addr]);
+        assert_eq!(parsed.profiles["web:8080"].value, profile);
+    }
+
+    #[test]
+    fn records_updates() {
+        let snapshot = snapshot();
+        let a = "10.1.2.3:8080".parse::<SocketAddr>().unwrap();
+        let b = "10.1.2.4:8080".parse::<SocketAddr>().unwrap();
+
+        // Recorders are registered without changing the snapshot.
+        let recorder = snapshot.recorder("web:8080");
+        assert!(!snapshot.changes.dirty.load(Ordering::Acquire));
+        assert!(snapshot.resolution("web:8080").is_none());
+
+        recorder.record(&[
+            Update::Add(a, Metadata::empty()),
+            Update::Add(b, Metadata::empty()),
+            Update::Remove(a),
+        ]);
+        assert!(snapshot.changes.dirty.load(Ordering::Acquire));
+        let (endpoints, _stale) = snapshot.resolution("web:8080").unwrap();
+        assert_eq!(endpoints.keys().collect::<Vec<_>>(), vec![&b]);
+
+        // Resolutions of the same authority share its endpoints.
+        snapshot.recorder("WEB:8080").record(&[Update::NoEndpoints]);
+        assert!(snapshot.resolution("web:8080").is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_updated() {
+        let mut entries = IndexMap::new();
+        for i in 0..MAX_ENTRIES {
+            record(&mut entries, &format!("web-{}:80", i), (), i);
+        }
+        // Updating the first entry prevents it from being evicted.
+        record(&mut entries, "web-0:80", (), MAX_ENTRIES + 1);
+        record(&mut entries, "new:80", (), MAX_ENTRIES + 2);
+
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert!(entries.contains_key("web-0:80"));
+        assert!(!entries.contains_key("web-1:80"));
+        assert!(entries.contains_key("new:80"));
+    }
+}
@@ -0,0 +1,260 @@
+//! Limits the number of endpoints in a resolution.
+//!
+//! When the Destination service returns more endpoints than the limit, a
+//! subset is chosen with rendezvous hashing: each endpoint is scored by
+//! hashing its address with a key that identifies this proxy, and the highest
+//! scoring endpoints are used. Proxies with different keys thus choose
+//! different subsets, and each proxy's subset only changes as much as
+//! necessary when endpoints are added or removed.
+//!
+//! Endpoints that are not in the subset are retained as standbys, up to the
+//! limit, so that they may replace endpoints that are removed. Any further
+//! endpoints are dropped.
+
+use indexmap::IndexMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+use super::{Metadata, Update};
+
+#[derive(Debug)]
+pub(super) struct Subset {
+    key: u64,
+    max: usize,
+
+    /// Endpoints that have been added to the resolution.
+    active: IndexMap<SocketAddr, Scored>,
+    standby: IndexMap<SocketAddr, Scored>,
+}
+
+#[derive(Debug)]
+struct Scored {
+    score: u64,
+    meta: Metadata,
+}
+
+impl Subset {
+    pub fn new(key: &str, max: usize) -> Self {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        Self {
+            key: hasher.finish(),
+            max,
+            active: IndexMap::new(),
+            standby: IndexMap::new(),
+        }
+    }
+
+    fn score(&self, addr: &SocketAddr) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.key.hash(&mut hasher);
+        addr.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Adds an endpoint, pushing the resulting updates to the resolution.
+    ///
+    /// Returns true if an endpoint that had not already been withheld was
+    /// withheld from the resolution.
+    pub fn add(
+        &mut self,
+        addr: SocketAddr,
+        meta: Metadata,
+        updates: &mut Vec<Update<Metadata>>,
+    ) -> bool {
+        if let Some(active) = self.active.get_mut(&addr) {
+            active.meta = meta.clone();
+            updates.push(Update::Add(addr, meta));
+            return false;
+        }
+
+        let was_standby = self.standby.swap_remove(&addr).is_some();
+        let score = self.score(&addr);
+        if self.active.len() < self.max {
+            self.active.insert(
+                addr,
+                Scored {
+                    score,
+                    meta: meta.clone(),
+                },
+            );
+            updates.push(Update::Add(addr, meta));
+            return false;
+        }
+
+        // The subset is full, so the endpoint only replaces the lowest scoring
+        // active endpoint if it scores higher.
+        let lowest = Self::lowest(&self.active).filter(|&(_, s)| s < score);
+        match lowest {
+            Some((evicted, _)) => {
+                let scored = self
+                    .active
+                    .swap_remove(&evicted)
+                    .expect("endpoint must exist");
+                self.stand_by(evicted, scored);
+                self.active.insert(
+                    addr,
+                    Scored {
+                        score,
+                        meta: meta.clone(),
+                    },
+                );
+                updates.push(Update::Remove(evicted));
+                updates.push(Update::Add(addr, meta));
+            }
+            None => {
+                self.stand_by(addr, Scored { score, meta });
+                return !was_standby;
+            }
+        }
+        true
+    }
+
+    /// Removes an endpoint, pushing the resulting updates to the resolution.
+    pub fn remove(&mut self, addr: SocketAddr, updates: &mut Vec<Update<Metadata>>) {
+        if self.active.swap_remove(&addr).is_none() {
+            self.standby.swap_remove(&addr);
+            return;
+        }
+        updates.push(Update::Remove(addr));
+
+        // Replace the endpoint with the highest scoring standby, if any.
+        let highest = self
+            .standby
+            .iter()
+            .max_by_key(|(_, s)| s.score)
+            .map(|(addr, _)| *addr);
+        if let Some(addr) = highest {
+            let scored = self
+                .standby
+                .swap_remove(&addr)
+                .expect("endpoint must exist");
+            updates.push(Update::Add(addr, scored.meta.clone()));
+            self.active.insert(addr, scored);
+        }
+    }
+
+    /// Removes all endpoints, returning the addresses of those that had been
+    /// added to the resolution.
+    pub fn clear(&mut self) -> Vec<SocketAddr> {
+        self.standby.clear();
+        self.active.drain(..).map(|(addr, _)| addr).collect()
+    }
+
+    /// Retains a standby endpoint, dropping the lowest scoring standby if
+    /// there are too many.
+    fn stand_by(&mut self, addr: SocketAddr, scored: Scored) {
+        self.standby.insert(addr, scored);
+        if self.standby.len() > self.max {
+            if let Some((addr, _)) = Self::lowest(&self.standby) {
+                self.standby.swap_remove(&addr);
+            }
+        }
+    }
+
+    fn lowest(endpoints: &IndexMap<SocketAddr, Scored>) -> Option<(SocketAddr, u64)> {
+        endpoints
+            .iter()
+            .min_by_key(|(_, s)| s.score)
+            .map(|(addr, s)| (*addr, s.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{Metadata, Subset, Update};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn active(subset: &Subset) -> Vec<SocketAddr> {
+        let mut addrs = subset.active.keys().cloned().collect::<Vec<_>>();
+        addrs.sort();
+        addrs
+    }
+
+    fn add_all(subset: &mut Subset, ports: &[u16]) -> Vec<Update<Metadata>> {
+        let mut updates = Vec::new();
+        for port in ports {
+            subset.add(addr(*port), Metadata::empty(), &mut updates);
+        }
+        updates
+    }
+
+    #[test]
+    fn unbounded_below_limit() {
+        let mut subset = Subset::new("proxy", 3);
+        let updates = add_all(&mut subset, &[1, 2, 3]);
+        assert_eq!(updates.len(), 3);
+        assert_eq!(active(&subset), vec![addr(1), addr(2), addr(3)]);
+    }
+
+    #[test]
+    fn subsets_are_deterministic() {
+        let ports = (1..=20).collect::<Vec<_>>();
+        let mut reversed = ports.clone();
+        reversed.reverse();
+
+        let mut a = Subset::new("proxy", 5);
+        add_all(&mut a, &ports);
+        let mut b = Subset::new("proxy", 5);
+        add_all(&mut b, &reversed);
+
+        assert_eq!(active(&a).len(), 5);
+        assert_eq!(
+            active(&a),
+            active(&b),
+            "the subset does not depend on the order of updates"
+        );
+    }
+
+    #[test]
+    fn standbys_replace_removed_endpoints() {
+        let mut subset = Subset::new("proxy", 2);
+        add_all(&mut subset, &[1, 2, 3, 4]);
+        assert_eq!(subset.active.len(), 2);
+        assert_eq!(subset.standby.len(), 2);
+
+        let removed = active(&subset)[0];
+        let mut updates = Vec::new();
+        subset.remove(removed, &mut updates);
+        assert_eq!(updates.len(), 2, "the endpoint is replaced by a standby");
+        assert_eq!(subset.active.len(), 2);
+        assert!(!subset.active.contains_key(&removed));
+
+        let cleared = subset.clear();
+        assert_eq!(cleared.len(), 2);
+        assert!(subset.active.is_empty() && subset.standby.is_empty());
+    }
+
+    #[test]
+    fn updated_standbys_are_withheld_once() {
+        let mut subset = Subset::new("proxy", 1);
+        let mut updates = Vec::new();
+        let withheld = (1..=4)
+            .filter(|&port| subset.add(addr(port), Metadata::empty(), &mut updates))
+            .count();
+        assert_eq!(withheld, 3, "each endpoint beyond the limit is withheld");
+
+        let standby = *subset.standby.keys().next().expect("standby must exist");
+        updates.clear();
+        assert!(
+            !subset.add(standby, Metadata::empty(), &mut updates),
+            "updating a standby does not withhold another endpoint"
+        );
+        assert!(updates.is_empty());
+    }
+
+    #[test]
+    fn standbys_are_bounded() {
+        let mut subset = Subset::new("proxy", 2);
+        let ports = (1..=100).collect::<Vec<_>>();
+        add_all(&mut subset, &ports);
+        assert_eq!(subset.active.len(), 2);
+        assert_eq!(subset.standby.len(), 2);
+    }
+}
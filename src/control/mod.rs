@@ -2,4 +2,4 @@ pub mod destination;
 mod remote_stream;
 mod serve_http;
 
-pub use self::serve_http::serve_http;
+pub use self::serve_http::{serve_http, RemoteAddr};
@@ -1,10 +1,21 @@
 use futures::{future, Future};
-use hyper::{server::conn::Http, service::Service, Body};
+use hyper::{server::conn::Http, service::Service, Body, Request};
+use std::net::SocketAddr;
 use tokio::executor::current_thread::TaskExecutor;
 
 use task;
 use transport::{tls, Listen};
 
+/// The address of the peer that sent a request, which is inserted into the
+/// extensions of every request served by `serve_http`.
+#[derive(Copy, Clone, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
+struct InsertRemoteAddr<S> {
+    inner: S,
+    remote: SocketAddr,
+}
+
 pub fn serve_http<L, S>(
     name: &'static str,
     bound_port: Listen<L, ()>,
@@ -22,7 +33,13 @@ where
         bound_port
             .listen_and_fold(Http::new(), move |hyper, (conn, remote)| {
                 let serve = hyper
-                    .serve_connection(conn, service.clone())
+                    .serve_connection(
+                        conn,
+                        InsertRemoteAddr {
+                            inner: service.clone(),
+                            remote,
+                        },
+                    )
                     .map(|_| {})
                     .map_err(move |e| {
                         error!("error serving {}: {:?}", name, e);
@@ -41,3 +58,17 @@ where
 
     log.future(fut)
 }
+
+// === impl InsertRemoteAddr ===
+
+impl<S: Service> Service for InsertRemoteAddr<S> {
+    type ReqBody = S::ReqBody;
+    type ResBody = S::ResBody;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&mut self, mut req: Request<S::ReqBody>) -> Self::Future {
+        req.extensions_mut().insert(RemoteAddr(self.remote));
+        self.inner.call(req)
+    }
+}
@@ -3,7 +3,9 @@ extern crate untrusted;
 extern crate webpki;
 
 use self::trust_dns_resolver::{
-    config::ResolverConfig, system_conf, AsyncResolver, BackgroundLookupIp,
+    config::ResolverConfig,
+    proto::rr::{RData, RecordType},
+    system_conf, AsyncResolver, BackgroundLookup, BackgroundLookupIp,
 };
 use convert::TryFrom;
 use futures::prelude::*;
@@ -35,6 +37,10 @@ pub struct IpAddrFuture(::logging::ContextualFuture<Ctx, BackgroundLookupIp>);
 
 pub struct RefineFuture(::logging::ContextualFuture<Ctx, BackgroundLookupIp>);
 
+pub struct IpAddrsFuture(::logging::ContextualFuture<Ctx, BackgroundLookupIp>);
+
+pub struct SrvFuture(::logging::ContextualFuture<Ctx, BackgroundLookup>);
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Suffix {
     Root, // The `.` suffix.
@@ -48,6 +54,26 @@ pub struct Refine {
     pub valid_until: Instant,
 }
 
+/// All of the addresses of a name.
+pub struct IpAddrs {
+    pub addrs: Vec<net::IpAddr>,
+    pub valid_until: Instant,
+}
+
+/// The SRV records of a name.
+pub struct Srvs {
+    pub records: Vec<Srv>,
+    pub valid_until: Instant,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: Name,
+}
+
 impl fmt::Display for Ctx {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(f, "dns={}", self.0)
@@ -152,6 +178,20 @@ impl Resolver {
         let f = self.resolver.lookup_ip(name.as_ref());
         RefineFuture(::logging::context_future(Ctx(name.clone()), f))
     }
+
+    /// Resolves all of the IPv4 and IPv6 addresses of `name`.
+    pub fn resolve_all_ips(&self, name: &Name) -> IpAddrsFuture {
+        let f = self.resolver.lookup_ip(name.as_ref());
+        IpAddrsFuture(::logging::context_future(Ctx(name.clone()), f))
+    }
+
+    /// Resolves the SRV records of a `service` (e.g. `http`) that is served
+    /// over TCP by `name`, i.e. the records of `_<service>._tcp.<name>`.
+    pub fn resolve_srv(&self, service: &str, name: &Name) -> SrvFuture {
+        let query = format!("_{}._tcp.{}", service, name);
+        let f = self.resolver.lookup(query.as_str(), RecordType::SRV);
+        SrvFuture(::logging::context_future(Ctx(name.clone()), f))
+    }
 }
 
 /// Note: `AsyncResolver` does not implement `Debug`, so we must manually
@@ -194,6 +234,49 @@ impl Future for RefineFuture {
     }
 }
 
+impl Future for IpAddrsFuture {
+    type Item = IpAddrs;
+    type Error = ResolveError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let lookup = try_ready!(self.0.poll());
+        let valid_until = lookup.valid_until();
+        let addrs = lookup.iter().collect();
+        Ok(Async::Ready(IpAddrs { addrs, valid_until }))
+    }
+}
+
+impl Future for SrvFuture {
+    type Item = Srvs;
+    type Error = ResolveError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let lookup = try_ready!(self.0.poll());
+        let valid_until = lookup.valid_until();
+        let records = lookup
+            .iter()
+            .filter_map(|rdata| match rdata {
+                RData::SRV(srv) => {
+                    // A target of "." indicates that the service is not
+                    // available at this name, and is not a valid `Name`.
+                    let target = Name::try_from(srv.target().to_ascii().as_bytes()).ok()?;
+                    Some(Srv {
+                        priority: srv.priority(),
+                        weight: srv.weight(),
+                        port: srv.port(),
+                        target,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        Ok(Async::Ready(Srvs {
+            records,
+            valid_until,
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Name, Suffix};
@@ -0,0 +1,112 @@
+//! Watches a file for changes, publishing its parsed contents.
+//!
+//! The file's modification time and length are polled, since the file may be
+//! replaced in ways that filesystem notifications do not reliably observe (for
+//! instance, when a Kubernetes ConfigMap is updated).
+
+use futures::{Async, Future, Poll};
+use futures_watch::{Store, Watch};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio_timer::{clock, Delay};
+
+use never::Never;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    /// How often the file is checked for changes.
+    pub interval: Duration,
+}
+
+/// Parses a file's contents.
+pub type Parse<T> = fn(&str) -> Result<T, String>;
+
+/// Reloads a file when it changes.
+pub struct Daemon<T> {
+    config: Config,
+    parse: Parse<T>,
+    modified: Option<(SystemTime, u64)>,
+    delay: Delay,
+    store: Store<Arc<T>>,
+}
+
+/// Loads a file, returning a `Watch` of its contents and a `Daemon` that must
+/// be spawned to reload it as it changes.
+///
+/// The file is loaded before this returns, so that its contents may be used
+/// immediately. If it cannot be loaded, `T::default()` is used until it can.
+/// If a change cannot be loaded, the previous contents continue to be used.
+pub fn watch<T: Default>(config: Config, parse: Parse<T>) -> (Watch<Arc<T>>, Daemon<T>) {
+    let modified = modified(&config);
+    let contents = match load(&config, parse) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("failed to load {}: {}", config.path.display(), e);
+            T::default()
+        }
+    };
+    let (watch, store) = Watch::new(Arc::new(contents));
+
+    let daemon = Daemon {
+        delay: Delay::new(clock::now() + config.interval),
+        config,
+        parse,
+        modified,
+        store,
+    };
+    (watch, daemon)
+}
+
+// === impl Daemon ===
+
+impl<T> Future for Daemon<T> {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(e) => {
+                    // The timer has failed, so the file cannot be polled.
+                    error!("{} will not be reloaded: {}", self.config.path.display(), e);
+                    return Ok(Async::Ready(()));
+                }
+            }
+            self.delay.reset(clock::now() + self.config.interval);
+
+            let modified = modified(&self.config);
+            if modified == self.modified {
+                continue;
+            }
+            self.modified = modified;
+
+            match load(&self.config, self.parse) {
+                Ok(contents) => {
+                    debug!("reloaded {}", self.config.path.display());
+                    if self.store.store(Arc::new(contents)).is_err() {
+                        // All watches have been dropped.
+                        return Ok(Async::Ready(()));
+                    }
+                }
+                Err(e) => warn!("failed to reload {}: {}", self.config.path.display(), e),
+            }
+        }
+    }
+}
+
+/// Identifies a version of the file by its modification time and length.
+fn modified(config: &Config) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(&config.path).ok()?;
+    let modified = meta.modified().ok()?;
+    Some((modified, meta.len()))
+}
+
+fn load<T>(config: &Config, parse: Parse<T>) -> Result<T, String> {
+    let contents = fs::read_to_string(&config.path).map_err(|e| e.to_string())?;
+    parse(&contents)
+}
@@ -0,0 +1,423 @@
+//! Just enough DER parsing to read X.509 certificates' validity, names, and
+//! subject alternative names, which neither `webpki` nor `rustls` expose.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BOOLEAN: u8 = 0x01;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const EXPLICIT_VERSION: u8 = 0xa0;
+const EXPLICIT_EXTENSIONS: u8 = 0xa3;
+
+const SAN_EMAIL: u8 = 0x81;
+const SAN_DNS: u8 = 0x82;
+const SAN_URI: u8 = 0x86;
+const SAN_IP: u8 = 0x87;
+
+/// The OID of the subject alternative name extension (2.5.29.17).
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Describes a certificate, for diagnostics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrtInfo {
+    pub subject: String,
+    pub issuer: String,
+    /// Subject alternative names, e.g. `DNS:foo.ns1.serviceaccount...`.
+    pub sans: Vec<String>,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+/// The fields of a certificate's `TBSCertificate` that are described.
+struct Fields<'a> {
+    issuer: &'a [u8],
+    validity: &'a [u8],
+    subject: &'a [u8],
+    /// The optional fields that follow the subject's public key.
+    optional: &'a [u8],
+}
+
+/// Splits concatenated DER values, e.g. the certificates of an X.509-SVID.
+pub fn split(mut der: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut values = Vec::new();
+    while !der.is_empty() {
+        let (_, _, rest) = next(der)?;
+        let len = der.len() - rest.len();
+        values.push(&der[..len]);
+        der = rest;
+    }
+    Some(values)
+}
+
+/// Reads the end of a DER-encoded X.509 certificate's validity period.
+pub fn not_after(crt: &[u8]) -> Option<SystemTime> {
+    let (_, _, validity) = next(fields(crt)?.validity)?; // notBefore
+    let (tag, time, _) = next(validity)?;
+    parse_time(tag, time)
+}
+
+/// Describes a DER-encoded X.509 certificate.
+pub fn describe(crt: &[u8]) -> Option<CrtInfo> {
+    let fields = fields(crt)?;
+
+    let (tag, time, validity) = next(fields.validity)?;
+    let not_before = parse_time(tag, time)?;
+    let (tag, time, _) = next(validity)?;
+    let not_after = parse_time(tag, time)?;
+
+    let mut sans = Vec::new();
+    let mut optional = fields.optional;
+    while !optional.is_empty() {
+        let (tag, value, rest) = next(optional)?;
+        if tag == EXPLICIT_EXTENSIONS {
+            sans = subject_alt_names(value)?;
+        }
+        optional = rest;
+    }
+
+    Some(CrtInfo {
+        subject: format_name(fields.subject)?,
+        issuer: format_name(fields.issuer)?,
+        sans,
+        not_before,
+        not_after,
+    })
+}
+
+/// Formats a time as RFC 3339, e.g. `2019-03-14T18:02:00Z`.
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = date_from_days(secs / (24 * 60 * 60));
+    let secs = secs % (24 * 60 * 60);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / (60 * 60),
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn fields(crt: &[u8]) -> Option<Fields> {
+    let (tag, crt, _) = next(crt)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, tbs, _) = next(crt)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    // The version is optional, so the first field may be the serial number.
+    let (tag, _, rest) = next(tbs)?;
+    let rest = if tag == EXPLICIT_VERSION {
+        next(rest)?.2
+    } else {
+        rest
+    };
+    let (_, _, rest) = next(rest)?; // signature
+
+    let (tag, issuer, rest) = next(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, validity, rest) = next(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, subject, rest) = next(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (_, _, optional) = next(rest)?; // subjectPublicKeyInfo
+
+    Some(Fields {
+        issuer,
+        validity,
+        subject,
+        optional,
+    })
+}
+
+/// Formats a distinguished name in the manner of RFC 4514, e.g.
+/// `CN=foo,OU=bar`, without escaping special characters.
+fn format_name(mut rdns: &[u8]) -> Option<String> {
+    let mut formatted = Vec::new();
+    while !rdns.is_empty() {
+        let (tag, mut rdn, rest) = next(rdns)?;
+        if tag != SET {
+            return None;
+        }
+        let mut attrs = Vec::new();
+        while !rdn.is_empty() {
+            let (_, attr, rest) = next(rdn)?;
+            let (_, oid, attr) = next(attr)?;
+            let (_, value, _) = next(attr)?;
+            attrs.push(format!(
+                "{}={}",
+                format_attr_type(oid),
+                String::from_utf8_lossy(value)
+            ));
+            rdn = rest;
+        }
+        formatted.push(attrs.join("+"));
+        rdns = rest;
+    }
+
+    // Names are formatted from the most specific RDN to the least.
+    formatted.reverse();
+    Some(formatted.join(","))
+}
+
+fn format_attr_type(oid: &[u8]) -> String {
+    let name = match oid {
+        [0x55, 0x04, 0x03] => "CN",
+        [0x55, 0x04, 0x06] => "C",
+        [0x55, 0x04, 0x07] => "L",
+        [0x55, 0x04, 0x08] => "ST",
+        [0x55, 0x04, 0x0a] => "O",
+        [0x55, 0x04, 0x0b] => "OU",
+        _ => return format_oid(oid),
+    };
+    name.to_owned()
+}
+
+/// Formats an OID in dotted-decimal notation.
+fn format_oid(oid: &[u8]) -> String {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for b in oid {
+        arc = (arc << 7) | u64::from(b & 0x7f);
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            // The first two arcs are encoded together.
+            let first = if arc < 80 { arc / 40 } else { 2 };
+            arcs.push(first);
+            arc -= first * 40;
+        }
+        arcs.push(arc);
+        arc = 0;
+    }
+    arcs.iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Reads the subject alternative names from a certificate's extensions.
+fn subject_alt_names(exts: &[u8]) -> Option<Vec<String>> {
+    let (_, mut exts, _) = next(exts)?;
+    while !exts.is_empty() {
+        let (_, ext, rest) = next(exts)?;
+        exts = rest;
+
+        let (_, oid, ext) = next(ext)?;
+        if oid != SUBJECT_ALT_NAME {
+            continue;
+        }
+        // The extension may be marked as critical before its value.
+        let (tag, value, ext) = next(ext)?;
+        let value = if tag == BOOLEAN { next(ext)?.1 } else { value };
+
+        let mut sans = Vec::new();
+        let (_, mut names, _) = next(value)?;
+        while !names.is_empty() {
+            let (tag, name, rest) = next(names)?;
+            names = rest;
+            let san = match tag {
+                SAN_DNS => format!("DNS:{}", String::from_utf8_lossy(name)),
+                SAN_URI => format!("URI:{}", String::from_utf8_lossy(name)),
+                SAN_EMAIL => format!("email:{}", String::from_utf8_lossy(name)),
+                SAN_IP => format!("IP:{}", parse_ip(name)?),
+                // Other kinds of names are not described.
+                _ => continue,
+            };
+            sans.push(san);
+        }
+        return Some(sans);
+    }
+    Some(Vec::new())
+}
+
+fn parse_ip(octets: &[u8]) -> Option<IpAddr> {
+    match octets.len() {
+        4 => Some(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).into()),
+        16 => {
+            let mut a = [0u8; 16];
+            a.copy_from_slice(octets);
+            Some(Ipv6Addr::from(a).into())
+        }
+        _ => None,
+    }
+}
+
+/// Reads a value, returning its tag, its contents, and the remaining input.
+fn next(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, &rest[n..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// Parses a `UTCTime` or `GeneralizedTime`, which DER requires to be in UTC
+/// and without fractional seconds (i.e. `YYMMDDHHMMSSZ` or `YYYYMMDDHHMMSSZ`).
+fn parse_time(tag: u8, value: &[u8]) -> Option<SystemTime> {
+    let s = str::from_utf8(value).ok()?;
+    let (year, s) = match tag {
+        UTC_TIME => {
+            let yy: u64 = s.get(..2)?.parse().ok()?;
+            let year = if yy >= 50 { 1900 + yy } else { 2000 + yy };
+            (year, &s[2..])
+        }
+        GENERALIZED_TIME => (s.get(..4)?.parse().ok()?, &s[4..]),
+        _ => return None,
+    };
+    if s.len() != 11 || !s.ends_with('Z') {
+        return None;
+    }
+
+    let field = |i: usize| -> Option<u64> { s.get(i..i + 2)?.parse().ok() };
+    let (month, day) = (field(0)?, field(2)?);
+    let (hour, min, sec) = (field(4)?, field(6)?, field(8)?);
+    if month < 1 || month > 12 || day < 1 || day > 31 || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    let days = days_since_epoch(year, month, day)?;
+    let secs = days * 24 * 60 * 60 + hour * 60 * 60 + min * 60 + sec;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Counts the days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> Option<u64> {
+    // Years are counted from March, so that leap days end the year.
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+/// Finds the date that is the given number of days after 1970-01-01, as the
+/// inverse of `days_since_epoch`.
+fn date_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn counts_days_since_epoch() {
+        assert_eq!(days_since_epoch(1970, 1, 1), Some(0));
+        assert_eq!(days_since_epoch(2000, 3, 1), Some(11_017));
+        assert_eq!(days_since_epoch(2019, 12, 31), Some(18_261));
+        assert_eq!(days_since_epoch(1969, 12, 31), None);
+    }
+
+    #[test]
+    fn parses_times() {
+        let t = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(parse_time(UTC_TIME, b"700101000000Z"), t(0));
+        assert_eq!(parse_time(UTC_TIME, b"191231235959Z"), t(1_577_836_799));
+        assert_eq!(
+            parse_time(GENERALIZED_TIME, b"20491231235959Z"),
+            t(2_524_607_999)
+        );
+        assert_eq!(parse_time(UTC_TIME, b"191231235959"), None);
+        assert_eq!(parse_time(UTC_TIME, b"191331235959Z"), None);
+    }
+
+    #[test]
+    fn reads_certificate_expiry() {
+        let crt = fs::read("src/identity/testdata/foo-ns1-ca1/crt.der").expect("crt");
+        let expiry = not_after(&crt).expect("certificate must have an expiry");
+        assert!(expiry > UNIX_EPOCH + Duration::from_secs(1_500_000_000));
+
+        let mut chain = crt.clone();
+        chain.extend_from_slice(&crt);
+        assert_eq!(split(&chain), Some(vec![&crt[..], &crt[..]]));
+        assert_eq!(split(&chain[..chain.len() - 1]), None);
+    }
+
+    #[test]
+    fn describes_certificates() {
+        let crt = fs::read("src/identity/testdata/foo-ns1-ca1/crt.der").expect("crt");
+        let info = describe(&crt).expect("certificate must be described");
+        assert_eq!(info.subject, "");
+        assert_eq!(info.issuer, "OU=None");
+        assert_eq!(
+            info.sans,
+            vec!["DNS:foo.ns1.serviceaccount.identity.linkerd.cluster.local"]
+        );
+        assert_eq!(format_time(info.not_before), "2019-03-14T18:02:00Z");
+        assert_eq!(Some(info.not_after), not_after(&crt));
+    }
+
+    #[test]
+    fn formats_names_and_times() {
+        // SET { SEQUENCE { OID 2.5.4.3, UTF8String "foo" } },
+        // SET { SEQUENCE { OID 1.2.840.113549.1.9.1, IA5String "a@b" } }
+        let name = [
+            0x31, 0x0c, 0x30, 0x0a, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x03, b'f', b'o', b'o',
+            0x31, 0x12, 0x30, 0x10, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09,
+            0x01, 0x16, 0x03, b'a', b'@', b'b',
+        ];
+        assert_eq!(
+            format_name(&name),
+            Some("1.2.840.113549.1.9.1=a@b,CN=foo".to_owned())
+        );
+
+        for &(secs, formatted) in &[
+            (0, "1970-01-01T00:00:00Z"),
+            (951_782_400, "2000-02-29T00:00:00Z"),
+            (1_577_836_799, "2019-12-31T23:59:59Z"),
+        ] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            assert_eq!(format_time(time), formatted);
+        }
+    }
+}
@@ -4,7 +4,7 @@ extern crate tokio_rustls;
 extern crate untrusted;
 
 use self::ring::rand;
-use self::ring::signature::EcdsaKeyPair;
+use self::ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
 use std::error::Error;
 use std::sync::Arc;
 use std::time::SystemTime;
@@ -14,11 +14,14 @@ pub use self::ring::error::KeyRejected;
 
 use convert::TryFrom;
 use dns;
+use json;
 use transport::tls;
 
+mod der;
 #[cfg(test)]
 pub mod test_util;
 
+pub use self::der::CrtInfo;
 pub use dns::InvalidName;
 
 pub trait LocalIdentity {
@@ -34,14 +37,54 @@ pub struct Csr(Arc<Vec<u8>>);
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct Name(Arc<dns::Name>);
 
+/// A private key, which may be an ECDSA (P-256 or P-384), RSA, or Ed25519
+/// key. Ed25519 keys can only be used with TLS 1.3.
 #[derive(Clone, Debug)]
-pub struct Key(Arc<EcdsaKeyPair>);
+pub struct Key(Arc<KeyPair>);
 
-struct SigningKey(Arc<EcdsaKeyPair>);
-struct Signer(Arc<EcdsaKeyPair>);
+enum KeyPair {
+    /// Holds the scheme that the key's curve signs with.
+    Ecdsa(EcdsaKeyPair, rustls::SignatureScheme),
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+struct SigningKey(Key);
+
+struct Signer {
+    key: Key,
+    scheme: rustls::SignatureScheme,
+}
 
 #[derive(Clone)]
-pub struct TrustAnchors(Arc<rustls::ClientConfig>);
+pub struct TrustAnchors {
+    client: Arc<rustls::ClientConfig>,
+    params: TlsParams,
+}
+
+/// Configures the TLS versions and cipher suites used for meshed TLS.
+///
+/// Key exchange groups cannot be configured: rustls always offers X25519,
+/// P-384, and P-256, in that order.
+#[derive(Clone, Debug)]
+pub struct TlsParams {
+    server_versions: Vec<rustls::ProtocolVersion>,
+    client_versions: Vec<rustls::ProtocolVersion>,
+    cipher_suites: Vec<&'static rustls::SupportedCipherSuite>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CipherSuite(&'static rustls::SupportedCipherSuite);
+
+/// Indicates that no cipher suite is usable with any allowed TLS version.
+#[derive(Copy, Clone, Debug)]
+pub struct NoUsableCipherSuites;
 
 #[derive(Clone, Debug)]
 pub struct TokenSource(Arc<String>);
@@ -57,7 +100,9 @@ pub struct Crt {
 pub struct CrtKey {
     name: Name,
     expiry: SystemTime,
+    chain: Vec<rustls::Certificate>,
     client_config: Arc<rustls::ClientConfig>,
+    h2_client_config: Arc<rustls::ClientConfig>,
     server_config: Arc<rustls::ServerConfig>,
 }
 
@@ -66,14 +111,38 @@ struct CertResolver(rustls::sign::CertifiedKey);
 #[derive(Clone, Debug)]
 pub struct InvalidCrt(rustls::TLSError);
 
-// These must be kept in sync:
-static SIGNATURE_ALG_RING_SIGNING: &ring::signature::EcdsaSigningAlgorithm =
-    &ring::signature::ECDSA_P256_SHA256_ASN1_SIGNING;
-const SIGNATURE_ALG_RUSTLS_SCHEME: rustls::SignatureScheme =
-    rustls::SignatureScheme::ECDSA_NISTP256_SHA256;
-const SIGNATURE_ALG_RUSTLS_ALGORITHM: rustls::internal::msgs::enums::SignatureAlgorithm =
-    rustls::internal::msgs::enums::SignatureAlgorithm::ECDSA;
-const TLS_VERSIONS: &[rustls::ProtocolVersion] = &[rustls::ProtocolVersion::TLSv1_2];
+/// The ECDSA curves that keys may use, with the schemes that they sign with.
+static ECDSA_ALGS: &[(&signature::EcdsaSigningAlgorithm, rustls::SignatureScheme)] = &[
+    (
+        &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+        rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+    ),
+    (
+        &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+        rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
+    ),
+];
+
+/// The schemes that RSA keys may sign with, in order of preference. Each must
+/// have an encoding in `rsa_encoding`.
+static RSA_SCHEMES: &[rustls::SignatureScheme] = &[
+    rustls::SignatureScheme::RSA_PSS_SHA512,
+    rustls::SignatureScheme::RSA_PSS_SHA384,
+    rustls::SignatureScheme::RSA_PSS_SHA256,
+    rustls::SignatureScheme::RSA_PKCS1_SHA512,
+    rustls::SignatureScheme::RSA_PKCS1_SHA384,
+    rustls::SignatureScheme::RSA_PKCS1_SHA256,
+];
+
+/// Servers only accept TLS 1.2 unless a minimum version is configured.
+const DEFAULT_SERVER_TLS_VERSIONS: &[rustls::ProtocolVersion] = &[rustls::ProtocolVersion::TLSv1_2];
+const DEFAULT_CLIENT_TLS_VERSIONS: &[rustls::ProtocolVersion] = &[
+    rustls::ProtocolVersion::TLSv1_3,
+    rustls::ProtocolVersion::TLSv1_2,
+];
+
+/// The key exchange groups that rustls offers, which are not configurable.
+const KX_GROUPS: &[&str] = &["X25519", "secp384r1", "secp256r1"];
 
 // === impl Csr ===
 
@@ -94,10 +163,133 @@ impl Csr {
 // === impl Key ===
 
 impl Key {
+    /// Reads a DER-encoded PKCS#8 ECDSA, RSA, or Ed25519 key.
+    ///
+    /// If the key is rejected, the error describes why it is not valid for
+    /// its algorithm, rather than that it is not of every other algorithm.
     pub fn from_pkcs8(b: &[u8]) -> Result<Self, KeyRejected> {
-        let i = untrusted::Input::from(b);
-        let k = EcdsaKeyPair::from_pkcs8(SIGNATURE_ALG_RING_SIGNING, i)?;
-        Ok(Key(Arc::new(k)))
+        let i = || untrusted::Input::from(b);
+        let mut rejected = Vec::new();
+
+        for &(alg, scheme) in ECDSA_ALGS {
+            match EcdsaKeyPair::from_pkcs8(alg, i()) {
+                Ok(k) => return Ok(Key(Arc::new(KeyPair::Ecdsa(k, scheme)))),
+                Err(e) => rejected.push(e),
+            }
+        }
+        match RsaKeyPair::from_pkcs8(i()) {
+            Ok(k) => return Ok(Key(Arc::new(KeyPair::Rsa(k)))),
+            Err(e) => rejected.push(e),
+        }
+        // Keys written by OpenSSL do not include the public key, so they are
+        // not checked against it.
+        match Ed25519KeyPair::from_pkcs8_maybe_unchecked(i()) {
+            Ok(k) => return Ok(Key(Arc::new(KeyPair::Ed25519(k)))),
+            Err(e) => rejected.push(e),
+        }
+
+        // ring does not expose its `WrongAlgorithm` error, so it is recognized
+        // by its description.
+        let i = rejected
+            .iter()
+            .position(|e| e.description_() != "WrongAlgorithm")
+            .unwrap_or(0);
+        Err(rejected.swap_remove(i))
+    }
+
+    /// Reads the first PEM-encoded PKCS#8 key, if there is a valid one.
+    pub fn from_pkcs8_pem(pem: &[u8]) -> Option<Self> {
+        use self::rustls::internal::pemfile;
+        use std::io::Cursor;
+
+        let keys = pemfile::pkcs8_private_keys(&mut Cursor::new(pem)).ok()?;
+        let key = keys.into_iter().next()?;
+        match Self::from_pkcs8(&key.0) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                debug!("invalid key: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Key {
+    /// Returns whether the key is the certificate's key, by checking a
+    /// signature made with the key against the certificate's public key.
+    fn is_valid_for(&self, crt: &Crt) -> bool {
+        use self::rustls::SignatureScheme::*;
+        const MSG: &[u8] = b"linkerd2-proxy key check";
+
+        let (scheme, alg) = match *self.0 {
+            KeyPair::Ecdsa(_, ECDSA_NISTP256_SHA256) => {
+                (ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256)
+            }
+            KeyPair::Ecdsa(_, ECDSA_NISTP384_SHA384) => {
+                (ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384)
+            }
+            KeyPair::Ecdsa(..) => return false,
+            KeyPair::Rsa(_) => (RSA_PKCS1_SHA256, &webpki::RSA_PKCS1_2048_8192_SHA256),
+            KeyPair::Ed25519(_) => (ED25519, &webpki::ED25519),
+        };
+        let signer = Signer {
+            key: self.clone(),
+            scheme,
+        };
+        let signature = match rustls::sign::Signer::sign(&signer, MSG) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        let leaf = crt.chain.first().map(|c| c.as_ref()).unwrap_or(&[]);
+        webpki::EndEntityCert::from(untrusted::Input::from(leaf))
+            .and_then(|c| {
+                c.verify_signature(
+                    alg,
+                    untrusted::Input::from(MSG),
+                    untrusted::Input::from(&signature),
+                )
+            })
+            .is_ok()
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyPair::Ecdsa(_, scheme) => write!(f, "Ecdsa({:?})", scheme),
+            KeyPair::Rsa(_) => f.write_str("Rsa"),
+            KeyPair::Ed25519(_) => f.write_str("Ed25519"),
+        }
+    }
+}
+
+impl KeyPair {
+    /// Chooses the scheme to sign with from those offered by the peer.
+    fn choose_scheme(
+        &self,
+        offered: &[rustls::SignatureScheme],
+    ) -> Option<rustls::SignatureScheme> {
+        match self {
+            KeyPair::Ecdsa(_, scheme) => Some(*scheme).filter(|s| offered.contains(s)),
+            KeyPair::Rsa(_) => RSA_SCHEMES.iter().find(|s| offered.contains(s)).cloned(),
+            KeyPair::Ed25519(_) => {
+                Some(rustls::SignatureScheme::ED25519).filter(|s| offered.contains(s))
+            }
+        }
+    }
+}
+
+fn rsa_encoding(scheme: rustls::SignatureScheme) -> Option<&'static signature::RsaEncoding> {
+    use self::rustls::SignatureScheme::*;
+    match scheme {
+        RSA_PSS_SHA512 => Some(&signature::RSA_PSS_SHA512),
+        RSA_PSS_SHA384 => Some(&signature::RSA_PSS_SHA384),
+        RSA_PSS_SHA256 => Some(&signature::RSA_PSS_SHA256),
+        RSA_PKCS1_SHA512 => Some(&signature::RSA_PKCS1_SHA512),
+        RSA_PKCS1_SHA384 => Some(&signature::RSA_PKCS1_SHA384),
+        RSA_PKCS1_SHA256 => Some(&signature::RSA_PKCS1_SHA256),
+        _ => None,
     }
 }
 
@@ -106,31 +298,45 @@ impl rustls::sign::SigningKey for SigningKey {
         &self,
         offered: &[rustls::SignatureScheme],
     ) -> Option<Box<rustls::sign::Signer>> {
-        if offered.contains(&SIGNATURE_ALG_RUSTLS_SCHEME) {
-            Some(Box::new(Signer(self.0.clone())))
-        } else {
-            None
-        }
+        let scheme = (self.0).0.choose_scheme(offered)?;
+        Some(Box::new(Signer {
+            key: self.0.clone(),
+            scheme,
+        }))
     }
 
     fn algorithm(&self) -> rustls::internal::msgs::enums::SignatureAlgorithm {
-        SIGNATURE_ALG_RUSTLS_ALGORITHM
+        use self::rustls::internal::msgs::enums::SignatureAlgorithm;
+        match *(self.0).0 {
+            KeyPair::Ecdsa(..) => SignatureAlgorithm::ECDSA,
+            KeyPair::Rsa(_) => SignatureAlgorithm::RSA,
+            KeyPair::Ed25519(_) => SignatureAlgorithm::ED25519,
+        }
     }
 }
 
 impl rustls::sign::Signer for Signer {
     fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::TLSError> {
         let rng = rand::SystemRandom::new();
-        self.0
-            .sign(&rng, untrusted::Input::from(message))
-            .map(|signature| signature.as_ref().to_owned())
-            .map_err(|ring::error::Unspecified| {
-                rustls::TLSError::General("Signing Failed".to_owned())
-            })
+        let signature = match *self.key.0 {
+            KeyPair::Ecdsa(ref k, _) => k
+                .sign(&rng, untrusted::Input::from(message))
+                .map(|signature| signature.as_ref().to_owned()),
+            KeyPair::Rsa(ref k) => {
+                let encoding = rsa_encoding(self.scheme).expect("RSA scheme must have an encoding");
+                let mut signature = vec![0; k.public_modulus_len()];
+                k.sign(encoding, &rng, message, &mut signature)
+                    .map(|()| signature)
+            }
+            KeyPair::Ed25519(ref k) => Ok(k.sign(message).as_ref().to_owned()),
+        };
+        signature.map_err(|ring::error::Unspecified| {
+            rustls::TLSError::General("Signing Failed".to_owned())
+        })
     }
 
     fn get_scheme(&self) -> rustls::SignatureScheme {
-        SIGNATURE_ALG_RUSTLS_SCHEME
+        self.scheme
     }
 }
 
@@ -195,7 +401,10 @@ impl TokenSource {
 impl TrustAnchors {
     #[cfg(test)]
     fn empty() -> Self {
-        TrustAnchors(Arc::new(rustls::ClientConfig::new()))
+        TrustAnchors {
+            client: Arc::new(rustls::ClientConfig::new()),
+            params: TlsParams::default(),
+        }
     }
 
     pub fn from_pem(s: &str) -> Option<Self> {
@@ -223,11 +432,43 @@ impl TrustAnchors {
         // more tested.
         c.enable_tickets = false;
 
-        Some(TrustAnchors(Arc::new(c)))
+        let params = TlsParams::default();
+        params.apply(&mut c);
+        Some(TrustAnchors {
+            client: Arc::new(c),
+            params,
+        })
+    }
+
+    /// Returns trust anchors that trust the roots of both `self` and `other`,
+    /// e.g. while trust anchors are being rotated.
+    pub fn union(&self, other: &TrustAnchors) -> Self {
+        let mut c = self.client.as_ref().clone();
+        c.root_store
+            .roots
+            .extend(other.client.root_store.roots.iter().cloned());
+        TrustAnchors {
+            client: Arc::new(c),
+            params: self.params.clone(),
+        }
+    }
+
+    /// Returns trust anchors that use `params` for TLS.
+    pub fn with_params(&self, params: &TlsParams) -> Self {
+        let mut c = self.client.as_ref().clone();
+        params.apply(&mut c);
+        TrustAnchors {
+            client: Arc::new(c),
+            params: params.clone(),
+        }
+    }
+
+    pub fn params(&self) -> &TlsParams {
+        &self.params
     }
 
     pub fn certify(&self, key: Key, crt: Crt) -> Result<CrtKey, InvalidCrt> {
-        let mut client = self.0.as_ref().clone();
+        let mut client = self.client.as_ref().clone();
 
         // Ensure the certificate is valid for the services we terminate for
         // TLS. This assumes that server cert validation does the same or
@@ -251,10 +492,20 @@ impl TrustAnchors {
                 NO_OCSP,
             )
             .map_err(InvalidCrt)?;
+        if !self.params.supports(&key) {
+            return Err(InvalidCrt::malformed(
+                "Ed25519 keys can only be used when TLS 1.2 is not",
+            ));
+        }
+        if !key.is_valid_for(&crt) {
+            return Err(InvalidCrt::malformed(
+                "the private key does not match the certificate",
+            ));
+        }
         debug!("certified {}", crt.name.as_ref());
 
-        let k = SigningKey(key.0.clone());
-        let key = rustls::sign::CertifiedKey::new(crt.chain, Arc::new(Box::new(k)));
+        let k = SigningKey(key);
+        let key = rustls::sign::CertifiedKey::new(crt.chain.clone(), Arc::new(Box::new(k)));
         let resolver = Arc::new(CertResolver(key));
 
         // Enable client authentication.
@@ -270,15 +521,25 @@ impl TrustAnchors {
         //
         // TODO: Change Rustls's API to Avoid needing to clone `root_cert_store`.
         let mut server = rustls::ServerConfig::new(
-            rustls::AllowAnyAnonymousOrAuthenticatedClient::new(self.0.root_store.clone()),
+            rustls::AllowAnyAnonymousOrAuthenticatedClient::new(self.client.root_store.clone()),
         );
-        server.versions = TLS_VERSIONS.to_vec();
+        server.versions = self.params.server_versions.clone();
+        server.ciphersuites = self.params.cipher_suites.clone();
         server.cert_resolver = resolver;
 
+        // Clients that speak HTTP/2 on a connection offer `l5d-h2`, so that
+        // it need not be detected.
+        server.set_protocols(&[tls::L5D_H2.to_vec()]);
+
+        let mut h2_client = client.clone();
+        h2_client.set_protocols(&[tls::L5D_H2.to_vec()]);
+
         Ok(CrtKey {
             name: crt.name,
             expiry: crt.expiry,
+            chain: crt.chain,
             client_config: Arc::new(client),
+            h2_client_config: Arc::new(h2_client),
             server_config: Arc::new(server),
         })
     }
@@ -286,7 +547,13 @@ impl TrustAnchors {
 
 impl tls::client::HasConfig for TrustAnchors {
     fn tls_client_config(&self) -> Arc<rustls::ClientConfig> {
-        self.0.clone()
+        self.client.clone()
+    }
+
+    /// Without a certificate, connections are not meshed, so no protocol is
+    /// offered.
+    fn tls_client_config_offering(&self, _: &'static [u8]) -> Option<Arc<rustls::ClientConfig>> {
+        None
     }
 }
 
@@ -296,6 +563,139 @@ impl fmt::Debug for TrustAnchors {
     }
 }
 
+// === impl TlsVersion ===
+
+impl TlsVersion {
+    fn allows(&self, version: rustls::ProtocolVersion) -> bool {
+        match (*self, version) {
+            (_, rustls::ProtocolVersion::TLSv1_3) => true,
+            (TlsVersion::Tls12, rustls::ProtocolVersion::TLSv1_2) => true,
+            _ => false,
+        }
+    }
+}
+
+// === impl TlsParams ===
+
+impl Default for TlsParams {
+    fn default() -> Self {
+        Self {
+            server_versions: DEFAULT_SERVER_TLS_VERSIONS.to_vec(),
+            client_versions: DEFAULT_CLIENT_TLS_VERSIONS.to_vec(),
+            cipher_suites: rustls::ALL_CIPHERSUITES.to_vec(),
+        }
+    }
+}
+
+impl TlsParams {
+    /// Restricts TLS to versions of at least `min_version` and to
+    /// `cipher_suites`, for both clients and servers.
+    ///
+    /// Versions below the minimum are removed from the default versions.
+    /// Servers, which only accept TLS 1.2 by default, accept TLS 1.3 when it
+    /// is the minimum. Versions that none of the cipher suites can be used
+    /// with are not allowed.
+    pub fn new(
+        min_version: Option<TlsVersion>,
+        cipher_suites: Option<Vec<CipherSuite>>,
+    ) -> Result<Self, NoUsableCipherSuites> {
+        let mut params = Self::default();
+        if let Some(suites) = cipher_suites {
+            params.cipher_suites = suites.into_iter().map(|CipherSuite(s)| s).collect();
+        }
+        if let Some(min) = min_version {
+            let allowed = |v: &rustls::ProtocolVersion| min.allows(*v);
+            params.server_versions.retain(&allowed);
+            params.client_versions.retain(&allowed);
+            if params.server_versions.is_empty() {
+                params.server_versions = params.client_versions.clone();
+            }
+        }
+
+        {
+            let suites = &params.cipher_suites;
+            let usable =
+                |v: &rustls::ProtocolVersion| suites.iter().any(|s| s.usable_for_version(*v));
+            params.server_versions.retain(&usable);
+            params.client_versions.retain(&usable);
+        }
+        if params.server_versions.is_empty() || params.client_versions.is_empty() {
+            return Err(NoUsableCipherSuites);
+        }
+        Ok(params)
+    }
+
+    /// Returns whether `key` can be used with every allowed TLS version.
+    ///
+    /// rustls only signs with Ed25519 keys in TLS 1.3, so they cannot be used
+    /// when TLS 1.2 is allowed.
+    pub fn supports(&self, key: &Key) -> bool {
+        match *key.0 {
+            KeyPair::Ed25519(_) => {
+                let tls12 = rustls::ProtocolVersion::TLSv1_2;
+                !self.server_versions.contains(&tls12) && !self.client_versions.contains(&tls12)
+            }
+            _ => true,
+        }
+    }
+
+    fn apply(&self, client: &mut rustls::ClientConfig) {
+        client.versions = self.client_versions.clone();
+        client.ciphersuites = self.cipher_suites.clone();
+    }
+
+    pub fn to_json(&self) -> json::Value {
+        let versions = |vs: &[rustls::ProtocolVersion]| {
+            json::Value::Array(
+                vs.iter()
+                    .map(|v| json::Value::String(format!("{:?}", v)))
+                    .collect(),
+            )
+        };
+        json::object(vec![
+            ("server_versions", versions(&self.server_versions)),
+            ("client_versions", versions(&self.client_versions)),
+            (
+                "cipher_suites",
+                json::Value::Array(
+                    self.cipher_suites
+                        .iter()
+                        .map(|s| json::Value::String(CipherSuite(*s).name()))
+                        .collect(),
+                ),
+            ),
+            (
+                "kx_groups",
+                json::Value::Array(
+                    KX_GROUPS
+                        .iter()
+                        .map(|g| json::Value::String((*g).to_owned()))
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+// === impl CipherSuite ===
+
+impl CipherSuite {
+    /// Finds a cipher suite that rustls supports by its IANA name, e.g.
+    /// `TLS_AES_128_GCM_SHA256` or `TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        rustls::ALL_CIPHERSUITES
+            .iter()
+            .map(|s| CipherSuite(*s))
+            .find(|s| s.name() == name)
+    }
+
+    /// Returns the suite's IANA name.
+    pub fn name(&self) -> String {
+        // rustls prefixes the names of TLS 1.3 suites with `TLS13_`.
+        format!("{:?}", self.0.suite).replacen("TLS13_", "TLS_", 1)
+    }
+}
+
 // === Crt ===
 
 impl Crt {
@@ -310,14 +710,96 @@ impl Crt {
             expiry,
         }
     }
+
+    /// Reads a chain of PEM-encoded certificates, leaf first. The chain's
+    /// expiry is read from the leaf certificate.
+    pub fn from_pem(name: Name, pem: &[u8]) -> Result<Self, InvalidCrt> {
+        use self::rustls::internal::pemfile;
+        use std::io::Cursor;
+
+        let chain = pemfile::certs(&mut Cursor::new(pem))
+            .map_err(|()| InvalidCrt::malformed("invalid PEM"))?;
+        Self::from_chain(name, chain)
+    }
+
+    /// Reads a chain of concatenated DER-encoded certificates, leaf first, as
+    /// in an X.509-SVID. The chain's expiry is read from the leaf certificate.
+    pub fn from_der(name: Name, der: &[u8]) -> Result<Self, InvalidCrt> {
+        let chain = der::split(der)
+            .ok_or_else(|| InvalidCrt::malformed("invalid DER"))?
+            .into_iter()
+            .map(|c| rustls::Certificate(c.to_vec()))
+            .collect();
+        Self::from_chain(name, chain)
+    }
+
+    fn from_chain(name: Name, chain: Vec<rustls::Certificate>) -> Result<Self, InvalidCrt> {
+        let expiry = {
+            let leaf = chain
+                .first()
+                .ok_or_else(|| InvalidCrt::malformed("no certificates"))?;
+            der::not_after(&leaf.0)
+                .ok_or_else(|| InvalidCrt::malformed("could not read the certificate's expiry"))?
+        };
+        Ok(Self {
+            name,
+            chain,
+            expiry,
+        })
+    }
+
+    pub fn expiry(&self) -> SystemTime {
+        self.expiry
+    }
+
+    /// Returns whether the leaf certificate has `uri` as a subject alternative
+    /// name, e.g. the SPIFFE ID of an X.509-SVID.
+    pub fn has_uri_san(&self, uri: &str) -> bool {
+        let san = format!("URI:{}", uri);
+        self.chain
+            .first()
+            .and_then(|leaf| der::describe(&leaf.0))
+            .map(|info| info.sans.contains(&san))
+            .unwrap_or(false)
+    }
 }
 
 // === CrtKey ===
 
+impl CrtKey {
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn expiry(&self) -> SystemTime {
+        self.expiry
+    }
+
+    /// Describes each certificate in the chain, leaf first. Certificates that
+    /// cannot be parsed are omitted.
+    pub fn describe_chain(&self) -> Vec<CrtInfo> {
+        self.chain
+            .iter()
+            .filter_map(|c| der::describe(&c.0))
+            .collect()
+    }
+}
+
 impl tls::client::HasConfig for CrtKey {
     fn tls_client_config(&self) -> Arc<tls::client::Config> {
         self.client_config.clone()
     }
+
+    fn tls_client_config_offering(
+        &self,
+        protocol: &'static [u8],
+    ) -> Option<Arc<tls::client::Config>> {
+        if protocol == tls::L5D_H2 {
+            return Some(self.h2_client_config.clone());
+        }
+
+        None
+    }
 }
 
 impl tls::listen::HasConfig for CrtKey {
@@ -362,7 +844,7 @@ impl CertResolver {
         &self,
         sigschemes: &[rustls::SignatureScheme],
     ) -> Option<rustls::sign::CertifiedKey> {
-        if !sigschemes.contains(&SIGNATURE_ALG_RUSTLS_SCHEME) {
+        if self.0.key.choose_scheme(sigschemes).is_none() {
             debug!("signature scheme not supported -> no certificate");
             return None;
         }
@@ -402,8 +884,42 @@ impl rustls::ResolvesServerCert for CertResolver {
     }
 }
 
+// === impl CrtInfo ===
+
+impl CrtInfo {
+    pub fn to_json(&self) -> json::Value {
+        json::object(vec![
+            ("subject", json::Value::String(self.subject.clone())),
+            ("issuer", json::Value::String(self.issuer.clone())),
+            (
+                "sans",
+                json::Value::Array(
+                    self.sans
+                        .iter()
+                        .map(|san| json::Value::String(san.clone()))
+                        .collect(),
+                ),
+            ),
+            (
+                "not_before",
+                json::Value::String(der::format_time(self.not_before)),
+            ),
+            (
+                "not_after",
+                json::Value::String(der::format_time(self.not_after)),
+            ),
+        ])
+    }
+}
+
 // === impl InvalidCrt ===
 
+impl InvalidCrt {
+    fn malformed(msg: &str) -> Self {
+        InvalidCrt(rustls::TLSError::General(msg.to_owned()))
+    }
+}
+
 impl fmt::Display for InvalidCrt {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Display::fmt(&self.0, f)
@@ -423,12 +939,44 @@ impl Error for InvalidCrt {
 #[cfg(test)]
 mod tests {
     use super::test_util::*;
+    use super::*;
+    use std::fs;
+
+    fn read_key(name: &str) -> Vec<u8> {
+        fs::read(format!("src/identity/testdata/keys/{}.p8", name)).unwrap()
+    }
 
     #[test]
     fn can_construct_client_and_server_config_from_valid_settings() {
         FOO_NS1.validate().expect("foo.ns1 must be valid");
     }
 
+    #[test]
+    fn servers_accept_l5d_h2() {
+        let crt_key = FOO_NS1.validate().expect("foo.ns1 must be valid");
+        assert_eq!(
+            crt_key.server_config.alpn_protocols,
+            vec![tls::L5D_H2.to_vec()]
+        );
+        assert!(crt_key.client_config.alpn_protocols.is_empty());
+    }
+
+    #[test]
+    fn clients_offer_l5d_h2_from_a_shared_config() {
+        use transport::tls::client::HasConfig;
+
+        let crt_key = FOO_NS1.validate().expect("foo.ns1 must be valid");
+        let a = crt_key
+            .tls_client_config_offering(tls::L5D_H2)
+            .expect("l5d-h2 must be offered");
+        let b = crt_key
+            .tls_client_config_offering(tls::L5D_H2)
+            .expect("l5d-h2 must be offered");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.alpn_protocols, vec![tls::L5D_H2.to_vec()]);
+        assert!(crt_key.tls_client_config_offering(b"h2").is_none());
+    }
+
     #[test]
     fn recognize_ca_did_not_issue_cert() {
         let s = Strings {
@@ -449,7 +997,22 @@ mod tests {
     }
 
     #[test]
-    #[ignore] // XXX this doesn't fail because we don't actually check the key against the cert...
+    fn union_trusts_the_roots_of_both() {
+        let ca1 = FOO_NS1.trust_anchors();
+        let ca2 = Strings {
+            trust_anchors: "ca2.pem",
+            ..FOO_NS1
+        }
+        .trust_anchors();
+
+        assert!(ca2.certify(FOO_NS1.key(), FOO_NS1.crt()).is_err());
+        assert!(ca2
+            .union(&ca1)
+            .certify(FOO_NS1.key(), FOO_NS1.crt())
+            .is_ok());
+    }
+
+    #[test]
     fn recognize_private_key_is_not_valid_for_cert() {
         let s = Strings {
             key: BAR_NS1.key,
@@ -457,4 +1020,106 @@ mod tests {
         };
         assert!(s.validate().is_err(), "identity should not be valid");
     }
+
+    #[test]
+    fn reads_keys_of_each_supported_algorithm() {
+        match *Key::from_pkcs8(&read_key("ecdsa-p384")).unwrap().0 {
+            KeyPair::Ecdsa(_, rustls::SignatureScheme::ECDSA_NISTP384_SHA384) => {}
+            ref k => panic!("expected a P-384 key: {:?}", k),
+        }
+        match *Key::from_pkcs8(&read_key("rsa")).unwrap().0 {
+            KeyPair::Rsa(_) => {}
+            ref k => panic!("expected an RSA key: {:?}", k),
+        }
+        match *Key::from_pkcs8(&read_key("ed25519")).unwrap().0 {
+            KeyPair::Ed25519(_) => {}
+            ref k => panic!("expected an Ed25519 key: {:?}", k),
+        }
+        assert!(Key::from_pkcs8(b"not a key").is_err());
+    }
+
+    #[test]
+    fn ed25519_keys_require_tls13() {
+        let ed25519 = Key::from_pkcs8(&read_key("ed25519")).unwrap();
+        let ecdsa = Key::from_pkcs8(&read_key("ecdsa-p384")).unwrap();
+
+        let params = TlsParams::default();
+        assert!(!params.supports(&ed25519));
+        assert!(params.supports(&ecdsa));
+
+        let params = TlsParams::new(Some(TlsVersion::Tls12), None).unwrap();
+        assert!(!params.supports(&ed25519));
+        assert_eq!(
+            params.server_versions, DEFAULT_SERVER_TLS_VERSIONS,
+            "a minimum of TLS 1.2 does not change the server's versions"
+        );
+        assert_eq!(params.client_versions, DEFAULT_CLIENT_TLS_VERSIONS);
+
+        let params = TlsParams::new(Some(TlsVersion::Tls13), None).unwrap();
+        assert!(params.supports(&ed25519));
+        assert!(params.supports(&ecdsa));
+    }
+
+    #[test]
+    fn keys_only_choose_offered_schemes() {
+        let ecdsa = &[rustls::SignatureScheme::ECDSA_NISTP256_SHA256];
+        let ed25519 = &[rustls::SignatureScheme::ED25519];
+
+        let key = Key::from_pkcs8(&read_key("ed25519")).unwrap();
+        assert!(key.0.choose_scheme(ecdsa).is_none());
+        assert!(key.0.choose_scheme(ed25519).is_some());
+
+        let key = Key::from_pkcs8(&read_key("rsa")).unwrap();
+        let offered = &[
+            rustls::SignatureScheme::RSA_PKCS1_SHA256,
+            rustls::SignatureScheme::RSA_PSS_SHA256,
+        ];
+        assert_eq!(
+            key.0.choose_scheme(offered),
+            Some(rustls::SignatureScheme::RSA_PSS_SHA256)
+        );
+        assert!(key.0.choose_scheme(ed25519).is_none());
+    }
+
+    #[test]
+    fn finds_cipher_suites_by_iana_name() {
+        for name in &[
+            "TLS_AES_128_GCM_SHA256",
+            "TLS_CHACHA20_POLY1305_SHA256",
+            "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        ] {
+            let suite = CipherSuite::from_name(name).expect("suite must be supported");
+            assert_eq!(&suite.name(), name);
+        }
+        assert!(CipherSuite::from_name("TLS_RSA_WITH_RC4_128_SHA").is_none());
+    }
+
+    #[test]
+    fn tls_versions_require_usable_cipher_suites() {
+        let tls12 = CipherSuite::from_name("TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256").unwrap();
+        let tls13 = CipherSuite::from_name("TLS_AES_128_GCM_SHA256").unwrap();
+
+        assert!(TlsParams::new(Some(TlsVersion::Tls13), Some(vec![tls12])).is_err());
+
+        let params = TlsParams::new(None, Some(vec![tls12, tls13])).unwrap();
+        assert_eq!(
+            params.server_versions,
+            vec![rustls::ProtocolVersion::TLSv1_2]
+        );
+
+        assert!(
+            TlsParams::new(Some(TlsVersion::Tls12), Some(vec![tls13])).is_err(),
+            "servers only accept TLS 1.2 unless the minimum is TLS 1.3"
+        );
+
+        let params = TlsParams::new(Some(TlsVersion::Tls13), Some(vec![tls13])).unwrap();
+        assert_eq!(
+            params.server_versions,
+            vec![rustls::ProtocolVersion::TLSv1_3]
+        );
+        assert_eq!(
+            params.client_versions,
+            vec![rustls::ProtocolVersion::TLSv1_3]
+        );
+    }
 }
@@ -34,12 +34,15 @@ ee() {
   openssl pkcs8 -topk8 -nocrypt -inform pem -outform der \
     -in "${ee}-key.pem" \
     -out "${ee}/key.p8"
+  openssl pkcs8 -topk8 -nocrypt -inform pem -outform pem \
+    -in "${ee}-key.pem" \
+    -out "${ee}/key.pem"
   rm "${ee}-key.pem"
 
   openssl x509 -inform pem -outform der \
     -in "${ee}.pem" \
     -out "${ee}/crt.der"
-  rm "${ee}.pem"
+  mv "${ee}.pem" "${ee}/crt.pem"
 
   ## TODO DER-encode?
   #openssl x509 -inform pem -outform der \
@@ -57,3 +60,46 @@ ee ca1 controller linkerd linkerd
 ee ca1 foo ns1 linkerd
 ee ca2 foo ns1 linkerd # Same, but different CA
 ee ca1 bar ns1 linkerd # Different service.
+
+# Keys of each other supported type.
+key() {
+  name=$1
+  shift
+
+  mkdir -p keys
+  openssl genpkey "$@" \
+    | openssl pkcs8 -topk8 -nocrypt -outform der -out "keys/${name}.p8"
+}
+
+key rsa -algorithm RSA -pkeyopt rsa_keygen_bits:2048
+key ecdsa-p384 -algorithm EC -pkeyopt ec_paramgen_curve:P-384
+key ed25519 -algorithm ED25519
+
+# An X.509-SVID, which has a SPIFFE ID as a URI SAN in addition to the DNS SAN.
+svid() {
+  ca_name=$1
+  ee_name=$2
+  ee_ns=$3
+  cp_ns=$4
+
+  hostname="${ee_name}.${ee_ns}.serviceaccount.identity.${cp_ns}.cluster.local"
+  spiffe_id="spiffe://cluster.local/ns/${ee_ns}/sa/${ee_name}"
+
+  ee="${ee_name}-${ee_ns}-${ca_name}-svid"
+  mkdir -p "${ee}"
+  openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "${ee}-key.pem"
+  openssl pkcs8 -topk8 -nocrypt -inform pem -outform der \
+    -in "${ee}-key.pem" \
+    -out "${ee}/key.p8"
+  openssl req -new -key "${ee}-key.pem" -subj "/" \
+    | openssl x509 -req -CA "${ca_name}.pem" -CAkey "${ca_name}-key.pem" -set_serial 1 \
+        -extfile <(printf '%s\n' \
+          "keyUsage = critical, digitalSignature, keyEncipherment" \
+          "extendedKeyUsage = serverAuth, clientAuth" \
+          "basicConstraints = critical, CA:FALSE" \
+          "subjectAltName = critical, DNS:${hostname}, URI:${spiffe_id}") \
+        -sha256 -outform der -out "${ee}/crt.der"
+  rm "${ee}-key.pem"
+}
+
+svid ca1 foo ns1 linkerd
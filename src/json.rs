@@ -0,0 +1,474 @@
+//! A minimal JSON parser and serializer.
+//!
+//! The proxy only reads and writes small documents (configuration files and
+//! admin responses), so a simple value tree is sufficient. This avoids
+//! depending on `serde` and `serde_json`, which the proxy does not otherwise
+//! use, for a handful of documents. The parser accepts only strict RFC 8259
+//! JSON, and it bounds the nesting of documents (see `MAX_DEPTH`).
+
+use indexmap::IndexMap;
+use std::{error, fmt};
+
+/// Limits the nesting of arrays and objects so that parsing a malicious
+/// document cannot overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(IndexMap<String, Value>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    offset: usize,
+    reason: &'static str,
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+/// Parses a JSON document.
+pub fn parse(s: &str) -> Result<Value, Error> {
+    let mut parser = Parser {
+        bytes: s.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Builds an object from its fields.
+pub fn object<'a, I>(fields: I) -> Value
+where
+    I: IntoIterator<Item = (&'a str, Value)>,
+{
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+// === impl Value ===
+
+impl Value {
+    /// Returns the value of an object's field, if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.as_object().and_then(|o| o.get(key))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&IndexMap<String, Value>> {
+        match self {
+            Value::Object(o) => Some(o),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            // JSON cannot represent infinities or NaN.
+            Value::Number(n) if !n.is_finite() => f.write_str("null"),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => fmt_str(f, s),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    fmt::Display::fmt(v, f)?;
+                }
+                f.write_str("]")
+            }
+            Value::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    fmt_str(f, k)?;
+                    f.write_str(":")?;
+                    fmt::Display::fmt(v, f)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn fmt_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+// === impl Parser ===
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: &'static str) -> Error {
+        Error {
+            offset: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, reason: &'static str) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.peek() != Some(byte) {
+            return Err(self.error(reason));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, literal: &'static str, value: Value) -> Result<Value, Error> {
+        if !self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(self.error("unexpected character"));
+        }
+        self.pos += literal.len();
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("unexpected end of input")),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.array(depth),
+            Some(b'{') => self.object(depth),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, Error> {
+        self.pos += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, Error> {
+        self.pos += 1;
+        let mut fields = IndexMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.expect(b':', "expected ':'")?;
+            let value = self.value(depth + 1)?;
+            fields.insert(key, value);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, Error> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            // Leading zeros are not allowed.
+            self.pos += 1;
+            if let Some(b'0'..=b'9') = self.peek() {
+                return Err(self.error("invalid number"));
+            }
+        } else if !self.digits() {
+            return Err(self.error("expected a digit"));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !self.digits() {
+                return Err(self.error("expected a digit"));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !self.digits() {
+                return Err(self.error("expected a digit"));
+            }
+        }
+
+        // The number only consists of ASCII characters.
+        let s = ::std::str::from_utf8(&self.bytes[start..self.pos]).expect("number must be ASCII");
+        s.parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    /// Consumes a sequence of digits, returning false if there were none.
+    fn digits(&mut self) -> bool {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = self.escape()?;
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) if b < 0x20 => return Err(self.error("control character in string")),
+                Some(b) => {
+                    self.pos += 1;
+                    bytes.push(b);
+                }
+            }
+        }
+
+        // The input is valid UTF-8 and escapes are only split at ASCII
+        // characters, so the string's bytes are valid UTF-8.
+        Ok(String::from_utf8(bytes).expect("string must be UTF-8"))
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        let b = self
+            .peek()
+            .ok_or_else(|| self.error("unterminated string"))?;
+        self.pos += 1;
+        match b {
+            b'"' => Ok('"'),
+            b'\\' => Ok('\\'),
+            b'/' => Ok('/'),
+            b'b' => Ok('\u{8}'),
+            b'f' => Ok('\u{c}'),
+            b'n' => Ok('\n'),
+            b'r' => Ok('\r'),
+            b't' => Ok('\t'),
+            b'u' => {
+                let hi = self.hex4()?;
+                if hi < 0xD800 || hi > 0xDFFF {
+                    return ::std::char::from_u32(hi).ok_or_else(|| self.error("invalid escape"));
+                }
+
+                // Characters outside of the BMP are escaped as surrogate pairs.
+                if hi > 0xDBFF || !self.bytes[self.pos..].starts_with(b"\\u") {
+                    return Err(self.error("invalid surrogate pair"));
+                }
+                self.pos += 2;
+                let lo = self.hex4()?;
+                if lo < 0xDC00 || lo > 0xDFFF {
+                    return Err(self.error("invalid surrogate pair"));
+                }
+                let c = 0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00);
+                ::std::char::from_u32(c).ok_or_else(|| self.error("invalid escape"))
+            }
+            _ => Err(self.error("invalid escape")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut n = 0;
+        for _ in 0..4 {
+            let digit = self
+                .peek()
+                .and_then(|b| (b as char).to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            n = (n << 4) | digit;
+            self.pos += 1;
+        }
+        Ok(n)
+    }
+}
+
+// === impl Error ===
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.reason, self.offset)
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_documents() {
+        let doc = r#"
+            {
+                "null": null,
+                "bools": [true, false],
+                "numbers": [0, -1, 2.5, 1e3, -4.25E-2],
+                "string": "a \"quoted\" \\ \n \u00e9 \ud83d\ude00",
+                "empty": [{}, []]
+            }
+        "#;
+        let value = parse(doc).expect("document must parse");
+
+        assert_eq!(value.get("null"), Some(&Value::Null));
+        assert_eq!(
+            value.get("bools"),
+            Some(&Value::Array(vec![Value::Bool(true), Value::Bool(false)]))
+        );
+        let numbers = value
+            .get("numbers")
+            .and_then(Value::as_array)
+            .unwrap()
+            .iter()
+            .map(|n| n.as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, vec![0.0, -1.0, 2.5, 1000.0, -0.0425]);
+        assert_eq!(
+            value.get("string").and_then(Value::as_str),
+            Some("a \"quoted\" \\ \n \u{e9} \u{1f600}")
+        );
+        assert_eq!(
+            value.get("empty"),
+            Some(&Value::Array(vec![
+                Value::Object(IndexMap::new()),
+                Value::Array(vec![])
+            ]))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for doc in &[
+            "",
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "{a: 1}",
+            "01x",
+            "01",
+            "-00.5",
+            "-",
+            "1.",
+            "\"unterminated",
+            "\"\\x\"",
+            "\"\\ud800\"",
+            "nul",
+            "[] []",
+        ] {
+            assert!(parse(doc).is_err(), "{:?} must not parse", doc);
+        }
+
+        let nested = "[".repeat(MAX_DEPTH + 2);
+        assert_eq!(parse(&nested).unwrap_err().reason, "too deeply nested");
+    }
+
+    #[test]
+    fn round_trips() {
+        let doc = r#"{"a":[1,2.5,-3],"b":{"c":"d\"\n\u0001"},"e":null,"f":true}"#;
+        let value = parse(doc).expect("document must parse");
+        assert_eq!(value.to_string(), doc);
+    }
+}
@@ -13,6 +13,8 @@ extern crate http_body;
 extern crate httparse;
 extern crate hyper;
 extern crate ipnet;
+#[macro_use]
+extern crate lazy_static;
 #[cfg(target_os = "linux")]
 extern crate libc;
 #[macro_use]
@@ -21,6 +23,8 @@ extern crate indexmap;
 #[cfg(target_os = "linux")]
 extern crate procinfo;
 extern crate prost;
+#[macro_use]
+extern crate prost_derive;
 extern crate prost_types;
 #[cfg(test)]
 #[macro_use]
@@ -51,7 +55,9 @@ pub mod control;
 pub mod convert;
 mod dns;
 mod drain;
+mod fs_watch;
 mod identity;
+mod json;
 pub mod logging;
 mod proxy;
 mod svc;
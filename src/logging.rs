@@ -1,14 +1,17 @@
 use env_logger;
 use futures::future::{ExecuteError, Executor};
 use futures::{Future, Poll};
-use log::Level;
+use log::{self, Level};
 use std::cell::RefCell;
 use std::env;
 use std::fmt;
 use std::io::Write;
+use std::mem;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio_timer::clock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use tokio_timer::{clock, Delay};
 
 use task;
 
@@ -16,10 +19,53 @@ const ENV_LOG: &str = "LINKERD2_PROXY_LOG";
 
 thread_local! {
     static CONTEXT: RefCell<Vec<*const fmt::Display>> = RefCell::new(Vec::new());
+
+    /// Each thread's copy of the current logger, with its version, so that
+    /// logging only takes a lock after the filter has changed.
+    static CURRENT: RefCell<Option<(usize, Arc<env_logger::Logger>)>> = RefCell::new(None);
+}
+
+lazy_static! {
+    /// The process's logger, whose filter may be replaced at runtime (e.g. by
+    /// the admin server).
+    static ref LOGGER: Logger = Logger::new(clock::now(), true);
+}
+
+/// Versions are unique across loggers, so that a thread's copy of one logger
+/// is never mistaken for another's.
+static VERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Wraps an `env_logger::Logger` so that it may be rebuilt with a new filter.
+struct Logger {
+    start_time: Instant,
+    state: RwLock<State>,
+    /// The version of the current filter.
+    version: AtomicUsize,
+    /// Whether the `log` crate's max level follows the filter. Only the
+    /// installed logger sets it.
+    installed: bool,
+}
+
+struct State {
+    filter: String,
+    inner: Arc<env_logger::Logger>,
+    /// Changes each time the filter is replaced, so that a `Revert` does not
+    /// undo a later change.
+    version: usize,
+}
+
+/// Restores the filter that was replaced by `set_filter`.
+#[derive(Debug)]
+pub struct Revert {
+    filter: String,
+    version: usize,
 }
 
 pub fn formatted_builder() -> env_logger::Builder {
-    let start_time = clock::now();
+    builder(clock::now())
+}
+
+fn builder(start_time: Instant) -> env_logger::Builder {
     let mut builder = env_logger::Builder::new();
     builder.format(move |fmt, record| {
         CONTEXT.with(move |ctxt| {
@@ -47,9 +93,176 @@ pub fn formatted_builder() -> env_logger::Builder {
 }
 
 pub fn init() {
-    formatted_builder()
-        .parse(&env::var(ENV_LOG).unwrap_or_default())
-        .init();
+    // Like `env_logger`, ignore invalid directives in the environment.
+    LOGGER.set_filter(&env::var(ENV_LOG).unwrap_or_default());
+    log::set_logger(&*LOGGER).expect("logger must only be initialized once");
+}
+
+/// Returns the current log filter.
+pub fn filter() -> String {
+    LOGGER.filter()
+}
+
+/// Replaces the log filter, which has the same format as `LINKERD2_PROXY_LOG`.
+///
+/// The returned `Revert` may be used to restore the previous filter. Unlike
+/// the environment, a filter with invalid directives is rejected.
+pub fn set_filter(filter: &str) -> Result<Revert, String> {
+    validate(filter)?;
+    Ok(LOGGER.set_filter(filter))
+}
+
+/// Checks a filter's directives as `env_logger` parses them, since it only
+/// warns about invalid directives and ignores them.
+fn validate(filter: &str) -> Result<(), String> {
+    let mut parts = filter.split('/');
+    let directives = parts.next().unwrap_or("");
+    if parts.next().is_some() && parts.next().is_some() {
+        return Err("too many '/'s".to_owned());
+    }
+
+    for directive in directives.split(',').filter(|d| !d.is_empty()) {
+        let mut parts = directive.split('=');
+        let _module = parts.next();
+        let level = parts.next().map(str::trim);
+        if parts.next().is_some() {
+            return Err(format!("invalid directive {:?}", directive));
+        }
+        if let Some(level) = level.filter(|l| !l.is_empty()) {
+            if level.parse::<log::LevelFilter>().is_err() {
+                return Err(format!("invalid level {:?}", level));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// === impl Logger ===
+
+impl Logger {
+    fn new(start_time: Instant, installed: bool) -> Self {
+        let state = State {
+            filter: String::new(),
+            inner: Arc::new(builder(start_time).build()),
+            version: VERSIONS.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        Self {
+            start_time,
+            version: AtomicUsize::new(state.version),
+            state: RwLock::new(state),
+            installed,
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<State> {
+        // A panic while logging does not corrupt the state, so a poisoned
+        // lock is still usable.
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<State> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn filter(&self) -> String {
+        self.read().filter.clone()
+    }
+
+    fn set_filter(&self, filter: &str) -> Revert {
+        let mut state = self.write();
+        let previous = self.replace(&mut state, filter);
+        Revert {
+            filter: previous,
+            version: state.version,
+        }
+    }
+
+    /// Restores the filter that `revert` replaced, unless the filter has been
+    /// replaced again since. Returns whether the filter was restored.
+    fn revert(&self, revert: &Revert) -> bool {
+        let mut state = self.write();
+        if state.version != revert.version {
+            return false;
+        }
+        self.replace(&mut state, &revert.filter);
+        true
+    }
+
+    /// Replaces the filter, returning the previous filter.
+    fn replace(&self, state: &mut State, filter: &str) -> String {
+        // The builder's format is rebuilt with the original start time so that
+        // uptimes remain consistent.
+        state.inner = Arc::new(builder(self.start_time).parse(filter).build());
+        state.version = VERSIONS.fetch_add(1, Ordering::Relaxed) + 1;
+        self.version.store(state.version, Ordering::Release);
+        if self.installed {
+            log::set_max_level(state.inner.filter());
+        }
+        mem::replace(&mut state.filter, filter.to_owned())
+    }
+
+    /// Calls `f` with the current logger, using the thread's copy unless the
+    /// filter has changed.
+    fn with_current<F, T>(&self, f: F) -> T
+    where
+        F: Fn(&env_logger::Logger) -> T,
+    {
+        let version = self.version.load(Ordering::Acquire);
+        let cached = CURRENT.try_with(|current| {
+            // The thread's copy is unavailable if it is already in use, or if
+            // the thread is exiting.
+            let mut current = current.try_borrow_mut().ok()?;
+            let is_current = match *current {
+                Some((v, _)) => v == version,
+                None => false,
+            };
+            if !is_current {
+                let state = self.read();
+                *current = Some((state.version, state.inner.clone()));
+            }
+            current.as_ref().map(|&(_, ref inner)| f(inner))
+        });
+        match cached {
+            Ok(Some(t)) => t,
+            _ => f(&self.read().inner),
+        }
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.with_current(|inner| inner.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.with_current(|inner| inner.log(record))
+    }
+
+    fn flush(&self) {
+        self.with_current(|inner| inner.flush())
+    }
+}
+
+// === impl Revert ===
+
+impl Revert {
+    /// Restores the previous filter, unless the filter has been replaced again
+    /// since. Returns whether the filter was restored.
+    pub fn revert(self) -> bool {
+        LOGGER.revert(&self)
+    }
+
+    /// Restores the previous filter once `after` has elapsed.
+    pub fn revert_after(self, after: Duration) -> Box<Future<Item = (), Error = ()> + Send> {
+        let f = Delay::new(clock::now() + after).then(move |_| {
+            if LOGGER.revert(&self) {
+                info!("reverted log filter to {:?}", self.filter);
+            }
+            Ok::<(), ()>(())
+        });
+        Box::new(f)
+    }
 }
 
 /// Execute a closure with a `Display` item attached to allow log messages.
@@ -385,3 +598,57 @@ impl<T: fmt::Display> fmt::Display for Bg<T> {
         write!(f, "{}={{bg={}}}", self.section, self.name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverts_filter_unless_replaced() {
+        let logger = Logger::new(clock::now(), false);
+        let r0 = logger.set_filter("info");
+        let r1 = logger.set_filter("linkerd2_proxy=debug");
+        assert_eq!(logger.filter(), "linkerd2_proxy=debug");
+
+        // The filter that `r0` replaced has itself been replaced.
+        assert!(!logger.revert(&r0));
+        assert_eq!(logger.filter(), "linkerd2_proxy=debug");
+
+        assert!(logger.revert(&r1));
+        assert_eq!(logger.filter(), "info");
+    }
+
+    #[test]
+    fn uses_current_filter() {
+        use log::Log;
+
+        let logger = Logger::new(clock::now(), false);
+        let debug = log::Metadata::builder()
+            .level(Level::Debug)
+            .target("linkerd2_proxy::app")
+            .build();
+
+        logger.set_filter("info");
+        assert!(!logger.enabled(&debug));
+        let revert = logger.set_filter("info,linkerd2_proxy::app=debug");
+        assert!(logger.enabled(&debug));
+        logger.revert(&revert);
+        assert!(!logger.enabled(&debug));
+    }
+
+    #[test]
+    fn validates_filters() {
+        for filter in &[
+            "",
+            "info",
+            "linkerd2_proxy=debug,warn",
+            "foo=",
+            "info/ready",
+        ] {
+            assert!(validate(filter).is_ok(), "{:?} must be valid", filter);
+        }
+        for filter in &["linkerd2_proxy=loud", "a=b=c", "info/a/b"] {
+            assert!(validate(filter).is_err(), "{:?} must be invalid", filter);
+        }
+    }
+}
@@ -0,0 +1,653 @@
+//! A balancer that uses consistent hashing to provide session affinity.
+//!
+//! Each request is hashed on a key extracted from the request (a header, a
+//! cookie, or the client's IP address) and dispatched to the endpoint that
+//! follows the key's hash on a hash ring. Each endpoint is placed on the ring
+//! at a number of points proportional to its weight, so that keys are spread
+//! evenly. When an endpoint is added or removed, only the keys that map to
+//! that endpoint move. If the chosen endpoint is not ready, the next endpoint
+//! on the ring is used instead.
+//!
+//! The ring is rebuilt only when the set of endpoints changes, or when an
+//! endpoint's weight changes its number of points, as it does while the
+//! endpoint's weight ramps up after it is added.
+//!
+//! Requests that have no key (including all requests to a balancer without a
+//! key, which balances round-robin) are distributed across ready endpoints in
+//! turn, in proportion to their weights.
+
+use futures::{future, Async, Future, Poll};
+use http;
+use indexmap::IndexMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::{error, fmt};
+
+use super::tower_discover::{Change, Discover};
+use super::HasWeight;
+use proxy;
+use svc;
+
+/// Determines which part of a request is hashed to choose an endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Header(http::header::HeaderName),
+    Cookie(String),
+    /// The IP address of the client that originated the request, as reported
+    /// by the `forwarded` or `x-forwarded-for` headers.
+    ///
+    /// The proxy's own source address is not used: outbound requests are
+    /// always sent by the local application. Clients may set these headers
+    /// to any value, so they should only be used when a trusted proxy sets
+    /// them.
+    ForwardedFor,
+}
+
+pub struct Balance<D: Discover> {
+    discover: D,
+    key: Option<HashKey>,
+    endpoints: IndexMap<D::Key, D::Service>,
+
+    /// Points on the hash ring, sorted, with the index of the endpoint at
+    /// each point.
+    ring: Vec<(u64, usize)>,
+    /// Set when the endpoints change, so that the ring must be rebuilt.
+    ring_stale: bool,
+    /// The number of points that each endpoint has on the ring.
+    points: Vec<u64>,
+
+    /// Marks the endpoints that have been tried by the current request.
+    tried: Vec<bool>,
+
+    /// The weight that each endpoint has accrued towards being chosen for a
+    /// request without a key.
+    credits: Vec<f64>,
+}
+
+/// The number of points at which each endpoint with the greatest weight is
+/// placed on the ring.
+const POINTS_PER_ENDPOINT: u64 = 64;
+
+#[derive(Debug)]
+pub struct NotReady;
+
+// === impl HashKey ===
+
+impl HashKey {
+    /// Hashes the key of a request, if the request has one.
+    fn hash<B>(&self, req: &http::Request<B>) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            HashKey::Header(name) => {
+                let value = req.headers().get(name)?;
+                value.as_bytes().hash(&mut hasher);
+            }
+            HashKey::Cookie(name) => {
+                let value = cookie(req.headers(), name)?;
+                value.hash(&mut hasher);
+            }
+            HashKey::ForwardedFor => {
+                let ip = client_ip(req.headers())?;
+                ip.hash(&mut hasher);
+            }
+        }
+        Some(hasher.finish())
+    }
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashKey::Header(name) => write!(f, "header:{}", name),
+            HashKey::Cookie(name) => write!(f, "cookie:{}", name),
+            HashKey::ForwardedFor => write!(f, "forwarded-for"),
+        }
+    }
+}
+
+/// Returns the value of the named cookie, if the request has one.
+fn cookie<'a>(headers: &'a http::HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(value)) if n == name => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Returns the IP address of the client that originated a request, as
+/// reported by the first proxy to forward it.
+fn client_ip(headers: &http::HeaderMap) -> Option<IpAddr> {
+    let forwarded = headers
+        .get_all(http::header::FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(|c| c == ',' || c == ';'))
+        .filter_map(|pair| {
+            let mut parts = pair.trim().splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(value)) if n.eq_ignore_ascii_case("for") => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .and_then(|node| {
+            // IPv6 addresses are quoted and bracketed, and may have a port.
+            let node = node.trim_matches('"');
+            let node = if node.starts_with('[') {
+                node[1..].splitn(2, ']').next()?
+            } else {
+                node.splitn(2, ':').next()?
+            };
+            node.parse().ok()
+        });
+    if forwarded.is_some() {
+        return forwarded;
+    }
+
+    headers
+        .get("x-forwarded-for")?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// === impl Balance ===
+
+impl<D> Balance<D>
+where
+    D: Discover,
+    D::Service: HasWeight,
+    D::Error: Into<proxy::Error>,
+{
+    pub fn new(discover: D, key: HashKey) -> Self {
+        Self::with_key(discover, Some(key))
+    }
+
+    /// Returns a balancer that distributes all requests in turn.
+    pub fn round_robin(discover: D) -> Self {
+        Self::with_key(discover, None)
+    }
+
+    fn with_key(discover: D, key: Option<HashKey>) -> Self {
+        Self {
+            discover,
+            key,
+            endpoints: IndexMap::new(),
+            ring: Vec::new(),
+            ring_stale: false,
+            points: Vec::new(),
+            tried: Vec::new(),
+            credits: Vec::new(),
+        }
+    }
+
+    fn poll_discover(&mut self) -> Result<(), proxy::Error> {
+        loop {
+            match self.discover.poll().map_err(Into::into)? {
+                Async::NotReady => return Ok(()),
+                Async::Ready(Change::Insert(key, svc)) => {
+                    self.endpoints.insert(key, svc);
+                    self.ring_stale = true;
+                }
+                Async::Ready(Change::Remove(key)) => {
+                    self.endpoints.swap_remove(&key);
+                    self.ring_stale = true;
+                }
+            }
+        }
+    }
+
+    /// Rebuilds the ring if the endpoints, or their number of points, have
+    /// changed since it was last built.
+    fn update_ring(&mut self) {
+        let points = self.ring_points();
+        if !self.ring_stale && points == self.points {
+            return;
+        }
+
+        if self.ring_stale {
+            // Endpoints may have moved, so their credits are forgotten.
+            self.credits.clear();
+            self.credits.resize(self.endpoints.len(), 0.0);
+            self.ring_stale = false;
+        }
+
+        self.ring.clear();
+        for (idx, key) in self.endpoints.keys().enumerate() {
+            for point in 0..points[idx] {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                point.hash(&mut hasher);
+                self.ring.push((hasher.finish(), idx));
+            }
+        }
+        self.ring.sort_unstable();
+        self.points = points;
+    }
+
+    /// Returns the number of points that each endpoint should have on the
+    /// ring, given its current weight.
+    ///
+    /// A balancer without a key does not use the ring.
+    fn ring_points(&self) -> Vec<u64> {
+        if self.key.is_none() {
+            return vec![0; self.endpoints.len()];
+        }
+
+        let weights = self.endpoints.values().map(weight).collect::<Vec<_>>();
+        let max = weights.iter().cloned().fold(0.0, f64::max);
+        weights.into_iter().map(|w| points(w, max)).collect()
+    }
+
+    /// Returns the index of the first ready endpoint at or after `hash` on
+    /// the ring.
+    fn find_ready<A>(&mut self, hash: u64) -> Option<usize>
+    where
+        D::Service: svc::Service<http::Request<A>>,
+    {
+        if self.ring.is_empty() {
+            return None;
+        }
+
+        self.tried.clear();
+        self.tried.resize(self.endpoints.len(), false);
+        let mut remaining = self.endpoints.len();
+
+        let start = match self.ring.binary_search_by(|&(point, _)| point.cmp(&hash)) {
+            Ok(i) | Err(i) => i,
+        };
+        let len = self.ring.len();
+        for i in 0..len {
+            let (_, idx) = self.ring[(start + i) % len];
+            if self.tried[idx] {
+                continue;
+            }
+            if is_ready::<_, _, A>(&mut self.endpoints, idx) {
+                return Some(idx);
+            }
+            self.tried[idx] = true;
+            remaining -= 1;
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        None
+    }
+
+    /// Returns the index of the next ready endpoint, in turn.
+    ///
+    /// Endpoints are chosen by smooth weighted round-robin: each ready
+    /// endpoint accrues its weight, and the endpoint with the most credit is
+    /// chosen and spends the total weight of all ready endpoints. Over time,
+    /// each endpoint is chosen in proportion to its weight, and requests are
+    /// interleaved rather than sent to the same endpoint in bursts.
+    fn next_ready<A>(&mut self) -> Option<usize>
+    where
+        D::Service: svc::Service<http::Request<A>>,
+    {
+        let mut total = 0.0;
+        let mut chosen = None;
+        for idx in 0..self.endpoints.len() {
+            if !is_ready::<_, _, A>(&mut self.endpoints, idx) {
+                continue;
+            }
+            let w = {
+                let (_, svc) = self.endpoints.get_index(idx).expect("index in bounds");
+                weight(svc)
+            };
+            self.credits[idx] += w;
+            total += w;
+            chosen = match chosen {
+                Some(c) if self.credits[c] >= self.credits[idx] => Some(c),
+                _ => Some(idx),
+            };
+        }
+
+        if let Some(idx) = chosen {
+            self.credits[idx] -= total;
+        }
+        chosen
+    }
+}
+
+fn weight<S: HasWeight>(svc: &S) -> f64 {
+    svc.weight().into()
+}
+
+/// Returns the number of points on the ring for an endpoint with `weight`,
+/// when the greatest weight of any endpoint is `max`.
+fn points(weight: f64, max: f64) -> u64 {
+    if max <= 0.0 {
+        // If no endpoint has a weight, all are weighted equally.
+        return POINTS_PER_ENDPOINT;
+    }
+
+    (POINTS_PER_ENDPOINT as f64 * weight / max).round() as u64
+}
+
+fn is_ready<K, S, A>(endpoints: &mut IndexMap<K, S>, idx: usize) -> bool
+where
+    K: Hash + Eq,
+    S: svc::Service<http::Request<A>>,
+{
+    let (_, svc) = endpoints.get_index_mut(idx).expect("index in bounds");
+    svc.poll_ready().map(|a| a.is_ready()).unwrap_or(false)
+}
+
+impl<D, A> svc::Service<http::Request<A>> for Balance<D>
+where
+    D: Discover,
+    D::Error: Into<proxy::Error>,
+    D::Service: svc::Service<http::Request<A>> + HasWeight,
+    <D::Service as svc::Service<http::Request<A>>>::Error: Into<proxy::Error>,
+{
+    type Response = <D::Service as svc::Service<http::Request<A>>>::Response;
+    type Error = proxy::Error;
+    type Future = future::Either<
+        future::MapErr<
+            <D::Service as svc::Service<http::Request<A>>>::Future,
+            fn(<D::Service as svc::Service<http::Request<A>>>::Error) -> proxy::Error,
+        >,
+        future::FutureResult<Self::Response, proxy::Error>,
+    >;
+
+    /// Ready when any endpoint is ready. Endpoints that fail are removed.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.poll_discover()?;
+
+        let mut ready = false;
+        let mut idx = 0;
+        while idx < self.endpoints.len() {
+            let poll = {
+                let (_, svc) = self.endpoints.get_index_mut(idx).expect("index in bounds");
+                svc.poll_ready()
+            };
+            match poll {
+                Ok(Async::Ready(())) => ready = true,
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    let e: proxy::Error = e.into();
+                    debug!("removing failed endpoint: {}", e);
+                    self.endpoints.swap_remove_index(idx);
+                    self.ring_stale = true;
+                    continue;
+                }
+            }
+            idx += 1;
+        }
+
+        if ready {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        self.update_ring();
+
+        let hash = self.key.as_ref().and_then(|k| k.hash(&req));
+        let ready = match hash {
+            Some(hash) => self.find_ready::<A>(hash),
+            None => self.next_ready::<A>(),
+        };
+
+        match ready {
+            Some(idx) => {
+                let (_, svc) = self.endpoints.get_index_mut(idx).expect("index in bounds");
+                future::Either::A(svc.call(req).map_err(Into::into))
+            }
+            None => future::Either::B(future::err(NotReady.into())),
+        }
+    }
+}
+
+impl<D> fmt::Debug for Balance<D>
+where
+    D: Discover + fmt::Debug,
+    D::Key: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Balance")
+            .field("discover", &self.discover)
+            .field("key", &self.key)
+            .field("endpoints", &self.endpoints.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+// === impl NotReady ===
+
+impl fmt::Display for NotReady {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt("no endpoints are ready", f)
+    }
+}
+
+impl error::Error for NotReady {}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future, Poll};
+    use http;
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    use super::super::tower_discover::{Change, Discover};
+    use super::super::{HasWeight, Weight};
+    use super::{client_ip, cookie, points, Balance, HashKey, POINTS_PER_ENDPOINT};
+    use proxy;
+    use svc::Service;
+
+    /// An endpoint that is always ready and responds with its own ID.
+    struct Endpoint {
+        id: usize,
+        weight: Rc<Cell<f64>>,
+    }
+
+    impl Service<http::Request<()>> for Endpoint {
+        type Response = usize;
+        type Error = proxy::Error;
+        type Future = future::FutureResult<usize, proxy::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), proxy::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::ok(self.id)
+        }
+    }
+
+    impl HasWeight for Endpoint {
+        fn weight(&self) -> Weight {
+            self.weight.get().into()
+        }
+    }
+
+    struct Fixed(VecDeque<Change<usize, Endpoint>>);
+
+    impl Discover for Fixed {
+        type Key = usize;
+        type Service = Endpoint;
+        type Error = proxy::Error;
+
+        fn poll(&mut self) -> Poll<Change<usize, Endpoint>, proxy::Error> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    /// Returns a `Discover` that inserts an endpoint for each of `weights`,
+    /// and handles with which the endpoints' weights may be changed.
+    fn fixed(weights: &[f64]) -> (Fixed, Vec<Rc<Cell<f64>>>) {
+        let weights = weights
+            .iter()
+            .map(|&w| Rc::new(Cell::new(w)))
+            .collect::<Vec<_>>();
+        let changes = weights
+            .iter()
+            .enumerate()
+            .map(|(id, weight)| {
+                let weight = weight.clone();
+                Change::Insert(id, Endpoint { id, weight })
+            })
+            .collect();
+        (Fixed(changes), weights)
+    }
+
+    fn by_user(user: &'static str) -> http::Request<()> {
+        http::Request::builder()
+            .header("x-user", user)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn ring_points_are_weighted() {
+        assert_eq!(points(1.0, 1.0), POINTS_PER_ENDPOINT);
+        assert_eq!(points(0.5, 1.0), POINTS_PER_ENDPOINT / 2);
+        assert_eq!(points(0.0, 1.0), 0);
+        assert_eq!(points(0.0, 0.0), POINTS_PER_ENDPOINT);
+
+        let key = HashKey::Header(http::header::HeaderName::from_static("x-user"));
+        let (discover, _) = fixed(&[1.0, 0.5, 0.0]);
+        let mut balance = Balance::new(discover, key);
+        assert!(balance.poll_ready().unwrap().is_ready());
+        balance.update_ring();
+
+        let count = |idx| balance.ring.iter().filter(|&&(_, i)| i == idx).count() as u64;
+        assert_eq!(count(0), POINTS_PER_ENDPOINT);
+        assert_eq!(count(1), POINTS_PER_ENDPOINT / 2);
+        assert_eq!(count(2), 0);
+    }
+
+    #[test]
+    fn ring_follows_ramping_weights() {
+        let key = HashKey::Header(http::header::HeaderName::from_static("x-user"));
+        let (discover, weights) = fixed(&[1.0, 0.1]);
+        let mut balance = Balance::new(discover, key);
+        let count = |b: &Balance<Fixed>, idx| b.ring.iter().filter(|&&(_, i)| i == idx).count();
+
+        assert!(balance.poll_ready().unwrap().is_ready());
+        balance.call(by_user("alice")).wait().unwrap();
+        assert_eq!(count(&balance, 1), 6, "a new endpoint has few points");
+
+        weights[1].set(1.0);
+        assert!(balance.poll_ready().unwrap().is_ready());
+        balance.call(by_user("alice")).wait().unwrap();
+        assert_eq!(
+            count(&balance, 1) as u64,
+            POINTS_PER_ENDPOINT,
+            "the endpoint gains points as its weight ramps up"
+        );
+    }
+
+    #[test]
+    fn round_robin_is_weighted() {
+        let (discover, _) = fixed(&[2.0, 1.0, 1.0]);
+        let mut balance = Balance::round_robin(discover);
+        let mut counts = [0; 3];
+        let mut order = Vec::new();
+        for _ in 0..8 {
+            assert!(balance.poll_ready().unwrap().is_ready());
+            let id = balance
+                .call(http::Request::new(()))
+                .wait()
+                .expect("endpoint must respond");
+            counts[id] += 1;
+            order.push(id);
+        }
+
+        assert_eq!(counts, [4, 2, 2]);
+        assert_ne!(order[0], order[1], "requests are interleaved");
+    }
+
+    #[test]
+    fn cookie_values() {
+        let mut headers = http::HeaderMap::new();
+        headers.append(http::header::COOKIE, "a=1; session=abc".parse().unwrap());
+        headers.append(http::header::COOKIE, "b=2".parse().unwrap());
+
+        assert_eq!(cookie(&headers, "session"), Some("abc"));
+        assert_eq!(cookie(&headers, "b"), Some("2"));
+        assert_eq!(cookie(&headers, "sess"), None);
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        let key = HashKey::Header(http::header::HeaderName::from_static("x-user"));
+        assert_eq!(key.hash(&by_user("alice")), key.hash(&by_user("alice")));
+        assert_ne!(key.hash(&by_user("alice")), key.hash(&by_user("bob")));
+        assert_eq!(key.hash(&http::Request::new(())), None);
+        assert_eq!(HashKey::ForwardedFor.hash(&by_user("alice")), None);
+    }
+
+    #[test]
+    fn client_ips() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = http::HeaderMap::new();
+            for &(name, value) in pairs {
+                headers.append(name, value.parse().unwrap());
+            }
+            headers
+        };
+
+        assert_eq!(client_ip(&headers(&[])), None);
+        assert_eq!(
+            client_ip(&headers(&[("x-forwarded-for", "10.1.1.1, 10.2.2.2")])),
+            Some("10.1.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers(&[(
+                "forwarded",
+                "for=10.1.1.1;proto=http, for=10.2.2.2"
+            )])),
+            Some("10.1.1.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers(&[("forwarded", "For=\"[2001:db8::1]:4711\"")])),
+            Some("2001:db8::1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers(&[
+                ("forwarded", "for=unknown"),
+                ("x-forwarded-for", "10.3.3.3"),
+            ])),
+            Some("10.3.3.3".parse().unwrap())
+        );
+
+        let by_client = |ip: &'static str| {
+            http::Request::builder()
+                .header("x-forwarded-for", ip)
+                .body(())
+                .unwrap()
+        };
+        assert_eq!(
+            HashKey::ForwardedFor.hash(&by_client("10.1.1.1")),
+            HashKey::ForwardedFor.hash(&by_client("10.1.1.1"))
+        );
+        assert_ne!(
+            HashKey::ForwardedFor.hash(&by_client("10.1.1.1")),
+            HashKey::ForwardedFor.hash(&by_client("10.2.2.2"))
+        );
+    }
+}
@@ -0,0 +1,417 @@
+extern crate hyper_balance;
+extern crate tower_balance;
+extern crate tower_discover;
+
+use std::{any::Any, error::Error, fmt, marker::PhantomData, net::SocketAddr, time::Duration};
+
+use futures::{future, Async, Future, Poll};
+use hyper::body::Payload;
+
+use self::tower_balance::load::Instrument;
+use self::tower_discover::Discover;
+
+pub use self::hash::HashKey;
+pub use self::hyper_balance::{PendingUntilFirstData, PendingUntilFirstDataBody};
+pub use self::tower_balance::{
+    choose::PowerOfTwoChoices,
+    load::{WithPeakEwma, WithPendingRequests},
+    Balance, HasWeight, Weight, WithWeighted,
+};
+
+use http;
+use proxy::{
+    self,
+    http::fallback,
+    resolve::{EndpointStatus, HasEndpointStatus},
+};
+use svc;
+use Addr;
+
+pub mod hash;
+pub mod report;
+pub mod weight;
+
+/// Selects how a balancer distributes requests over its endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// Chooses the less loaded of two random endpoints, where load is a
+    /// peak-sensitive moving average of response latency, divided by the
+    /// endpoint's weight.
+    PeakEwma,
+
+    /// Chooses the less loaded of two random endpoints, where load is the
+    /// number of pending requests, divided by the endpoint's weight.
+    LeastRequests,
+
+    /// Chooses ready endpoints in turn, in proportion to their weights.
+    RoundRobin,
+
+    /// Chooses endpoints by hashing each request on a key, so that requests
+    /// with the same key are sent to the same endpoint. Endpoints are placed
+    /// on the hash ring in proportion to their weights.
+    ConsistentHash(HashKey),
+}
+
+/// Implemented by targets that may override a balancer's default algorithm.
+pub trait HasAlgorithm {
+    fn algorithm(&self) -> Option<&Algorithm>;
+}
+
+/// Configures a stack to resolve `T` typed targets to balance requests over
+/// `M`-typed endpoint stacks.
+#[derive(Debug)]
+pub struct Layer<A, B> {
+    decay: Duration,
+    default_rtt: Duration,
+    default_algorithm: Algorithm,
+    report: report::Report,
+    _marker: PhantomData<fn(A) -> B>,
+}
+
+/// Resolves `T` typed targets to balance requests over `M`-typed endpoint stacks.
+#[derive(Debug)]
+pub struct MakeSvc<M, A, B> {
+    decay: Duration,
+    default_rtt: Duration,
+    algorithm: Algorithm,
+    report: report::Report,
+    inner: M,
+    _marker: PhantomData<fn(A) -> B>,
+}
+
+#[derive(Debug)]
+pub struct MakeFuture<F, A, B> {
+    decay: Duration,
+    default_rtt: Duration,
+    algorithm: Algorithm,
+    report: report::Report,
+    dst: Addr,
+    inner: F,
+    _marker: PhantomData<fn(A) -> B>,
+}
+
+#[derive(Debug)]
+pub struct Service<S> {
+    balance: S,
+    status: EndpointStatus,
+}
+
+/// A balancer using one of the supported algorithms.
+///
+/// All algorithms instrument responses with the same body type, so that
+/// balancers using different algorithms may be used interchangeably.
+pub enum Balancer<D: Discover> {
+    PeakEwma(PeakEwmaBalance<D>),
+    LeastRequests(LeastRequestsBalance<D>),
+    RoundRobin(RoundRobinBalance<D>),
+    ConsistentHash(HashBalance<D>),
+}
+
+pub type PeakEwmaBalance<D> = Balance<
+    weight::WithWeight<report::Loads<WithPeakEwma<weight::Record<D>, InstrumentLoad>>>,
+    PowerOfTwoChoices,
+>;
+pub type LeastRequestsBalance<D> = Balance<
+    weight::WithWeight<WithPendingRequests<weight::Record<D>, InstrumentLoad>>,
+    PowerOfTwoChoices,
+>;
+pub type RoundRobinBalance<D> =
+    hash::Balance<weight::WithWeight<WithPendingRequests<weight::Record<D>, InstrumentLoad>>>;
+pub type HashBalance<D> =
+    hash::Balance<weight::WithWeight<WithPendingRequests<weight::Record<D>, InstrumentLoad>>>;
+
+/// Instruments responses with a type-erased load handle, holding it until
+/// the first data frame is received.
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentLoad(PendingUntilFirstData);
+
+/// A load handle of any load metric.
+pub struct LoadHandle(Box<dyn Any + Send>);
+
+/// The response body of all balancers.
+pub type Body<B> = PendingUntilFirstDataBody<LoadHandle, B>;
+
+#[derive(Debug)]
+pub struct NoEndpoints;
+
+// === impl Algorithm ===
+
+/// Formats the algorithm as it is configured.
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::PeakEwma => f.write_str("peak-ewma"),
+            Algorithm::LeastRequests => f.write_str("least-requests"),
+            Algorithm::RoundRobin => f.write_str("round-robin"),
+            Algorithm::ConsistentHash(HashKey::ForwardedFor) => {
+                f.write_str("consistent-hash:forwarded-for")
+            }
+            Algorithm::ConsistentHash(HashKey::Header(ref name)) => {
+                write!(f, "consistent-hash:header:{}", name.as_str())
+            }
+            Algorithm::ConsistentHash(HashKey::Cookie(ref name)) => {
+                write!(f, "consistent-hash:cookie:{}", name)
+            }
+        }
+    }
+}
+
+// === impl Layer ===
+
+pub fn layer<A, B>(
+    default_rtt: Duration,
+    decay: Duration,
+    default_algorithm: Algorithm,
+    report: report::Report,
+) -> Layer<A, B> {
+    Layer {
+        decay,
+        default_rtt,
+        default_algorithm,
+        report,
+        _marker: PhantomData,
+    }
+}
+
+impl<A, B> Clone for Layer<A, B> {
+    fn clone(&self) -> Self {
+        Layer {
+            decay: self.decay,
+            default_rtt: self.default_rtt,
+            default_algorithm: self.default_algorithm.clone(),
+            report: self.report.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M, A, B> svc::Layer<M> for Layer<A, B>
+where
+    A: Payload,
+    B: Payload,
+{
+    type Service = MakeSvc<M, A, B>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeSvc {
+            decay: self.decay,
+            default_rtt: self.default_rtt,
+            algorithm: self.default_algorithm.clone(),
+            report: self.report.clone(),
+            inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<M: Clone, A, B> Clone for MakeSvc<M, A, B> {
+    fn clone(&self) -> Self {
+        MakeSvc {
+            decay: self.decay,
+            default_rtt: self.default_rtt,
+            algorithm: self.algorithm.clone(),
+            report: self.report.clone(),
+            inner: self.inner.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, M, A, B> svc::Service<T> for MakeSvc<M, A, B>
+where
+    T: HasAlgorithm + AsRef<Addr>,
+    M: svc::Service<T>,
+    M::Response: Discover<Key = SocketAddr> + HasEndpointStatus,
+    <M::Response as Discover>::Service:
+        svc::Service<http::Request<A>, Response = http::Response<B>> + weight::HasRamp,
+    A: Payload,
+    B: Payload,
+{
+    type Response = Service<Balancer<M::Response>>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, A, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let algorithm = target
+            .algorithm()
+            .cloned()
+            .unwrap_or_else(|| self.algorithm.clone());
+        trace!("balancing with {:?}", algorithm);
+        let dst = target.as_ref().clone();
+        let inner = self.inner.call(target);
+
+        MakeFuture {
+            decay: self.decay,
+            default_rtt: self.default_rtt,
+            algorithm,
+            report: self.report.clone(),
+            dst,
+            inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F, A, B> Future for MakeFuture<F, A, B>
+where
+    F: Future,
+    F::Item: Discover<Key = SocketAddr> + HasEndpointStatus,
+    <F::Item as Discover>::Service:
+        svc::Service<http::Request<A>, Response = http::Response<B>> + weight::HasRamp,
+    A: Payload,
+    B: Payload,
+{
+    type Item = Service<Balancer<F::Item>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let discover = try_ready!(self.inner.poll());
+        let status = discover.endpoint_status();
+        let instrument = InstrumentLoad::default();
+        let balance = match self.algorithm {
+            Algorithm::PeakEwma => {
+                let (rtt, decay) = (self.default_rtt, self.decay);
+                let (dst, report) = (self.dst.clone(), self.report.clone());
+                let loaded = weight::with_load(discover, move |d| {
+                    let loaded = WithPeakEwma::new(d, rtt, decay, instrument);
+                    report::Loads::new(loaded, dst, report)
+                });
+                Balancer::PeakEwma(Balance::p2c(loaded))
+            }
+            Algorithm::LeastRequests => {
+                let loaded =
+                    weight::with_load(discover, |d| WithPendingRequests::new(d, instrument));
+                Balancer::LeastRequests(Balance::p2c(loaded))
+            }
+            Algorithm::RoundRobin => {
+                let loaded =
+                    weight::with_load(discover, |d| WithPendingRequests::new(d, instrument));
+                Balancer::RoundRobin(hash::Balance::round_robin(loaded))
+            }
+            Algorithm::ConsistentHash(ref key) => {
+                let loaded =
+                    weight::with_load(discover, |d| WithPendingRequests::new(d, instrument));
+                Balancer::ConsistentHash(hash::Balance::new(loaded, key.clone()))
+            }
+        };
+        Ok(Async::Ready(Service { balance, status }))
+    }
+}
+
+// === impl Balancer ===
+
+impl<D, A, B> svc::Service<http::Request<A>> for Balancer<D>
+where
+    D: Discover,
+    PeakEwmaBalance<D>:
+        svc::Service<http::Request<A>, Response = http::Response<B>, Error = proxy::Error>,
+    LeastRequestsBalance<D>:
+        svc::Service<http::Request<A>, Response = http::Response<B>, Error = proxy::Error>,
+    RoundRobinBalance<D>:
+        svc::Service<http::Request<A>, Response = http::Response<B>, Error = proxy::Error>,
+    HashBalance<D>:
+        svc::Service<http::Request<A>, Response = http::Response<B>, Error = proxy::Error>,
+{
+    type Response = http::Response<B>;
+    type Error = proxy::Error;
+    type Future = future::Either<
+        <PeakEwmaBalance<D> as svc::Service<http::Request<A>>>::Future,
+        future::Either<
+            <LeastRequestsBalance<D> as svc::Service<http::Request<A>>>::Future,
+            future::Either<
+                <RoundRobinBalance<D> as svc::Service<http::Request<A>>>::Future,
+                <HashBalance<D> as svc::Service<http::Request<A>>>::Future,
+            >,
+        >,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self {
+            Balancer::PeakEwma(b) => b.poll_ready(),
+            Balancer::LeastRequests(b) => b.poll_ready(),
+            Balancer::RoundRobin(b) => b.poll_ready(),
+            Balancer::ConsistentHash(b) => b.poll_ready(),
+        }
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        use futures::future::Either::{A as First, B as Rest};
+
+        match self {
+            Balancer::PeakEwma(b) => First(b.call(req)),
+            Balancer::LeastRequests(b) => Rest(First(b.call(req))),
+            Balancer::RoundRobin(b) => Rest(Rest(First(b.call(req)))),
+            Balancer::ConsistentHash(b) => Rest(Rest(Rest(b.call(req)))),
+        }
+    }
+}
+
+// === impl InstrumentLoad ===
+
+impl<H, B> Instrument<H, http::Response<B>> for InstrumentLoad
+where
+    H: Send + 'static,
+    B: Payload,
+{
+    type Output = http::Response<Body<B>>;
+
+    fn instrument(&self, handle: H, rsp: http::Response<B>) -> Self::Output {
+        self.0.instrument(LoadHandle(Box::new(handle)), rsp)
+    }
+}
+
+impl fmt::Debug for LoadHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("LoadHandle").finish()
+    }
+}
+
+impl<S, A, B> svc::Service<http::Request<A>> for Service<S>
+where
+    S: svc::Service<http::Request<A>, Response = http::Response<B>, Error = proxy::Error>,
+{
+    type Response = http::Response<B>;
+    type Error = fallback::Error<A>;
+    type Future = future::Either<
+        future::MapErr<S::Future, fn(proxy::Error) -> Self::Error>,
+        future::FutureResult<http::Response<B>, fallback::Error<A>>,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let ready = self.balance.poll_ready().map_err(fallback::Error::from)?;
+        if self.status.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(ready)
+        }
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        // The endpoint status is updated by the Discover instance, which is
+        // driven by calling `poll_ready` on the balancer.
+        if self.status.is_empty() {
+            trace!("no endpoints for {}", req.uri());
+            future::Either::B(future::err(fallback::Error::fallback(req, NoEndpoints)))
+        } else {
+            future::Either::A(self.balance.call(req).map_err(From::from))
+        }
+    }
+}
+
+// === impl NoEndpoints ===
+
+impl fmt::Display for NoEndpoints {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt("load balancer has no endpoints", f)
+    }
+}
+
+impl Error for NoEndpoints {}
@@ -0,0 +1,472 @@
+//! Describes each destination's balanced endpoints for the admin server.
+//!
+//! Endpoints are recorded as their resolutions report them, so that endpoints
+//! that have been ejected or are failing health checks are still listed.
+//! Balancers that use the peak-EWMA algorithm also record each endpoint's
+//! load as it was last observed by the balancer.
+
+use futures::{Async, Poll};
+use indexmap::{IndexMap, IndexSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::tower_balance::load::Load;
+use super::tower_discover::{Change, Discover};
+use json;
+use proxy::resolve::{self, Update};
+use svc;
+use Addr;
+
+/// Describes an endpoint's metadata (e.g. its labels and identity).
+pub trait Describe {
+    fn describe(&self) -> json::Value;
+}
+
+/// Lists the endpoints of each destination.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<Inner>>);
+
+/// Wraps a `Resolve` so that its resolutions' endpoints are recorded.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    report: Report,
+}
+
+pub struct Resolution<R> {
+    inner: R,
+    dst: Addr,
+    report: Report,
+    endpoints: IndexSet<SocketAddr>,
+}
+
+/// Wraps a balancer's `Discover` so that the load of each endpoint is
+/// recorded.
+pub struct Loads<D> {
+    inner: D,
+    dst: Addr,
+    report: Report,
+}
+
+/// An endpoint service that records its load.
+#[derive(Debug)]
+pub struct Loaded<S> {
+    inner: S,
+    load: Arc<LastLoad>,
+    key: Key,
+    report: Report,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Each endpoint's metadata, with the number of resolutions that include
+    /// it. Balancers for the same destination (e.g. for HTTP/1 and HTTP/2
+    /// requests) share a resolution's endpoints.
+    endpoints: IndexMap<Key, (usize, json::Value)>,
+    loads: IndexMap<Key, Arc<LastLoad>>,
+}
+
+/// The load of an endpoint as it was last observed, stored as the bits of an
+/// `f64` so that balancers need not take a lock to record it.
+#[derive(Debug)]
+struct LastLoad(AtomicU64);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    dst: Addr,
+    addr: SocketAddr,
+}
+
+// === impl Report ===
+
+impl Report {
+    /// Records an endpoint's metadata. `is_new` is false when a resolution
+    /// updates the metadata of an endpoint that it already includes.
+    fn add(&self, key: Key, metadata: json::Value, is_new: bool) {
+        if let Ok(mut inner) = self.0.lock() {
+            let entry = inner
+                .endpoints
+                .entry(key)
+                .or_insert_with(|| (0, json::Value::Null));
+            if is_new {
+                entry.0 += 1;
+            }
+            entry.1 = metadata;
+        }
+    }
+
+    fn remove(&self, key: &Key) {
+        if let Ok(mut inner) = self.0.lock() {
+            let remaining = match inner.endpoints.get_mut(key) {
+                Some(entry) => {
+                    entry.0 -= 1;
+                    entry.0
+                }
+                None => return,
+            };
+            if remaining == 0 {
+                inner.endpoints.swap_remove(key);
+            }
+        }
+    }
+
+    fn register_load(&self, key: Key) -> Arc<LastLoad> {
+        let load = Arc::new(LastLoad::default());
+        if let Ok(mut inner) = self.0.lock() {
+            inner.loads.insert(key, load.clone());
+        }
+        load
+    }
+
+    fn deregister_load(&self, key: &Key, load: &Arc<LastLoad>) {
+        if let Ok(mut inner) = self.0.lock() {
+            // The endpoint may have been replaced by another balancer.
+            let replaced = inner
+                .loads
+                .get(key)
+                .map(|l| !Arc::ptr_eq(l, load))
+                .unwrap_or(true);
+            if !replaced {
+                inner.loads.swap_remove(key);
+            }
+        }
+    }
+
+    /// Describes the endpoints of each destination.
+    pub fn to_json(&self) -> json::Value {
+        let inner = match self.0.lock() {
+            Ok(inner) => inner,
+            Err(_) => return json::Value::Null,
+        };
+
+        let mut by_dst = IndexMap::<String, Vec<json::Value>>::new();
+        for (key, &(_, ref metadata)) in &inner.endpoints {
+            let load = inner
+                .loads
+                .get(key)
+                .and_then(|l| l.get())
+                .map(json::Value::Number)
+                .unwrap_or(json::Value::Null);
+            by_dst
+                .entry(key.dst.to_string())
+                .or_insert_with(Vec::new)
+                .push(json::object(vec![
+                    ("addr", json::Value::String(key.addr.to_string())),
+                    ("metadata", metadata.clone()),
+                    ("load", load),
+                ]));
+        }
+
+        let dsts = by_dst.into_iter().map(|(dst, endpoints)| {
+            json::object(vec![
+                ("dst", json::Value::String(dst)),
+                ("endpoints", json::Value::Array(endpoints)),
+            ])
+        });
+        json::Value::Array(dsts.collect())
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, report: Report) -> Self {
+        Self { inner, report }
+    }
+}
+
+impl<T, R> resolve::Resolve<T> for Resolve<R>
+where
+    T: AsRef<Addr>,
+    R: resolve::Resolve<T>,
+    R::Endpoint: Describe,
+{
+    type Endpoint = R::Endpoint;
+    type Resolution = Resolution<R::Resolution>;
+
+    fn resolve(&self, target: &T) -> Self::Resolution {
+        Resolution {
+            inner: self.inner.resolve(target),
+            dst: target.as_ref().clone(),
+            report: self.report.clone(),
+            endpoints: IndexSet::new(),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R> {
+    fn key(&self, addr: SocketAddr) -> Key {
+        Key {
+            dst: self.dst.clone(),
+            addr,
+        }
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Describe,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        let update = try_ready!(self.inner.poll());
+        match update {
+            Update::Add(addr, ref endpoint) => {
+                let is_new = self.endpoints.insert(addr);
+                self.report.add(self.key(addr), endpoint.describe(), is_new);
+            }
+            Update::Remove(addr) => {
+                if self.endpoints.swap_remove(&addr) {
+                    self.report.remove(&self.key(addr));
+                }
+            }
+            Update::NoEndpoints => {}
+        }
+        Ok(Async::Ready(update))
+    }
+}
+
+impl<R> Drop for Resolution<R> {
+    fn drop(&mut self) {
+        for addr in self.endpoints.drain(..) {
+            self.report.remove(&Key {
+                dst: self.dst.clone(),
+                addr,
+            });
+        }
+    }
+}
+
+// === impl Loads ===
+
+impl<D> Loads<D> {
+    pub fn new(inner: D, dst: Addr, report: Report) -> Self {
+        Self { inner, dst, report }
+    }
+}
+
+impl<D> Discover for Loads<D>
+where
+    D: Discover<Key = SocketAddr>,
+{
+    type Key = SocketAddr;
+    type Service = Loaded<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(addr, inner) => {
+                let key = Key {
+                    dst: self.dst.clone(),
+                    addr,
+                };
+                let load = self.report.register_load(key.clone());
+                let svc = Loaded {
+                    inner,
+                    load,
+                    key,
+                    report: self.report.clone(),
+                };
+                Change::Insert(addr, svc)
+            }
+            Change::Remove(addr) => Change::Remove(addr),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl LastLoad ===
+
+/// No load has been observed: a NaN that is never stored by `set`, which
+/// stores NaNs canonically.
+const NO_LOAD: u64 = ::std::u64::MAX;
+
+impl Default for LastLoad {
+    fn default() -> Self {
+        LastLoad(AtomicU64::new(NO_LOAD))
+    }
+}
+
+impl LastLoad {
+    fn set(&self, load: f64) {
+        let bits = if load.is_nan() {
+            ::std::f64::NAN.to_bits()
+        } else {
+            load.to_bits()
+        };
+        self.0.store(bits, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> Option<f64> {
+        match self.0.load(Ordering::Relaxed) {
+            NO_LOAD => None,
+            bits => Some(f64::from_bits(bits)),
+        }
+    }
+}
+
+// === impl Loaded ===
+
+impl<S> Load for Loaded<S>
+where
+    S: Load,
+    S::Metric: Clone + Into<f64>,
+{
+    type Metric = S::Metric;
+
+    fn load(&self) -> Self::Metric {
+        let load = self.inner.load();
+        self.load.set(load.clone().into());
+        load
+    }
+}
+
+impl<S, Req> svc::Service<Req> for Loaded<S>
+where
+    S: svc::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+impl<S> Drop for Loaded<S> {
+    fn drop(&mut self) {
+        self.report.deregister_load(&self.key, &self.load);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::Poll;
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct Endpoint(&'static str);
+
+    impl Describe for Endpoint {
+        fn describe(&self) -> json::Value {
+            json::object(vec![("zone", json::Value::String(self.0.to_owned()))])
+        }
+    }
+
+    struct Fixed(Vec<Update<Endpoint>>);
+
+    struct Updates(VecDeque<Update<Endpoint>>);
+
+    impl resolve::Resolve<Addr> for Fixed {
+        type Endpoint = Endpoint;
+        type Resolution = Updates;
+
+        fn resolve(&self, _: &Addr) -> Updates {
+            Updates(self.0.clone().into())
+        }
+    }
+
+    impl resolve::Resolution for Updates {
+        type Endpoint = Endpoint;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Update<Endpoint>, ()> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    fn endpoints(report: &Report) -> Vec<(String, String)> {
+        let dsts = report.to_json();
+        let mut endpoints = Vec::new();
+        for dst in dsts.as_array().expect("report must be an array") {
+            let name = dst.get("dst").and_then(json::Value::as_str).unwrap();
+            for ep in dst
+                .get("endpoints")
+                .and_then(json::Value::as_array)
+                .unwrap()
+            {
+                let zone = ep
+                    .get("metadata")
+                    .and_then(|m| m.get("zone"))
+                    .and_then(json::Value::as_str)
+                    .unwrap();
+                endpoints.push((name.to_owned(), zone.to_owned()));
+            }
+        }
+        endpoints
+    }
+
+    #[test]
+    fn records_resolved_endpoints() {
+        let a = "10.1.1.1:80".parse().unwrap();
+        let b = "10.1.1.2:80".parse().unwrap();
+        let dst = Addr::Socket("10.1.1.0:80".parse().unwrap());
+        let report = Report::default();
+        let resolve = Resolve::new(
+            Fixed(vec![
+                Update::Add(a, Endpoint("west")),
+                Update::Add(b, Endpoint("east")),
+                Update::Add(a, Endpoint("north")),
+                Update::Remove(b),
+            ]),
+            report.clone(),
+        );
+
+        let mut r0 = resolve::Resolve::resolve(&resolve, &dst);
+        let mut r1 = resolve::Resolve::resolve(&resolve, &dst);
+        for _ in 0..3 {
+            resolve::Resolution::poll(&mut r0).unwrap();
+        }
+        assert_eq!(
+            endpoints(&report),
+            vec![
+                ("10.1.1.0:80".to_owned(), "north".to_owned()),
+                ("10.1.1.0:80".to_owned(), "east".to_owned()),
+            ]
+        );
+
+        // Another resolution of the same destination shares its endpoints,
+        // which remain until neither resolution includes them.
+        resolve::Resolution::poll(&mut r1).unwrap();
+        resolve::Resolution::poll(&mut r0).unwrap();
+        assert_eq!(
+            endpoints(&report),
+            vec![("10.1.1.0:80".to_owned(), "west".to_owned())]
+        );
+
+        drop(r1);
+        assert_eq!(
+            endpoints(&report),
+            vec![("10.1.1.0:80".to_owned(), "west".to_owned())]
+        );
+        drop(r0);
+        assert!(endpoints(&report).is_empty());
+    }
+
+    #[test]
+    fn records_last_load() {
+        let load = LastLoad::default();
+        assert_eq!(load.get(), None);
+        load.set(2.5);
+        assert_eq!(load.get(), Some(2.5));
+        load.set(::std::f64::NAN);
+        assert!(load.get().expect("load must be recorded").is_nan());
+    }
+}
@@ -0,0 +1,419 @@
+//! Weights balanced endpoints.
+//!
+//! An endpoint's weight may be ramped up when the endpoint is added to a
+//! balancer, so that a new endpoint (e.g. a backend that is still warming its
+//! caches) is not sent its full share of requests right away.
+//!
+//! Balancers that choose endpoints by load divide each endpoint's load by its
+//! current weight. Since the load is measured by a `Discover` that wraps the
+//! endpoints, the weight of each endpoint is recorded before the endpoint is
+//! instrumented (by `Record`) and applied to the instrumented endpoint
+//! afterwards (by `WithWeight`).
+
+extern crate linkerd2_router as rt;
+
+use futures::{Async, Poll};
+use indexmap::IndexMap;
+use std::f64;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+
+use super::tower_balance::{load::Load, HasWeight, Weight};
+use super::tower_discover::{Change, Discover};
+use svc;
+
+/// Configures how the weight of a new endpoint ramps up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SlowStart {
+    /// The duration over which an endpoint's weight ramps up to its full
+    /// weight.
+    pub window: Duration,
+
+    /// The fraction of its full weight with which an endpoint starts.
+    pub min_weight: f64,
+
+    /// Shapes the ramp: the weight grows with the fraction of the window that
+    /// has elapsed, raised to the power of `1 / aggression`. `1.0` ramps
+    /// linearly; larger values ramp up more quickly at first.
+    pub aggression: f64,
+}
+
+/// Implemented by services whose weight may change over time.
+pub trait HasRamp {
+    fn ramp(&self) -> Ramp;
+}
+
+/// An endpoint's full weight, and how it ramps up to it.
+#[derive(Copy, Clone, Debug)]
+pub struct Ramp {
+    weight: Weight,
+    slow_start: Option<(SlowStart, Instant)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeSvc<M> {
+    inner: M,
+    slow_start: Option<SlowStart>,
+}
+
+/// A service with a weight, which may ramp up after the service is built.
+///
+/// When the service is instrumented with a load, its load is divided by its
+/// weight.
+#[derive(Debug)]
+pub struct Weighted<S> {
+    inner: S,
+    ramp: Ramp,
+}
+
+/// Records the weight of each endpoint inserted by a `Discover`, so that it
+/// may be applied by `WithWeight` once the endpoint is instrumented.
+pub struct Record<D> {
+    inner: D,
+    ramps: Ramps,
+}
+
+/// Weights the load of each endpoint inserted by a `Discover`.
+pub struct WithWeight<D> {
+    inner: D,
+    ramps: Ramps,
+}
+
+type Ramps = Arc<Mutex<IndexMap<SocketAddr, Ramp>>>;
+
+pub fn layer<M>(slow_start: Option<SlowStart>) -> impl svc::Layer<M, Service = MakeSvc<M>> + Copy {
+    svc::layer::mk(move |inner| MakeSvc { inner, slow_start })
+}
+
+/// Weights the load of endpoints discovered by `discover`, as instrumented
+/// by the `Discover` returned by `load`.
+pub fn with_load<D, L, F>(discover: D, load: F) -> WithWeight<L>
+where
+    F: FnOnce(Record<D>) -> L,
+{
+    let ramps = Ramps::default();
+    let record = Record {
+        inner: discover,
+        ramps: ramps.clone(),
+    };
+    WithWeight {
+        inner: load(record),
+        ramps,
+    }
+}
+
+// === impl SlowStart ===
+
+impl SlowStart {
+    /// Returns the fraction of its full weight that an endpoint has after
+    /// `elapsed`.
+    fn factor(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.window {
+            return 1.0;
+        }
+
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let window = self.window.as_secs() as f64 + f64::from(self.window.subsec_nanos()) / 1e9;
+        let ramp = (elapsed / window).powf(1.0 / self.aggression);
+        ramp.max(self.min_weight).min(1.0)
+    }
+}
+
+// === impl Ramp ===
+
+impl HasWeight for Ramp {
+    fn weight(&self) -> Weight {
+        match self.slow_start {
+            None => self.weight,
+            Some((ref slow_start, started)) => {
+                let factor = slow_start.factor(clock::now() - started);
+                if factor >= 1.0 {
+                    return self.weight;
+                }
+                let weight: f64 = self.weight.into();
+                (weight * factor).into()
+            }
+        }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<T, M> rt::Make<T> for MakeSvc<M>
+where
+    T: HasWeight,
+    M: rt::Make<T>,
+{
+    type Value = Weighted<M::Value>;
+
+    fn make(&self, target: &T) -> Self::Value {
+        // The ramp starts once the endpoint is added to the balancer.
+        let ramp = Ramp {
+            weight: target.weight(),
+            slow_start: self.slow_start.map(|s| (s, clock::now())),
+        };
+        Weighted {
+            inner: self.inner.make(target),
+            ramp,
+        }
+    }
+}
+
+// === impl Weighted ===
+
+impl<S> HasWeight for Weighted<S> {
+    fn weight(&self) -> Weight {
+        self.ramp.weight()
+    }
+}
+
+impl<S> HasRamp for Weighted<S> {
+    fn ramp(&self) -> Ramp {
+        self.ramp
+    }
+}
+
+impl<L> Load for Weighted<L>
+where
+    L: Load,
+    L::Metric: Into<f64>,
+{
+    type Metric = f64;
+
+    fn load(&self) -> f64 {
+        let load: f64 = self.inner.load().into();
+        let weight: f64 = self.ramp.weight().into();
+        if weight > 0.0 {
+            load / weight
+        } else {
+            f64::INFINITY
+        }
+    }
+}
+
+impl<S, Req> svc::Service<Req> for Weighted<S>
+where
+    S: svc::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+// === impl Record ===
+
+impl<D> Discover for Record<D>
+where
+    D: Discover<Key = SocketAddr>,
+    D::Service: HasRamp,
+{
+    type Key = SocketAddr;
+    type Service = D::Service;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = try_ready!(self.inner.poll());
+        if let Ok(mut ramps) = self.ramps.lock() {
+            match change {
+                Change::Insert(addr, ref svc) => {
+                    ramps.insert(addr, svc.ramp());
+                }
+                Change::Remove(ref addr) => {
+                    ramps.swap_remove(addr);
+                }
+            }
+        }
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl WithWeight ===
+
+impl<D> Discover for WithWeight<D>
+where
+    D: Discover<Key = SocketAddr>,
+{
+    type Key = SocketAddr;
+    type Service = Weighted<D::Service>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<Self::Key, Self::Service>, Self::Error> {
+        let change = match try_ready!(self.inner.poll()) {
+            Change::Insert(addr, inner) => {
+                // The endpoint's weight is recorded as `inner` polls the
+                // `Record` that it wraps.
+                let ramp = self
+                    .ramps
+                    .lock()
+                    .ok()
+                    .and_then(|ramps| ramps.get(&addr).cloned())
+                    .expect("endpoint weight must be recorded before it is instrumented");
+                Change::Insert(addr, Weighted { inner, ramp })
+            }
+            Change::Remove(addr) => Change::Remove(addr),
+        };
+        Ok(Async::Ready(change))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::rt::Make;
+    use super::*;
+    use svc::Layer;
+
+    fn slow_start(aggression: f64) -> SlowStart {
+        SlowStart {
+            window: Duration::from_secs(100),
+            min_weight: 0.1,
+            aggression,
+        }
+    }
+
+    fn assert_factor(s: &SlowStart, secs: u64, expected: f64) {
+        let factor = s.factor(Duration::from_secs(secs));
+        assert!(
+            (factor - expected).abs() < 1e-9,
+            "factor after {}s was {}, not {}",
+            secs,
+            factor,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_ramp() {
+        let s = slow_start(1.0);
+        assert_factor(&s, 0, 0.1);
+        assert_factor(&s, 5, 0.1);
+        assert_factor(&s, 50, 0.5);
+        assert_factor(&s, 100, 1.0);
+        assert_factor(&s, 200, 1.0);
+    }
+
+    #[test]
+    fn aggressive_ramp() {
+        let s = slow_start(2.0);
+        assert_factor(&s, 1, 0.1);
+        assert_factor(&s, 25, 0.5);
+        assert_factor(&s, 100, 1.0);
+    }
+
+    /// An endpoint with a fixed load.
+    struct Constant(f64);
+
+    impl Load for Constant {
+        type Metric = f64;
+
+        fn load(&self) -> f64 {
+            self.0
+        }
+    }
+
+    struct Target(f64);
+
+    impl HasWeight for Target {
+        fn weight(&self) -> Weight {
+            self.0.into()
+        }
+    }
+
+    struct MakeConstant(f64);
+
+    impl rt::Make<Target> for MakeConstant {
+        type Value = Constant;
+
+        fn make(&self, _: &Target) -> Constant {
+            Constant(self.0)
+        }
+    }
+
+    struct Fixed(VecDeque<Change<SocketAddr, Weighted<Constant>>>);
+
+    impl Discover for Fixed {
+        type Key = SocketAddr;
+        type Service = Weighted<Constant>;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Change<SocketAddr, Weighted<Constant>>, ()> {
+            Ok(self
+                .0
+                .pop_front()
+                .map(Async::Ready)
+                .unwrap_or(Async::NotReady))
+        }
+    }
+
+    /// Stands in for a `Discover` that instruments endpoints with a load,
+    /// replacing each endpoint with its own load.
+    struct Instrument<D>(D);
+
+    impl<D: Discover<Service = Weighted<Constant>>> Discover for Instrument<D> {
+        type Key = D::Key;
+        type Service = Constant;
+        type Error = D::Error;
+
+        fn poll(&mut self) -> Poll<Change<D::Key, Constant>, D::Error> {
+            let change = match try_ready!(self.0.poll()) {
+                Change::Insert(key, svc) => Change::Insert(key, svc.inner),
+                Change::Remove(key) => Change::Remove(key),
+            };
+            Ok(Async::Ready(change))
+        }
+    }
+
+    #[test]
+    fn loads_are_divided_by_weights() {
+        let steady = layer(None).layer(MakeConstant(1.0));
+        let ramping = layer(Some(slow_start(1.0))).layer(MakeConstant(1.0));
+
+        let old = "10.1.1.1:80".parse().unwrap();
+        let heavy = "10.1.1.2:80".parse().unwrap();
+        let new = "10.1.1.3:80".parse().unwrap();
+        let fixed = Fixed(
+            vec![
+                Change::Insert(old, steady.make(&Target(1.0))),
+                Change::Insert(heavy, steady.make(&Target(2.0))),
+                Change::Insert(new, ramping.make(&Target(1.0))),
+            ]
+            .into(),
+        );
+
+        let mut discover = with_load(fixed, Instrument);
+        let mut loads = Vec::new();
+        for _ in 0..3 {
+            match discover.poll() {
+                Ok(Async::Ready(Change::Insert(addr, svc))) => loads.push((addr, svc.load())),
+                _ => panic!("endpoint must be inserted"),
+            }
+        }
+
+        let addrs = loads.iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(addrs, vec![old, heavy, new]);
+        let (old, heavy, new) = (loads[0].1, loads[1].1, loads[2].1);
+        assert!(
+            (heavy * 2.0 - old).abs() < 1e-6,
+            "heavier endpoints are less loaded: {} vs {}",
+            heavy,
+            old
+        );
+        assert!(
+            new > old * 9.0,
+            "new endpoints are more loaded until their weight ramps up: {} vs {}",
+            new,
+            old
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use futures::{Async, Future, Poll};
+use futures::{future, Async, Future, Poll};
 use http;
 use hyper;
 use std::fmt;
@@ -7,7 +7,7 @@ use std::marker::PhantomData;
 use super::glue::{HttpBody, HyperConnect};
 use super::upgrade::{Http11Upgrade, HttpConnect};
 use super::{
-    h1, h2,
+    h1, h2, orig_proto,
     settings::{HasSettings, Settings},
 };
 use app::config::H2Settings;
@@ -45,6 +45,18 @@ where
 {
     Http1(Option<HyperClient<C, T, B>>),
     Http2(::tower_util::Oneshot<h2::Connect<C, B>, T>),
+    /// Connects to a peer that is offered `l5d-h2`, to determine whether
+    /// HTTP/1 requests are upgraded to HTTP/2 or remain HTTP/1.
+    Negotiate {
+        future: C::Future,
+        h1: Option<HyperClient<C, T, B>>,
+        h2: h2::Connect<C, B>,
+        was_absolute_form: bool,
+    },
+    Upgrade {
+        future: h2::ConnectFuture<future::FutureResult<C::Connection, Error>, B>,
+        was_absolute_form: bool,
+    },
 }
 
 /// The `Service` yielded by `Client::new_service()`.
@@ -55,6 +67,9 @@ where
 {
     Http1(HyperClient<C, T, B>),
     Http2(h2::Connection<B>),
+    /// Upgrades HTTP/1 requests to HTTP/2 on a connection that negotiated
+    /// `l5d-h2`.
+    Upgrade(orig_proto::Upgrade<h2::Connection<B>>),
 }
 
 pub enum ClientServiceFuture {
@@ -64,6 +79,9 @@ pub enum ClientServiceFuture {
         is_http_connect: bool,
     },
     Http2(h2::ResponseFuture),
+    Upgrade(
+        future::Map<h2::ResponseFuture, fn(http::Response<HttpBody>) -> http::Response<HttpBody>>,
+    ),
 }
 
 // === impl Layer ===
@@ -117,8 +135,14 @@ where
     C: svc::MakeConnection<T> + Clone + Send + Sync + 'static,
     C::Future: Send + 'static,
     <C::Future as Future>::Error: Into<Error>,
-    C::Connection: tls::HasStatus + Send + 'static,
-    T: connect::HasPeerAddr + HasSettings + fmt::Debug + Clone + Send + Sync,
+    C::Connection: tls::HasStatus + tls::HasNegotiatedProtocol + Send + 'static,
+    T: connect::HasPeerAddr
+        + HasSettings
+        + tls::client::HasAlpnProtocol
+        + fmt::Debug
+        + Clone
+        + Send
+        + Sync,
     B: hyper::body::Payload + 'static,
 {
     type Response = ClientService<C, T, B>;
@@ -132,7 +156,7 @@ where
     fn call(&mut self, config: T) -> Self::Future {
         debug!("building client={:?}", config);
 
-        let connect = self.connect.clone();
+        let mut connect = self.connect.clone();
         let executor = ::logging::Client::proxy(self.proxy_name, config.peer_addr())
             .with_settings(config.http_settings().clone())
             .executor();
@@ -140,17 +164,36 @@ where
         match *config.http_settings() {
             Settings::Http1 {
                 keep_alive,
-                wants_h1_upgrade: _,
+                wants_h1_upgrade,
                 was_absolute_form,
             } => {
+                // Connections to a peer that is offered `l5d-h2` carry
+                // HTTP/2 if the peer accepts it, so the first connection
+                // determines the protocol that is used.
+                let negotiate = !wants_h1_upgrade
+                    && tls::client::HasAlpnProtocol::alpn_protocol(&config) == Some(tls::L5D_H2);
                 let h1 = hyper::Client::builder()
-                    .executor(executor)
+                    .executor(executor.clone())
                     .keep_alive(keep_alive)
                     // hyper should never try to automatically set the Host
                     // header, instead always just passing whatever we received.
                     .set_host(false)
-                    .build(HyperConnect::new(connect, config, was_absolute_form));
-                ClientNewServiceFuture::Http1(Some(h1))
+                    .build(HyperConnect::new(
+                        connect.clone(),
+                        config.clone(),
+                        was_absolute_form,
+                    ));
+                if !negotiate {
+                    return ClientNewServiceFuture::Http1(Some(h1));
+                }
+
+                let h2 = h2::Connect::new(connect.clone(), executor, self.h2_settings.clone());
+                ClientNewServiceFuture::Negotiate {
+                    future: connect.make_connection(config),
+                    h1: Some(h1),
+                    h2,
+                    was_absolute_form,
+                }
             }
             Settings::Http2 => {
                 let h2 =
@@ -183,7 +226,7 @@ where
 impl<C, T, B> Future for ClientNewServiceFuture<C, T, B>
 where
     C: svc::MakeConnection<T> + Send + Sync + 'static,
-    C::Connection: tls::HasStatus + Send + 'static,
+    C::Connection: tls::HasStatus + tls::HasNegotiatedProtocol + Send + 'static,
     C::Future: Send + 'static,
     C::Error: Into<Error>,
     B: hyper::body::Payload + 'static,
@@ -192,16 +235,55 @@ where
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let svc = match *self {
-            ClientNewServiceFuture::Http1(ref mut h1) => {
-                ClientService::Http1(h1.take().expect("poll more than once"))
-            }
-            ClientNewServiceFuture::Http2(ref mut h2) => {
-                let svc = try_ready!(h2.poll());
-                ClientService::Http2(svc)
-            }
-        };
-        Ok(Async::Ready(svc))
+        loop {
+            let io = match *self {
+                ClientNewServiceFuture::Http1(ref mut h1) => {
+                    let svc = ClientService::Http1(h1.take().expect("poll more than once"));
+                    return Ok(Async::Ready(svc));
+                }
+                ClientNewServiceFuture::Http2(ref mut h2) => {
+                    let svc = try_ready!(h2.poll());
+                    return Ok(Async::Ready(ClientService::Http2(svc)));
+                }
+                ClientNewServiceFuture::Upgrade {
+                    ref mut future,
+                    was_absolute_form,
+                } => {
+                    let svc = try_ready!(future.poll());
+                    let svc = orig_proto::Upgrade::normalized(svc, was_absolute_form);
+                    return Ok(Async::Ready(ClientService::Upgrade(svc)));
+                }
+                ClientNewServiceFuture::Negotiate {
+                    ref mut future,
+                    ref mut h1,
+                    ..
+                } => {
+                    let io = try_ready!(future.poll().map_err(Into::into));
+                    if tls::HasNegotiatedProtocol::negotiated_protocol(&io) != Some(tls::L5D_H2) {
+                        // The peer does not accept `l5d-h2`, so requests
+                        // remain HTTP/1. hyper establishes its own
+                        // connections, so this one is dropped.
+                        let svc = ClientService::Http1(h1.take().expect("poll more than once"));
+                        return Ok(Async::Ready(svc));
+                    }
+                    io
+                }
+            };
+
+            debug!("peer negotiated l5d-h2; upgrading HTTP/1 requests");
+            let upgrade = match *self {
+                ClientNewServiceFuture::Negotiate {
+                    ref h2,
+                    was_absolute_form,
+                    ..
+                } => ClientNewServiceFuture::Upgrade {
+                    future: h2.handshake(io),
+                    was_absolute_form,
+                },
+                _ => unreachable!("only negotiated connections are upgraded"),
+            };
+            *self = upgrade;
+        }
     }
 }
 
@@ -210,7 +292,7 @@ where
 impl<C, T, B> svc::Service<http::Request<B>> for ClientService<C, T, B>
 where
     C: svc::MakeConnection<T> + Clone + Send + Sync + 'static,
-    C::Connection: tls::HasStatus + Send,
+    C::Connection: tls::HasStatus + tls::HasNegotiatedProtocol + Send,
     C::Future: Send + 'static,
     <C::Future as Future>::Error: Into<Error>,
     T: Clone + Send + Sync + 'static,
@@ -224,6 +306,7 @@ where
         match *self {
             ClientService::Http1(_) => Ok(Async::Ready(())),
             ClientService::Http2(ref mut h2) => h2.poll_ready().map_err(Into::into),
+            ClientService::Upgrade(ref mut h2) => h2.poll_ready().map_err(Into::into),
         }
     }
 
@@ -250,6 +333,7 @@ where
                 }
             }
             ClientService::Http2(ref mut h2) => ClientServiceFuture::Http2(h2.call(req)),
+            ClientService::Upgrade(ref mut h2) => ClientServiceFuture::Upgrade(h2.call(req)),
         }
     }
 }
@@ -283,6 +367,224 @@ impl Future for ClientServiceFuture {
                 Ok(Async::Ready(res))
             }
             ClientServiceFuture::Http2(f) => f.poll().map_err(Into::into),
+            ClientServiceFuture::Upgrade(f) => f.poll().map_err(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Future, Poll, Stream};
+    use http;
+    use hyper::{self, server::conn::Http, service::service_fn};
+    use std::io::{self, Read, Write};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use tokio::executor::current_thread;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::super::settings::{HasSettings, Settings};
+    use super::{layer, ClientService};
+    use app::config::H2Settings;
+    use svc::{self, Layer, Service};
+    use transport::{connect, tls};
+    use Conditional;
+
+    type Seen = Arc<Mutex<Vec<(http::Version, Option<http::HeaderValue>)>>>;
+
+    /// A meshed HTTP/1 endpoint without a protocol hint, whose connections
+    /// negotiate `protocol`.
+    #[derive(Clone, Debug)]
+    struct Target {
+        addr: SocketAddr,
+        settings: Settings,
+        protocol: Option<&'static [u8]>,
+    }
+
+    /// A connection that reports the protocol negotiated by its target.
+    struct Negotiated {
+        io: TcpStream,
+        protocol: Option<&'static [u8]>,
+    }
+
+    impl Target {
+        fn new(addr: SocketAddr, protocol: Option<&'static [u8]>) -> Self {
+            Self {
+                addr,
+                settings: Settings::Http1 {
+                    keep_alive: true,
+                    wants_h1_upgrade: false,
+                    was_absolute_form: false,
+                },
+                protocol,
+            }
+        }
+    }
+
+    impl connect::HasPeerAddr for Target {
+        fn peer_addr(&self) -> SocketAddr {
+            self.addr
+        }
+    }
+
+    impl HasSettings for Target {
+        fn http_settings(&self) -> &Settings {
+            &self.settings
+        }
+    }
+
+    impl tls::client::HasAlpnProtocol for Target {
+        fn alpn_protocol(&self) -> Option<&'static [u8]> {
+            Some(tls::L5D_H2)
+        }
+    }
+
+    impl io::Read for Negotiated {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.io.read(buf)
         }
     }
+
+    impl io::Write for Negotiated {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.io.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.io.flush()
+        }
+    }
+
+    impl AsyncRead for Negotiated {}
+
+    impl AsyncWrite for Negotiated {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            AsyncWrite::shutdown(&mut self.io)
+        }
+    }
+
+    impl tls::HasPeerIdentity for Negotiated {
+        fn peer_identity(&self) -> tls::PeerIdentity {
+            Conditional::None(tls::ReasonForNoPeerName::Loopback.into())
+        }
+    }
+
+    impl tls::HasNegotiatedProtocol for Negotiated {
+        fn negotiated_protocol(&self) -> Option<&[u8]> {
+            self.protocol
+        }
+    }
+
+    /// Serves HTTP/2 (or HTTP/1) on a local port, recording the version and
+    /// `l5d-orig-proto` header of each request.
+    fn serve(rt: &mut Runtime, h2: bool, seen: Seen) -> SocketAddr {
+        let listener = TcpListener::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let srv = listener
+            .incoming()
+            .map_err(|e| panic!("accept failed: {}", e))
+            .for_each(move |io| {
+                let seen = seen.clone();
+                let svc = service_fn(move |req: http::Request<hyper::Body>| {
+                    let orig_proto = req.headers().get("l5d-orig-proto").cloned();
+                    seen.lock()
+                        .unwrap()
+                        .push((req.version(), orig_proto.clone()));
+                    let mut rsp = http::Response::new(hyper::Body::empty());
+                    if let Some(orig_proto) = orig_proto {
+                        rsp.headers_mut().insert("l5d-orig-proto", orig_proto);
+                    }
+                    future::ok::<_, hyper::Error>(rsp)
+                });
+                let conn = Http::new()
+                    .http2_only(h2)
+                    .serve_connection(io, svc)
+                    .map_err(|_| ());
+                current_thread::spawn(conn);
+                Ok(())
+            });
+        rt.spawn(srv);
+        addr
+    }
+
+    /// Connects to a target, reporting the protocol its connections negotiate.
+    #[derive(Clone, Debug)]
+    struct Connect;
+
+    impl svc::Service<Target> for Connect {
+        type Response = Negotiated;
+        type Error = io::Error;
+        type Future = Box<Future<Item = Negotiated, Error = io::Error> + Send>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, target: Target) -> Self::Future {
+            let protocol = target.protocol;
+            Box::new(TcpStream::connect(&target.addr).map(move |io| Negotiated { io, protocol }))
+        }
+    }
+
+    fn request() -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .uri("http://example.com/")
+            .header(http::header::HOST, "example.com")
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn unhinted_endpoints_are_upgraded_when_l5d_h2_is_negotiated() {
+        let mut rt = Runtime::new().unwrap();
+        let seen = Seen::default();
+        let addr = serve(&mut rt, true, seen.clone());
+
+        let mut client = layer::<Target, hyper::Body>("test", H2Settings::default()).layer(Connect);
+        let mut svc = rt
+            .block_on(client.call(Target::new(addr, Some(tls::L5D_H2))))
+            .expect("client must be built");
+        match svc {
+            ClientService::Upgrade(_) => {}
+            _ => panic!("requests must be upgraded"),
+        }
+
+        rt.block_on(future::poll_fn(|| svc.poll_ready()))
+            .expect("client must be ready");
+        let rsp = rt
+            .block_on(svc.call(request()))
+            .expect("request must succeed");
+        assert_eq!(rsp.version(), http::Version::HTTP_11);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(
+                http::Version::HTTP_2,
+                Some(http::HeaderValue::from_static("HTTP/1.1"))
+            )]
+        );
+    }
+
+    #[test]
+    fn unhinted_endpoints_remain_http1_without_l5d_h2() {
+        let mut rt = Runtime::new().unwrap();
+        let seen = Seen::default();
+        let addr = serve(&mut rt, false, seen.clone());
+
+        let mut client = layer::<Target, hyper::Body>("test", H2Settings::default()).layer(Connect);
+        let mut svc = rt
+            .block_on(client.call(Target::new(addr, None)))
+            .expect("client must be built");
+        match svc {
+            ClientService::Http1(_) => {}
+            _ => panic!("requests must not be upgraded"),
+        }
+
+        let rsp = rt
+            .block_on(svc.call(request()))
+            .expect("request must succeed");
+        assert_eq!(rsp.version(), http::Version::HTTP_11);
+        assert_eq!(*seen.lock().unwrap(), vec![(http::Version::HTTP_11, None)]);
+    }
 }
@@ -2,11 +2,12 @@ use futures::{Async, Future, Poll};
 use http;
 use hyper::client::connect as hyper_connect;
 use hyper::{self, body::Payload};
+use std::{error, fmt};
 
 use proxy;
 use proxy::http::{upgrade::Http11Upgrade, HasH2Reason};
 use svc;
-use transport::tls::HasStatus as HasTlsStatus;
+use transport::tls::{self, HasNegotiatedProtocol, HasStatus as HasTlsStatus};
 use Conditional;
 
 /// Provides optional HTTP/1.1 upgrade support on the body.
@@ -38,6 +39,11 @@ pub struct HyperConnectFuture<F> {
     absolute_form: bool,
 }
 
+/// Indicates that a connection for HTTP/1 negotiated `l5d-h2`, so that the
+/// peer expects HTTP/2.
+#[derive(Debug)]
+pub struct NegotiatedH2(());
+
 /// Marker in `Response` extensions if the connection used TLS.
 #[derive(Clone, Debug)]
 pub struct ClientUsedTls(pub(super) ());
@@ -172,11 +178,11 @@ where
     C: svc::MakeConnection<T> + Clone + Send + Sync,
     C::Future: Send + 'static,
     <C::Future as Future>::Error: Into<proxy::Error>,
-    C::Connection: HasTlsStatus + Send + 'static,
+    C::Connection: HasTlsStatus + HasNegotiatedProtocol + Send + 'static,
     T: Clone + Send + Sync,
 {
     type Transport = C::Connection;
-    type Error = <C::Future as Future>::Error;
+    type Error = proxy::Error;
     type Future = HyperConnectFuture<C::Future>;
 
     fn connect(&self, _dst: hyper_connect::Destination) -> Self::Future {
@@ -190,14 +196,19 @@ where
 impl<F> Future for HyperConnectFuture<F>
 where
     F: Future + 'static,
-    F::Item: HasTlsStatus,
+    F::Item: HasTlsStatus + HasNegotiatedProtocol,
     F::Error: Into<proxy::Error>,
 {
     type Item = (F::Item, hyper_connect::Connected);
-    type Error = F::Error;
+    type Error = proxy::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let transport = try_ready!(self.inner.poll());
+        let transport = try_ready!(self.inner.poll().map_err(Into::into));
+        if transport.negotiated_protocol() == Some(tls::L5D_H2) {
+            // The peer began accepting `l5d-h2` after the client chose to
+            // use HTTP/1, so the connection cannot be used.
+            return Err(NegotiatedH2(()).into());
+        }
         let connected = hyper_connect::Connected::new().proxy(self.absolute_form);
         let connected = if let Conditional::Some(()) = transport.tls_status() {
             connected.extra(ClientUsedTls(()))
@@ -208,6 +219,16 @@ where
     }
 }
 
+// === impl NegotiatedH2 ===
+
+impl fmt::Display for NegotiatedH2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt("the peer negotiated l5d-h2 on an HTTP/1 connection", f)
+    }
+}
+
+impl error::Error for NegotiatedH2 {}
+
 // === impl Error ===
 
 impl HasH2Reason for hyper::Error {
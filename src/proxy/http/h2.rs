@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use futures::{Future, Poll};
+use futures::{future, Future, Poll};
 use http;
 use hyper::{
     body::Payload,
@@ -63,6 +63,16 @@ impl<C, B> Connect<C, B> {
         }
     }
 
+    /// Performs an HTTP/2 handshake on a connection that has already been
+    /// established.
+    pub fn handshake<I>(&self, io: I) -> ConnectFuture<future::FutureResult<I, Error>, B> {
+        ConnectFuture {
+            executor: self.executor.clone(),
+            state: ConnectState::Connect(future::ok(io)),
+            h2_settings: self.h2_settings,
+        }
+    }
+
     pub fn set_executor<E>(&mut self, executor: E)
     where
         E: Executor<BoxSendFuture> + Clone + Send + Sync + 'static,
@@ -0,0 +1,1009 @@
+//! Active health checking of load-balanced endpoints.
+//!
+//! Each endpoint produced by a resolution is probed periodically, either with
+//! an HTTP request for a configured path or with a gRPC `Health/Check` call.
+//! An endpoint that fails `unhealthy_threshold` consecutive probes is removed
+//! from the resolution's `Discover` set, and it is re-added once it passes
+//! `healthy_threshold` consecutive probes. Endpoints are considered healthy
+//! until they are first probed.
+//!
+//! The last endpoint in a resolution is never removed, since a balancer
+//! without endpoints cannot serve any requests.
+//!
+//! Each endpoint's probes share a client, so that a connection is reused
+//! across probes. The client is discarded when a probe fails without a
+//! response or times out.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{future, task::AtomicTask, Async, Future, Poll};
+use http;
+use hyper::{self, body::Payload};
+use indexmap::IndexMap;
+use json;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use std::{error, fmt};
+use tokio;
+use tokio_timer::{clock, Delay};
+
+use metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge};
+use proxy::resolve::{self, Update};
+use proxy::Error;
+use svc::{self, ServiceExt};
+use Addr;
+
+metrics! {
+    health_check_probes_total: Counter { "Total count of health check probes sent to endpoints" },
+    health_check_healthy: Gauge { "Whether an endpoint is passing its health checks" }
+}
+
+/// Configures how endpoints are probed.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub probe: Probe,
+
+    /// The time between the end of a probe and the start of the next.
+    pub interval: Duration,
+    pub timeout: Duration,
+
+    /// The number of consecutive failed probes after which an endpoint is
+    /// unhealthy.
+    pub unhealthy_threshold: u32,
+
+    /// The number of consecutive successful probes after which an unhealthy
+    /// endpoint is healthy again.
+    pub healthy_threshold: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// Sends a `GET` request for a path, expecting a successful status.
+    Http { path: String },
+
+    /// Calls `grpc.health.v1.Health/Check` for a service, expecting it to be
+    /// `SERVING`. An empty service name checks the server's overall health.
+    Grpc { service: String },
+}
+
+/// Wraps a `Resolve` so that unhealthy endpoints are removed from its
+/// resolutions.
+///
+/// Probes are sent with clients built by `make` for each endpoint.
+#[derive(Clone, Debug)]
+pub struct Resolve<R, M> {
+    inner: R,
+    make: M,
+    config: Option<Arc<Config>>,
+    registry: Registry,
+}
+
+pub struct Resolution<R: resolve::Resolution, M> {
+    inner: R,
+    dst: Addr,
+    make: M,
+    config: Option<Arc<Config>>,
+    registry: Registry,
+    endpoints: IndexMap<SocketAddr, Checked<R::Endpoint>>,
+
+    /// Notified when an endpoint's health changes.
+    task: Arc<AtomicTask>,
+}
+
+/// The reason a probe failed.
+#[derive(Debug)]
+pub enum Unhealthy {
+    Status(http::StatusCode),
+    GrpcStatus(String),
+    NotServing,
+    Malformed,
+    TimedOut,
+}
+
+/// Records the health of endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<IndexMap<Key, Arc<Health>>>>);
+
+/// Implements `FmtMetrics` to render prometheus-formatted health metrics, and
+/// lists the health of each endpoint for the admin server.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<IndexMap<Key, Arc<Health>>>>);
+
+struct Checked<T> {
+    endpoint: T,
+    health: Arc<Health>,
+
+    /// Indicates whether the endpoint has been added to the balancer.
+    added: bool,
+}
+
+/// Probes an endpoint until it is removed from its resolution.
+struct Daemon<T, M, S> {
+    addr: SocketAddr,
+    target: T,
+    make: M,
+    config: Arc<Config>,
+    health: Weak<Health>,
+
+    /// The client used by the previous probe, if it may be reused.
+    client: Option<S>,
+    state: State<S>,
+}
+
+enum State<S> {
+    Idle(Delay),
+    Probing {
+        future: ProbeFuture<S>,
+        timeout: Delay,
+    },
+}
+
+/// Completes with the probe's client, which is returned on failure if it
+/// received a response and may be reused.
+type ProbeFuture<S> = Box<Future<Item = S, Error = (Option<S>, Error)> + Send>;
+
+/// Reads a `grpc.health.v1.HealthCheckResponse`.
+struct GrpcResponse<B> {
+    body: B,
+    buf: BytesMut,
+    status: Option<http::HeaderValue>,
+}
+
+/// Tracks the results of probes to a single endpoint.
+#[derive(Debug)]
+struct Health {
+    config: Arc<Config>,
+    status: Mutex<Status>,
+    task: Arc<AtomicTask>,
+}
+
+#[derive(Debug)]
+struct Status {
+    healthy: bool,
+    consecutive_successes: u32,
+    consecutive_failures: u32,
+    last_failure: Option<String>,
+    successes_total: Counter,
+    failures_total: Counter,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    dst: Addr,
+    addr: SocketAddr,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Outcome {
+    Success,
+    Failure,
+}
+
+pub fn new() -> (Registry, Report) {
+    let inner = Arc::new(Mutex::new(IndexMap::new()));
+    (Registry(inner.clone()), Report(inner))
+}
+
+// === impl Probe ===
+
+impl Probe {
+    fn request(&self, addr: SocketAddr) -> http::Request<hyper::Body> {
+        let builder = &mut http::Request::builder();
+        builder.header(http::header::HOST, addr.to_string());
+
+        let body = match self {
+            Probe::Http { path } => {
+                builder
+                    .method(http::Method::GET)
+                    .uri(format!("http://{}{}", addr, path));
+                hyper::Body::empty()
+            }
+            Probe::Grpc { service } => {
+                builder
+                    .method(http::Method::POST)
+                    .uri(format!("http://{}/grpc.health.v1.Health/Check", addr))
+                    .header(http::header::CONTENT_TYPE, "application/grpc")
+                    .header(http::header::TE, "trailers");
+                hyper::Body::from(grpc_check_request(service))
+            }
+        };
+
+        builder.body(body).expect("probe request must be valid")
+    }
+
+    fn check<B>(
+        &self,
+        rsp: http::Response<B>,
+    ) -> future::Either<future::FutureResult<(), Error>, GrpcResponse<B>>
+    where
+        B: Payload,
+    {
+        if !rsp.status().is_success() {
+            return future::Either::A(future::err(Unhealthy::Status(rsp.status()).into()));
+        }
+
+        match self {
+            Probe::Http { .. } => future::Either::A(future::ok(())),
+            Probe::Grpc { .. } => {
+                let status = rsp.headers().get("grpc-status").cloned();
+                future::Either::B(GrpcResponse {
+                    body: rsp.into_body(),
+                    buf: BytesMut::new(),
+                    status,
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Probe::Http { path } => write!(f, "http:{}", path),
+            Probe::Grpc { service } if service.is_empty() => write!(f, "grpc"),
+            Probe::Grpc { service } => write!(f, "grpc:{}", service),
+        }
+    }
+}
+
+/// Encodes a framed `grpc.health.v1.HealthCheckRequest` for a service.
+fn grpc_check_request(service: &str) -> Bytes {
+    let mut msg = Vec::new();
+    if !service.is_empty() {
+        // Field 1 (`service`), length-delimited.
+        msg.push(0x0a);
+        let mut len = service.len();
+        while len >= 0x80 {
+            msg.push((len as u8) | 0x80);
+            len >>= 7;
+        }
+        msg.push(len as u8);
+        msg.extend_from_slice(service.as_bytes());
+    }
+
+    let mut buf = BytesMut::with_capacity(5 + msg.len());
+    buf.put_u8(0); // Uncompressed.
+    buf.put_u32_be(msg.len() as u32);
+    buf.put_slice(&msg);
+    buf.freeze()
+}
+
+/// Decodes the `status` of a framed `grpc.health.v1.HealthCheckResponse`.
+fn grpc_check_status(buf: &[u8]) -> Result<u64, Unhealthy> {
+    if buf.len() < 5 || buf[0] != 0 {
+        return Err(Unhealthy::Malformed);
+    }
+    let len = ((buf[1] as usize) << 24)
+        | ((buf[2] as usize) << 16)
+        | ((buf[3] as usize) << 8)
+        | (buf[4] as usize);
+    let mut msg = match buf.get(5..5 + len) {
+        Some(msg) => msg,
+        None => return Err(Unhealthy::Malformed),
+    };
+
+    // `UNKNOWN` is omitted from the message, since it is the default value.
+    let mut status = 0;
+    while !msg.is_empty() {
+        let tag = msg[0];
+        msg = &msg[1..];
+        // Only the `status` field (1, varint) is expected.
+        if tag != 0x08 {
+            return Err(Unhealthy::Malformed);
+        }
+
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = match msg.first() {
+                Some(b) if shift < 64 => *b,
+                _ => return Err(Unhealthy::Malformed),
+            };
+            msg = &msg[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        status = value;
+    }
+
+    Ok(status)
+}
+
+// === impl Resolve ===
+
+impl<R, M> Resolve<R, M> {
+    /// Endpoints are not probed unless `config` is set.
+    pub fn new(inner: R, make: M, config: Option<Config>, registry: Registry) -> Self {
+        Self {
+            inner,
+            make,
+            config: config.map(Arc::new),
+            registry,
+        }
+    }
+}
+
+impl<T, R, M, B> resolve::Resolve<T> for Resolve<R, M>
+where
+    T: AsRef<Addr>,
+    R: resolve::Resolve<T>,
+    R::Endpoint: Clone + Send + 'static,
+    M: svc::Service<R::Endpoint> + Clone + Send + 'static,
+    M::Response: svc::Service<http::Request<hyper::Body>, Response = http::Response<B>>,
+    M::Response: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Error: Into<Error>,
+    B: Payload,
+{
+    type Endpoint = R::Endpoint;
+    type Resolution = Resolution<R::Resolution, M>;
+
+    fn resolve(&self, target: &T) -> Self::Resolution {
+        Resolution {
+            inner: self.inner.resolve(target),
+            dst: target.as_ref().clone(),
+            make: self.make.clone(),
+            config: self.config.clone(),
+            registry: self.registry.clone(),
+            endpoints: IndexMap::new(),
+            task: Arc::new(AtomicTask::new()),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R, M, B> Resolution<R, M>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone + Send + 'static,
+    M: svc::Service<R::Endpoint> + Clone + Send + 'static,
+    M::Response: svc::Service<http::Request<hyper::Body>, Response = http::Response<B>>,
+    M::Response: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Error: Into<Error>,
+    B: Payload,
+{
+    /// Starts probing a new endpoint.
+    fn check(&mut self, config: Arc<Config>, addr: SocketAddr, endpoint: R::Endpoint) {
+        let health = Arc::new(Health::new(config.clone(), self.task.clone()));
+        self.registry.register(&self.dst, addr, &health);
+
+        let daemon = Daemon {
+            addr,
+            target: endpoint.clone(),
+            make: self.make.clone(),
+            config,
+            health: Arc::downgrade(&health),
+            client: None,
+            state: State::Idle(Delay::new(clock::now())),
+        };
+        tokio::spawn(Box::new(daemon));
+
+        self.endpoints.insert(
+            addr,
+            Checked {
+                endpoint,
+                health,
+                added: true,
+            },
+        );
+    }
+
+    /// Removes endpoints that have become unhealthy and re-adds endpoints
+    /// that have recovered.
+    fn poll_health(&mut self) -> Option<Update<R::Endpoint>> {
+        let mut added = self.endpoints.values().filter(|c| c.added).count();
+
+        for (addr, checked) in self.endpoints.iter_mut() {
+            let healthy = checked.health.is_healthy();
+            if healthy && !checked.added {
+                debug!("adding healthy endpoint {}", addr);
+                checked.added = true;
+                return Some(Update::Add(*addr, checked.endpoint.clone()));
+            }
+
+            if !healthy && checked.added {
+                if added <= 1 {
+                    trace!("not removing last endpoint {}", addr);
+                    continue;
+                }
+
+                debug!("removing unhealthy endpoint {}", addr);
+                checked.added = false;
+                added -= 1;
+                return Some(Update::Remove(*addr));
+            }
+        }
+
+        None
+    }
+}
+
+impl<R, M, B> resolve::Resolution for Resolution<R, M>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone + Send + 'static,
+    M: svc::Service<R::Endpoint> + Clone + Send + 'static,
+    M::Response: svc::Service<http::Request<hyper::Body>, Response = http::Response<B>>,
+    M::Response: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Error: Into<Error>,
+    B: Payload,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        let config = match self.config {
+            Some(ref config) => config.clone(),
+            None => return self.inner.poll(),
+        };
+
+        // Register before checking endpoint health so that changes made after
+        // this point notify the task.
+        self.task.register();
+
+        if let Some(update) = self.poll_health() {
+            return Ok(Async::Ready(update));
+        }
+
+        loop {
+            match try_ready!(self.inner.poll()) {
+                Update::Add(addr, endpoint) => {
+                    if let Some(checked) = self.endpoints.get_mut(&addr) {
+                        checked.endpoint = endpoint.clone();
+                        if !checked.added {
+                            // The endpoint is added when it is healthy.
+                            continue;
+                        }
+                        return Ok(Async::Ready(Update::Add(addr, endpoint)));
+                    }
+
+                    self.check(config.clone(), addr, endpoint.clone());
+                    return Ok(Async::Ready(Update::Add(addr, endpoint)));
+                }
+                Update::Remove(addr) => {
+                    if let Some(checked) = self.endpoints.swap_remove(&addr) {
+                        self.registry.deregister(&self.dst, addr, &checked.health);
+                        if !checked.added {
+                            // The endpoint was already removed when it became
+                            // unhealthy.
+                            continue;
+                        }
+                    }
+                    return Ok(Async::Ready(Update::Remove(addr)));
+                }
+                Update::NoEndpoints => {
+                    for (addr, checked) in self.endpoints.drain(..) {
+                        self.registry.deregister(&self.dst, addr, &checked.health);
+                    }
+                    return Ok(Async::Ready(Update::NoEndpoints));
+                }
+            }
+        }
+    }
+}
+
+impl<R, M> Drop for Resolution<R, M>
+where
+    R: resolve::Resolution,
+{
+    fn drop(&mut self) {
+        for (addr, checked) in &self.endpoints {
+            self.registry.deregister(&self.dst, *addr, &checked.health);
+        }
+    }
+}
+
+impl<R, M> fmt::Debug for Resolution<R, M>
+where
+    R: resolve::Resolution + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolution")
+            .field("inner", &self.inner)
+            .field("dst", &self.dst)
+            .field("endpoints", &self.endpoints.len())
+            .finish()
+    }
+}
+
+// === impl Daemon ===
+
+impl<T, M, B> Daemon<T, M, M::Response>
+where
+    T: Clone,
+    M: svc::Service<T> + Clone + Send + 'static,
+    M::Response: svc::Service<http::Request<hyper::Body>, Response = http::Response<B>>,
+    M::Response: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Error: Into<Error>,
+    B: Payload,
+{
+    fn probe(&mut self) -> ProbeFuture<M::Response> {
+        let req = self.config.probe.request(self.addr);
+        let probe = self.config.probe.clone();
+
+        let client = self.client.take();
+        let client: Box<Future<Item = M::Response, Error = Error> + Send> = match client {
+            Some(client) => Box::new(future::ok(client)),
+            None => {
+                trace!("building a probe client for {}", self.addr);
+                let make = self.make.clone().oneshot(self.target.clone());
+                Box::new(make.map_err(Into::into))
+            }
+        };
+
+        let future = client
+            .and_then(|client| client.ready().map_err(Into::into))
+            .map_err(|e| (None, e))
+            .and_then(move |mut client| {
+                let rsp = svc::Service::call(&mut client, req);
+                rsp.map_err(|e| (None, e.into())).and_then(move |rsp| {
+                    probe.check(rsp).then(move |result| match result {
+                        Ok(()) => Ok(client),
+                        Err(e) => Err((Some(client), e)),
+                    })
+                })
+            });
+        Box::new(future)
+    }
+}
+
+impl<T, M, B> Future for Daemon<T, M, M::Response>
+where
+    T: Clone,
+    M: svc::Service<T> + Clone + Send + 'static,
+    M::Response: svc::Service<http::Request<hyper::Body>, Response = http::Response<B>>,
+    M::Response: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    <M::Response as svc::Service<http::Request<hyper::Body>>>::Error: Into<Error>,
+    M::Future: Send + 'static,
+    M::Error: Into<Error>,
+    B: Payload,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            // Stop probing once the endpoint has been removed.
+            let health = match self.health.upgrade() {
+                Some(health) => health,
+                None => return Ok(Async::Ready(())),
+            };
+
+            let result = match self.state {
+                State::Idle(ref mut delay) => {
+                    if let Ok(Async::NotReady) = delay.poll() {
+                        return Ok(Async::NotReady);
+                    }
+                    None
+                }
+                State::Probing {
+                    ref mut future,
+                    ref mut timeout,
+                } => match future.poll() {
+                    Ok(Async::Ready(client)) => Some((Some(client), Ok(()))),
+                    Err((client, e)) => Some((client, Err(e))),
+                    Ok(Async::NotReady) => match timeout.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        // The client is dropped with the probe, since its
+                        // connection may be stuck.
+                        _ => Some((None, Err(Unhealthy::TimedOut.into()))),
+                    },
+                },
+            };
+
+            self.state = match result {
+                None => State::Probing {
+                    future: self.probe(),
+                    timeout: Delay::new(clock::now() + self.config.timeout),
+                },
+                Some((client, result)) => {
+                    if let Err(ref e) = result {
+                        debug!("probe to {} failed: {}", self.addr, e);
+                    }
+                    self.client = client;
+                    health.record(result);
+                    State::Idle(Delay::new(clock::now() + self.config.interval))
+                }
+            };
+        }
+    }
+}
+
+// === impl GrpcResponse ===
+
+impl<B: Payload> Future for GrpcResponse<B> {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        while let Some(data) = try_ready!(self.body.poll_data().map_err(Into::into)) {
+            self.buf.reserve(data.remaining());
+            self.buf.put(data);
+        }
+
+        // A trailers-only response includes its status in the headers.
+        if self.status.is_none() {
+            let trailers = try_ready!(self.body.poll_trailers().map_err(Into::into));
+            self.status = trailers.and_then(|mut t| t.remove("grpc-status"));
+        }
+
+        match self.status {
+            Some(ref s) if s == "0" => {}
+            Some(ref s) => {
+                let status = s.to_str().unwrap_or("invalid").to_owned();
+                return Err(Unhealthy::GrpcStatus(status).into());
+            }
+            None => return Err(Unhealthy::Malformed.into()),
+        }
+
+        // `SERVING` is 1.
+        match grpc_check_status(&self.buf)? {
+            1 => Ok(Async::Ready(())),
+            _ => Err(Unhealthy::NotServing.into()),
+        }
+    }
+}
+
+// === impl Unhealthy ===
+
+impl fmt::Display for Unhealthy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unhealthy::Status(status) => write!(f, "unexpected status: {}", status),
+            Unhealthy::GrpcStatus(status) => write!(f, "unexpected grpc-status: {}", status),
+            Unhealthy::NotServing => write!(f, "service is not serving"),
+            Unhealthy::Malformed => write!(f, "malformed health check response"),
+            Unhealthy::TimedOut => write!(f, "probe timed out"),
+        }
+    }
+}
+
+impl error::Error for Unhealthy {}
+
+// === impl Health ===
+
+impl Health {
+    fn new(config: Arc<Config>, task: Arc<AtomicTask>) -> Self {
+        Self {
+            config,
+            status: Mutex::new(Status {
+                healthy: true,
+                consecutive_successes: 0,
+                consecutive_failures: 0,
+                last_failure: None,
+                successes_total: Counter::default(),
+                failures_total: Counter::default(),
+            }),
+            task,
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.status.lock().map(|s| s.healthy).unwrap_or(true)
+    }
+
+    fn record(&self, result: Result<(), Error>) {
+        let changed = {
+            let mut status = match self.status.lock() {
+                Ok(status) => status,
+                Err(_) => return,
+            };
+            let was_healthy = status.healthy;
+
+            match result {
+                Ok(()) => {
+                    status.successes_total.incr();
+                    status.consecutive_failures = 0;
+                    status.consecutive_successes = status.consecutive_successes.saturating_add(1);
+                    if status.consecutive_successes >= self.config.healthy_threshold {
+                        status.healthy = true;
+                    }
+                }
+                Err(e) => {
+                    status.failures_total.incr();
+                    status.consecutive_successes = 0;
+                    status.consecutive_failures = status.consecutive_failures.saturating_add(1);
+                    status.last_failure = Some(e.to_string());
+                    if status.consecutive_failures >= self.config.unhealthy_threshold {
+                        status.healthy = false;
+                    }
+                }
+            }
+
+            status.healthy != was_healthy
+        };
+
+        if changed {
+            self.task.notify();
+        }
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn register(&self, dst: &Addr, addr: SocketAddr, health: &Arc<Health>) {
+        if let Ok(mut endpoints) = self.0.lock() {
+            endpoints.insert(Key::new(dst, addr), health.clone());
+        }
+    }
+
+    fn deregister(&self, dst: &Addr, addr: SocketAddr, health: &Arc<Health>) {
+        if let Ok(mut endpoints) = self.0.lock() {
+            let key = Key::new(dst, addr);
+            // Another resolution may have registered the same endpoint since.
+            let registered = endpoints
+                .get(&key)
+                .map(|h| Arc::ptr_eq(h, health))
+                .unwrap_or(false);
+            if registered {
+                endpoints.swap_remove(&key);
+            }
+        }
+    }
+}
+
+// === impl Report ===
+
+impl Report {
+    /// Lists the health of all probed endpoints, by destination.
+    pub fn to_json(&self) -> json::Value {
+        let endpoints = match self.0.lock() {
+            Ok(lock) => lock,
+            Err(_) => return json::Value::Null,
+        };
+
+        let mut by_dst = IndexMap::<String, Vec<json::Value>>::new();
+        for (key, health) in endpoints.iter() {
+            let status = match health.status.lock() {
+                Ok(status) => status,
+                Err(_) => continue,
+            };
+            let last_failure = match status.last_failure {
+                Some(ref failure) => json::Value::String(failure.clone()),
+                None => json::Value::Null,
+            };
+            by_dst
+                .entry(key.dst.to_string())
+                .or_insert_with(Vec::new)
+                .push(json::object(vec![
+                    ("addr", json::Value::String(key.addr.to_string())),
+                    ("healthy", json::Value::Bool(status.healthy)),
+                    ("last_failure", last_failure),
+                ]));
+        }
+
+        json::Value::Object(
+            by_dst
+                .into_iter()
+                .map(|(dst, endpoints)| (dst, json::Value::Array(endpoints)))
+                .collect(),
+        )
+    }
+}
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let endpoints = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        if endpoints.is_empty() {
+            return Ok(());
+        }
+
+        health_check_probes_total.fmt_help(f)?;
+        for (key, health) in endpoints.iter() {
+            if let Ok(status) = health.status.lock() {
+                let name = health_check_probes_total.name;
+                status
+                    .successes_total
+                    .fmt_metric_labeled(f, name, (key, Outcome::Success))?;
+                status
+                    .failures_total
+                    .fmt_metric_labeled(f, name, (key, Outcome::Failure))?;
+            }
+        }
+
+        health_check_healthy.fmt_help(f)?;
+        for (key, health) in endpoints.iter() {
+            let healthy = Gauge::from(u64::from(health.is_healthy()));
+            healthy.fmt_metric_labeled(f, health_check_healthy.name, key)?;
+        }
+
+        Ok(())
+    }
+}
+
+// === impl Key ===
+
+impl Key {
+    fn new(dst: &Addr, addr: SocketAddr) -> Self {
+        Self {
+            dst: dst.clone(),
+            addr,
+        }
+    }
+}
+
+impl FmtLabels for Key {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dst=\"{}\",addr=\"{}\"", self.dst, self.addr)
+    }
+}
+
+impl FmtLabels for Outcome {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcome = match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+        };
+        write!(f, "result=\"{}\"", outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, task::AtomicTask};
+    use http;
+    use hyper;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio_timer::{clock, Delay};
+
+    use super::{
+        grpc_check_request, grpc_check_status, Config, Daemon, Health, Probe, State, Unhealthy,
+    };
+    use json;
+    use svc;
+    use Addr;
+
+    fn health(unhealthy_threshold: u32, healthy_threshold: u32) -> Health {
+        let config = Config {
+            probe: Probe::Http {
+                path: "/ready".to_owned(),
+            },
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(1),
+            unhealthy_threshold,
+            healthy_threshold,
+        };
+        Health::new(Arc::new(config), Arc::new(AtomicTask::new()))
+    }
+
+    #[test]
+    fn thresholds() {
+        let health = health(3, 2);
+        assert!(health.is_healthy(), "endpoints are healthy until probed");
+
+        health.record(Err(Unhealthy::TimedOut.into()));
+        health.record(Err(Unhealthy::TimedOut.into()));
+        health.record(Ok(()));
+        health.record(Err(Unhealthy::TimedOut.into()));
+        health.record(Err(Unhealthy::TimedOut.into()));
+        assert!(health.is_healthy());
+
+        health.record(Err(Unhealthy::TimedOut.into()));
+        assert!(!health.is_healthy());
+
+        health.record(Ok(()));
+        assert!(!health.is_healthy());
+        health.record(Ok(()));
+        assert!(health.is_healthy());
+    }
+
+    #[test]
+    fn grpc_requests() {
+        assert_eq!(&grpc_check_request("")[..], &[0, 0, 0, 0, 0][..]);
+        assert_eq!(
+            &grpc_check_request("svc")[..],
+            &[0, 0, 0, 0, 5, 0x0a, 3, b's', b'v', b'c'][..]
+        );
+    }
+
+    #[test]
+    fn grpc_responses() {
+        assert_eq!(grpc_check_status(&[0, 0, 0, 0, 2, 0x08, 1]).ok(), Some(1));
+        assert_eq!(grpc_check_status(&[0, 0, 0, 0, 2, 0x08, 2]).ok(), Some(2));
+        assert_eq!(
+            grpc_check_status(&[0, 0, 0, 0, 0]).ok(),
+            Some(0),
+            "UNKNOWN is the default status"
+        );
+        assert!(grpc_check_status(&[0, 0, 0, 0, 2, 0x08]).is_err());
+        assert!(grpc_check_status(&[0, 0, 0, 0, 2, 0x10, 1]).is_err());
+        assert!(grpc_check_status(&[1, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn probes_reuse_a_client() {
+        let made = Arc::new(AtomicUsize::new(0));
+        let make = {
+            let made = made.clone();
+            svc::mk(move |_: ()| {
+                made.fetch_add(1, Ordering::SeqCst);
+                let client = svc::mk(|_: http::Request<hyper::Body>| {
+                    future::ok::<_, ::proxy::Error>(http::Response::new(hyper::Body::empty()))
+                });
+                future::ok::<_, ::proxy::Error>(client)
+            })
+        };
+
+        let config = Arc::new(Config {
+            probe: Probe::Http {
+                path: "/ready".to_owned(),
+            },
+            interval: Duration::from_millis(1),
+            timeout: Duration::from_secs(1),
+            unhealthy_threshold: 1,
+            healthy_threshold: 1,
+        });
+        let health = Arc::new(Health::new(config.clone(), Arc::new(AtomicTask::new())));
+        let daemon = Daemon {
+            addr: ([127, 0, 0, 1], 8080).into(),
+            target: (),
+            make,
+            config,
+            health: Arc::downgrade(&health),
+            client: None,
+            state: State::Idle(Delay::new(clock::now())),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(daemon);
+        rt.block_on(Delay::new(clock::now() + Duration::from_millis(50)))
+            .unwrap();
+
+        let probes = health.status.lock().unwrap().successes_total.value();
+        assert!(probes > 1, "endpoint must be probed repeatedly");
+        assert_eq!(made.load(Ordering::SeqCst), 1, "client must be reused");
+    }
+
+    #[test]
+    fn reports_json() {
+        let (registry, report) = super::new();
+        let dst = Addr::from_str("web.example.com:80").unwrap();
+        let addr = ([10, 1, 1, 1], 8080).into();
+        let health = Arc::new(health(1, 1));
+        registry.register(&dst, addr, &health);
+        health.record(Err(Unhealthy::TimedOut.into()));
+
+        let endpoint = json::object(vec![
+            ("addr", json::Value::String("10.1.1.1:8080".to_owned())),
+            ("healthy", json::Value::Bool(false)),
+            (
+                "last_failure",
+                json::Value::String("probe timed out".to_owned()),
+            ),
+        ]);
+        assert_eq!(
+            report.to_json(),
+            json::object(vec![(
+                "web.example.com:80",
+                json::Value::Array(vec![endpoint])
+            )])
+        );
+
+        registry.deregister(&dst, addr, &health);
+        assert_eq!(report.to_json(), json::object(vec![]));
+    }
+}
@@ -0,0 +1,561 @@
+//! Locality-aware endpoint selection.
+//!
+//! Endpoints whose labels match all of the proxy's locality labels (e.g.
+//! `zone=us-west-2a`) are local; all others are remote. A resolution only
+//! provides its local endpoints to the balancer, so that requests stay within
+//! the proxy's locality, unless:
+//!
+//! - there are no local endpoints (e.g. because all of them have been ejected
+//!   as outliers), or
+//! - the local endpoints are overloaded, i.e. the number of requests pending
+//!   on them reaches `max_pending` per endpoint.
+//!
+//! In either case the resolution spills over, adding remote endpoints to the
+//! balancer until local endpoints are available again. To avoid flapping, an
+//! overloaded resolution stops spilling over once its pending requests fall
+//! below half of the limit.
+
+use futures::{task::AtomicTask, Async, Future, Poll};
+use indexmap::IndexMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use proxy::http::balance::{HasWeight, Weight};
+use proxy::http::outlier;
+use proxy::resolve::{self, Update};
+use svc;
+
+/// Configures the proxy's locality.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The labels an endpoint must have to be local.
+    ///
+    /// If empty, all endpoints are local.
+    pub labels: Vec<(String, String)>,
+
+    /// The number of pending requests per local endpoint at which remote
+    /// endpoints are used as well.
+    pub max_pending: Option<usize>,
+}
+
+/// Implemented by endpoint targets that have labels.
+pub trait HasLabels {
+    fn label(&self, key: &str) -> Option<&str>;
+}
+
+/// Wraps a `Resolve` so that its resolutions prefer local endpoints.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    config: Arc<Config>,
+}
+
+pub struct Resolution<R: resolve::Resolution> {
+    inner: R,
+    config: Arc<Config>,
+    local: IndexMap<SocketAddr, R::Endpoint>,
+    remote: IndexMap<SocketAddr, R::Endpoint>,
+
+    /// Indicates whether remote endpoints have been added to the balancer.
+    spilled: bool,
+    pending: Arc<Pending>,
+    updates: VecDeque<Update<Located<R::Endpoint>>>,
+}
+
+/// An endpoint target, tracking requests to local endpoints.
+#[derive(Clone)]
+pub struct Located<T> {
+    target: T,
+    pending: Option<Arc<Pending>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer(());
+
+#[derive(Clone, Debug)]
+pub struct MakeSvc<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    inner: F,
+    pending: Option<Arc<Pending>>,
+}
+
+/// Counts the requests pending on a local endpoint.
+#[derive(Clone, Debug)]
+pub struct Service<S> {
+    inner: S,
+    pending: Option<Arc<Pending>>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: F,
+    _handle: Option<Handle>,
+}
+
+/// The number of requests pending on a resolution's local endpoints.
+struct Pending {
+    requests: AtomicUsize,
+
+    /// Notified as requests are dispatched and completed, so that the
+    /// resolution may spill over.
+    task: AtomicTask,
+}
+
+/// Decrements the pending count when dropped.
+struct Handle(Arc<Pending>);
+
+// === impl Config ===
+
+impl Config {
+    fn is_local<T: HasLabels>(&self, endpoint: &T) -> bool {
+        self.labels
+            .iter()
+            .all(|(k, v)| endpoint.label(k) == Some(v.as_str()))
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, config: Config) -> Self {
+        Self {
+            inner,
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<T, R> resolve::Resolve<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+    R::Endpoint: HasLabels + Clone,
+{
+    type Endpoint = Located<R::Endpoint>;
+    type Resolution = Resolution<R::Resolution>;
+
+    fn resolve(&self, target: &T) -> Self::Resolution {
+        Resolution {
+            inner: self.inner.resolve(target),
+            config: self.config.clone(),
+            local: IndexMap::new(),
+            remote: IndexMap::new(),
+            spilled: false,
+            pending: Arc::new(Pending {
+                requests: AtomicUsize::new(0),
+                task: AtomicTask::new(),
+            }),
+            updates: VecDeque::new(),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: HasLabels + Clone,
+{
+    fn as_local(&self, endpoint: R::Endpoint) -> Located<R::Endpoint> {
+        // Pending requests are only tracked when they may cause a spill over.
+        let pending = self.config.max_pending.map(|_| self.pending.clone());
+        Located {
+            target: endpoint,
+            pending,
+        }
+    }
+
+    fn as_remote(endpoint: R::Endpoint) -> Located<R::Endpoint> {
+        Located {
+            target: endpoint,
+            pending: None,
+        }
+    }
+
+    fn add(&mut self, addr: SocketAddr, endpoint: R::Endpoint) {
+        if self.config.is_local(&endpoint) {
+            self.remote.swap_remove(&addr);
+            self.local.insert(addr, endpoint.clone());
+            let located = self.as_local(endpoint);
+            self.updates.push_back(Update::Add(addr, located));
+        } else {
+            // An endpoint that is no longer local must be removed from the
+            // balancer unless remote endpoints are in use.
+            if self.local.swap_remove(&addr).is_some() && !self.spilled {
+                self.updates.push_back(Update::Remove(addr));
+            }
+            self.remote.insert(addr, endpoint.clone());
+            if self.spilled {
+                self.updates
+                    .push_back(Update::Add(addr, Self::as_remote(endpoint)));
+            }
+        }
+    }
+
+    fn remove(&mut self, addr: SocketAddr) {
+        let known = self.local.swap_remove(&addr).is_some()
+            || (self.remote.swap_remove(&addr).is_some() && self.spilled);
+        if known {
+            self.updates.push_back(Update::Remove(addr));
+        }
+    }
+
+    fn is_overloaded(&self) -> bool {
+        let max = match self.config.max_pending {
+            Some(max) => max,
+            None => return false,
+        };
+
+        let limit = max.saturating_mul(self.local.len());
+        let limit = if self.spilled { limit / 2 } else { limit };
+        self.pending.requests.load(Ordering::Acquire) >= limit
+    }
+
+    /// Adds or removes remote endpoints as the local endpoints' availability
+    /// changes.
+    fn update_spill(&mut self) {
+        let spill = self.local.is_empty() || self.is_overloaded();
+        if spill == self.spilled || self.remote.is_empty() {
+            return;
+        }
+
+        self.spilled = spill;
+        if spill {
+            debug!("spilling over to {} remote endpoints", self.remote.len());
+            for (addr, endpoint) in &self.remote {
+                let located = Self::as_remote(endpoint.clone());
+                self.updates.push_back(Update::Add(*addr, located));
+            }
+        } else {
+            debug!("using {} local endpoints", self.local.len());
+            for addr in self.remote.keys() {
+                self.updates.push_back(Update::Remove(*addr));
+            }
+        }
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: HasLabels + Clone,
+{
+    type Endpoint = Located<R::Endpoint>;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        self.pending.task.register();
+
+        loop {
+            if let Some(update) = self.updates.pop_front() {
+                return Ok(Async::Ready(update));
+            }
+
+            match self.inner.poll()? {
+                Async::Ready(Update::Add(addr, endpoint)) => self.add(addr, endpoint),
+                Async::Ready(Update::Remove(addr)) => self.remove(addr),
+                Async::Ready(Update::NoEndpoints) => {
+                    self.local.clear();
+                    self.remote.clear();
+                    self.spilled = false;
+                    return Ok(Async::Ready(Update::NoEndpoints));
+                }
+                Async::NotReady => {
+                    self.update_spill();
+                    if self.updates.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for Resolution<R>
+where
+    R: resolve::Resolution + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolution")
+            .field("inner", &self.inner)
+            .field("local", &self.local.len())
+            .field("remote", &self.remote.len())
+            .field("spilled", &self.spilled)
+            .finish()
+    }
+}
+
+// === impl Located ===
+
+impl<T: HasWeight> HasWeight for Located<T> {
+    fn weight(&self) -> Weight {
+        self.target.weight()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Located<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.target.fmt(f)
+    }
+}
+
+impl<T: HasLabels> HasLabels for outlier::Observed<T> {
+    fn label(&self, key: &str) -> Option<&str> {
+        self.target().label(key)
+    }
+}
+
+// === impl Layer ===
+
+pub fn layer() -> Layer {
+    Layer(())
+}
+
+impl<M> svc::Layer<M> for Layer {
+    type Service = MakeSvc<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeSvc { inner }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<T, M> svc::Service<Located<T>> for MakeSvc<M>
+where
+    M: svc::Service<T>,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, located: Located<T>) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(located.target),
+            pending: located.pending,
+        }
+    }
+}
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Service {
+            inner,
+            pending: self.pending.take(),
+        }
+        .into())
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> svc::Service<Req> for Service<S>
+where
+    S: svc::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let handle = self.pending.as_ref().map(|p| Handle::new(p.clone()));
+        ResponseFuture {
+            inner: self.inner.call(req),
+            _handle: handle,
+        }
+    }
+}
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+// === impl Pending ===
+
+impl fmt::Debug for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pending")
+            .field("requests", &self.requests.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+// === impl Handle ===
+
+impl Handle {
+    fn new(pending: Arc<Pending>) -> Self {
+        pending.requests.fetch_add(1, Ordering::AcqRel);
+        pending.task.notify();
+        Handle(pending)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.0.requests.fetch_sub(1, Ordering::AcqRel);
+        self.0.task.notify();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, Async, Future, Poll};
+    use indexmap::IndexMap;
+    use std::net::SocketAddr;
+
+    use super::{Config, HasLabels, Resolve};
+    use proxy::resolve::{self, Resolution as _Resolution, Resolve as _Resolve, Update};
+
+    #[derive(Clone, Debug)]
+    struct Endpoint(IndexMap<String, String>);
+
+    impl HasLabels for Endpoint {
+        fn label(&self, key: &str) -> Option<&str> {
+            self.0.get(key).map(|s| s.as_str())
+        }
+    }
+
+    /// A resolution that yields a fixed sequence of updates.
+    struct Fixed(Vec<Update<Endpoint>>);
+
+    impl resolve::Resolution for Fixed {
+        type Endpoint = Endpoint;
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Update<Endpoint>, ()> {
+            if self.0.is_empty() {
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(self.0.remove(0)))
+        }
+    }
+
+    #[derive(Clone)]
+    struct FixedResolve(Vec<Update<Endpoint>>);
+
+    impl resolve::Resolve<()> for FixedResolve {
+        type Endpoint = Endpoint;
+        type Resolution = Fixed;
+
+        fn resolve(&self, _: &()) -> Fixed {
+            Fixed(self.0.clone())
+        }
+    }
+
+    fn endpoint(zone: &str) -> Endpoint {
+        let mut labels = IndexMap::new();
+        labels.insert("zone".to_owned(), zone.to_owned());
+        Endpoint(labels)
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        ([10, 0, 0, 1], port).into()
+    }
+
+    fn config() -> Config {
+        Config {
+            labels: vec![("zone".to_owned(), "a".to_owned())],
+            max_pending: None,
+        }
+    }
+
+    /// Polls the resolution until it is not ready, returning the added and
+    /// removed addresses.
+    fn drain<R>(resolution: &mut R) -> (Vec<SocketAddr>, Vec<SocketAddr>)
+    where
+        R: resolve::Resolution,
+        R::Error: ::std::fmt::Debug,
+    {
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        while let Async::Ready(up) = resolution.poll().expect("poll") {
+            match up {
+                Update::Add(addr, _) => added.push(addr),
+                Update::Remove(addr) => removed.push(addr),
+                Update::NoEndpoints => {}
+            }
+        }
+        (added, removed)
+    }
+
+    #[test]
+    fn prefers_local_endpoints() {
+        future::lazy(|| {
+            let updates = vec![
+                Update::Add(addr(1), endpoint("a")),
+                Update::Add(addr(2), endpoint("b")),
+            ];
+            let resolve = Resolve::new(FixedResolve(updates), config());
+            let mut resolution = resolve.resolve(&());
+
+            assert_eq!(drain(&mut resolution), (vec![addr(1)], vec![]));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn spills_over_without_local_endpoints() {
+        future::lazy(|| {
+            let updates = vec![
+                Update::Add(addr(2), endpoint("b")),
+                Update::Add(addr(1), endpoint("a")),
+            ];
+            let resolve = Resolve::new(FixedResolve(updates), config());
+            let mut resolution = resolve.resolve(&());
+
+            // Updates are applied before spilling over, so the remote
+            // endpoint is never added while a local endpoint exists.
+            assert_eq!(drain(&mut resolution), (vec![addr(1)], vec![]));
+
+            resolution.inner.0.push(Update::Remove(addr(1)));
+            assert_eq!(drain(&mut resolution), (vec![addr(2)], vec![addr(1)]));
+
+            resolution.inner.0.push(Update::Add(addr(3), endpoint("a")));
+            assert_eq!(drain(&mut resolution), (vec![addr(3)], vec![addr(2)]));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn all_endpoints_are_local_without_labels() {
+        future::lazy(|| {
+            let updates = vec![
+                Update::Add(addr(1), endpoint("a")),
+                Update::Add(addr(2), endpoint("b")),
+            ];
+            let resolve = Resolve::new(FixedResolve(updates), Config::default());
+            let mut resolution = resolve.resolve(&());
+
+            assert_eq!(drain(&mut resolution), (vec![addr(1), addr(2)], vec![]));
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
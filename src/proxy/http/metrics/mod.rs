@@ -39,6 +39,8 @@ pub trait Scoped<T> {
 
 pub trait Stats {
     fn incr_retry_skipped_budget(&self);
+    fn incr_retry_skipped_body_too_large(&self);
+    fn record_retry_backoff(&self, backoff: Duration);
 }
 
 #[derive(Debug)]
@@ -49,6 +51,8 @@ where
     last_update: Instant,
     total: Counter,
     by_retry_skipped: IndexMap<RetrySkipped, Counter>,
+    /// Only set for targets that have backed off before retrying.
+    retry_backoff: Option<Histogram<latency::Ms>>,
     by_status: IndexMap<http::StatusCode, StatusMetrics<C>>,
 }
 
@@ -69,6 +73,7 @@ pub struct ClassMetrics {
 #[derive(Debug, PartialEq, Eq, Hash)]
 enum RetrySkipped {
     Budget,
+    BodyTooLarge,
 }
 
 impl<T, C> Default for Registry<T, C>
@@ -135,6 +140,7 @@ where
             last_update: clock::now(),
             total: Counter::default(),
             by_retry_skipped: IndexMap::default(),
+            retry_backoff: None,
             by_status: IndexMap::default(),
         }
     }
@@ -150,6 +156,23 @@ where
             metrics.incr_retry_skipped(RetrySkipped::Budget);
         }
     }
+
+    fn incr_retry_skipped_body_too_large(&self) {
+        if let Ok(mut metrics) = self.lock() {
+            metrics.last_update = clock::now();
+            metrics.incr_retry_skipped(RetrySkipped::BodyTooLarge);
+        }
+    }
+
+    fn record_retry_backoff(&self, backoff: Duration) {
+        if let Ok(mut metrics) = self.lock() {
+            metrics.last_update = clock::now();
+            metrics
+                .retry_backoff
+                .get_or_insert_with(Histogram::default)
+                .add(backoff);
+        }
+    }
 }
 
 impl<C> Default for StatusMetrics<C>
@@ -29,6 +29,7 @@ struct Scope {
     response_total_key: String,
     response_latency_ms_key: String,
     retry_skipped_total_key: String,
+    retry_backoff_ms_key: String,
 }
 
 // ===== impl Report =====
@@ -93,6 +94,11 @@ where
         self.scope.retry_skipped_total().fmt_help(f)?;
         registry.fmt_by_retry(f, self.scope.retry_skipped_total())?;
 
+        if registry.has_retry_backoff() {
+            self.scope.retry_backoff_ms().fmt_help(f)?;
+            registry.fmt_retry_backoff(f, self.scope.retry_backoff_ms())?;
+        }
+
         Ok(())
     }
 }
@@ -137,6 +143,30 @@ where
         Ok(())
     }
 
+    fn has_retry_backoff(&self) -> bool {
+        self.by_target.values().any(|tm| {
+            tm.lock()
+                .map(|tm| tm.retry_backoff.is_some())
+                .unwrap_or(false)
+        })
+    }
+
+    fn fmt_retry_backoff(
+        &self,
+        f: &mut fmt::Formatter,
+        metric: Metric<Histogram<latency::Ms>>,
+    ) -> fmt::Result {
+        for (tgt, tm) in &self.by_target {
+            if let Ok(tm) = tm.lock() {
+                if let Some(ref m) = tm.retry_backoff {
+                    m.fmt_metric_labeled(f, metric.name, tgt)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn fmt_by_status<M, F>(
         &self,
         f: &mut fmt::Formatter,
@@ -193,6 +223,7 @@ impl Default for Scope {
             response_total_key: "response_total".to_owned(),
             response_latency_ms_key: "response_latency_ms".to_owned(),
             retry_skipped_total_key: "retry_skipped_total".to_owned(),
+            retry_backoff_ms_key: "retry_backoff_ms".to_owned(),
         }
     }
 }
@@ -208,6 +239,7 @@ impl Scope {
             response_total_key: format!("{}_response_total", prefix),
             response_latency_ms_key: format!("{}_response_latency_ms", prefix),
             retry_skipped_total_key: format!("{}_retry_skipped_total", prefix),
+            retry_backoff_ms_key: format!("{}_retry_backoff_ms", prefix),
         }
     }
 
@@ -233,6 +265,10 @@ impl Scope {
         )
     }
 
+    fn retry_backoff_ms(&self) -> Metric<Histogram<latency::Ms>> {
+        Metric::new(&self.retry_backoff_ms_key, &Self::RETRY_BACKOFF_MS_HELP)
+    }
+
     const REQUEST_TOTAL_HELP: &'static str = "Total count of HTTP requests.";
 
     const RESPONSE_TOTAL_HELP: &'static str = "Total count of HTTP responses.";
@@ -243,6 +279,9 @@ impl Scope {
 
     const RETRY_SKIPPED_TOTAL_HELP: &'static str =
         "Total count of retryable HTTP responses that were not retried.";
+
+    const RETRY_BACKOFF_MS_HELP: &'static str =
+        "Delays between a retryable HTTP response and the request being retried.";
 }
 
 impl FmtLabels for Status {
@@ -258,6 +297,7 @@ impl FmtLabels for RetrySkipped {
             "skipped=\"{}\"",
             match self {
                 RetrySkipped::Budget => "budget",
+                RetrySkipped::BodyTooLarge => "body_too_large",
             }
         )
     }
@@ -7,11 +7,15 @@ pub(super) mod glue;
 pub mod h1;
 pub mod h2;
 pub mod header_from_target;
+pub mod health;
 pub mod insert;
+pub mod locality;
 pub mod metrics;
 pub mod normalize_uri;
 pub mod orig_proto;
+pub mod outlier;
 pub mod profiles;
+pub mod replay;
 pub mod retry;
 pub mod router;
 pub mod settings;
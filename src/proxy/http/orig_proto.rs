@@ -11,6 +11,9 @@ pub const L5D_ORIG_PROTO: &str = "l5d-orig-proto";
 #[derive(Clone, Debug)]
 pub struct Upgrade<S> {
     inner: S,
+    /// Set if requests' URIs have already been normalized, in which case
+    /// whether they were in absolute-form cannot be determined from them.
+    was_absolute_form: Option<bool>,
 }
 
 /// Downgrades HTTP2 requests that were previousl upgraded to their original
@@ -27,7 +30,21 @@ impl<S> Upgrade<S> {
     where
         S: svc::Service<http::Request<A>, Response = http::Response<B>>,
     {
-        Self { inner }
+        Self {
+            inner,
+            was_absolute_form: None,
+        }
+    }
+
+    /// Upgrades requests whose URIs have already been normalized.
+    pub fn normalized<A, B>(inner: S, was_absolute_form: bool) -> Self
+    where
+        S: svc::Service<http::Request<A>, Response = http::Response<B>>,
+    {
+        Self {
+            inner,
+            was_absolute_form: Some(was_absolute_form),
+        }
     }
 }
 
@@ -54,13 +71,19 @@ where
         // absolute-form is far less common, origin-form is the usual,
         // so only encode the extra information if it's different than
         // the normal.
-        let was_absolute_form = h1::is_absolute_form(req.uri());
-        if !was_absolute_form {
-            // Since the version is going to set to HTTP_2, the NormalizeUri
-            // middleware won't normalize the URI automatically, so it
-            // needs to be done now.
-            h1::normalize_our_view_of_uri(&mut req);
-        }
+        let was_absolute_form = match self.was_absolute_form {
+            Some(was_absolute_form) => was_absolute_form,
+            None => {
+                let was_absolute_form = h1::is_absolute_form(req.uri());
+                if !was_absolute_form {
+                    // Since the version is going to set to HTTP_2, the
+                    // NormalizeUri middleware won't normalize the URI
+                    // automatically, so it needs to be done now.
+                    h1::normalize_our_view_of_uri(&mut req);
+                }
+                was_absolute_form
+            }
+        };
 
         let val = match (req.version(), was_absolute_form) {
             (http::Version::HTTP_11, false) => "HTTP/1.1",
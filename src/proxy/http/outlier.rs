@@ -0,0 +1,1061 @@
+//! Outlier detection for load-balanced endpoints.
+//!
+//! Each endpoint produced by a resolution is observed: the classification of
+//! every response it serves is recorded so that endpoints that fail
+//! consecutively, or that fail at too high a rate, may be ejected from the
+//! balancer. An ejected endpoint is removed from the resolution's `Discover`
+//! set and is re-added once its ejection period elapses. Each successive
+//! ejection of an endpoint doubles its ejection period, up to a maximum.
+//!
+//! The last endpoint in a resolution is never ejected, since a balancer
+//! without endpoints cannot serve any requests.
+
+use futures::{task::AtomicTask, Async, Future, Poll};
+use http;
+use hyper::body::Payload;
+use indexmap::IndexMap;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, u32};
+use tokio_timer::{clock, Delay};
+
+use metrics::{Counter, FmtLabels, FmtMetric, FmtMetrics, Gauge, Metric};
+use proxy::http::balance::{HasWeight, Weight};
+use proxy::http::metrics::classify::{ClassifyEos, ClassifyResponse};
+use proxy::resolve::{self, Update};
+use svc;
+use Addr;
+
+metrics! {
+    outlier_ejections_total: Counter { "Total count of endpoints ejected from load balancers" },
+    outlier_ejected_endpoints: Gauge { "Number of endpoints currently ejected from load balancers" }
+}
+
+/// Configures when endpoints are ejected and for how long.
+///
+/// Detection is disabled unless at least one threshold is set.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Ejects an endpoint after this many consecutive failures.
+    pub consecutive_failures: Option<u32>,
+    pub failure_rate: Option<FailureRate>,
+
+    /// The duration of an endpoint's first ejection.
+    pub base_ejection: Duration,
+    pub max_ejection: Duration,
+}
+
+/// Ejects an endpoint when the ratio of failed requests within a window
+/// reaches `threshold`.
+#[derive(Clone, Debug)]
+pub struct FailureRate {
+    pub threshold: f64,
+
+    /// The minimum number of requests in a window before the failure rate is
+    /// considered.
+    pub min_requests: u32,
+    pub window: Duration,
+}
+
+/// Determines whether a response classification is a failure.
+pub trait IsFailure {
+    fn is_failure(&self) -> bool;
+}
+
+/// Wraps a `Resolve` so that its endpoints may be ejected.
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    inner: R,
+    config: Arc<Config>,
+    registry: Registry,
+}
+
+pub struct Resolution<R: resolve::Resolution> {
+    inner: R,
+    dst: Addr,
+    config: Arc<Config>,
+    registry: Registry,
+    endpoints: IndexMap<SocketAddr, Tracked<R::Endpoint>>,
+
+    /// Notified when an endpoint should be ejected.
+    task: Arc<AtomicTask>,
+}
+
+/// An endpoint target that is observed for outlier detection.
+#[derive(Clone)]
+pub struct Observed<T> {
+    target: T,
+    state: Arc<State>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<C> {
+    _p: PhantomData<fn() -> C>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeSvc<M, C> {
+    inner: M,
+    _p: PhantomData<fn() -> C>,
+}
+
+pub struct MakeFuture<F, C> {
+    inner: F,
+    state: Option<Arc<State>>,
+    _p: PhantomData<fn() -> C>,
+}
+
+/// Records the classification of each response served by an endpoint.
+pub struct Observe<S, C> {
+    inner: S,
+    state: Arc<State>,
+    _p: PhantomData<fn() -> C>,
+}
+
+pub struct ResponseFuture<F, C> {
+    inner: F,
+    classify: Option<C>,
+    state: Option<Arc<State>>,
+}
+
+pub struct ResponseBody<B, E>
+where
+    E: ClassifyEos,
+    E::Class: IsFailure,
+{
+    inner: B,
+    eos: Option<E>,
+    state: Option<Arc<State>>,
+
+    /// Set once the inner body has returned all of its data.
+    data_done: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Reason {
+    ConsecutiveFailures,
+    FailureRate,
+}
+
+/// Records outlier ejections.
+#[derive(Clone, Debug, Default)]
+pub struct Registry(Arc<Mutex<IndexMap<Addr, DstMetrics>>>);
+
+/// Implements `FmtMetrics` to render prometheus-formatted ejection metrics.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<IndexMap<Addr, DstMetrics>>>);
+
+struct Tracked<T> {
+    endpoint: T,
+    state: Arc<State>,
+
+    /// The number of times this endpoint has been ejected.
+    ejections: u32,
+    ejection: Option<Ejection>,
+}
+
+struct Ejection {
+    reason: Reason,
+    readmit: Delay,
+}
+
+/// Tracks the results of requests to a single endpoint.
+struct State {
+    config: Arc<Config>,
+    stats: Mutex<Stats>,
+    task: Arc<AtomicTask>,
+}
+
+struct Stats {
+    consecutive_failures: u32,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+
+    /// Set when the endpoint should be ejected by the resolution.
+    pending: Option<Reason>,
+    ejected: bool,
+}
+
+/// A destination's metrics, which are retained while it is resolved (i.e.
+/// while a router caches a balancer for it).
+#[derive(Debug, Default)]
+struct DstMetrics {
+    resolutions: usize,
+    by_reason: IndexMap<Reason, Metrics>,
+}
+
+struct Key<'a> {
+    dst: &'a Addr,
+    reason: Reason,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    ejections_total: Counter,
+    ejected_endpoints: Gauge,
+}
+
+pub fn new() -> (Registry, Report) {
+    let inner = Arc::new(Mutex::new(IndexMap::new()));
+    (Registry(inner.clone()), Report(inner))
+}
+
+// === impl Config ===
+
+impl Config {
+    fn is_enabled(&self) -> bool {
+        self.consecutive_failures.is_some() || self.failure_rate.is_some()
+    }
+
+    /// Returns the duration of an endpoint's ejection, given the number of
+    /// times it has been ejected previously.
+    fn ejection_timeout(&self, ejections: u32) -> Duration {
+        let factor = 1u32.checked_shl(ejections).unwrap_or(u32::MAX);
+        self.base_ejection
+            .checked_mul(factor)
+            .map(|d| d.min(self.max_ejection))
+            .unwrap_or(self.max_ejection)
+    }
+
+    fn check(&self, stats: &Stats) -> Option<Reason> {
+        if let Some(max) = self.consecutive_failures {
+            if stats.consecutive_failures >= max {
+                return Some(Reason::ConsecutiveFailures);
+            }
+        }
+
+        if let Some(ref rate) = self.failure_rate {
+            if stats.requests > 0 && stats.requests >= rate.min_requests {
+                let ratio = f64::from(stats.failures) / f64::from(stats.requests);
+                if ratio >= rate.threshold {
+                    return Some(Reason::FailureRate);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new(inner: R, config: Config, registry: Registry) -> Self {
+        Self {
+            inner,
+            config: Arc::new(config),
+            registry,
+        }
+    }
+}
+
+impl<T, R> resolve::Resolve<T> for Resolve<R>
+where
+    T: AsRef<Addr>,
+    R: resolve::Resolve<T>,
+    R::Endpoint: Clone,
+{
+    type Endpoint = Observed<R::Endpoint>;
+    type Resolution = Resolution<R::Resolution>;
+
+    fn resolve(&self, target: &T) -> Self::Resolution {
+        self.registry.resolved(target.as_ref());
+        Resolution {
+            inner: self.inner.resolve(target),
+            dst: target.as_ref().clone(),
+            config: self.config.clone(),
+            registry: self.registry.clone(),
+            endpoints: IndexMap::new(),
+            task: Arc::new(AtomicTask::new()),
+        }
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone,
+{
+    /// Ejects endpoints that have been flagged as outliers and readmits
+    /// endpoints whose ejections have elapsed.
+    fn poll_ejections(&mut self) -> Option<Update<Observed<R::Endpoint>>> {
+        let mut active = self
+            .endpoints
+            .values()
+            .filter(|t| t.ejection.is_none())
+            .count();
+
+        for (addr, tracked) in self.endpoints.iter_mut() {
+            let readmit = match tracked.ejection.as_mut().map(|e| e.readmit.poll()) {
+                None => false,
+                Some(Ok(Async::NotReady)) => continue,
+                // If the timer fails, readmit the endpoint rather than
+                // leaving it ejected indefinitely.
+                Some(_) => true,
+            };
+
+            if readmit {
+                let ejection = tracked.ejection.take().expect("endpoint must be ejected");
+                debug!("readmitting {} after {:?}", addr, ejection.reason);
+                self.registry.readmitted(&self.dst, ejection.reason);
+                tracked.state.reset();
+                return Some(Update::Add(*addr, tracked.observed()));
+            }
+
+            if let Some(reason) = tracked.state.take_pending() {
+                if active <= 1 {
+                    debug!("not ejecting last endpoint {}", addr);
+                    tracked.state.reset();
+                    continue;
+                }
+
+                let timeout = self.config.ejection_timeout(tracked.ejections);
+                debug!("ejecting {} for {:?}: {:?}", addr, timeout, reason);
+                self.registry.ejected(&self.dst, reason);
+                tracked.ejections = tracked.ejections.saturating_add(1);
+                tracked.ejection = Some(Ejection {
+                    reason,
+                    readmit: Delay::new(clock::now() + timeout),
+                });
+                active -= 1;
+                return Some(Update::Remove(*addr));
+            }
+        }
+
+        None
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone,
+{
+    type Endpoint = Observed<R::Endpoint>;
+    type Error = R::Error;
+
+    fn poll(&mut self) -> Poll<Update<Self::Endpoint>, Self::Error> {
+        // Register before checking endpoint state so that ejections flagged
+        // after this point notify the task.
+        self.task.register();
+
+        if let Some(update) = self.poll_ejections() {
+            return Ok(Async::Ready(update));
+        }
+
+        loop {
+            match try_ready!(self.inner.poll()) {
+                Update::Add(addr, endpoint) => {
+                    if let Some(tracked) = self.endpoints.get_mut(&addr) {
+                        tracked.endpoint = endpoint;
+                        if tracked.ejection.is_some() {
+                            // The endpoint is added when it is readmitted.
+                            continue;
+                        }
+                        return Ok(Async::Ready(Update::Add(addr, tracked.observed())));
+                    }
+
+                    let state = Arc::new(State::new(self.config.clone(), self.task.clone()));
+                    let tracked = Tracked {
+                        endpoint,
+                        state,
+                        ejections: 0,
+                        ejection: None,
+                    };
+                    let observed = tracked.observed();
+                    self.endpoints.insert(addr, tracked);
+                    return Ok(Async::Ready(Update::Add(addr, observed)));
+                }
+                Update::Remove(addr) => {
+                    if let Some(tracked) = self.endpoints.swap_remove(&addr) {
+                        if let Some(ejection) = tracked.ejection {
+                            // The endpoint was already removed when it was
+                            // ejected.
+                            self.registry.readmitted(&self.dst, ejection.reason);
+                            continue;
+                        }
+                    }
+                    return Ok(Async::Ready(Update::Remove(addr)));
+                }
+                Update::NoEndpoints => {
+                    for (_, tracked) in self.endpoints.drain(..) {
+                        if let Some(ejection) = tracked.ejection {
+                            self.registry.readmitted(&self.dst, ejection.reason);
+                        }
+                    }
+                    return Ok(Async::Ready(Update::NoEndpoints));
+                }
+            }
+        }
+    }
+}
+
+impl<R> Drop for Resolution<R>
+where
+    R: resolve::Resolution,
+{
+    fn drop(&mut self) {
+        for tracked in self.endpoints.values() {
+            if let Some(ref ejection) = tracked.ejection {
+                self.registry.readmitted(&self.dst, ejection.reason);
+            }
+        }
+        self.registry.released(&self.dst);
+    }
+}
+
+impl<R> fmt::Debug for Resolution<R>
+where
+    R: resolve::Resolution + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolution")
+            .field("inner", &self.inner)
+            .field("dst", &self.dst)
+            .field("endpoints", &self.endpoints.len())
+            .finish()
+    }
+}
+
+// === impl Tracked ===
+
+impl<T: Clone> Tracked<T> {
+    fn observed(&self) -> Observed<T> {
+        Observed {
+            target: self.endpoint.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+// === impl State ===
+
+impl State {
+    fn new(config: Arc<Config>, task: Arc<AtomicTask>) -> Self {
+        Self {
+            config,
+            stats: Mutex::new(Stats::new(clock::now())),
+            task,
+        }
+    }
+
+    fn record(&self, is_failure: bool) {
+        if !self.config.is_enabled() {
+            return;
+        }
+
+        let reason = {
+            let mut stats = match self.stats.lock() {
+                Ok(stats) => stats,
+                Err(_) => return,
+            };
+            if stats.ejected || stats.pending.is_some() {
+                return;
+            }
+
+            if is_failure {
+                stats.consecutive_failures += 1;
+            } else {
+                stats.consecutive_failures = 0;
+            }
+
+            if let Some(ref rate) = self.config.failure_rate {
+                let now = clock::now();
+                if now - stats.window_start >= rate.window {
+                    stats.window_start = now;
+                    stats.requests = 0;
+                    stats.failures = 0;
+                }
+
+                stats.requests += 1;
+                if is_failure {
+                    stats.failures += 1;
+                }
+            }
+
+            let reason = self.config.check(&stats);
+            stats.pending = reason;
+            reason
+        };
+
+        if reason.is_some() {
+            self.task.notify();
+        }
+    }
+
+    /// Returns the reason the endpoint should be ejected, if any, marking the
+    /// endpoint as ejected.
+    fn take_pending(&self) -> Option<Reason> {
+        let mut stats = self.stats.lock().ok()?;
+        let reason = stats.pending.take();
+        if reason.is_some() {
+            stats.ejected = true;
+        }
+        reason
+    }
+
+    fn reset(&self) {
+        if let Ok(mut stats) = self.stats.lock() {
+            *stats = Stats::new(clock::now());
+        }
+    }
+}
+
+impl Stats {
+    fn new(now: Instant) -> Self {
+        Self {
+            consecutive_failures: 0,
+            window_start: now,
+            requests: 0,
+            failures: 0,
+            pending: None,
+            ejected: false,
+        }
+    }
+}
+
+// === impl Observed ===
+
+impl<T> Observed<T> {
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+}
+
+impl<T: HasWeight> HasWeight for Observed<T> {
+    fn weight(&self) -> Weight {
+        self.target.weight()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Observed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.target.fmt(f)
+    }
+}
+
+// === impl Layer ===
+
+/// Observes the responses of `Observed` endpoint stacks, classifying them
+/// with the `C`-typed request extension.
+pub fn layer<C>() -> Layer<C>
+where
+    C: ClassifyResponse + Clone + Default + Send + Sync + 'static,
+    C::Class: IsFailure,
+{
+    Layer { _p: PhantomData }
+}
+
+impl<M, C> svc::Layer<M> for Layer<C> {
+    type Service = MakeSvc<M, C>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeSvc {
+            inner,
+            _p: PhantomData,
+        }
+    }
+}
+
+// === impl MakeSvc ===
+
+impl<T, M, C> svc::Service<Observed<T>> for MakeSvc<M, C>
+where
+    M: svc::Service<T>,
+{
+    type Response = Observe<M::Response, C>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, observed: Observed<T>) -> Self::Future {
+        MakeFuture {
+            inner: self.inner.call(observed.target),
+            state: Some(observed.state),
+            _p: PhantomData,
+        }
+    }
+}
+
+impl<F: Future, C> Future for MakeFuture<F, C> {
+    type Item = Observe<F::Item, C>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let state = self.state.take().expect("polled more than once");
+        Ok(Observe {
+            inner,
+            state,
+            _p: PhantomData,
+        }
+        .into())
+    }
+}
+
+// === impl Observe ===
+
+impl<S, C, A, B> svc::Service<http::Request<A>> for Observe<S, C>
+where
+    S: svc::Service<http::Request<A>, Response = http::Response<B>>,
+    B: Payload,
+    C: ClassifyResponse + Clone + Default + Send + Sync + 'static,
+    C::Class: IsFailure,
+{
+    type Response = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, C>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let classify = req.extensions().get::<C>().cloned().unwrap_or_default();
+
+        ResponseFuture {
+            classify: Some(classify),
+            state: Some(self.state.clone()),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+impl<F, C, B> Future for ResponseFuture<F, C>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Payload,
+    C: ClassifyResponse,
+    C::Class: IsFailure,
+{
+    type Item = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = match self.inner.poll() {
+            Ok(Async::Ready(rsp)) => rsp,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                // Errors from the endpoint stack (e.g. failed connections)
+                // are always failures.
+                if let Some(state) = self.state.take() {
+                    state.record(true);
+                }
+                return Err(e);
+            }
+        };
+
+        let eos = self.classify.take().map(|c| c.start(&rsp));
+        let (head, inner) = rsp.into_parts();
+        let body = ResponseBody {
+            inner,
+            eos,
+            state: self.state.take(),
+            data_done: false,
+        };
+        Ok(http::Response::from_parts(head, body).into())
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<B, E> ResponseBody<B, E>
+where
+    E: ClassifyEos,
+    E::Class: IsFailure,
+{
+    fn record_eos(&mut self, trailers: Option<&http::HeaderMap>) {
+        if let Some(eos) = self.eos.take() {
+            let is_failure = eos.eos(trailers).is_failure();
+            if let Some(state) = self.state.take() {
+                state.record(is_failure);
+            }
+        }
+    }
+
+    fn record_error(&mut self) {
+        self.eos = None;
+        if let Some(state) = self.state.take() {
+            state.record(true);
+        }
+    }
+}
+
+impl<B, E> Payload for ResponseBody<B, E>
+where
+    B: Payload,
+    E: ClassifyEos + Send + 'static,
+    E::Class: IsFailure,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let data = try_ready!(self.inner.poll_data().map_err(|e| {
+            self.record_error();
+            e
+        }));
+        if data.is_none() {
+            self.data_done = true;
+        }
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self.inner.poll_trailers().map_err(|e| {
+            self.record_error();
+            e
+        }));
+
+        self.record_eos(trailers.as_ref());
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B, E> Default for ResponseBody<B, E>
+where
+    B: Default,
+    E: ClassifyEos,
+    E::Class: IsFailure,
+{
+    fn default() -> Self {
+        Self {
+            inner: B::default(),
+            eos: None,
+            state: None,
+            data_done: false,
+        }
+    }
+}
+
+impl<B, E> Drop for ResponseBody<B, E>
+where
+    E: ClassifyEos,
+    E::Class: IsFailure,
+{
+    fn drop(&mut self) {
+        // Responses are classified when they are dropped without trailers
+        // having been read. Bodies that are dropped before they end (e.g.
+        // because the request was canceled) are not recorded at all, since
+        // it's not known whether the endpoint would have failed.
+        if self.data_done {
+            self.record_eos(None);
+        }
+    }
+}
+
+// === impl Registry ===
+
+impl Registry {
+    fn resolved(&self, dst: &Addr) {
+        if let Ok(mut metrics) = self.0.lock() {
+            metrics
+                .entry(dst.clone())
+                .or_insert_with(DstMetrics::default)
+                .resolutions += 1;
+        }
+    }
+
+    /// Drops a destination's metrics once it is no longer resolved.
+    fn released(&self, dst: &Addr) {
+        if let Ok(mut metrics) = self.0.lock() {
+            let unused = match metrics.get_mut(dst) {
+                Some(m) => {
+                    m.resolutions = m.resolutions.saturating_sub(1);
+                    m.resolutions == 0
+                }
+                None => false,
+            };
+            if unused {
+                metrics.swap_remove(dst);
+            }
+        }
+    }
+
+    fn ejected(&self, dst: &Addr, reason: Reason) {
+        if let Ok(mut metrics) = self.0.lock() {
+            if let Some(m) = metrics.get_mut(dst) {
+                let m = m.by_reason.entry(reason).or_insert_with(Metrics::default);
+                m.ejections_total.incr();
+                m.ejected_endpoints.incr();
+            }
+        }
+    }
+
+    fn readmitted(&self, dst: &Addr, reason: Reason) {
+        if let Ok(mut metrics) = self.0.lock() {
+            if let Some(m) = metrics
+                .get_mut(dst)
+                .and_then(|m| m.by_reason.get_mut(&reason))
+            {
+                m.ejected_endpoints.decr();
+            }
+        }
+    }
+}
+
+// === impl Report ===
+
+impl FmtMetrics for Report {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let metrics = match self.0.lock() {
+            Err(_) => return Ok(()),
+            Ok(lock) => lock,
+        };
+
+        if metrics.values().all(|m| m.by_reason.is_empty()) {
+            return Ok(());
+        }
+
+        fmt_by(&metrics, f, outlier_ejections_total, |m| &m.ejections_total)?;
+        fmt_by(&metrics, f, outlier_ejected_endpoints, |m| {
+            &m.ejected_endpoints
+        })?;
+
+        Ok(())
+    }
+}
+
+fn fmt_by<F, M>(
+    metrics: &IndexMap<Addr, DstMetrics>,
+    f: &mut fmt::Formatter,
+    metric: Metric<M>,
+    get_metric: F,
+) -> fmt::Result
+where
+    F: Fn(&Metrics) -> &M,
+    M: FmtMetric,
+{
+    metric.fmt_help(f)?;
+    for (dst, m) in metrics {
+        for (reason, m) in &m.by_reason {
+            let key = Key {
+                dst,
+                reason: *reason,
+            };
+            get_metric(m).fmt_metric_labeled(f, metric.name, key)?;
+        }
+    }
+
+    Ok(())
+}
+
+// === impl Key ===
+
+impl<'a> FmtLabels for Key<'a> {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "dst=\"{}\",", self.dst)?;
+        self.reason.fmt_labels(f)
+    }
+}
+
+impl FmtLabels for Reason {
+    fn fmt_labels(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Reason::ConsecutiveFailures => "consecutive_failures",
+            Reason::FailureRate => "failure_rate",
+        };
+        write!(f, "reason=\"{}\"", reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future, task::AtomicTask, Async, Future, Poll};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::runtime::current_thread;
+    use tokio_timer::{clock, Delay};
+
+    use super::{new, Config, FailureRate, Reason, Resolve, State};
+    use metrics::FmtMetrics;
+    use proxy::resolve::{self, Resolution as _Resolution, Resolve as _Resolve, Update};
+    use Addr;
+
+    /// A resolution that yields a fixed sequence of updates.
+    struct Fixed(Vec<Update<()>>);
+
+    impl resolve::Resolution for Fixed {
+        type Endpoint = ();
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<Update<()>, ()> {
+            if self.0.is_empty() {
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(self.0.remove(0)))
+        }
+    }
+
+    #[derive(Clone)]
+    struct FixedResolve(Vec<Update<()>>);
+
+    impl resolve::Resolve<Addr> for FixedResolve {
+        type Endpoint = ();
+        type Resolution = Fixed;
+
+        fn resolve(&self, _: &Addr) -> Fixed {
+            Fixed(self.0.clone())
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        ([10, 0, 0, 1], port).into()
+    }
+
+    fn config(consecutive_failures: Option<u32>, failure_rate: Option<FailureRate>) -> Config {
+        Config {
+            consecutive_failures,
+            failure_rate,
+            base_ejection: Duration::from_secs(30),
+            max_ejection: Duration::from_secs(300),
+        }
+    }
+
+    fn state(config: Config) -> State {
+        State::new(Arc::new(config), Arc::new(AtomicTask::new()))
+    }
+
+    #[test]
+    fn consecutive_failures() {
+        let state = state(config(Some(3), None));
+
+        state.record(true);
+        state.record(true);
+        state.record(false);
+        state.record(true);
+        state.record(true);
+        assert_eq!(state.take_pending(), None);
+
+        state.record(true);
+        assert_eq!(state.take_pending(), Some(Reason::ConsecutiveFailures));
+
+        // Ejected endpoints are not observed until they are reset.
+        state.record(true);
+        assert_eq!(state.take_pending(), None);
+    }
+
+    #[test]
+    fn failure_rate() {
+        let rate = FailureRate {
+            threshold: 0.5,
+            min_requests: 4,
+            window: Duration::from_secs(60),
+        };
+        let state = state(config(None, Some(rate)));
+
+        state.record(true);
+        state.record(false);
+        state.record(true);
+        assert_eq!(state.take_pending(), None, "too few requests");
+
+        state.record(false);
+        assert_eq!(state.take_pending(), Some(Reason::FailureRate));
+
+        state.reset();
+        for _ in 0..10 {
+            state.record(false);
+        }
+        state.record(true);
+        assert_eq!(state.take_pending(), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let state = state(config(None, None));
+        for _ in 0..100 {
+            state.record(true);
+        }
+        assert_eq!(state.take_pending(), None);
+    }
+
+    #[test]
+    fn ejection_backoff() {
+        let config = config(Some(1), None);
+        assert_eq!(config.ejection_timeout(0), Duration::from_secs(30));
+        assert_eq!(config.ejection_timeout(1), Duration::from_secs(60));
+        assert_eq!(config.ejection_timeout(3), Duration::from_secs(240));
+        assert_eq!(config.ejection_timeout(4), Duration::from_secs(300));
+        assert_eq!(config.ejection_timeout(40), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn ejects_and_readmits_endpoints() {
+        let (registry, report) = new();
+        let config = Config {
+            base_ejection: Duration::from_millis(10),
+            ..config(Some(2), None)
+        };
+        let updates = vec![Update::Add(addr(1), ()), Update::Add(addr(2), ())];
+        let resolve = Resolve::new(FixedResolve(updates), config, registry);
+        let dst = Addr::Socket(addr(80));
+        let mut resolution = resolve.resolve(&dst);
+
+        let mut rt = current_thread::Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            let mut states = Vec::new();
+            for port in 1..3 {
+                match resolution.poll() {
+                    Ok(Async::Ready(Update::Add(a, observed))) => {
+                        assert_eq!(a, addr(port));
+                        states.push(observed.state);
+                    }
+                    _ => panic!("expected endpoint {} to be added", port),
+                }
+            }
+
+            states[0].record(true);
+            assert!(resolution.poll().unwrap().is_not_ready());
+            states[0].record(true);
+            match resolution.poll() {
+                Ok(Async::Ready(Update::Remove(a))) => assert_eq!(a, addr(1)),
+                _ => panic!("expected endpoint 1 to be ejected"),
+            }
+
+            // The last active endpoint is never ejected.
+            states[1].record(true);
+            states[1].record(true);
+            assert!(resolution.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let text = report.as_display().to_string();
+        assert!(text.contains(
+            "outlier_ejected_endpoints{dst=\"10.0.0.1:80\",reason=\"consecutive_failures\"} 1"
+        ));
+
+        rt.block_on(Delay::new(clock::now() + Duration::from_millis(50)))
+            .unwrap();
+        rt.block_on(future::lazy(|| {
+            match resolution.poll() {
+                Ok(Async::Ready(Update::Add(a, _))) => assert_eq!(a, addr(1)),
+                _ => panic!("expected endpoint 1 to be readmitted"),
+            }
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let text = report.as_display().to_string();
+        assert!(text.contains(
+            "outlier_ejected_endpoints{dst=\"10.0.0.1:80\",reason=\"consecutive_failures\"} 0"
+        ));
+
+        // Metrics are dropped with the last resolution of the destination.
+        drop(resolution);
+        assert_eq!(report.as_display().to_string(), "");
+    }
+}
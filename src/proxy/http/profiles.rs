@@ -13,10 +13,33 @@ use std::time::Duration;
 
 use never::Never;
 
-use super::retry::Budget;
+use super::balance;
+use super::retry::{Backoff, Budget};
 use NameAddr;
 
-pub type Routes = Vec<(RequestMatch, Route)>;
+/// A destination's profile.
+///
+/// Requests are matched against `routes` to determine per-route policy. If
+/// `dst_overrides` is not empty, each request is then dispatched to one of the
+/// listed concrete destinations, chosen randomly according to its weight,
+/// instead of the logical destination.
+///
+/// If `load_balancer` is set, it overrides the default algorithm used to
+/// balance requests over each concrete destination's endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct Routes {
+    pub routes: Vec<(RequestMatch, Route)>,
+    pub dst_overrides: Vec<WeightedAddr>,
+    pub load_balancer: Option<balance::Algorithm>,
+}
+
+/// A concrete destination that receives a share of a logical destination's
+/// traffic.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WeightedAddr {
+    pub addr: NameAddr,
+    pub weight: u32,
+}
 
 /// Watches a destination's Routes.
 ///
@@ -35,6 +58,18 @@ pub trait WithRoute {
     fn with_route(self, route: Route) -> Self::Output;
 }
 
+/// Implemented by target types that may be rebound to a concrete destination
+/// when a profile splits traffic.
+pub trait WithAddr {
+    fn with_addr(self, addr: NameAddr) -> Self;
+}
+
+/// Implemented by target types that may be configured with a destination's
+/// load-balancing algorithm.
+pub trait WithLoadBalancer {
+    fn with_load_balancer(self, load_balancer: Option<balance::Algorithm>) -> Self;
+}
+
 /// Implemented by target types that may have a `NameAddr` destination that
 /// can be discovered via `GetRoutes`.
 pub trait CanGetDestination {
@@ -47,8 +82,14 @@ pub struct Route {
     response_classes: ResponseClasses,
     retries: Option<Retries>,
     timeout: Option<Duration>,
+    per_try_timeout: Option<Duration>,
 }
 
+/// Matches requests to a route.
+///
+/// The Destination API (as of linkerd2-proxy-api v0.1.8) can only describe
+/// `All`, `Any`, `Not`, `Path`, and `Method` matches, so the other variants
+/// can only be configured by local profiles (see `app::profiles::file`).
 #[derive(Clone, Debug)]
 pub enum RequestMatch {
     All(Vec<RequestMatch>),
@@ -56,6 +97,32 @@ pub enum RequestMatch {
     Not(Box<RequestMatch>),
     Path(Regex),
     Method(http::Method),
+    /// Configured by an `authority` condition in the profile file.
+    Authority(ValueMatch),
+    /// Configured by a `header` condition in the profile file.
+    Header {
+        name: http::header::HeaderName,
+        value: ValueMatch,
+    },
+    /// Configured by a `query_param` condition in the profile file.
+    QueryParam {
+        name: String,
+        value: ValueMatch,
+    },
+    /// Configured by a `scheme` condition in the profile file.
+    Scheme(http::uri::Scheme),
+}
+
+/// Describes how a single request value (e.g. a header or query parameter) is
+/// matched.
+#[derive(Clone, Debug)]
+pub enum ValueMatch {
+    /// The value must be exactly equal to the given string.
+    Exact(String),
+    /// The value must match the given regular expression.
+    Regex(Regex),
+    /// The value must be present, but its contents are ignored.
+    Present,
 }
 
 #[derive(Clone, Debug)]
@@ -67,6 +134,11 @@ pub struct ResponseClass {
 #[derive(Clone, Default)]
 pub struct ResponseClasses(Arc<Vec<ResponseClass>>);
 
+/// Matches responses to a response class.
+///
+/// The Destination API (as of linkerd2-proxy-api v0.1.8) can only describe
+/// `All`, `Any`, `Not`, and `Status` matches, so the other variants can only
+/// be configured by local profiles (see `app::profiles::file`).
 #[derive(Clone, Debug)]
 pub enum ResponseMatch {
     All(Vec<ResponseMatch>),
@@ -76,11 +148,26 @@ pub enum ResponseMatch {
         min: http::StatusCode,
         max: http::StatusCode,
     },
+    /// Configured by a `header` condition in the profile file.
+    Header {
+        name: http::header::HeaderName,
+        value: ValueMatch,
+    },
+    /// Matches a response's `grpc-status`, which is read from its trailers or,
+    /// for trailers-only responses, from its headers.
+    ///
+    /// Configured by a `grpc_status` condition in the profile file.
+    GrpcStatus {
+        min: u32,
+        max: u32,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct Retries {
     budget: Arc<Budget>,
+    backoff: Option<Backoff>,
+    retry_connection_errors: bool,
 }
 
 #[derive(Clone, Default)]
@@ -104,6 +191,7 @@ impl Route {
             response_classes: ResponseClasses(response_classes.into()),
             retries: None,
             timeout: None,
+            per_try_timeout: None,
         }
     }
 
@@ -123,13 +211,23 @@ impl Route {
         self.timeout
     }
 
-    pub fn set_retries(&mut self, budget: Arc<Budget>) {
-        self.retries = Some(Retries { budget });
+    /// The timeout for each attempt of a retried request. Unlike `timeout`,
+    /// this does not bound the total time spent on retries.
+    pub fn per_try_timeout(&self) -> Option<Duration> {
+        self.per_try_timeout
+    }
+
+    pub fn set_retries(&mut self, retries: Retries) {
+        self.retries = Some(retries);
     }
 
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    pub fn set_per_try_timeout(&mut self, timeout: Duration) {
+        self.per_try_timeout = Some(timeout);
+    }
 }
 
 // === impl RequestMatch ===
@@ -139,6 +237,35 @@ impl RequestMatch {
         match self {
             RequestMatch::Method(ref method) => req.method() == *method,
             RequestMatch::Path(ref re) => re.is_match(req.uri().path()),
+            RequestMatch::Authority(ref m) => {
+                let authority = req
+                    .uri()
+                    .authority_part()
+                    .cloned()
+                    .or_else(|| super::h1::authority_from_host(req));
+                match authority {
+                    Some(ref a) => m.is_match_authority(a.as_str()),
+                    None => false,
+                }
+            }
+            RequestMatch::Header {
+                ref name,
+                ref value,
+            } => req
+                .headers()
+                .get_all(name)
+                .iter()
+                .any(|v| value.is_match_header(v)),
+            RequestMatch::QueryParam {
+                ref name,
+                ref value,
+            } => query_params(req.uri()).any(|(k, v)| k == name.as_str() && value.is_match_str(v)),
+            RequestMatch::Scheme(ref scheme) => match req.uri().scheme_part() {
+                Some(s) => s == scheme,
+                // Origin-form requests do not carry a scheme, so they are
+                // considered plaintext HTTP.
+                None => *scheme == http::uri::Scheme::HTTP,
+            },
             RequestMatch::Not(ref m) => !m.is_match(req),
             RequestMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
             RequestMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
@@ -146,6 +273,52 @@ impl RequestMatch {
     }
 }
 
+/// Iterates over the `name=value` pairs in a URI's query string.
+///
+/// Parameters without a `=` have an empty value. Names and values are not
+/// percent-decoded.
+fn query_params<'a>(uri: &'a http::Uri) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+    uri.query()
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let mut kv = p.splitn(2, '=');
+            let k = kv.next().unwrap_or("");
+            let v = kv.next().unwrap_or("");
+            (k, v)
+        })
+}
+
+// === impl ValueMatch ===
+
+impl ValueMatch {
+    fn is_match_str(&self, value: &str) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Exact(ref s) => s == value,
+            ValueMatch::Regex(ref re) => re.is_match(value),
+        }
+    }
+
+    fn is_match_header(&self, value: &http::header::HeaderValue) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Exact(ref s) => value.as_bytes() == s.as_bytes(),
+            ValueMatch::Regex(ref re) => value.to_str().map(|v| re.is_match(v)).unwrap_or(false),
+        }
+    }
+
+    /// Authorities are matched case-insensitively, since DNS names are.
+    fn is_match_authority(&self, authority: &str) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Exact(ref s) => s.eq_ignore_ascii_case(authority),
+            ValueMatch::Regex(ref re) => re.is_match(authority),
+        }
+    }
+}
+
 // === impl ResponseClass ===
 
 impl ResponseClass {
@@ -157,8 +330,23 @@ impl ResponseClass {
         self.is_failure
     }
 
-    pub fn is_match<B>(&self, req: &http::Response<B>) -> bool {
-        self.match_.is_match(req)
+    pub fn response_match(&self) -> &ResponseMatch {
+        &self.match_
+    }
+
+    pub fn is_match<B>(&self, rsp: &http::Response<B>) -> bool {
+        self.match_.is_match(rsp.status(), rsp.headers(), None)
+    }
+
+    /// Matches a response once its stream has completed, so that trailers
+    /// may be considered.
+    pub fn is_match_eos(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        self.match_.is_match(status, headers, trailers)
     }
 }
 
@@ -172,6 +360,14 @@ impl Deref for ResponseClasses {
     }
 }
 
+impl ResponseClasses {
+    /// Indicates whether any response class may depend on trailers, in which
+    /// case responses cannot be classified until their streams complete.
+    pub fn requires_trailers(&self) -> bool {
+        self.0.iter().any(|c| c.match_.requires_trailers())
+    }
+}
+
 impl PartialEq for ResponseClasses {
     fn eq(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.0, &other.0)
@@ -195,29 +391,96 @@ impl fmt::Debug for ResponseClasses {
 // === impl ResponseMatch ===
 
 impl ResponseMatch {
-    fn is_match<B>(&self, req: &http::Response<B>) -> bool {
+    fn is_match(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        match self {
+            ResponseMatch::Status { ref min, ref max } => *min <= status && status <= *max,
+            ResponseMatch::Header {
+                ref name,
+                ref value,
+            } => headers
+                .get_all(name)
+                .iter()
+                .any(|v| value.is_match_header(v)),
+            ResponseMatch::GrpcStatus { min, max } => trailers
+                .and_then(grpc_status)
+                .or_else(|| grpc_status(headers))
+                .map(|s| *min <= s && s <= *max)
+                .unwrap_or(false),
+            ResponseMatch::Not(ref m) => !m.is_match(status, headers, trailers),
+            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(status, headers, trailers)),
+            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(status, headers, trailers)),
+        }
+    }
+
+    fn requires_trailers(&self) -> bool {
         match self {
-            ResponseMatch::Status { ref min, ref max } => {
-                *min <= req.status() && req.status() <= *max
+            ResponseMatch::GrpcStatus { .. } => true,
+            ResponseMatch::Not(ref m) => m.requires_trailers(),
+            ResponseMatch::All(ref ms) | ResponseMatch::Any(ref ms) => {
+                ms.iter().any(|m| m.requires_trailers())
             }
-            ResponseMatch::Not(ref m) => !m.is_match(req),
-            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
-            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
+            ResponseMatch::Status { .. } | ResponseMatch::Header { .. } => false,
         }
     }
 }
 
+fn grpc_status(headers: &http::HeaderMap) -> Option<u32> {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u32>().ok())
+}
+
 // === impl Retries ===
 
 impl Retries {
+    pub fn new(budget: Arc<Budget>) -> Self {
+        Self {
+            budget,
+            backoff: None,
+            retry_connection_errors: false,
+        }
+    }
+
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self {
+            backoff: Some(backoff),
+            ..self
+        }
+    }
+
+    /// Enables retries for requests that fail due to a connection error
+    /// before any part of a response is received.
+    pub fn with_retry_connection_errors(self) -> Self {
+        Self {
+            retry_connection_errors: true,
+            ..self
+        }
+    }
+
     pub fn budget(&self) -> &Arc<Budget> {
         &self.budget
     }
+
+    pub fn backoff(&self) -> Option<&Backoff> {
+        self.backoff.as_ref()
+    }
+
+    pub fn retry_connection_errors(&self) -> bool {
+        self.retry_connection_errors
+    }
 }
 
 impl PartialEq for Retries {
     fn eq(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.budget, &other.budget)
+            && self.backoff == other.backoff
+            && self.retry_connection_errors == other.retry_connection_errors
     }
 }
 
@@ -226,6 +489,8 @@ impl Eq for Retries {}
 impl Hash for Retries {
     fn hash<H: Hasher>(&self, state: &mut H) {
         state.write_usize(Arc::as_ref(&self.budget) as *const _ as usize);
+        self.backoff.hash(state);
+        self.retry_connection_errors.hash(state);
     }
 }
 
@@ -261,11 +526,19 @@ impl fmt::Debug for Labels {
 /// underlying stack is buffered, and so `poll_ready` is NOT called on the routes
 /// before requests are dispatched. If an individual route wishes to apply
 /// backpressure, it must implement its own buffer/limit strategy.
+///
+/// When a profile has `dst_overrides`, the underlying stack is built once per
+/// concrete destination, and each request is dispatched to a concrete
+/// destination chosen by weight after its route policy has been applied.
 pub mod router {
     extern crate linkerd2_router as rt;
 
     use futures::{Async, Poll, Stream};
     use http;
+    use rand::{
+        distributions::{Distribution, WeightedIndex},
+        thread_rng,
+    };
     use std::hash::Hash;
 
     use never::Never;
@@ -276,11 +549,11 @@ pub mod router {
 
     use super::*;
 
-    pub fn layer<G, M, R, B>(
+    pub fn layer<G, M, R, B, C>(
         suffixes: Vec<dns::Suffix>,
         get_routes: G,
         route_layer: svc::Builder<R>,
-    ) -> Layer<G, M, R, B>
+    ) -> Layer<G, M, R, B, C>
     where
         G: GetRoutes + Clone,
         R: Clone,
@@ -295,48 +568,68 @@ pub mod router {
     }
 
     #[derive(Debug)]
-    pub struct Layer<G, M, R, B> {
+    pub struct Layer<G, M, R, B, C> {
         get_routes: G,
         route_layer: svc::Builder<R>,
         suffixes: Vec<dns::Suffix>,
         /// This is saved into a field so that the same `Arc`s are used and
         /// cloned, instead of calling `Route::default()` every time.
         default_route: Route,
-        _p: ::std::marker::PhantomData<fn() -> (M, B)>,
+        _p: ::std::marker::PhantomData<fn() -> (M, B, C)>,
     }
 
     #[derive(Debug)]
-    pub struct MakeSvc<G, M, R, B> {
+    pub struct MakeSvc<G, M, R, B, C> {
         inner: M,
         get_routes: G,
         route_layer: svc::Builder<R>,
         suffixes: Vec<dns::Suffix>,
         default_route: Route,
-        _p: ::std::marker::PhantomData<fn(B)>,
+        _p: ::std::marker::PhantomData<fn(B, C)>,
     }
 
-    pub struct Service<G, T, R, B>
+    pub struct Service<G, T, M, R, B, C>
     where
-        T: WithRoute + Clone,
+        T: WithRoute + WithAddr + WithLoadBalancer + Clone + Eq + Hash,
         T::Output: Eq + Hash,
-        R: rt::Make<T::Output>,
-        R::Value: svc::Service<http::Request<B>> + Clone,
+        M: rt::Make<T>,
+        M::Value: svc::Service<http::Request<C>> + Clone,
+        R: svc::Layer<svc::shared::Shared<Concrete<C, T, M>>>,
+        R::Service: rt::Make<T::Output>,
+        <R::Service as rt::Make<T::Output>>::Value: svc::Service<http::Request<B>> + Clone,
     {
         target: T,
-        stack: R,
+        inner: M,
+        route_layer: svc::Builder<R>,
+        stack: R::Service,
         route_stream: Option<G>,
-        router: Router<B, T, R>,
+        router: Router<B, T, R::Service>,
+        dst_overrides: Vec<WeightedAddr>,
+        load_balancer: Option<balance::Algorithm>,
         default_route: Route,
     }
 
     type Router<B, T, M> = rt::Router<http::Request<B>, Recognize<T>, M>;
 
+    /// Dispatches requests to the concrete destinations of a logical target.
+    ///
+    /// The route layer may wrap request bodies, so requests dispatched to
+    /// concrete destinations have a different body type than those received
+    /// by the profile router.
+    pub type Concrete<B, T, M> = rt::Router<http::Request<B>, RecognizeConcrete<T>, M>;
+
     pub struct Recognize<T> {
         target: T,
-        routes: Routes,
+        routes: Vec<(RequestMatch, Route)>,
         default_route: Route,
     }
 
+    pub struct RecognizeConcrete<T> {
+        target: T,
+        dst_overrides: Vec<WeightedAddr>,
+        distribution: Option<WeightedIndex<u32>>,
+    }
+
     impl<B, T> rt::Recognize<http::Request<B>> for Recognize<T>
     where
         T: WithRoute + Clone,
@@ -357,12 +650,77 @@ pub mod router {
         }
     }
 
-    impl<G, M, R, B> svc::Layer<M> for Layer<G, M, R, B>
+    impl<T> RecognizeConcrete<T> {
+        fn new(target: T, dst_overrides: Vec<WeightedAddr>) -> Self {
+            let distribution = if dst_overrides.is_empty() {
+                None
+            } else {
+                match WeightedIndex::new(dst_overrides.iter().map(|w| w.weight)) {
+                    Ok(distribution) => Some(distribution),
+                    Err(e) => {
+                        warn!("ignoring invalid dst_overrides: {}", e);
+                        None
+                    }
+                }
+            };
+
+            Self {
+                target,
+                dst_overrides,
+                distribution,
+            }
+        }
+    }
+
+    impl<B, T> rt::Recognize<http::Request<B>> for RecognizeConcrete<T>
+    where
+        T: WithAddr + Clone + Eq + Hash,
+    {
+        type Target = T;
+
+        fn recognize(&self, _: &http::Request<B>) -> Option<Self::Target> {
+            let idx = match self.distribution {
+                Some(ref d) => d.sample(&mut thread_rng()),
+                None => return Some(self.target.clone()),
+            };
+
+            let addr = self.dst_overrides[idx].addr.clone();
+            trace!("using concrete dst: {}", addr);
+            Some(self.target.clone().with_addr(addr))
+        }
+    }
+
+    fn concrete<B, T, M>(
+        target: &T,
+        dst_overrides: Vec<WeightedAddr>,
+        load_balancer: Option<balance::Algorithm>,
+        make: M,
+    ) -> Concrete<B, T, M>
+    where
+        T: WithAddr + WithLoadBalancer + Clone + Eq + Hash,
+        M: rt::Make<T>,
+        M::Value: svc::Service<http::Request<B>> + Clone,
+    {
+        // There is never more than one service per concrete destination.
+        let capacity = ::std::cmp::max(dst_overrides.len(), 1);
+        rt::Router::new(
+            RecognizeConcrete::new(
+                target.clone().with_load_balancer(load_balancer),
+                dst_overrides,
+            ),
+            make,
+            capacity,
+            // Doesn't matter, since we are guaranteed to have enough capacity.
+            Duration::from_secs(0),
+        )
+    }
+
+    impl<G, M, R, B, C> svc::Layer<M> for Layer<G, M, R, B, C>
     where
         G: GetRoutes + Clone,
         R: Clone,
     {
-        type Service = MakeSvc<G, M, R, B>;
+        type Service = MakeSvc<G, M, R, B, C>;
 
         fn layer(&self, inner: M) -> Self::Service {
             MakeSvc {
@@ -376,7 +734,7 @@ pub mod router {
         }
     }
 
-    impl<G, M, R, B> Clone for Layer<G, M, R, B>
+    impl<G, M, R, B, C> Clone for Layer<G, M, R, B, C>
     where
         G: Clone,
         R: Clone,
@@ -392,19 +750,20 @@ pub mod router {
         }
     }
 
-    impl<T, G, M, R, B, RMk, RSvc> svc::Service<T> for MakeSvc<G, M, R, B>
+    impl<T, G, M, R, B, C, RMk, RSvc> svc::Service<T> for MakeSvc<G, M, R, B, C>
     where
-        T: CanGetDestination + WithRoute + Clone,
+        T: CanGetDestination + WithRoute + WithAddr + WithLoadBalancer + Clone + Eq + Hash,
         <T as WithRoute>::Output: Eq + Hash + Clone,
-        M: rt::Make<T>,
-        M::Value: Clone,
+        M: rt::Make<T> + Clone,
+        M::Value: svc::Service<http::Request<C>> + Clone,
+        <M::Value as svc::Service<http::Request<C>>>::Error: Into<Error>,
         G: GetRoutes,
-        R: svc::Layer<svc::shared::Shared<M::Value>, Service = RMk> + Clone,
+        R: svc::Layer<svc::shared::Shared<Concrete<C, T, M>>, Service = RMk> + Clone,
         RMk: rt::Make<<T as WithRoute>::Output, Value = RSvc> + Clone,
         RSvc: svc::Service<http::Request<B>> + Clone,
         RSvc::Error: Into<Error>,
     {
-        type Response = Service<G::Stream, T, RMk, B>;
+        type Response = Service<G::Stream, T, M, R, B, C>;
         type Error = never::Never;
         type Future = futures::future::FutureResult<Self::Response, Self::Error>;
 
@@ -413,8 +772,8 @@ pub mod router {
         }
 
         fn call(&mut self, target: T) -> Self::Future {
-            let inner = self.inner.make(&target);
-            let stack = self.route_layer.clone().service(svc::shared(inner));
+            let concrete = concrete(&target, Vec::new(), None, self.inner.clone());
+            let stack = self.route_layer.clone().service(svc::shared(concrete));
 
             let router = Router::new(
                 Recognize {
@@ -447,15 +806,19 @@ pub mod router {
 
             futures::future::ok(Service {
                 target: target.clone(),
+                inner: self.inner.clone(),
+                route_layer: self.route_layer.clone(),
                 stack,
                 route_stream,
                 router,
+                dst_overrides: Vec::new(),
+                load_balancer: None,
                 default_route: self.default_route.clone(),
             })
         }
     }
 
-    impl<G, M, R, B> Clone for MakeSvc<G, M, R, B>
+    impl<G, M, R, B, C> Clone for MakeSvc<G, M, R, B, C>
     where
         G: Clone,
         M: Clone,
@@ -473,15 +836,43 @@ pub mod router {
         }
     }
 
-    impl<G, T, R, B> Service<G, T, R, B>
+    impl<G, T, M, R, B, C> Service<G, T, M, R, B, C>
     where
         G: Stream<Item = Routes, Error = Never>,
-        T: WithRoute + Clone,
+        T: WithRoute + WithAddr + WithLoadBalancer + Clone + Eq + Hash,
         T::Output: Eq + Hash,
-        R: rt::Make<T::Output> + Clone,
-        R::Value: svc::Service<http::Request<B>> + Clone,
+        M: rt::Make<T> + Clone,
+        M::Value: svc::Service<http::Request<C>> + Clone,
+        R: svc::Layer<svc::shared::Shared<Concrete<C, T, M>>> + Clone,
+        R::Service: rt::Make<T::Output> + Clone,
+        <R::Service as rt::Make<T::Output>>::Value: svc::Service<http::Request<B>> + Clone,
     {
         fn update_routes(&mut self, routes: Routes) {
+            let Routes {
+                routes,
+                dst_overrides,
+                load_balancer,
+            } = routes;
+
+            // The concrete stacks are only rebuilt when the split or the
+            // load-balancing algorithm changes, so that route updates do not
+            // discard the existing balancers.
+            if dst_overrides != self.dst_overrides || load_balancer != self.load_balancer {
+                debug!(
+                    "updating dst_overrides: {:?}; load_balancer: {:?}",
+                    dst_overrides, load_balancer
+                );
+                let concrete = concrete(
+                    &self.target,
+                    dst_overrides.clone(),
+                    load_balancer.clone(),
+                    self.inner.clone(),
+                );
+                self.stack = self.route_layer.clone().service(svc::shared(concrete));
+                self.dst_overrides = dst_overrides;
+                self.load_balancer = load_balancer;
+            }
+
             let slots = routes.len() + 1;
             self.router = Router::new(
                 Recognize {
@@ -503,12 +894,15 @@ pub mod router {
         }
     }
 
-    impl<G, T, Stk, B, Svc> svc::Service<http::Request<B>> for Service<G, T, Stk, B>
+    impl<G, T, M, R, B, C, Svc> svc::Service<http::Request<B>> for Service<G, T, M, R, B, C>
     where
         G: Stream<Item = Routes, Error = Never>,
-        T: WithRoute + Clone,
+        T: WithRoute + WithAddr + WithLoadBalancer + Clone + Eq + Hash,
         T::Output: Eq + Hash,
-        Stk: rt::Make<T::Output, Value = Svc> + Clone,
+        M: rt::Make<T> + Clone,
+        M::Value: svc::Service<http::Request<C>> + Clone,
+        R: svc::Layer<svc::shared::Shared<Concrete<C, T, M>>> + Clone,
+        R::Service: rt::Make<T::Output, Value = Svc> + Clone,
         Svc: svc::Service<http::Request<B>> + Clone,
         Svc::Error: Into<Error>,
     {
@@ -529,3 +923,156 @@ pub mod router {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(uri: &str) -> http::request::Builder {
+        let mut b = http::Request::builder();
+        b.uri(uri);
+        b
+    }
+
+    #[test]
+    fn header_exact() {
+        let m = RequestMatch::Header {
+            name: http::header::HeaderName::from_static("x-tenant"),
+            value: ValueMatch::Exact("blue".into()),
+        };
+
+        let r = req("/").header("x-tenant", "blue").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/").header("x-tenant", "green").body(()).unwrap();
+        assert!(!m.is_match(&r));
+
+        let r = req("/").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn header_regex_matches_any_value() {
+        let m = RequestMatch::Header {
+            name: http::header::HeaderName::from_static("x-api-version"),
+            value: ValueMatch::Regex(Regex::new("^v2(\\.[0-9]+)?$").unwrap()),
+        };
+
+        let r = req("/")
+            .header("x-api-version", "v1")
+            .header("x-api-version", "v2.3")
+            .body(())
+            .unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/").header("x-api-version", "v3").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn header_present() {
+        let m = RequestMatch::Header {
+            name: http::header::HeaderName::from_static("x-debug"),
+            value: ValueMatch::Present,
+        };
+
+        let r = req("/").header("x-debug", "").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn authority_from_uri_or_host() {
+        let m = RequestMatch::Authority(ValueMatch::Exact("web.example.com".into()));
+
+        let r = req("http://WEB.example.com/").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/").header("host", "web.example.com").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/").header("host", "api.example.com").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn query_param() {
+        let m = RequestMatch::QueryParam {
+            name: "op".into(),
+            value: ValueMatch::Exact("getUser".into()),
+        };
+
+        let r = req("/graphql?v=1&op=getUser").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/graphql?op=listUsers").body(()).unwrap();
+        assert!(!m.is_match(&r));
+
+        let r = req("/graphql?xop=getUser").body(()).unwrap();
+        assert!(!m.is_match(&r));
+
+        let r = req("/graphql").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn query_param_present_without_value() {
+        let m = RequestMatch::QueryParam {
+            name: "debug".into(),
+            value: ValueMatch::Present,
+        };
+
+        let r = req("/rpc?debug&method=x").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/rpc?method=debug").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn query_param_regex() {
+        let m = RequestMatch::QueryParam {
+            name: "method".into(),
+            value: ValueMatch::Regex(Regex::new("^users\\.").unwrap()),
+        };
+
+        let r = req("/rpc?method=users.get").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/rpc?method=orders.get").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+
+    #[test]
+    fn scheme() {
+        let http = RequestMatch::Scheme(http::uri::Scheme::HTTP);
+        let https = RequestMatch::Scheme(http::uri::Scheme::HTTPS);
+
+        let r = req("https://example.com/").body(()).unwrap();
+        assert!(!http.is_match(&r));
+        assert!(https.is_match(&r));
+
+        let r = req("/").body(()).unwrap();
+        assert!(http.is_match(&r));
+        assert!(!https.is_match(&r));
+    }
+
+    #[test]
+    fn header_combined_with_path() {
+        let m = RequestMatch::All(vec![
+            RequestMatch::Path(Regex::new("^/api$").unwrap()),
+            RequestMatch::Not(Box::new(RequestMatch::Header {
+                name: http::header::HeaderName::from_static("x-tenant"),
+                value: ValueMatch::Exact("blue".into()),
+            })),
+        ]);
+
+        let r = req("/api").header("x-tenant", "green").body(()).unwrap();
+        assert!(m.is_match(&r));
+
+        let r = req("/api").header("x-tenant", "blue").body(()).unwrap();
+        assert!(!m.is_match(&r));
+    }
+}
@@ -0,0 +1,390 @@
+//! A request body that can be replayed so that requests may be retried.
+//!
+//! A `ReplayBody` records the data it reads from its inner body, up to a
+//! maximum number of bytes, so that clones of the body may replay it. If the
+//! limit is exceeded, the recorded data is discarded and the body can no
+//! longer be cloned; but the original body continues to stream normally.
+//!
+//! Only one clone of a body may be read at a time. A clone that is polled
+//! while another clone still holds the body waits until it is dropped.
+
+use bytes::Bytes;
+use futures::{task, Async, Poll};
+use http;
+use hyper::body::Payload;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{error, fmt};
+
+use super::retry::TryClone;
+
+pub struct ReplayBody<B>(Inner<B>);
+
+enum Inner<B> {
+    /// Streams the body without recording it, for requests that are never
+    /// retried.
+    Passthrough(B),
+    Replay(Replay<B>),
+}
+
+struct Replay<B> {
+    /// The state of the body, held while this clone is being read.
+    state: Option<BodyState<B>>,
+    shared: Arc<Shared<B>>,
+
+    /// The number of chunks this clone has returned.
+    position: usize,
+}
+
+/// Indicates that a clone could not be replayed because the original body
+/// exceeded the buffer limit.
+#[derive(Clone, Debug)]
+pub struct Capped(());
+
+struct Shared<B> {
+    max_bytes: usize,
+    is_capped: AtomicBool,
+    idle: Mutex<Idle<B>>,
+}
+
+/// The body state when no clone is reading it.
+struct Idle<B> {
+    state: Option<BodyState<B>>,
+    waiting: Option<task::Task>,
+}
+
+struct BodyState<B> {
+    inner: B,
+    buffered: Vec<Bytes>,
+    buffered_bytes: usize,
+
+    /// The number of chunks read from the inner body.
+    chunks_read: usize,
+    trailers: Option<http::HeaderMap>,
+}
+
+// === impl ReplayBody ===
+
+impl<B: Payload<Data = ::hyper::Chunk>> ReplayBody<B> {
+    /// Wraps a body so that up to `max_bytes` of its data may be replayed.
+    pub fn new(inner: B, max_bytes: usize) -> Self {
+        let state = BodyState {
+            inner,
+            buffered: Vec::new(),
+            buffered_bytes: 0,
+            chunks_read: 0,
+            trailers: None,
+        };
+        ReplayBody(Inner::Replay(Replay {
+            state: Some(state),
+            shared: Arc::new(Shared {
+                max_bytes,
+                is_capped: AtomicBool::new(false),
+                idle: Mutex::new(Idle {
+                    state: None,
+                    waiting: None,
+                }),
+            }),
+            position: 0,
+        }))
+    }
+
+    /// Wraps a body that is never replayed, so that nothing is recorded.
+    pub fn passthrough(inner: B) -> Self {
+        ReplayBody(Inner::Passthrough(inner))
+    }
+
+    /// Returns true if the body has exceeded its buffer limit, so that it
+    /// cannot be replayed.
+    pub fn is_capped(&self) -> bool {
+        match self.0 {
+            Inner::Passthrough(_) => false,
+            Inner::Replay(ref r) => r.is_capped(),
+        }
+    }
+}
+
+impl<B> Payload for ReplayBody<B>
+where
+    B: Payload<Data = ::hyper::Chunk>,
+{
+    type Data = ::hyper::Chunk;
+    type Error = ::proxy::Error;
+
+    fn is_end_stream(&self) -> bool {
+        match self.0 {
+            Inner::Passthrough(ref b) => b.is_end_stream(),
+            Inner::Replay(ref r) => r.is_end_stream(),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        match self.0 {
+            Inner::Passthrough(ref mut b) => b.poll_data().map_err(Into::into),
+            Inner::Replay(ref mut r) => r.poll_data(),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        match self.0 {
+            Inner::Passthrough(ref mut b) => b.poll_trailers().map_err(Into::into),
+            Inner::Replay(ref mut r) => r.poll_trailers(),
+        }
+    }
+}
+
+impl<B> TryClone for ReplayBody<B>
+where
+    B: Payload<Data = ::hyper::Chunk>,
+{
+    fn try_clone(&self) -> Option<Self> {
+        match self.0 {
+            Inner::Passthrough(_) => None,
+            Inner::Replay(ref r) if r.is_capped() => None,
+            Inner::Replay(ref r) => Some(ReplayBody(Inner::Replay(Replay {
+                state: None,
+                shared: r.shared.clone(),
+                position: 0,
+            }))),
+        }
+    }
+}
+
+impl<B> fmt::Debug for ReplayBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Inner::Passthrough(_) => f.debug_struct("ReplayBody").finish(),
+            Inner::Replay(ref r) => f
+                .debug_struct("ReplayBody")
+                .field("position", &r.position)
+                .field("max_bytes", &r.shared.max_bytes)
+                .finish(),
+        }
+    }
+}
+
+// === impl Replay ===
+
+impl<B: Payload<Data = ::hyper::Chunk>> Replay<B> {
+    fn is_capped(&self) -> bool {
+        self.shared.is_capped.load(Ordering::Acquire)
+    }
+
+    /// Takes the body state from the shared idle slot if this clone does
+    /// not already hold it.
+    fn acquire(&mut self) -> Async<()> {
+        if self.state.is_none() {
+            let mut idle = self.shared.idle.lock().expect("replay body lock");
+            match idle.state.take() {
+                Some(state) => self.state = Some(state),
+                None => {
+                    idle.waiting = Some(task::current());
+                    return Async::NotReady;
+                }
+            }
+        }
+
+        Async::Ready(())
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let position = self.position;
+        match self.state {
+            Some(ref state) => state.is_end_stream(position),
+            None => self
+                .shared
+                .idle
+                .lock()
+                .ok()
+                .and_then(|idle| idle.state.as_ref().map(|s| s.is_end_stream(position)))
+                .unwrap_or(false),
+        }
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<::hyper::Chunk>, ::proxy::Error> {
+        if self.acquire().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        let is_capped = self.is_capped();
+        let max_bytes = self.shared.max_bytes;
+        let position = self.position;
+
+        let chunk = {
+            let state = self.state.as_mut().expect("state must be acquired");
+
+            if position < state.chunks_read {
+                // Replay data that was read by a prior clone.
+                if is_capped {
+                    return Err(Capped(()).into());
+                }
+                state.buffered[position].clone()
+            } else {
+                let chunk = match try_ready!(state.inner.poll_data().map_err(Into::into)) {
+                    Some(chunk) => Bytes::from(chunk),
+                    None => return Ok(Async::Ready(None)),
+                };
+                state.chunks_read += 1;
+
+                if !is_capped {
+                    state.buffered_bytes += chunk.len();
+                    if state.buffered_bytes > max_bytes {
+                        trace!("request body exceeds replay limit; max={}B", max_bytes);
+                        self.shared.is_capped.store(true, Ordering::Release);
+                        state.buffered = Vec::new();
+                    } else {
+                        state.buffered.push(chunk.clone());
+                    }
+                }
+
+                chunk
+            }
+        };
+
+        self.position += 1;
+        Ok(Async::Ready(Some(chunk.into())))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, ::proxy::Error> {
+        if self.acquire().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        let state = self.state.as_mut().expect("state must be acquired");
+        if let Some(ref trailers) = state.trailers {
+            return Ok(Async::Ready(Some(trailers.clone())));
+        }
+
+        let trailers = try_ready!(state.inner.poll_trailers().map_err(Into::into));
+        state.trailers = trailers.clone();
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> Drop for Replay<B> {
+    fn drop(&mut self) {
+        // Return the body state so that another clone may read it.
+        if let Some(state) = self.state.take() {
+            if let Ok(mut idle) = self.shared.idle.lock() {
+                idle.state = Some(state);
+                if let Some(task) = idle.waiting.take() {
+                    task.notify();
+                }
+            }
+        }
+    }
+}
+
+// === impl BodyState ===
+
+impl<B: Payload> BodyState<B> {
+    fn is_end_stream(&self, position: usize) -> bool {
+        position == self.chunks_read && self.inner.is_end_stream()
+    }
+}
+
+// === impl Capped ===
+
+impl fmt::Display for Capped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("request body exceeded the replay buffer")
+    }
+}
+
+impl error::Error for Capped {}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Async, Future};
+    use hyper::body::Payload;
+    use hyper::Body;
+    use std::io;
+
+    use super::ReplayBody;
+    use proxy::http::retry::TryClone;
+
+    fn read_all(body: &mut ReplayBody<Body>) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Async::Ready(Some(chunk)) = body.poll_data().expect("poll data") {
+            data.extend_from_slice(&chunk);
+        }
+        data
+    }
+
+    fn chunked(chunks: &'static [&'static str]) -> Body {
+        Body::wrap_stream(stream::iter_ok::<_, io::Error>(chunks.iter().cloned()))
+    }
+
+    #[test]
+    fn replays_buffered_body() {
+        ::futures::future::lazy(|| {
+            let mut body = ReplayBody::new(chunked(&["hello", " ", "world"]), 64);
+            let mut clone = body.try_clone().expect("body must be cloneable");
+
+            assert_eq!(read_all(&mut body), b"hello world".to_vec());
+            drop(body);
+
+            assert_eq!(read_all(&mut clone), b"hello world".to_vec());
+            assert!(clone.try_clone().is_some());
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn clone_waits_for_original() {
+        ::futures::future::lazy(|| {
+            let body = ReplayBody::new(chunked(&["hello"]), 64);
+            let mut clone = body.try_clone().expect("body must be cloneable");
+
+            assert!(clone.poll_data().expect("poll data").is_not_ready());
+            drop(body);
+            assert_eq!(read_all(&mut clone), b"hello".to_vec());
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn exceeding_limit_disables_replay() {
+        ::futures::future::lazy(|| {
+            let mut body = ReplayBody::new(chunked(&["hello", " ", "world"]), 8);
+            let mut clone = body.try_clone().expect("body must be cloneable");
+
+            // The original body still streams in its entirety.
+            assert_eq!(read_all(&mut body), b"hello world".to_vec());
+            assert!(body.is_capped());
+            assert!(body.try_clone().is_none());
+            drop(body);
+
+            assert!(clone.poll_data().is_err());
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn empty_body_is_end_stream() {
+        let body = ReplayBody::new(Body::empty(), 64);
+        assert!(body.is_end_stream());
+        let clone = body.try_clone().expect("body must be cloneable");
+        drop(body);
+        assert!(clone.is_end_stream());
+    }
+
+    #[test]
+    fn passthrough_is_not_replayed() {
+        ::futures::future::lazy(|| {
+            let mut body = ReplayBody::passthrough(chunked(&["hello", " ", "world"]));
+            assert!(body.try_clone().is_none());
+            assert_eq!(read_all(&mut body), b"hello world".to_vec());
+            assert!(!body.is_capped());
+            Ok::<_, ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
@@ -1,11 +1,18 @@
+use rand::{self, Rng};
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::time::Duration;
 
-use futures::{future, Future, Poll};
+use futures::{Async, Future, Poll};
 use http::{Request, Response};
+use hyper::body::Payload;
+use tokio_timer::{clock, Delay};
 use tower::retry as tower_retry;
 pub use tower::retry::budget::Budget;
 
 use proxy::http::metrics::{Scoped, Stats};
+pub use proxy::http::replay::ReplayBody;
+use proxy::Error;
 use svc;
 
 pub trait CanRetry {
@@ -14,13 +21,48 @@ pub trait CanRetry {
 }
 
 pub trait Retry: Sized {
+    /// Determines whether a response should be retried.
+    ///
+    /// This does not withdraw from the retry budget; see `withdraw`.
     fn retry<B1, B2>(&self, req: &Request<B1>, res: &Response<B2>) -> Result<(), NoRetry>;
     fn clone_request<B: TryClone>(&self, req: &Request<B>) -> Option<Request<B>>;
+
+    /// Determines whether a request that failed before a response was
+    /// received should be retried.
+    fn retry_error(&self, _err: &Error) -> Result<(), NoRetry> {
+        Err(NoRetry::Error)
+    }
+
+    /// Withdraws a retry from the budget, once a request is known to be
+    /// retryable.
+    fn withdraw(&self) -> Result<(), NoRetry> {
+        Ok(())
+    }
+
+    /// Returns the backoff to wait for between attempts, if any.
+    fn backoff(&self) -> Option<&Backoff> {
+        None
+    }
 }
 
+/// An exponential backoff between retry attempts.
+///
+/// The backoff doubles with each attempt, from `min` up to `max`. A random
+/// jitter of up to `jitter` times the backoff is added to each delay.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    jitter: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct InvalidBackoff(&'static str);
+
 pub enum NoRetry {
     Success,
     Budget,
+    Error,
 }
 
 pub trait TryClone: Sized {
@@ -29,30 +71,60 @@ pub trait TryClone: Sized {
 
 pub struct Layer<S, K, A, B> {
     registry: S,
+    max_buffer_bytes: usize,
     _p: PhantomData<(K, fn(A) -> B)>,
 }
 
 pub struct Stack<M, S, K, A, B> {
     inner: M,
     registry: S,
+    max_buffer_bytes: usize,
     _p: PhantomData<(K, fn(A) -> B)>,
 }
 
 pub struct MakeFuture<F, R, S> {
     inner: F,
     policy: Option<Policy<R, S>>,
+    max_buffer_bytes: usize,
+}
+
+/// Wraps request bodies in a `ReplayBody` so that they may be retried.
+///
+/// Bodies are only recorded for retryable services, i.e. when
+/// `max_buffer_bytes` is set; otherwise, the `ReplayBody` simply streams the
+/// inner body.
+#[derive(Clone, Debug)]
+pub struct Replay<S> {
+    inner: S,
+    max_buffer_bytes: Option<usize>,
 }
 
-pub type Service<R, Svc, St> = tower_retry::Retry<Policy<R, St>, Svc>;
+pub type Service<R, Svc, St> = Replay<tower_retry::Retry<Policy<R, St>, Svc>>;
 
 #[derive(Clone)]
-pub struct Policy<R, S>(R, S);
+pub struct Policy<R, S> {
+    retry: R,
+    stats: S,
+    /// The number of retries that have been attempted.
+    attempts: u32,
+}
+
+/// Waits for the policy's backoff, if any, before a request is retried.
+pub struct BackoffFuture<R, S> {
+    policy: Option<Policy<R, S>>,
+    delay: Option<Delay>,
+}
 
 // === impl Layer ===
 
-pub fn layer<S, K, A, B>(registry: S) -> Layer<S, K, A, B> {
+/// Retries requests on retryable routes.
+///
+/// Request bodies of up to `max_buffer_bytes` are buffered so that they may
+/// be replayed; requests with larger bodies are not retried.
+pub fn layer<S, K, A, B>(registry: S, max_buffer_bytes: usize) -> Layer<S, K, A, B> {
     Layer {
         registry,
+        max_buffer_bytes,
         _p: PhantomData,
     }
 }
@@ -61,6 +133,7 @@ impl<S: Clone, K, A, B> Clone for Layer<S, K, A, B> {
     fn clone(&self) -> Self {
         Layer {
             registry: self.registry.clone(),
+            max_buffer_bytes: self.max_buffer_bytes,
             _p: PhantomData,
         }
     }
@@ -70,7 +143,7 @@ impl<M, S, K, A, B> svc::Layer<M> for Layer<S, K, A, B>
 where
     S: Scoped<K> + Clone,
     S::Scope: Clone,
-    A: TryClone,
+    A: Payload<Data = ::hyper::Chunk>,
 {
     type Service = Stack<M, S, K, A, B>;
 
@@ -78,6 +151,7 @@ where
         Stack {
             inner,
             registry: self.registry.clone(),
+            max_buffer_bytes: self.max_buffer_bytes,
             _p: PhantomData,
         }
     }
@@ -90,6 +164,7 @@ impl<M: Clone, S: Clone, K, A, B> Clone for Stack<M, S, K, A, B> {
         Stack {
             inner: self.inner.clone(),
             registry: self.registry.clone(),
+            max_buffer_bytes: self.max_buffer_bytes,
             _p: PhantomData,
         }
     }
@@ -99,14 +174,14 @@ impl<M: Clone, S: Clone, K, A, B> Clone for Stack<M, S, K, A, B> {
 impl<T, M, S, K, A, B> svc::Service<T> for Stack<M, S, K, A, B>
 where
     T: CanRetry + Clone,
-    M: svc::MakeService<T, Request<A>, Response = Response<B>>,
+    M: svc::MakeService<T, Request<ReplayBody<A>>, Response = Response<B>>,
     M::Service: Clone,
     S: Scoped<K>,
     S::Scope: Clone,
     K: From<T>,
-    A: TryClone,
+    A: Payload<Data = ::hyper::Chunk>,
 {
-    type Response = svc::Either<Service<T::Retry, M::Service, S::Scope>, M::Service>;
+    type Response = svc::Either<Service<T::Retry, M::Service, S::Scope>, Replay<M::Service>>;
     type Error = M::MakeError;
     type Future = MakeFuture<M::Future, T::Retry, S::Scope>;
 
@@ -118,13 +193,21 @@ where
         let policy = if let Some(retries) = target.can_retry() {
             trace!("stack is retryable");
             let stats = self.registry.scoped(target.clone().into());
-            Some(Policy(retries, stats))
+            Some(Policy {
+                retry: retries,
+                stats,
+                attempts: 0,
+            })
         } else {
             None
         };
 
         let inner = self.inner.make_service(target);
-        MakeFuture { inner, policy }
+        MakeFuture {
+            inner,
+            policy,
+            max_buffer_bytes: self.max_buffer_bytes,
+        }
     }
 }
 
@@ -134,51 +217,114 @@ impl<F, R, S> Future for MakeFuture<F, R, S>
 where
     F: Future,
 {
-    type Item = svc::Either<Service<R, F::Item, S>, F::Item>;
+    type Item = svc::Either<Service<R, F::Item, S>, Replay<F::Item>>;
     type Error = F::Error;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let inner = try_ready!(self.inner.poll());
         if let Some(policy) = self.policy.take() {
-            Ok(svc::Either::A(tower_retry::Retry::new(policy, inner)).into())
+            let inner = Replay {
+                inner: tower_retry::Retry::new(policy, inner),
+                max_buffer_bytes: Some(self.max_buffer_bytes),
+            };
+            Ok(svc::Either::A(inner).into())
         } else {
+            // Nothing is recorded for requests that are never retried.
+            let inner = Replay {
+                inner,
+                max_buffer_bytes: None,
+            };
             Ok(svc::Either::B(inner).into())
         }
     }
 }
 
+// === impl Replay ===
+
+impl<S, A> svc::Service<Request<A>> for Replay<S>
+where
+    S: svc::Service<Request<ReplayBody<A>>>,
+    A: Payload<Data = ::hyper::Chunk>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Request<A>) -> Self::Future {
+        let req = match self.max_buffer_bytes {
+            Some(max) => req.map(|body| ReplayBody::new(body, max)),
+            None => req.map(ReplayBody::passthrough),
+        };
+        self.inner.call(req)
+    }
+}
+
 // === impl Policy ===
 
-impl<R, S, A, B, E> tower_retry::Policy<Request<A>, Response<B>, E> for Policy<R, S>
+impl<R, S, A, B> tower_retry::Policy<Request<ReplayBody<A>>, Response<B>, Error> for Policy<R, S>
 where
     R: Retry + Clone,
     S: Stats + Clone,
-    A: TryClone,
+    A: Payload<Data = ::hyper::Chunk>,
 {
-    type Future = future::FutureResult<Self, ()>;
+    type Future = BackoffFuture<R, S>;
+
+    fn retry(
+        &self,
+        req: &Request<ReplayBody<A>>,
+        result: Result<&Response<B>, &Error>,
+    ) -> Option<Self::Future> {
+        let retry = match result {
+            Ok(res) => self.retry.retry(req, res),
+            Err(err) => self.retry.retry_error(err),
+        };
+
+        // Requests that cannot be replayed must not spend the budget.
+        if retry.is_ok() && req.body().is_capped() {
+            trace!("request body too large to retry");
+            self.stats.incr_retry_skipped_body_too_large();
+            return None;
+        }
 
-    fn retry(&self, req: &Request<A>, result: Result<&Response<B>, &E>) -> Option<Self::Future> {
-        match result {
-            Ok(res) => match self.0.retry(req, res) {
-                Ok(()) => {
+        match retry.and_then(|()| self.retry.withdraw()) {
+            Ok(()) => {
+                let delay = self.retry.backoff().map(|backoff| {
+                    let delay = backoff.for_attempt(self.attempts, rand::thread_rng());
+                    trace!("retrying request after {:?}", delay);
+                    self.stats.record_retry_backoff(delay);
+                    Delay::new(clock::now() + delay)
+                });
+                if delay.is_none() {
                     trace!("retrying request");
-                    Some(future::ok(self.clone()))
-                }
-                Err(NoRetry::Budget) => {
-                    self.1.incr_retry_skipped_budget();
-                    None
                 }
-                Err(NoRetry::Success) => None,
-            },
-            Err(_err) => {
-                trace!("cannot retry transport error");
+
+                let policy = Policy {
+                    attempts: self.attempts + 1,
+                    ..self.clone()
+                };
+                Some(BackoffFuture {
+                    policy: Some(policy),
+                    delay,
+                })
+            }
+            Err(NoRetry::Budget) => {
+                self.stats.incr_retry_skipped_budget();
+                None
+            }
+            Err(NoRetry::Success) => None,
+            Err(NoRetry::Error) => {
+                trace!("cannot retry error");
                 None
             }
         }
     }
 
-    fn clone_request(&self, req: &Request<A>) -> Option<Request<A>> {
-        if let Some(clone) = self.0.clone_request(req) {
+    fn clone_request(&self, req: &Request<ReplayBody<A>>) -> Option<Request<ReplayBody<A>>> {
+        if let Some(clone) = self.retry.clone_request(req) {
             trace!("cloning request");
             Some(clone)
         } else {
@@ -188,6 +334,126 @@ where
     }
 }
 
+/// Indicates whether an error was caused by the connection to an endpoint
+/// failing before a response was received.
+///
+/// The failed endpoint is not ready while it reconnects, so a retried
+/// request is dispatched to another endpoint.
+pub fn is_connection_error(err: &Error) -> bool {
+    let mut cause: Option<&(::std::error::Error + 'static)> = Some(&**err);
+    while let Some(err) = cause {
+        if let Some(e) = err.downcast_ref::<::hyper::Error>() {
+            // The request was never written to the connection.
+            if e.is_canceled() || e.is_connect() {
+                return true;
+            }
+        }
+
+        if let Some(e) = err.downcast_ref::<::h2::Error>() {
+            // The server did not process the stream.
+            if e.reason() == Some(::h2::Reason::REFUSED_STREAM) {
+                return true;
+            }
+        }
+
+        if let Some(e) = err.downcast_ref::<::std::io::Error>() {
+            match e.kind() {
+                ::std::io::ErrorKind::ConnectionRefused | ::std::io::ErrorKind::ConnectionReset => {
+                    return true
+                }
+                _ => {}
+            }
+        }
+
+        cause = err.source();
+    }
+
+    false
+}
+
+// === impl BackoffFuture ===
+
+impl<R, S> Future for BackoffFuture<R, S> {
+    type Item = Policy<R, S>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut delay) = self.delay {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                Err(e) => {
+                    // These are unexpected, and mean the runtime is in a bad
+                    // place; don't hold up the request.
+                    error!("unexpected runtime timer error: {}", e);
+                }
+            }
+        }
+
+        let policy = self.policy.take().expect("polled after ready");
+        Ok(Async::Ready(policy))
+    }
+}
+
+// === impl Backoff ===
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration, jitter: f64) -> Result<Self, InvalidBackoff> {
+        if min > max {
+            return Err(InvalidBackoff("min must not exceed max"));
+        }
+        if !(jitter >= 0.0 && jitter <= 1.0) {
+            return Err(InvalidBackoff("jitter must be between 0 and 1"));
+        }
+
+        Ok(Self { min, max, jitter })
+    }
+
+    /// Returns the delay before the retry following `attempts` prior
+    /// retries.
+    fn for_attempt<G: Rng>(&self, attempts: u32, mut rng: G) -> Duration {
+        let backoff = self
+            .min
+            .checked_mul(2_u32.saturating_pow(attempts))
+            .map(|b| Duration::min(b, self.max))
+            .unwrap_or(self.max);
+
+        if self.jitter == 0.0 {
+            return backoff;
+        }
+
+        let nanos = backoff.as_secs() as f64 * 1e9 + f64::from(backoff.subsec_nanos());
+        let jitter = nanos * self.jitter * rng.gen::<f64>();
+        backoff + Duration::from_nanos(jitter as u64)
+    }
+}
+
+impl PartialEq for Backoff {
+    fn eq(&self, other: &Self) -> bool {
+        self.min == other.min
+            && self.max == other.max
+            && self.jitter.to_bits() == other.jitter.to_bits()
+    }
+}
+
+impl Eq for Backoff {}
+
+impl Hash for Backoff {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.min.hash(state);
+        self.max.hash(state);
+        self.jitter.to_bits().hash(state);
+    }
+}
+
+impl ::std::fmt::Display for InvalidBackoff {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "invalid backoff: {}", self.0)
+    }
+}
+
+impl ::std::error::Error for InvalidBackoff {}
+
 impl<B: TryClone> TryClone for Request<B> {
     fn try_clone(&self) -> Option<Self> {
         if let Some(body) = self.body().try_clone() {
@@ -207,3 +473,58 @@ impl<B: TryClone> TryClone for Request<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Backoff;
+    use rand;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let backoff =
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(50), 0.0).unwrap();
+        let rng = rand::thread_rng;
+        assert_eq!(backoff.for_attempt(0, rng()), Duration::from_millis(10));
+        assert_eq!(backoff.for_attempt(1, rng()), Duration::from_millis(20));
+        assert_eq!(backoff.for_attempt(2, rng()), Duration::from_millis(40));
+        assert_eq!(backoff.for_attempt(3, rng()), Duration::from_millis(50));
+        assert_eq!(backoff.for_attempt(64, rng()), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn backoff_jitter_is_bounded() {
+        let backoff =
+            Backoff::new(Duration::from_millis(10), Duration::from_millis(10), 0.5).unwrap();
+        for _ in 0..100 {
+            let d = backoff.for_attempt(0, rand::thread_rng());
+            assert!(d >= Duration::from_millis(10), "{:?}", d);
+            assert!(d < Duration::from_millis(15), "{:?}", d);
+        }
+    }
+
+    #[test]
+    fn connection_errors() {
+        use std::io;
+
+        let refused: super::Error = io::Error::from(io::ErrorKind::ConnectionRefused).into();
+        assert!(super::is_connection_error(&refused));
+
+        let reset: super::Error = io::Error::from(io::ErrorKind::ConnectionReset).into();
+        assert!(super::is_connection_error(&reset));
+
+        let refused_stream: super::Error = ::h2::Error::from(::h2::Reason::REFUSED_STREAM).into();
+        assert!(super::is_connection_error(&refused_stream));
+
+        let other: super::Error = io::Error::from(io::ErrorKind::TimedOut).into();
+        assert!(!super::is_connection_error(&other));
+    }
+
+    #[test]
+    fn invalid_backoff() {
+        let ms = Duration::from_millis;
+        assert!(Backoff::new(ms(20), ms(10), 0.0).is_err());
+        assert!(Backoff::new(ms(10), ms(20), 1.5).is_err());
+        assert!(Backoff::new(ms(10), ms(20), -0.1).is_err());
+    }
+}
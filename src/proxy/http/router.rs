@@ -1,11 +1,16 @@
 use futures::Poll;
 use http;
+use indexmap::IndexMap;
 use std::fmt;
 use std::marker::PhantomData;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
 
 use never::Never;
 
+use json;
 use proxy::Error;
 use svc;
 
@@ -18,6 +23,52 @@ pub struct Config {
     capacity: usize,
     max_idle_age: Duration,
     proxy_name: &'static str,
+    report: Option<Report>,
+}
+
+/// Lists the targets in each router's cache, for the admin server.
+#[derive(Clone, Debug, Default)]
+pub struct Report(Arc<Mutex<Routes>>);
+
+#[derive(Debug, Default)]
+struct Routes {
+    next_id: u64,
+    by_id: IndexMap<u64, Arc<Route>>,
+}
+
+#[derive(Debug)]
+struct Route {
+    router: &'static str,
+    target: String,
+    registered: Instant,
+    /// Milliseconds from `registered` until the route was last accessed,
+    /// updated without a lock as requests are routed.
+    last_access_ms: AtomicU64,
+}
+
+/// Removes a route from the report once all of its services are dropped,
+/// i.e. once it has been evicted from its router's cache and has no requests
+/// in flight.
+#[derive(Debug)]
+struct Registration {
+    id: u64,
+    route: Arc<Route>,
+    routes: Weak<Mutex<Routes>>,
+}
+
+/// Registers each service built by an `Mk`-typed router stack in a `Report`.
+#[derive(Clone, Debug)]
+pub struct MakeRouted<Mk> {
+    inner: Mk,
+    router: &'static str,
+    report: Option<Report>,
+}
+
+/// A routed service, which records when it was last accessed.
+#[derive(Clone, Debug)]
+pub struct Routed<S> {
+    inner: S,
+    registration: Option<Arc<Registration>>,
 }
 
 /// A layer that that builds a routing service.
@@ -43,10 +94,11 @@ pub struct Stack<Req, Rec: Recognize<Req>, Mk> {
 pub struct Service<Req, Rec, Mk>
 where
     Rec: Recognize<Req>,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target>,
     Mk::Value: svc::Service<Req>,
 {
-    inner: Router<Req, Rec, Mk>,
+    inner: Router<Req, Rec, MakeRouted<Mk>>,
 }
 
 // === impl Config ===
@@ -57,6 +109,15 @@ impl Config {
             proxy_name,
             capacity,
             max_idle_age,
+            report: None,
+        }
+    }
+
+    /// Lists the targets in the router's cache in `report`.
+    pub fn with_report(self, report: Report) -> Self {
+        Self {
+            report: Some(report),
+            ..self
         }
     }
 }
@@ -84,6 +145,7 @@ where
 impl<Req, Rec, Mk, B> svc::Layer<Mk> for Layer<Req, Rec>
 where
     Rec: Recognize<Req> + Clone + Send + Sync + 'static,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target> + Clone + Send + Sync + 'static,
     Mk::Value: svc::Service<Req, Response = http::Response<B>> + Clone,
     <Mk::Value as svc::Service<Req>>::Error: Into<Error>,
@@ -114,15 +176,21 @@ where
 impl<Req, Rec, Mk, B> Stack<Req, Rec, Mk>
 where
     Rec: Recognize<Req> + Clone + Send + Sync + 'static,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target> + Clone + Send + Sync + 'static,
     Mk::Value: svc::Service<Req, Response = http::Response<B>> + Clone,
     <Mk::Value as svc::Service<Req>>::Error: Into<Error>,
     B: Default + Send + 'static,
 {
     pub fn make(&self) -> Service<Req, Rec, Mk> {
+        let make = MakeRouted {
+            inner: self.inner.clone(),
+            router: self.config.proxy_name,
+            report: self.config.report.clone(),
+        };
         let inner = Router::new(
             self.recognize.clone(),
-            self.inner.clone(),
+            make,
             self.config.capacity,
             self.config.max_idle_age,
         );
@@ -133,6 +201,7 @@ where
 impl<Req, Rec, Mk, B, T> svc::Service<T> for Stack<Req, Rec, Mk>
 where
     Rec: Recognize<Req> + Clone + Send + Sync + 'static,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target> + Clone + Send + Sync + 'static,
     Mk::Value: svc::Service<Req, Response = http::Response<B>> + Clone,
     <Mk::Value as svc::Service<Req>>::Error: Into<Error>,
@@ -170,14 +239,15 @@ where
 impl<Req, Rec, Mk, B> svc::Service<Req> for Service<Req, Rec, Mk>
 where
     Rec: Recognize<Req> + Send + Sync + 'static,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target> + Send + Sync + 'static,
     Mk::Value: svc::Service<Req, Response = http::Response<B>> + Clone,
     <Mk::Value as svc::Service<Req>>::Error: Into<Error>,
     B: Default + Send + 'static,
 {
-    type Response = <Router<Req, Rec, Mk> as svc::Service<Req>>::Response;
-    type Error = <Router<Req, Rec, Mk> as svc::Service<Req>>::Error;
-    type Future = <Router<Req, Rec, Mk> as svc::Service<Req>>::Future;
+    type Response = <Router<Req, Rec, MakeRouted<Mk>> as svc::Service<Req>>::Response;
+    type Error = <Router<Req, Rec, MakeRouted<Mk>> as svc::Service<Req>>::Error;
+    type Future = <Router<Req, Rec, MakeRouted<Mk>> as svc::Service<Req>>::Future;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         self.inner.poll_ready()
@@ -192,9 +262,10 @@ where
 impl<Req, Rec, Mk> Clone for Service<Req, Rec, Mk>
 where
     Rec: Recognize<Req>,
+    Rec::Target: fmt::Display,
     Mk: rt::Make<Rec::Target>,
     Mk::Value: svc::Service<Req>,
-    Router<Req, Rec, Mk>: Clone,
+    Router<Req, Rec, MakeRouted<Mk>>: Clone,
 {
     fn clone(&self) -> Self {
         Self {
@@ -202,3 +273,175 @@ where
         }
     }
 }
+
+// === impl MakeRouted ===
+
+impl<T, Mk> rt::Make<T> for MakeRouted<Mk>
+where
+    T: fmt::Display,
+    Mk: rt::Make<T>,
+{
+    type Value = Routed<Mk::Value>;
+
+    fn make(&self, target: &T) -> Self::Value {
+        let registration = self
+            .report
+            .as_ref()
+            .map(|r| r.register(self.router, target.to_string()));
+        Routed {
+            inner: self.inner.make(target),
+            registration,
+        }
+    }
+}
+
+// === impl Routed ===
+
+impl<S, Req> svc::Service<Req> for Routed<S>
+where
+    S: svc::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        if let Some(ref r) = self.registration {
+            r.route.touch();
+        }
+        self.inner.call(req)
+    }
+}
+
+// === impl Report ===
+
+impl Report {
+    fn register(&self, router: &'static str, target: String) -> Arc<Registration> {
+        let route = Arc::new(Route {
+            router,
+            target,
+            registered: clock::now(),
+            last_access_ms: AtomicU64::new(0),
+        });
+        let id = match self.0.lock() {
+            Ok(mut routes) => {
+                let id = routes.next_id;
+                routes.next_id += 1;
+                routes.by_id.insert(id, route.clone());
+                id
+            }
+            Err(_) => 0,
+        };
+        Arc::new(Registration {
+            id,
+            route,
+            routes: Arc::downgrade(&self.0),
+        })
+    }
+
+    /// Describes the targets in each router's cache, with the number of
+    /// milliseconds since each was last used.
+    pub fn to_json(&self) -> json::Value {
+        let routes = match self.0.lock() {
+            Ok(routes) => routes,
+            Err(_) => return json::Value::Null,
+        };
+
+        let now = clock::now();
+        let mut by_router = IndexMap::<&'static str, Vec<json::Value>>::new();
+        for route in routes.by_id.values() {
+            // The route may have been accessed since `now`.
+            let last_access_ms = route.last_access_ms.load(Ordering::Relaxed);
+            let idle_ms = millis(now, route.registered).saturating_sub(last_access_ms);
+            by_router
+                .entry(route.router)
+                .or_insert_with(Vec::new)
+                .push(json::object(vec![
+                    ("target", json::Value::String(route.target.clone())),
+                    ("idle_ms", json::Value::Number(idle_ms as f64)),
+                ]));
+        }
+
+        json::object(
+            by_router
+                .into_iter()
+                .map(|(router, targets)| (router, json::Value::Array(targets))),
+        )
+    }
+}
+
+// === impl Route ===
+
+impl Route {
+    fn touch(&self) {
+        let ms = millis(clock::now(), self.registered);
+        self.last_access_ms.store(ms, Ordering::Relaxed);
+    }
+}
+
+/// Returns the number of milliseconds from `earlier` until `now`.
+fn millis(now: Instant, earlier: Instant) -> u64 {
+    if now <= earlier {
+        return 0;
+    }
+    let d = now - earlier;
+    d.as_secs() * 1_000 + u64::from(d.subsec_millis())
+}
+
+// === impl Registration ===
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(routes) = self.routes.upgrade() {
+            if let Ok(mut routes) = routes.lock() {
+                routes.by_id.swap_remove(&self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rt::Make;
+    use super::*;
+
+    #[test]
+    fn reports_cached_targets_until_dropped() {
+        let report = Report::default();
+        let make = MakeRouted {
+            inner: |t: &usize| *t,
+            router: "out addr",
+            report: Some(report.clone()),
+        };
+
+        let a = make.make(&1);
+        let b = make.make(&2);
+        let a2 = a.clone();
+        let targets = |report: &Report| -> Vec<String> {
+            report
+                .to_json()
+                .get("out addr")
+                .and_then(json::Value::as_array)
+                .map(|ts| {
+                    ts.iter()
+                        .filter_map(|t| t.get("target").and_then(json::Value::as_str))
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        assert_eq!(targets(&report), vec!["1", "2"]);
+
+        // A route remains until all of its clones are dropped.
+        drop(a);
+        assert_eq!(targets(&report), vec!["1", "2"]);
+        drop(a2);
+        assert_eq!(targets(&report), vec!["2"]);
+        drop(b);
+        assert_eq!(targets(&report), Vec::<String>::new());
+    }
+}
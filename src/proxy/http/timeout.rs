@@ -10,6 +10,11 @@ use svc::linkerd2_timeout::{error, Timeout};
 /// Implement on targets to determine if a service has a timeout.
 pub trait HasTimeout {
     fn timeout(&self) -> Option<Duration>;
+
+    /// The timeout for each attempt of a retried request.
+    fn per_try_timeout(&self) -> Option<Duration> {
+        None
+    }
 }
 
 /// An HTTP-specific optional timeout layer.
@@ -20,15 +25,26 @@ pub trait HasTimeout {
 /// Timeout errors are translated into `http::Response`s with appropiate
 /// status codes.
 pub fn layer() -> Layer {
-    Layer
+    Layer { per_try: false }
+}
+
+/// Like `layer`, but applies the target's `HasTimeout::per_try_timeout`.
+///
+/// This is intended to be used below a retry layer, so that each attempt
+/// is timed out individually.
+pub fn per_try_layer() -> Layer {
+    Layer { per_try: true }
 }
 
 #[derive(Clone, Debug)]
-pub struct Layer;
+pub struct Layer {
+    per_try: bool,
+}
 
 #[derive(Clone, Debug)]
 pub struct Stack<M> {
     inner: M,
+    per_try: bool,
 }
 
 pub struct MakeFuture<F> {
@@ -48,7 +64,10 @@ impl<M> svc::Layer<M> for Layer {
     type Service = Stack<M>;
 
     fn layer(&self, inner: M) -> Self::Service {
-        Stack { inner }
+        Stack {
+            inner,
+            per_try: self.per_try,
+        }
     }
 }
 
@@ -66,7 +85,11 @@ where
     }
 
     fn call(&mut self, target: T) -> Self::Future {
-        let timeout = target.timeout();
+        let timeout = if self.per_try {
+            target.per_try_timeout()
+        } else {
+            target.timeout()
+        };
         let inner = self.inner.call(target);
 
         MakeFuture { inner, timeout }
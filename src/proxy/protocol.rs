@@ -1,7 +1,7 @@
 use httparse;
 
 /// Transport protocols that can be transparently detected by `Server`.
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Protocol {
     Http1,
     Http2,
@@ -246,6 +246,7 @@ where
     ) -> impl Future<Item = (), Error = ()> {
         let orig_dst = connection.original_dst_addr();
         let disable_protocol_detection = !connection.should_detect_protocol();
+        let negotiated_h2 = connection.negotiated_protocol() == Some(tls::L5D_H2);
 
         let log = self.log.clone().with_remote(remote_addr);
 
@@ -268,13 +269,7 @@ where
             return log.future(Either::B(fut));
         }
 
-        let detect_protocol = io
-            .peek()
-            .map_err(|e| debug!("peek error: {}", e))
-            .map(|io| {
-                let p = Protocol::detect(io.peeked());
-                (p, io)
-            });
+        let detect_protocol = detect_protocol(io, negotiated_h2);
 
         let mut http = self.http.clone();
         let mut route = self.route.clone();
@@ -344,3 +339,73 @@ where
         log.future(Either::A(serve))
     }
 }
+
+/// Determines the protocol spoken on a connection.
+///
+/// A meshed client that negotiated HTTP/2 via ALPN will send an HTTP/2
+/// preface, so there's no need to wait for it.
+fn detect_protocol<I: Peek>(
+    io: I,
+    negotiated_h2: bool,
+) -> impl Future<Item = (Option<Protocol>, I), Error = ()> {
+    if negotiated_h2 {
+        trace!("negotiated HTTP/2 via ALPN");
+        return Either::A(future::ok((Some(Protocol::Http2), io)));
+    }
+
+    Either::B(
+        io.peek()
+            .map_err(|e| debug!("peek error: {}", e))
+            .map(|io| {
+                let p = Protocol::detect(io.peeked());
+                (p, io)
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_protocol;
+    use futures::{Async, Future, Poll};
+    use proxy::protocol::Protocol;
+    use std::io;
+    use transport::Peek;
+
+    /// Fails the test if the connection is peeked.
+    struct NoPeek;
+
+    /// Has already peeked an HTTP/2 preface.
+    struct Preface;
+
+    impl Peek for NoPeek {
+        fn poll_peek(&mut self) -> Poll<usize, io::Error> {
+            panic!("connection must not be peeked");
+        }
+
+        fn peeked(&self) -> &[u8] {
+            panic!("connection must not be peeked");
+        }
+    }
+
+    impl Peek for Preface {
+        fn poll_peek(&mut self) -> Poll<usize, io::Error> {
+            Ok(Async::Ready(self.peeked().len()))
+        }
+
+        fn peeked(&self) -> &[u8] {
+            b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n"
+        }
+    }
+
+    #[test]
+    fn negotiated_h2_is_not_peeked() {
+        let (proto, _) = detect_protocol(NoPeek, true).wait().expect("must detect");
+        assert_eq!(proto, Some(Protocol::Http2));
+    }
+
+    #[test]
+    fn unnegotiated_h2_is_peeked() {
+        let (proto, _) = detect_protocol(Preface, false).wait().expect("must detect");
+        assert_eq!(proto, Some(Protocol::Http2));
+    }
+}
@@ -102,3 +102,9 @@ impl<T: tls::HasStatus> tls::HasStatus for Io<T> {
         self.io.tls_status()
     }
 }
+
+impl<T: tls::HasNegotiatedProtocol> tls::HasNegotiatedProtocol for Io<T> {
+    fn negotiated_protocol(&self) -> Option<&[u8]> {
+        self.io.negotiated_protocol()
+    }
+}
@@ -11,6 +11,18 @@ pub use super::rustls::ClientConfig as Config;
 
 pub trait HasConfig {
     fn tls_client_config(&self) -> Arc<Config>;
+
+    /// Returns a config that offers `protocol` via ALPN, if one was built.
+    ///
+    /// Configs are shared by all connections, so they are built ahead of time
+    /// rather than copied as connections are established.
+    fn tls_client_config_offering(&self, protocol: &'static [u8]) -> Option<Arc<Config>>;
+}
+
+/// Determines the ALPN protocol, if any, that is offered to a target when a
+/// TLS connection is established.
+pub trait HasAlpnProtocol {
+    fn alpn_protocol(&self) -> Option<&'static [u8]>;
 }
 
 #[derive(Clone, Debug)]
@@ -27,6 +39,7 @@ pub enum ConnectFuture<L, F: Future> {
     Init {
         future: F,
         tls: tls::Conditional<(identity::Name, L)>,
+        alpn_protocol: Option<&'static [u8]>,
     },
     Handshake {
         future: tls::tokio_rustls::Connect<F::Item>,
@@ -59,7 +72,7 @@ where
 /// impl MakeConnection
 impl<L, C, Target> svc::Service<Target> for Connect<L, C>
 where
-    Target: tls::HasPeerIdentity,
+    Target: tls::HasPeerIdentity + HasAlpnProtocol,
     L: HasConfig + fmt::Debug + Clone,
     C: svc::MakeConnection<Target>,
     C::Connection: Io + Send + 'static,
@@ -79,6 +92,7 @@ where
         let server_name = target.peer_identity();
         let tls = self.local.clone().and_then(|l| server_name.map(|n| (n, l)));
         ConnectFuture::Init {
+            alpn_protocol: target.alpn_protocol(),
             future: self.inner.make_connection(target),
             tls,
         }
@@ -100,13 +114,20 @@ where
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         loop {
             *self = match self {
-                ConnectFuture::Init { future, tls } => {
+                ConnectFuture::Init {
+                    future,
+                    tls,
+                    alpn_protocol,
+                } => {
                     let io = try_ready!(future.poll());
 
                     match tls {
                         Conditional::Some((server_name, local_tls)) => {
                             trace!("initiating TLS to {}", server_name.as_ref());
-                            let future = tls::Connector::from(local_tls.tls_client_config())
+                            let config = alpn_protocol
+                                .and_then(|p| local_tls.tls_client_config_offering(p))
+                                .unwrap_or_else(|| local_tls.tls_client_config());
+                            let future = tls::Connector::from(config)
                                 .connect(server_name.as_dns_name_ref(), io);
                             ConnectFuture::Handshake {
                                 future,
@@ -124,9 +145,18 @@ where
                     server_name,
                 } => {
                     let io = try_ready!(future.poll());
+                    let protocol = {
+                        use super::rustls::Session;
+                        let (_, session) = io.get_ref();
+                        session.get_alpn_protocol().map(|p| p.to_vec())
+                    };
                     let io = BoxedIo::new(super::TlsIo::from(io));
-                    trace!("established TLS to {}", server_name.as_ref());
-                    let c = Connection::tls(io, Conditional::Some(server_name.clone()));
+                    trace!(
+                        "established TLS to {}; protocol={:?}",
+                        server_name.as_ref(),
+                        protocol.as_ref().map(|p| String::from_utf8_lossy(p)),
+                    );
+                    let c = Connection::tls(io, Conditional::Some(server_name.clone()), protocol);
                     return Ok(Async::Ready(c));
                 }
             };
@@ -31,6 +31,9 @@ pub struct Connection {
     /// Whether or not the connection is secured with TLS.
     tls_peer_identity: super::PeerIdentity,
 
+    /// The protocol that was negotiated via ALPN, if any.
+    negotiated_protocol: Option<Vec<u8>>,
+
     /// If true, the proxy should attempt to detect the protocol for this
     /// connection. If false, protocol detection should be skipped.
     detect_protocol: bool,
@@ -53,6 +56,7 @@ impl Connection {
             tls_peer_identity: Conditional::None(ReasonForNoIdentity::NoPeerName(
                 ReasonForNoPeerName::NotHttp,
             )),
+            negotiated_protocol: None,
             detect_protocol: false,
             orig_dst: None,
         }
@@ -67,6 +71,7 @@ impl Connection {
             io: BoxedIo::new(io),
             peek_buf,
             tls_peer_identity: Conditional::None(why_no_tls),
+            negotiated_protocol: None,
             detect_protocol: true,
             orig_dst: None,
         }
@@ -75,11 +80,13 @@ impl Connection {
     pub(super) fn tls(
         io: BoxedIo,
         tls_peer_identity: Conditional<identity::Name, super::ReasonForNoPeerName>,
+        negotiated_protocol: Option<Vec<u8>>,
     ) -> Self {
         Connection {
             io: io,
             peek_buf: BytesMut::new(),
             tls_peer_identity: tls_peer_identity.map_reason(|r| r.into()),
+            negotiated_protocol,
             detect_protocol: true,
             orig_dst: None,
         }
@@ -100,6 +107,17 @@ impl Connection {
     pub fn should_detect_protocol(&self) -> bool {
         self.detect_protocol
     }
+
+    /// Returns the protocol that was negotiated via ALPN, if any.
+    pub fn negotiated_protocol(&self) -> Option<&[u8]> {
+        self.negotiated_protocol.as_ref().map(Vec::as_slice)
+    }
+}
+
+impl super::HasNegotiatedProtocol for Connection {
+    fn negotiated_protocol(&self) -> Option<&[u8]> {
+        Connection::negotiated_protocol(self)
+    }
 }
 
 impl super::HasPeerIdentity for Connection {
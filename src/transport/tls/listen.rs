@@ -284,6 +284,15 @@ impl Handshake {
         let n = dns_names.first()?.to_owned();
         Some(identity::Name::from(dns::Name::from(n)))
     }
+
+    fn negotiated_protocol<S>(
+        tls: &tokio_rustls::TlsStream<S, rustls::ServerSession>,
+    ) -> Option<Vec<u8>> {
+        use super::rustls::Session;
+
+        let (_io, session) = tls.get_ref();
+        session.get_alpn_protocol().map(|p| p.to_vec())
+    }
 }
 
 impl Future for Handshake {
@@ -316,15 +325,20 @@ impl Future for Handshake {
                 }
                 Handshake::Upgrade(future) => {
                     let io = try_ready!(future.poll());
+                    let protocol = Self::negotiated_protocol(&io);
                     let client_id = Self::client_identity(&io)
                         .map(Conditional::Some)
                         .unwrap_or_else(|| {
                             Conditional::None(super::ReasonForNoPeerName::NotProvidedByRemote)
                         });
-                    trace!("accepted TLS connection; client={:?}", client_id);
+                    trace!(
+                        "accepted TLS connection; client={:?}; protocol={:?}",
+                        client_id,
+                        protocol.as_ref().map(|p| String::from_utf8_lossy(p)),
+                    );
 
                     let io = BoxedIo::new(super::TlsIo::from(io));
-                    return Ok(Async::Ready(Connection::tls(io, client_id)));
+                    return Ok(Async::Ready(Connection::tls(io, client_id, protocol)));
                 }
             }
         }
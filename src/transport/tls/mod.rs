@@ -28,6 +28,10 @@ pub type Conditional<T> = ::Conditional<T, ReasonForNoIdentity>;
 pub type PeerIdentity = Conditional<identity::Name>;
 pub type Status = Conditional<()>;
 
+/// The ALPN protocol that meshed peers negotiate when a connection carries
+/// HTTP/2, so that the server need not detect the connection's protocol.
+pub const L5D_H2: &[u8] = b"l5d-h2";
+
 pub trait HasPeerIdentity {
     fn peer_identity(&self) -> PeerIdentity;
 }
@@ -36,6 +40,11 @@ pub trait HasStatus {
     fn tls_status(&self) -> Status;
 }
 
+/// Describes the protocol that was negotiated via ALPN on a connection.
+pub trait HasNegotiatedProtocol {
+    fn negotiated_protocol(&self) -> Option<&[u8]>;
+}
+
 impl<T: HasPeerIdentity> HasStatus for T {
     fn tls_status(&self) -> Status {
         self.peer_identity().map(|_| ())
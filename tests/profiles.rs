@@ -29,6 +29,7 @@ macro_rules! profile_test {
         let counter = AtomicUsize::new(0);
         let counter2 = AtomicUsize::new(0);
         let counter3 = AtomicUsize::new(0);
+        let counter4 = ::std::sync::Arc::new(AtomicUsize::new(0));
         let host = "profiles.test.svc.cluster.local";
 
         let srv = server::$http()
@@ -87,6 +88,22 @@ macro_rules! profile_test {
                         .unwrap()
                 }
             })
+            .route_async("/0.5/body", move |req| {
+                let counter4 = counter4.clone();
+                req.into_body().concat2().map(move |body| {
+                    if counter4.fetch_add(1, Ordering::Relaxed) % 2 == 0 {
+                        Response::builder()
+                            .status(533)
+                            .body("nope".into())
+                            .unwrap()
+                    } else {
+                        Response::builder()
+                            .status(200)
+                            .body(body)
+                            .unwrap()
+                    }
+                })
+            })
             .run();
         let ctrl = controller::new();
 
@@ -210,7 +227,7 @@ fn does_not_retry_if_earlier_response_class_is_success() {
 }
 
 #[test]
-fn does_not_retry_if_request_has_body() {
+fn retry_with_small_request_body() {
     profile_test! {
         routes: [
             controller::route()
@@ -220,12 +237,41 @@ fn does_not_retry_if_request_has_body() {
         ],
         budget: Some(controller::retry_budget(Duration::from_secs(10), 0.1, 1)),
         with_client: |client: client::Client| {
-            let req = client.request_builder("/0.5")
+            let req = client.request_builder("/0.5/body")
                 .method("POST")
                 .body("req has a body".into())
                 .unwrap();
             let res = client.request_body(req);
+            assert_eq!(res.status(), 200);
+            let body = res.into_body().concat2().wait().unwrap();
+            assert_eq!(body, "req has a body");
+        }
+    }
+}
+
+#[test]
+fn does_not_retry_if_request_body_exceeds_buffer() {
+    profile_test! {
+        routes: [
+            controller::route()
+                .request_any()
+                .response_failure(500..600)
+                .retryable(true)
+        ],
+        budget: Some(controller::retry_budget(Duration::from_secs(10), 0.1, 1)),
+        with_client: |client: client::Client| {
+            let req = client.request_builder("/0.5/body")
+                .method("POST")
+                .body(vec![b'x'; 1024 * 100].into())
+                .unwrap();
+            let res = client.request_body(req);
             assert_eq!(res.status(), 533);
+        },
+        with_metrics: |metrics: client::Client| {
+            assert_eventually_contains!(
+                metrics.get("/metrics"),
+                "route_actual_retry_skipped_total{direction=\"outbound\",dst=\"profiles.test.svc.cluster.local:80\",skipped=\"body_too_large\"} 1"
+            );
         }
     }
 }